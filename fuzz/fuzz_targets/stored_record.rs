@@ -0,0 +1,11 @@
+#![no_main]
+//! Feeds arbitrary bytes to `from_stored`, which reads every cached user and group, so values that
+//! were truncated or written by something else come back as errors rather than panics.
+use libfuzzer_sys::fuzz_target;
+use slack_user_cache::libs::redis::from_stored;
+use slack_user_cache::libs::{SlackUser, SlackUserGroup};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = from_stored::<SlackUser>(data);
+    let _ = from_stored::<SlackUserGroup>(data);
+});
@@ -0,0 +1,63 @@
+use tracing::info;
+
+use super::redis::run_updater;
+use super::server::serve;
+use crate::error::CliErrors;
+use crate::libs::health::Components;
+use crate::libs::progress::Progress;
+use crate::libs::RedisPools;
+use crate::{AllInOneArgs, UpdateRedisArgs, WebArgs};
+
+/// Runs the web server and the scheduled updater in one process, sharing Redis connections. The
+/// options both take are given once, and each reports its health apart, as `/healthz/web` and
+/// `/healthz/updater`.
+pub async fn all_in_one(args: AllInOneArgs, progress: &Progress) -> Result<(), CliErrors> {
+    if args.updater_opts.schedule.is_none() && args.updater_opts.interval.is_none() {
+        return Err(CliErrors::UnscheduledUpdater);
+    }
+
+    let pools = RedisPools::connect(&args.shared_opts.redis_opts.redis_address)?;
+    let web_args = WebArgs {
+        shared_opts: args.shared_opts.clone(),
+        web_opts: args.web_opts,
+    };
+    let updater_args = UpdateRedisArgs {
+        shared_opts: args.shared_opts,
+        updater_opts: args.updater_opts,
+    };
+    let components = Components::default();
+    info!("Running the web server and updater together");
+    tokio::try_join!(
+        serve(&web_args, &pools, &components),
+        run_updater(&updater_args, progress, &pools, &components),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Clap;
+
+    use super::*;
+
+    #[test]
+    fn options_of_both_commands_are_taken_with_the_shared_ones_once() {
+        let args = AllInOneArgs::parse_from(&[
+            "all-in-one",
+            "--server-id",
+            "test",
+            "--redis-address",
+            "redis://cache/",
+            "--listen-server",
+            "0.0.0.0:9000",
+            "--interval",
+            "60",
+        ]);
+        assert_eq!(
+            args.shared_opts.redis_opts.redis_address,
+            vec!["redis://cache/"]
+        );
+        assert_eq!(args.web_opts.listen_server, "0.0.0.0:9000");
+        assert_eq!(args.updater_opts.interval, Some(60));
+    }
+}
@@ -0,0 +1,101 @@
+use std::fs;
+
+use serde::Serialize;
+use tracing::info;
+
+use crate::error::{CliErrors, RedisErrors};
+use crate::libs::anonymize::Pseudonymizer;
+use crate::libs::{RedisResponse, RedisServer};
+use crate::ExportArgs;
+
+/// Everything in the cache, in one document.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Serialize)]
+struct Export<U, G> {
+    users: Vec<U>,
+    user_groups: Vec<G>,
+}
+
+pub async fn export(args: &ExportArgs) -> Result<(), CliErrors> {
+    let redis_server = RedisServer::new(&args.redis_opts.redis_address)
+        .await?
+        .with_key_layout(args.key_opts.layout())
+        .with_email_hasher(args.privacy_opts.email_hasher())
+        .with_strict_scans(true);
+
+    let mut users = all(redis_server.get_all_users().await)?;
+    users.sort();
+    let mut user_groups = all(redis_server.get_all_user_groups().await)?;
+    user_groups.sort();
+    let counts = format!(
+        "{} users and {} user groups",
+        users.len(),
+        user_groups.len()
+    );
+
+    let document = match &args.pseudonym_key {
+        Some(key) if args.anonymize => {
+            let pseudonymizer = Pseudonymizer::new(key);
+            // Sorted by pseudonym, as Slack ids would give away roughly when users joined.
+            let mut users: Vec<_> = users.iter().map(|user| pseudonymizer.user(user)).collect();
+            users.sort_by(|a, b| a.id.cmp(&b.id));
+            serde_json::to_string_pretty(&Export {
+                users,
+                user_groups: user_groups
+                    .iter()
+                    .map(|group| pseudonymizer.group(group))
+                    .collect(),
+            })
+        }
+        _ => serde_json::to_string_pretty(&Export { users, user_groups }),
+    }
+    .expect("exports are always serializable");
+
+    match &args.output {
+        Some(path) => {
+            fs::write(path, document).map_err(|e| CliErrors::UnableToWriteExport {
+                path: path.display().to_string(),
+                source: e,
+            })?;
+            info!("Exported {} to {}", counts, path.display());
+        }
+        // Logs are written to stderr while the export is printed.
+        None => println!("{}", document),
+    }
+    Ok(())
+}
+
+fn all<T>(response: RedisResponse<Vec<T>, RedisErrors>) -> Result<Vec<T>, RedisErrors> {
+    match response {
+        RedisResponse::Ok(values) => Ok(values),
+        RedisResponse::Missing => Ok(Vec::new()),
+        RedisResponse::Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fake_redis::FakeRedis;
+    use mobc_redis::redis::{self, AsyncCommands};
+
+    #[tokio::test]
+    async fn unreadable_users_fail_strict_scans() {
+        let redis = FakeRedis::start().await;
+        let client = redis::Client::open(redis.address()).unwrap();
+        let mut con = client.get_async_connection().await.unwrap();
+        let _: () = con.set("user:id:U1", "not a user").await.unwrap();
+
+        let redis_server = RedisServer::new(&[redis.address()]).await.unwrap();
+        assert!(matches!(
+            redis_server.get_all_users().await,
+            RedisResponse::Ok(users) if users.is_empty()
+        ));
+
+        let redis_server = redis_server.with_strict_scans(true);
+        assert!(matches!(
+            redis_server.get_all_users().await,
+            RedisResponse::Err(RedisErrors::UnableToReadValue { .. })
+        ));
+    }
+}
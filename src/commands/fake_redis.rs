@@ -0,0 +1,1024 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::libs::redis::{
+    ADJUST_COUNT_SCRIPT, CLAIM_LEASE_SCRIPT, COUNT_REQUEST_SCRIPT, MARK_MODIFIED_SCRIPT,
+};
+
+/// An in-memory stand-in for Redis, speaking enough of its protocol for `RedisServer` to run on
+/// in tests. Expiry is recorded but never acted on, and the scripts `RedisServer` runs are done
+/// natively.
+pub struct FakeRedis {
+    address: String,
+}
+
+impl FakeRedis {
+    /// Starts answering on a free local port.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("redis://{}", listener.local_addr().unwrap());
+        let store = Arc::new(Mutex::new(Store::default()));
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, store.clone()));
+            }
+        });
+
+        Self { address }
+    }
+
+    /// The URL to connect to it with.
+    pub fn address(&self) -> String {
+        self.address.clone()
+    }
+}
+
+async fn serve(stream: TcpStream, store: Arc<Mutex<Store>>) {
+    let (read, mut write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+    let mut connection = Connection::default();
+    while let Some(command) = read_command(&mut reader).await {
+        let mut out = Vec::new();
+        connection.handle(&store, command).encode(&mut out);
+        if write.write_all(&out).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn read_line(reader: &mut BufReader<OwnedReadHalf>) -> Option<Vec<u8>> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line).await.ok()? == 0 {
+        return None;
+    }
+    while line.last() == Some(&b'\n') || line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Some(line)
+}
+
+/// Reads a command, sent as an array of bulk strings.
+async fn read_command(reader: &mut BufReader<OwnedReadHalf>) -> Option<Vec<Vec<u8>>> {
+    let header = read_line(reader).await?;
+    let count: usize = std::str::from_utf8(header.strip_prefix(b"*")?)
+        .ok()?
+        .parse()
+        .ok()?;
+
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let header = read_line(reader).await?;
+        let len: usize = std::str::from_utf8(header.strip_prefix(b"$")?)
+            .ok()?
+            .parse()
+            .ok()?;
+        let mut arg = vec![0; len + 2];
+        reader.read_exact(&mut arg).await.ok()?;
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Some(args)
+}
+
+enum Reply {
+    Status(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+impl Reply {
+    fn ok() -> Self {
+        Reply::Status("OK")
+    }
+
+    fn bulk(value: impl Into<Vec<u8>>) -> Self {
+        Reply::Bulk(Some(value.into()))
+    }
+
+    fn bulks(values: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        Reply::Array(Some(values.into_iter().map(Reply::bulk).collect()))
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Reply::Status(status) => out.extend(format!("+{}\r\n", status).into_bytes()),
+            Reply::Error(message) => out.extend(format!("-{}\r\n", message).into_bytes()),
+            Reply::Integer(value) => out.extend(format!(":{}\r\n", value).into_bytes()),
+            Reply::Bulk(None) => out.extend(b"$-1\r\n"),
+            Reply::Bulk(Some(value)) => {
+                out.extend(format!("${}\r\n", value.len()).into_bytes());
+                out.extend(value);
+                out.extend(b"\r\n");
+            }
+            Reply::Array(None) => out.extend(b"*-1\r\n"),
+            Reply::Array(Some(items)) => {
+                out.extend(format!("*{}\r\n", items.len()).into_bytes());
+                for item in items {
+                    item.encode(out);
+                }
+            }
+        }
+    }
+}
+
+/// What a connection is in the middle of: a transaction, or loading a script.
+#[derive(Default)]
+struct Connection {
+    queued: Option<Vec<Vec<Vec<u8>>>>,
+    loaded: Option<Vec<u8>>,
+}
+
+impl Connection {
+    fn handle(&mut self, store: &Mutex<Store>, args: Vec<Vec<u8>>) -> Reply {
+        let name = match args.first() {
+            Some(name) => String::from_utf8_lossy(name).to_uppercase(),
+            None => return Reply::Error("ERR empty command".to_owned()),
+        };
+
+        match name.as_str() {
+            "MULTI" => {
+                self.queued = Some(Vec::new());
+                Reply::ok()
+            }
+            "DISCARD" => {
+                self.queued = None;
+                Reply::ok()
+            }
+            "EXEC" => {
+                let queued = self.queued.take().unwrap_or_default();
+                let mut store = store.lock().unwrap();
+                Reply::Array(Some(
+                    queued
+                        .into_iter()
+                        .map(|command| store.execute(&command))
+                        .collect(),
+                ))
+            }
+            _ if self.queued.is_some() => {
+                self.queued.as_mut().unwrap().push(args);
+                Reply::Status("QUEUED")
+            }
+            "SCRIPT" => {
+                self.loaded = args.get(2).cloned();
+                Reply::bulk("loaded")
+            }
+            "EVALSHA" => {
+                let mut store = store.lock().unwrap();
+                let sha = args[1].clone();
+                if let Some(script) = self.loaded.take() {
+                    store.scripts.insert(sha.clone(), script);
+                }
+                match store.scripts.get(&sha).cloned() {
+                    Some(script) => store.run_script(&script, &args[2..]),
+                    None => Reply::Error("NOSCRIPT No matching script.".to_owned()),
+                }
+            }
+            "EVAL" => {
+                let script = args[1].clone();
+                store.lock().unwrap().run_script(&script, &args[2..])
+            }
+            _ => store.lock().unwrap().execute(&args),
+        }
+    }
+}
+
+enum Value {
+    String(Vec<u8>),
+    Set(BTreeSet<Vec<u8>>),
+    Hash(BTreeMap<Vec<u8>, Vec<u8>>),
+    SortedSet(BTreeMap<Vec<u8>, f64>),
+    Stream(Vec<(StreamId, Vec<Vec<u8>>)>),
+}
+
+impl Value {
+    fn is_empty(&self) -> bool {
+        match self {
+            Value::String(_) => false,
+            Value::Set(members) => members.is_empty(),
+            Value::Hash(fields) => fields.is_empty(),
+            Value::SortedSet(members) => members.is_empty(),
+            Value::Stream(_) => false,
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Set(_) => "set",
+            Value::Hash(_) => "hash",
+            Value::SortedSet(_) => "zset",
+            Value::Stream(_) => "stream",
+        }
+    }
+}
+
+type StreamId = (u64, u64);
+
+const WRONG_TYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+#[derive(Default)]
+struct Store {
+    values: BTreeMap<Vec<u8>, Value>,
+    /// Seconds each key was last given to live.
+    ttls: HashMap<Vec<u8>, i64>,
+    scripts: HashMap<Vec<u8>, Vec<u8>>,
+    last_stream_id: StreamId,
+}
+
+macro_rules! typed {
+    ($name:ident, $name_mut:ident, $variant:ident, $type:ty) => {
+        fn $name(&self, key: &[u8]) -> Result<Option<&$type>, String> {
+            match self.values.get(key) {
+                None => Ok(None),
+                Some(Value::$variant(value)) => Ok(Some(value)),
+                Some(_) => Err(WRONG_TYPE.to_owned()),
+            }
+        }
+
+        fn $name_mut(&mut self, key: &[u8]) -> Result<&mut $type, String> {
+            match self
+                .values
+                .entry(key.to_vec())
+                .or_insert_with(|| Value::$variant(Default::default()))
+            {
+                Value::$variant(value) => Ok(value),
+                _ => Err(WRONG_TYPE.to_owned()),
+            }
+        }
+    };
+}
+
+impl Store {
+    typed!(string, string_mut, String, Vec<u8>);
+    typed!(set, set_mut, Set, BTreeSet<Vec<u8>>);
+    typed!(hash, hash_mut, Hash, BTreeMap<Vec<u8>, Vec<u8>>);
+    typed!(
+        sorted_set,
+        sorted_set_mut,
+        SortedSet,
+        BTreeMap<Vec<u8>, f64>
+    );
+    typed!(stream, stream_mut, Stream, Vec<(StreamId, Vec<Vec<u8>>)>);
+
+    fn execute(&mut self, args: &[Vec<u8>]) -> Reply {
+        let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+        let reply = match self.run(&name, &args[1..]) {
+            Ok(reply) => reply,
+            Err(message) => Reply::Error(message),
+        };
+
+        // Like Redis, collections are gone once they're emptied.
+        let emptied: Vec<Vec<u8>> = self
+            .values
+            .iter()
+            .filter(|(_, value)| value.is_empty())
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in emptied {
+            self.remove(&key);
+        }
+        reply
+    }
+
+    fn remove(&mut self, key: &[u8]) -> bool {
+        self.ttls.remove(key);
+        self.values.remove(key).is_some()
+    }
+
+    fn set_string(&mut self, key: &[u8], value: &[u8], ttl: Option<i64>) {
+        self.values
+            .insert(key.to_vec(), Value::String(value.to_vec()));
+        match ttl {
+            Some(ttl) => self.ttls.insert(key.to_vec(), ttl),
+            None => self.ttls.remove(key),
+        };
+    }
+
+    fn run(&mut self, name: &str, args: &[Vec<u8>]) -> Result<Reply, String> {
+        let arg = |index: usize| -> Result<&[u8], String> {
+            args.get(index)
+                .map(Vec::as_slice)
+                .ok_or_else(|| format!("ERR wrong number of arguments for '{}'", name))
+        };
+
+        Ok(match name {
+            "PING" => Reply::Status("PONG"),
+            "SELECT" | "FLUSHDB" | "FLUSHALL" => Reply::ok(),
+            "INFO" => {
+                Reply::bulk("# Server\r\nredis_version:6.2.0\r\n# Memory\r\nused_memory:0\r\n")
+            }
+            "DBSIZE" => Reply::Integer(self.values.len() as i64),
+            "TYPE" => Reply::Status(self.values.get(arg(0)?).map_or("none", Value::type_name)),
+            "MEMORY" => match self.values.get(arg(1)?) {
+                Some(_) => Reply::Integer(64),
+                None => Reply::Bulk(None),
+            },
+
+            "GET" => Reply::Bulk(self.string(arg(0)?)?.cloned()),
+            "MGET" => Reply::Array(Some(
+                args.iter()
+                    .map(|key| match self.values.get(key) {
+                        Some(Value::String(value)) => Reply::bulk(value.clone()),
+                        _ => Reply::Bulk(None),
+                    })
+                    .collect(),
+            )),
+            "SET" => {
+                let (key, value) = (arg(0)?, arg(1)?);
+                let mut ttl = None;
+                let mut only_new = false;
+                let mut index = 2;
+                while index < args.len() {
+                    match upper(&args[index]).as_str() {
+                        "NX" => only_new = true,
+                        "EX" => {
+                            ttl = Some(integer(arg(index + 1)?)?);
+                            index += 1;
+                        }
+                        "PX" => {
+                            ttl = Some(integer(arg(index + 1)?)? / 1000);
+                            index += 1;
+                        }
+                        _ => {}
+                    }
+                    index += 1;
+                }
+                if only_new && self.values.contains_key(key) {
+                    Reply::Bulk(None)
+                } else {
+                    self.set_string(key, value, ttl);
+                    Reply::ok()
+                }
+            }
+            "GETSET" => {
+                let previous = self.string(arg(0)?)?.cloned();
+                self.set_string(arg(0)?, arg(1)?, None);
+                Reply::Bulk(previous)
+            }
+            "SETNX" => {
+                if self.values.contains_key(arg(0)?) {
+                    Reply::Integer(0)
+                } else {
+                    self.set_string(arg(0)?, arg(1)?, None);
+                    Reply::Integer(1)
+                }
+            }
+            "SETEX" => {
+                self.set_string(arg(0)?, arg(2)?, Some(integer(arg(1)?)?));
+                Reply::ok()
+            }
+            "INCR" | "INCRBY" | "DECR" | "DECRBY" => {
+                let by = match name {
+                    "INCR" => 1,
+                    "DECR" => -1,
+                    "INCRBY" => integer(arg(1)?)?,
+                    _ => -integer(arg(1)?)?,
+                };
+                let value = self.string_mut(arg(0)?)?;
+                let current = if value.is_empty() { 0 } else { integer(value)? };
+                *value = (current + by).to_string().into_bytes();
+                Reply::Integer(current + by)
+            }
+            "DEL" | "UNLINK" => {
+                Reply::Integer(args.iter().filter(|key| self.remove(key)).count() as i64)
+            }
+            "EXISTS" => Reply::Integer(
+                args.iter()
+                    .filter(|key| self.values.contains_key(*key))
+                    .count() as i64,
+            ),
+            "EXPIRE" | "PEXPIRE" => {
+                let key = arg(0)?;
+                if self.values.contains_key(key) {
+                    let mut ttl = integer(arg(1)?)?;
+                    if name == "PEXPIRE" {
+                        ttl /= 1000;
+                    }
+                    self.ttls.insert(key.to_vec(), ttl);
+                    Reply::Integer(1)
+                } else {
+                    Reply::Integer(0)
+                }
+            }
+            "TTL" | "PTTL" => {
+                let key = arg(0)?;
+                let scale = if name == "PTTL" { 1000 } else { 1 };
+                Reply::Integer(match (self.values.contains_key(key), self.ttls.get(key)) {
+                    (false, _) => -2,
+                    (true, None) => -1,
+                    (true, Some(ttl)) => ttl * scale,
+                })
+            }
+            "KEYS" => Reply::bulks(self.matching_keys(arg(0)?)),
+            "SCAN" => {
+                let mut pattern: &[u8] = b"*";
+                let mut index = 1;
+                while index < args.len() {
+                    if upper(&args[index]) == "MATCH" {
+                        pattern = arg(index + 1)?;
+                    }
+                    index += 2;
+                }
+                Reply::Array(Some(vec![
+                    Reply::bulk("0"),
+                    Reply::bulks(self.matching_keys(pattern)),
+                ]))
+            }
+
+            "SADD" => {
+                let members = self.set_mut(arg(0)?)?;
+                Reply::Integer(
+                    args[1..]
+                        .iter()
+                        .filter(|member| members.insert(member.to_vec()))
+                        .count() as i64,
+                )
+            }
+            "SREM" => {
+                let members = self.set_mut(arg(0)?)?;
+                Reply::Integer(
+                    args[1..]
+                        .iter()
+                        .filter(|member| members.remove(*member))
+                        .count() as i64,
+                )
+            }
+            "SMEMBERS" => Reply::bulks(self.set(arg(0)?)?.cloned().unwrap_or_default()),
+            "SISMEMBER" => Reply::Integer(
+                self.set(arg(0)?)?
+                    .map_or(false, |members| members.contains(arg(1).unwrap()))
+                    as i64,
+            ),
+            "SCARD" => Reply::Integer(self.set(arg(0)?)?.map_or(0, BTreeSet::len) as i64),
+
+            "HSET" | "HMSET" => {
+                let fields = self.hash_mut(arg(0)?)?;
+                let mut added = 0;
+                for pair in args[1..].chunks(2) {
+                    if pair.len() == 2 && fields.insert(pair[0].clone(), pair[1].clone()).is_none()
+                    {
+                        added += 1;
+                    }
+                }
+                if name == "HMSET" {
+                    Reply::ok()
+                } else {
+                    Reply::Integer(added)
+                }
+            }
+            "HGET" => Reply::Bulk(
+                self.hash(arg(0)?)?
+                    .and_then(|fields| fields.get(arg(1).unwrap()).cloned()),
+            ),
+            "HDEL" => {
+                let fields = self.hash_mut(arg(0)?)?;
+                Reply::Integer(
+                    args[1..]
+                        .iter()
+                        .filter(|field| fields.remove(*field).is_some())
+                        .count() as i64,
+                )
+            }
+            "HGETALL" => Reply::bulks(
+                self.hash(arg(0)?)?
+                    .into_iter()
+                    .flatten()
+                    .flat_map(|(field, value)| vec![field.clone(), value.clone()]),
+            ),
+            "HKEYS" => Reply::bulks(
+                self.hash(arg(0)?)?
+                    .into_iter()
+                    .flat_map(|fields| fields.keys().cloned()),
+            ),
+            "HVALS" => Reply::bulks(
+                self.hash(arg(0)?)?
+                    .into_iter()
+                    .flat_map(|fields| fields.values().cloned()),
+            ),
+            "HLEN" => Reply::Integer(self.hash(arg(0)?)?.map_or(0, BTreeMap::len) as i64),
+
+            "ZADD" => {
+                // Skips options like `NX` to the first score.
+                let mut index = 1;
+                while index < args.len() && parse_score(&args[index]).is_err() {
+                    index += 1;
+                }
+                let members = self.sorted_set_mut(arg(0)?)?;
+                let mut added = 0;
+                for pair in args[index..].chunks(2) {
+                    if pair.len() == 2 {
+                        let score = parse_score(&pair[0])?;
+                        if members.insert(pair[1].clone(), score).is_none() {
+                            added += 1;
+                        }
+                    }
+                }
+                Reply::Integer(added)
+            }
+            "ZREM" => {
+                let members = self.sorted_set_mut(arg(0)?)?;
+                Reply::Integer(
+                    args[1..]
+                        .iter()
+                        .filter(|member| members.remove(*member).is_some())
+                        .count() as i64,
+                )
+            }
+            "ZCARD" => Reply::Integer(self.sorted(arg(0)?)?.len() as i64),
+            "ZSCORE" => Reply::Bulk(
+                self.sorted_set(arg(0)?)?
+                    .and_then(|members| members.get(arg(1).unwrap()))
+                    .map(|score| format_score(*score)),
+            ),
+            "ZRANGE" | "ZREVRANGE" => {
+                let mut sorted = self.sorted(arg(0)?)?;
+                if name == "ZREVRANGE" {
+                    sorted.reverse();
+                }
+                let range = rank_range(sorted.len(), integer(arg(1)?)?, integer(arg(2)?)?);
+                scored_reply(&sorted[range], has_flag(args, "WITHSCORES"))
+            }
+            "ZREMRANGEBYRANK" => {
+                let sorted = self.sorted(arg(0)?)?;
+                let range = rank_range(sorted.len(), integer(arg(1)?)?, integer(arg(2)?)?);
+                let members = self.sorted_set_mut(arg(0)?)?;
+                for (member, _) in &sorted[range.clone()] {
+                    members.remove(member);
+                }
+                Reply::Integer(range.len() as i64)
+            }
+            "ZRANGEBYSCORE" | "ZREVRANGEBYSCORE" | "ZCOUNT" | "ZREMRANGEBYSCORE" => {
+                let reverse = name == "ZREVRANGEBYSCORE";
+                let (min, max) = if reverse {
+                    (arg(2)?, arg(1)?)
+                } else {
+                    (arg(1)?, arg(2)?)
+                };
+                let (min, max) = (score_bound(min)?, score_bound(max)?);
+                let mut matched: Vec<(Vec<u8>, f64)> = self
+                    .sorted(arg(0)?)?
+                    .into_iter()
+                    .filter(|(_, score)| min.below(score) && max.above(score))
+                    .collect();
+                if reverse {
+                    matched.reverse();
+                }
+                match name {
+                    "ZCOUNT" => Reply::Integer(matched.len() as i64),
+                    "ZREMRANGEBYSCORE" => {
+                        let members = self.sorted_set_mut(arg(0)?)?;
+                        for (member, _) in &matched {
+                            members.remove(member);
+                        }
+                        Reply::Integer(matched.len() as i64)
+                    }
+                    _ => scored_reply(&limit(args, matched)?, has_flag(args, "WITHSCORES")),
+                }
+            }
+            "ZRANGEBYLEX" => {
+                let (min, max) = (lex_bound(arg(1)?)?, lex_bound(arg(2)?)?);
+                let matched: Vec<(Vec<u8>, f64)> = self
+                    .sorted(arg(0)?)?
+                    .into_iter()
+                    .filter(|(member, _)| min.below(member) && max.above(member))
+                    .collect();
+                scored_reply(&limit(args, matched)?, false)
+            }
+
+            "XADD" => {
+                let mut index = 1;
+                let mut max_len = None;
+                loop {
+                    match upper(arg(index)?).as_str() {
+                        "NOMKSTREAM" => index += 1,
+                        "MAXLEN" => {
+                            index += 1;
+                            if arg(index)? == b"~" || arg(index)? == b"=" {
+                                index += 1;
+                            }
+                            max_len = Some(integer(arg(index)?)? as usize);
+                            index += 1;
+                        }
+                        _ => break,
+                    }
+                }
+                let id = match arg(index)? {
+                    b"*" => {
+                        let millis = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap()
+                            .as_millis() as u64;
+                        if millis > self.last_stream_id.0 {
+                            (millis, 0)
+                        } else {
+                            (self.last_stream_id.0, self.last_stream_id.1 + 1)
+                        }
+                    }
+                    id => stream_id(id, 0)?,
+                };
+                self.last_stream_id = id;
+                let fields = args[index + 1..].to_vec();
+                let entries = self.stream_mut(arg(0)?)?;
+                entries.push((id, fields));
+                if let Some(max_len) = max_len {
+                    let excess = entries.len().saturating_sub(max_len);
+                    entries.drain(..excess);
+                }
+                Reply::bulk(format!("{}-{}", id.0, id.1))
+            }
+            "XLEN" => Reply::Integer(self.stream(arg(0)?)?.map_or(0, Vec::len) as i64),
+            "XRANGE" | "XREVRANGE" => {
+                let reverse = name == "XREVRANGE";
+                let (start, end) = if reverse {
+                    (arg(2)?, arg(1)?)
+                } else {
+                    (arg(1)?, arg(2)?)
+                };
+                let (start, end) = (stream_bound(start, 0)?, stream_bound(end, u64::MAX)?);
+                let mut entries: Vec<(StreamId, Vec<Vec<u8>>)> = self
+                    .stream(arg(0)?)?
+                    .into_iter()
+                    .flatten()
+                    .filter(|(id, _)| start.below(id) && end.above(id))
+                    .cloned()
+                    .collect();
+                if reverse {
+                    entries.reverse();
+                }
+                if let Some(count) = option_value(args, "COUNT") {
+                    entries.truncate(integer(count)? as usize);
+                }
+                stream_reply(&entries)
+            }
+            "XREAD" => {
+                let streams = args
+                    .iter()
+                    .position(|arg| upper(arg) == "STREAMS")
+                    .ok_or_else(|| "ERR syntax error".to_owned())?;
+                let keys = &args[streams + 1..];
+                let (keys, ids) = keys.split_at(keys.len() / 2);
+                let mut replies = Vec::new();
+                for (key, id) in keys.iter().zip(ids) {
+                    let after = if id.as_slice() == b"$" {
+                        self.last_stream_id
+                    } else {
+                        stream_id(id, 0)?
+                    };
+                    let mut entries: Vec<(StreamId, Vec<Vec<u8>>)> = self
+                        .stream(key)?
+                        .into_iter()
+                        .flatten()
+                        .filter(|(id, _)| *id > after)
+                        .cloned()
+                        .collect();
+                    if let Some(count) = option_value(args, "COUNT") {
+                        entries.truncate(integer(count)? as usize);
+                    }
+                    if !entries.is_empty() {
+                        replies.push(Reply::Array(Some(vec![
+                            Reply::bulk(key.clone()),
+                            stream_reply(&entries),
+                        ])));
+                    }
+                }
+                if replies.is_empty() {
+                    Reply::Array(None)
+                } else {
+                    Reply::Array(Some(replies))
+                }
+            }
+
+            _ => return Err(format!("ERR unknown command '{}'", name)),
+        })
+    }
+
+    /// Runs one of the scripts `RedisServer` uses, which it must send as written in
+    /// `libs::redis`. Any other script is an error, so one changed there without being changed here
+    /// fails the tests rather than being run as something it no longer is.
+    fn run_script(&mut self, script: &[u8], args: &[Vec<u8>]) -> Reply {
+        let keys: usize = match std::str::from_utf8(&args[0])
+            .ok()
+            .and_then(|n| n.parse().ok())
+        {
+            Some(keys) => keys,
+            None => return Reply::Error("ERR value is not an integer".to_owned()),
+        };
+        let (keys, argv) = args[1..].split_at(keys);
+        let command =
+            |parts: &[&[u8]]| -> Vec<Vec<u8>> { parts.iter().map(|part| part.to_vec()).collect() };
+
+        if script == CLAIM_LEASE_SCRIPT.as_bytes() {
+            let holder = self.values.get(&keys[0]).and_then(|value| match value {
+                Value::String(holder) => Some(holder.clone()),
+                _ => None,
+            });
+            if holder.is_none() || holder.as_ref() == Some(&argv[0]) {
+                self.execute(&command(&[b"SET", &keys[0], &argv[0], b"EX", &argv[1]]));
+                Reply::Integer(1)
+            } else {
+                Reply::Integer(0)
+            }
+        } else if script == ADJUST_COUNT_SCRIPT.as_bytes() {
+            if self.values.contains_key(&keys[0]) {
+                self.execute(&command(&[b"INCRBY", &keys[0], &argv[0]]))
+            } else {
+                Reply::Bulk(None)
+            }
+        } else if script == COUNT_REQUEST_SCRIPT.as_bytes() {
+            let count = match self.execute(&command(&[b"INCR", &keys[0]])) {
+                Reply::Integer(count) => count,
+                other => return other,
+            };
+            if count == 1 {
+                self.execute(&command(&[b"EXPIRE", &keys[0], &argv[0]]));
+            }
+            Reply::Integer(count)
+        } else if script == MARK_MODIFIED_SCRIPT.as_bytes() {
+            if argv[2] == b"1" || !self.values.contains_key(&keys[0]) {
+                self.execute(&command(&[b"SET", &keys[0], &argv[0], b"EX", &argv[1]]));
+                Reply::Integer(1)
+            } else {
+                self.execute(&command(&[b"EXPIRE", &keys[0], &argv[1]]));
+                Reply::Integer(0)
+            }
+        } else {
+            Reply::Error(format!(
+                "ERR script not faked: {}",
+                String::from_utf8_lossy(script).trim()
+            ))
+        }
+    }
+
+    fn matching_keys(&self, pattern: &[u8]) -> Vec<Vec<u8>> {
+        self.values
+            .keys()
+            .filter(|key| glob_matches(pattern, key))
+            .cloned()
+            .collect()
+    }
+
+    /// The members of a sorted set in order, by score then member.
+    fn sorted(&self, key: &[u8]) -> Result<Vec<(Vec<u8>, f64)>, String> {
+        let mut sorted: Vec<(Vec<u8>, f64)> = self
+            .sorted_set(key)?
+            .into_iter()
+            .flatten()
+            .map(|(member, score)| (member.clone(), *score))
+            .collect();
+        sorted.sort_by(|a, b| {
+            a.1.partial_cmp(&b.1)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.0.cmp(&b.0))
+        });
+        Ok(sorted)
+    }
+}
+
+fn upper(arg: &[u8]) -> String {
+    String::from_utf8_lossy(arg).to_uppercase()
+}
+
+fn integer(arg: &[u8]) -> Result<i64, String> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|arg| arg.parse().ok())
+        .ok_or_else(|| "ERR value is not an integer or out of range".to_owned())
+}
+
+fn parse_score(arg: &[u8]) -> Result<f64, String> {
+    match upper(arg).as_str() {
+        "-INF" => Ok(f64::NEG_INFINITY),
+        "+INF" | "INF" => Ok(f64::INFINITY),
+        other => other
+            .parse()
+            .map_err(|_| "ERR value is not a valid float".to_owned()),
+    }
+}
+
+fn format_score(score: f64) -> Vec<u8> {
+    score.to_string().into_bytes()
+}
+
+fn has_flag(args: &[Vec<u8>], flag: &str) -> bool {
+    args.iter().any(|arg| upper(arg) == flag)
+}
+
+/// The argument after `option`, like the count in `COUNT 10`.
+fn option_value<'a>(args: &'a [Vec<u8>], option: &str) -> Option<&'a [u8]> {
+    let index = args.iter().position(|arg| upper(arg) == option)?;
+    args.get(index + 1).map(Vec::as_slice)
+}
+
+/// Applies `LIMIT <offset> <count>`, if it was given.
+fn limit<T>(args: &[Vec<u8>], items: Vec<T>) -> Result<Vec<T>, String> {
+    let index = match args.iter().position(|arg| upper(arg) == "LIMIT") {
+        Some(index) => index,
+        None => return Ok(items),
+    };
+    let offset = integer(args.get(index + 1).map_or(&b""[..], Vec::as_slice))? as usize;
+    let count = integer(args.get(index + 2).map_or(&b""[..], Vec::as_slice))?;
+    let items = items.into_iter().skip(offset);
+    Ok(if count < 0 {
+        items.collect()
+    } else {
+        items.take(count as usize).collect()
+    })
+}
+
+/// The ranks `start` to `stop` cover, counting negative ones from the end.
+fn rank_range(len: usize, start: i64, stop: i64) -> std::ops::Range<usize> {
+    let len = len as i64;
+    let resolve = |rank: i64| if rank < 0 { len + rank } else { rank };
+    let start = resolve(start).max(0);
+    let stop = resolve(stop).min(len - 1);
+    if start > stop {
+        0..0
+    } else {
+        start as usize..stop as usize + 1
+    }
+}
+
+fn scored_reply(members: &[(Vec<u8>, f64)], with_scores: bool) -> Reply {
+    let mut replies = Vec::new();
+    for (member, score) in members {
+        replies.push(Reply::bulk(member.clone()));
+        if with_scores {
+            replies.push(Reply::bulk(format_score(*score)));
+        }
+    }
+    Reply::Array(Some(replies))
+}
+
+fn stream_reply(entries: &[(StreamId, Vec<Vec<u8>>)]) -> Reply {
+    Reply::Array(Some(
+        entries
+            .iter()
+            .map(|(id, fields)| {
+                Reply::Array(Some(vec![
+                    Reply::bulk(format!("{}-{}", id.0, id.1)),
+                    Reply::bulks(fields.clone()),
+                ]))
+            })
+            .collect(),
+    ))
+}
+
+/// One end of a range, which may leave out the value it's at.
+struct Bound<T> {
+    value: Option<T>,
+    exclusive: bool,
+    low: bool,
+}
+
+impl<T: PartialOrd> Bound<T> {
+    /// Whether `value` is on or above this lower end.
+    fn below(&self, value: &T) -> bool {
+        match &self.value {
+            None => self.low,
+            Some(bound) if self.exclusive => bound < value,
+            Some(bound) => bound <= value,
+        }
+    }
+
+    /// Whether `value` is on or below this upper end.
+    fn above(&self, value: &T) -> bool {
+        match &self.value {
+            None => !self.low,
+            Some(bound) if self.exclusive => bound > value,
+            Some(bound) => bound >= value,
+        }
+    }
+}
+
+fn score_bound(arg: &[u8]) -> Result<Bound<f64>, String> {
+    let (exclusive, arg) = match arg.strip_prefix(b"(") {
+        Some(rest) => (true, rest),
+        None => (false, arg),
+    };
+    Ok(Bound {
+        value: Some(parse_score(arg)?),
+        exclusive,
+        low: true,
+    })
+}
+
+fn lex_bound(arg: &[u8]) -> Result<Bound<Vec<u8>>, String> {
+    match arg.split_first() {
+        Some((b'-', [])) => Ok(Bound {
+            value: None,
+            exclusive: false,
+            low: true,
+        }),
+        Some((b'+', [])) => Ok(Bound {
+            value: None,
+            exclusive: false,
+            low: false,
+        }),
+        Some((b'[', rest)) => Ok(Bound {
+            value: Some(rest.to_vec()),
+            exclusive: false,
+            low: true,
+        }),
+        Some((b'(', rest)) => Ok(Bound {
+            value: Some(rest.to_vec()),
+            exclusive: true,
+            low: true,
+        }),
+        _ => Err("ERR min or max not valid string range item".to_owned()),
+    }
+}
+
+fn stream_id(arg: &[u8], default_sequence: u64) -> Result<StreamId, String> {
+    let invalid = || "ERR Invalid stream ID specified as stream command argument".to_owned();
+    let arg = std::str::from_utf8(arg).map_err(|_| invalid())?;
+    let mut parts = arg.splitn(2, '-');
+    let millis = parts
+        .next()
+        .unwrap_or_default()
+        .parse()
+        .map_err(|_| invalid())?;
+    let sequence = match parts.next() {
+        Some(sequence) => sequence.parse().map_err(|_| invalid())?,
+        None => default_sequence,
+    };
+    Ok((millis, sequence))
+}
+
+fn stream_bound(arg: &[u8], default_sequence: u64) -> Result<Bound<StreamId>, String> {
+    match arg {
+        b"-" => Ok(Bound {
+            value: None,
+            exclusive: false,
+            low: true,
+        }),
+        b"+" => Ok(Bound {
+            value: None,
+            exclusive: false,
+            low: false,
+        }),
+        _ => {
+            let (exclusive, arg) = match arg.strip_prefix(b"(") {
+                Some(rest) => (true, rest),
+                None => (false, arg),
+            };
+            Ok(Bound {
+                value: Some(stream_id(arg, default_sequence)?),
+                exclusive,
+                low: true,
+            })
+        }
+    }
+}
+
+/// Whether `key` matches a `SCAN`/`KEYS` glob, with `*`, `?` and `\` escapes.
+fn glob_matches(pattern: &[u8], key: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => key.is_empty(),
+        Some((b'*', rest)) => (0..=key.len()).any(|skip| glob_matches(rest, &key[skip..])),
+        Some((b'?', rest)) => !key.is_empty() && glob_matches(rest, &key[1..]),
+        Some((b'\\', rest)) if !rest.is_empty() => {
+            key.first() == Some(&rest[0]) && glob_matches(&rest[1..], &key[1..])
+        }
+        Some((first, rest)) => key.first() == Some(first) && glob_matches(rest, &key[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mobc_redis::redis::{self, AsyncCommands};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn answers_commands_as_redis_does() {
+        let redis = FakeRedis::start().await;
+        let client = redis::Client::open(redis.address()).unwrap();
+        let mut con = client.get_async_connection().await.unwrap();
+
+        let _: () = con.set("greeting", "hello").await.unwrap();
+        let greeting: Option<String> = con.get("greeting").await.unwrap();
+        assert_eq!(greeting.as_deref(), Some("hello"));
+        let missing: Option<String> = con.get("missing").await.unwrap();
+        assert_eq!(missing, None);
+
+        let _: () = con.sadd("members", &["U1", "U2"]).await.unwrap();
+        let members: Vec<String> = con.smembers("members").await.unwrap();
+        assert_eq!(members, vec!["U1", "U2"]);
+
+        let count: i64 = con.incr("count", 2).await.unwrap();
+        assert_eq!(count, 2);
+
+        let unknown: redis::RedisResult<i64> = redis::Script::new("return 1")
+            .key("count")
+            .invoke_async(&mut con)
+            .await;
+        assert!(unknown.is_err());
+    }
+}
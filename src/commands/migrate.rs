@@ -0,0 +1,28 @@
+use tracing::info;
+
+use crate::error::CliErrors;
+use crate::libs::key_layout::KeyLayout;
+use crate::libs::RedisServer;
+use crate::MigrateArgs;
+
+pub async fn migrate(args: &MigrateArgs) -> Result<(), CliErrors> {
+    let redis_server = RedisServer::new(&args.redis_opts.redis_address)
+        .await?
+        .with_key_layout(KeyLayout::new(args.key_prefix.as_deref(), false));
+
+    let report = redis_server
+        .migrate_legacy_keys(args.yes, args.delete_legacy_keys)
+        .await?;
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).expect("reports are always serializable")
+    );
+    if !args.yes {
+        info!(
+            "{} keys need migrating. Run again with --yes to copy them",
+            report.to_migrate
+        );
+    }
+    Ok(())
+}
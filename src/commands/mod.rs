@@ -1,5 +1,25 @@
+mod all_in_one;
+mod export;
+#[cfg(test)]
+mod fake_redis;
+mod migrate;
+mod purge;
 mod redis;
 mod server;
+mod shell;
+mod socket_listener;
+mod stats;
+mod verify;
+mod write_back;
 
+pub use all_in_one::all_in_one;
+pub use export::export;
+pub use migrate::migrate;
+pub use purge::purge;
 pub use redis::redis_update;
 pub use server::web_server;
+pub use shell::shell;
+pub use socket_listener::socket_listener;
+pub use stats::cache_stats;
+pub use verify::verify;
+pub use write_back::{apply_write_back, plan_write_back};
@@ -0,0 +1,31 @@
+use tracing::info;
+
+use crate::error::CliErrors;
+use crate::libs::redis::prefix_pattern;
+use crate::libs::{redact, RedisServer};
+use crate::PurgeArgs;
+
+pub async fn purge(args: &PurgeArgs) -> Result<(), CliErrors> {
+    let redis_server = RedisServer::new(&args.redis_opts.redis_address)
+        .await?
+        .with_key_layout(args.key_opts.layout());
+    let keys = redis_server
+        .scan_keys(&prefix_pattern(&args.prefix))
+        .await?;
+
+    if !args.yes {
+        for key in &keys {
+            println!("{}", redact::key(key));
+        }
+        info!(
+            "{} keys start with `{}`. Run again with --yes to delete them",
+            keys.len(),
+            args.prefix
+        );
+        return Ok(());
+    }
+
+    let removed = redis_server.unlink(&keys).await?;
+    info!("Deleted {} keys starting with `{}`", removed, args.prefix);
+    Ok(())
+}
@@ -1,52 +1,1748 @@
-use tracing::{debug, info, warn};
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::error::{CliErrors, SlackErrors};
+use tracing::{debug, error, info, warn};
+
+use crate::error::{CliErrors, DirectoryErrors, RedisErrors, SecretErrors};
 use crate::UpdateRedisArgs;
 
-use crate::libs::{RedisServer, SlackApi};
+use crate::libs::alerting::FailureTracker;
+use crate::libs::backfill;
+use crate::libs::changes::{self, CHANGES_STREAM_KEY, CHANGES_STREAM_MAX_LEN};
+use crate::libs::directory::{self, DirectorySource, SlackDirectory, SourceKind};
+use crate::libs::github;
+use crate::libs::health::Components;
+use crate::libs::kubernetes::{self, KubernetesClient, LockBackend, SYNC_LEASE_DURATION};
+use crate::libs::leader::LeaderElection;
+use crate::libs::membership::MembershipFilter;
+use crate::libs::progress::Progress;
+use crate::libs::schedule::{random_splay, Timing};
+use crate::libs::secrets;
+use crate::libs::slack::{blocks, ApiUsage, FetchedGroups, UserId, UsersCrawl, UsersPage};
+use crate::libs::staging::{self, SyncFailure};
+use crate::libs::summary::{self, DirectorySummary, UserCounts};
+use crate::libs::synthetic::{self, Mirrors};
+use crate::libs::systemd;
+#[cfg(feature = "transform")]
+use crate::libs::transform::Transform;
+use crate::libs::watchers::{self, GroupWatchers, MembershipChange};
+use crate::libs::write_back;
+use crate::libs::{
+    RedisPools, RedisResponse, RedisServer, SlackApi, SlackClientConfig, SlackUser, SlackUserGroup,
+    SyncReport, TokenRotation,
+};
+
+pub async fn redis_update(args: &UpdateRedisArgs, progress: &Progress) -> Result<(), CliErrors> {
+    let pools = RedisPools::connect(&args.shared_opts.redis_opts.redis_address)?;
+    run_updater(args, progress, &pools, &Components::default()).await
+}
+
+/// Syncs once, or on the schedule given, with connections from `pools`. Each scheduled sync is
+/// reported as the `updater` component of `components`.
+pub async fn run_updater(
+    args: &UpdateRedisArgs,
+    progress: &Progress,
+    pools: &RedisPools,
+    components: &Components,
+) -> Result<(), CliErrors> {
+    let splay = args
+        .updater_opts
+        .start_splay
+        .map(random_splay)
+        .unwrap_or_default();
+    if splay > Duration::default() {
+        info!("Waiting {}ms before the first sync", splay.as_millis());
+        tokio::time::sleep(splay).await;
+    }
+
+    let election = if args.updater_opts.leader_election {
+        let redis_server =
+            RedisServer::from_pools(pools).with_key_layout(args.shared_opts.key_opts.layout());
+        let ttl = Duration::from_secs(args.updater_opts.leader_lease_ttl);
+        Some(LeaderElection::start(redis_server, &args.updater_opts.server_id, ttl).await)
+    } else {
+        None
+    };
 
-pub async fn redis_update(args: &UpdateRedisArgs) -> Result<(), CliErrors> {
-    let redis_server = match RedisServer::new(&args.redis_address).await {
-        Ok(redis_server) => redis_server,
-        Err(e) => return Err(CliErrors::Redis(e)),
+    let timing = match (&args.updater_opts.schedule, args.updater_opts.interval) {
+        (Some(schedule), _) => Timing::Cron {
+            schedule: Box::new(schedule.clone()),
+            timezone: args.updater_opts.timezone,
+            splay,
+        },
+        (None, Some(seconds)) => Timing::Interval(Duration::from_secs(seconds)),
+        (None, None) => {
+            let force = args.updater_opts.force;
+            return sync_if_leader(args, force, election.as_ref(), progress, pools).await;
+        }
     };
 
-    debug!("Getting server lock");
-    let has_lock = redis_server.acquire_lock(&args.server_id).await?;
-    if args.ignore_lock {
-        warn!("Ignoring existing lock (if it exists). Be careful!");
-    } else if has_lock {
-        info!("Another server has the lock. Giving up");
-        return Ok(());
+    info!("Syncing {}", timing);
+    let mut failures = FailureTracker::new(
+        args.updater_opts
+            .alerting_opts
+            .notifiers(args.shared_opts.opsgenie_api_key.as_deref()),
+        args.updater_opts.alerting_opts.alert_after_failures,
+        &args.updater_opts.server_id,
+    );
+    let health = components.register("updater");
+    systemd::start_watchdog(systemd::Component::Updater);
+    let mut first_sync = true;
+    loop {
+        systemd::alive(systemd::Component::Updater);
+        // `--force` is for getting past one expected drop in users, not for turning the check off.
+        let force = first_sync && args.updater_opts.force;
+        match sync_if_leader(args, force, election.as_ref(), progress, pools).await {
+            Ok(()) => {
+                failures.record_success().await;
+                systemd::status("Last sync succeeded");
+                health.healthy("last sync succeeded");
+            }
+            Err(e) => {
+                error!("Sync failed. Error: {}", e);
+                failures.record_failure(&e.chain()).await;
+                systemd::status(&format!("Last sync failed: {}", e));
+                health.unhealthy(&format!("last sync failed: {}", e));
+            }
+        }
+        // Ready once the first sync is over, failed or not, as later ones retry on their own.
+        if first_sync {
+            systemd::ready();
+            first_sync = false;
+        }
+
+        systemd::waiting(systemd::Component::Updater);
+        tokio::time::sleep(timing.next_delay()).await;
+    }
+}
+
+async fn sync_if_leader(
+    args: &UpdateRedisArgs,
+    force: bool,
+    election: Option<&LeaderElection>,
+    progress: &Progress,
+    pools: &RedisPools,
+) -> Result<(), CliErrors> {
+    match election {
+        Some(election) if !election.is_leader() => {
+            info!("Another replica is the leader, standing by");
+            Ok(())
+        }
+        _ => sync_and_alert(args, force, progress, pools).await,
     }
-    debug!("Server lock acquired");
+}
 
-    let slack_api = SlackApi::new(&args.slack_token);
+async fn sync_and_alert(
+    args: &UpdateRedisArgs,
+    force: bool,
+    progress: &Progress,
+    pools: &RedisPools,
+) -> Result<(), CliErrors> {
+    let result = sync(args, force, progress, pools).await;
+    if let (Err(e), Some(channel)) = (&result, &args.updater_opts.alert_channel) {
+        send_failure_alert(args, channel, e).await;
+    }
+    if args.updater_opts.kubernetes_opts.kubernetes_events {
+        record_sync_event(&result).await;
+    }
 
-    debug!("Getting user profiles");
-    let slack_users = match slack_api.list_all_users().await {
-        None => return Err(CliErrors::Slack(SlackErrors::UnableToFetch)),
-        Some(users) => users,
+    result
+}
+
+/// Records how the sync went on the pod. Failing to doesn't fail the sync.
+async fn record_sync_event(result: &Result<(), CliErrors>) {
+    let pod = match kubernetes::pod_name() {
+        Some(pod) => pod,
+        None => {
+            warn!("Not recording the sync on the pod, as the pod's name isn't known");
+            return;
+        }
+    };
+
+    let (reason, message, warning) = match result {
+        Ok(()) => ("SyncSucceeded", "Synced users and groups".to_owned(), false),
+        Err(e) => ("SyncFailed", e.chain().join(": "), true),
+    };
+    let recorded = match KubernetesClient::in_cluster() {
+        Ok(client) => {
+            client
+                .record_pod_event(&pod, reason, &message, warning)
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = recorded {
+        warn!("Unable to record the sync on pod {}. Error: {}", pod, e);
+    }
+}
+
+/// Runs a sync, then logs the Slack API calls it made, whether it finished or not. With `force`,
+/// it isn't failed for fetching far fewer users than the last.
+async fn sync(
+    args: &UpdateRedisArgs,
+    force: bool,
+    progress: &Progress,
+    pools: &RedisPools,
+) -> Result<(), CliErrors> {
+    let usage = Arc::new(ApiUsage::new(args.updater_opts.max_api_calls));
+    let result = sync_counting(args, force, progress, pools, &usage).await;
+
+    let summary = usage.summary();
+    if summary.total > 0 {
+        match summary.budget {
+            Some(budget) => info!(
+                "Made {} of {} allowed Slack API calls: {}",
+                summary.total,
+                budget,
+                summary.describe()
+            ),
+            None => info!(
+                "Made {} Slack API calls: {}",
+                summary.total,
+                summary.describe()
+            ),
+        }
+    }
+
+    result
+}
+
+/// Syncs, counting every Slack API call against `usage`.
+async fn sync_counting(
+    args: &UpdateRedisArgs,
+    force: bool,
+    progress: &Progress,
+    pools: &RedisPools,
+    usage: &Arc<ApiUsage>,
+) -> Result<(), CliErrors> {
+    let redis_server = RedisServer::from_pools(pools)
+        .with_key_layout(args.shared_opts.key_opts.layout())
+        .with_email_hasher(args.shared_opts.privacy_opts.email_hasher())
+        .with_email_aliases(args.updater_opts.email_alias_rules.clone())
+        .with_domain_allowlist(
+            args.updater_opts.domain_opts.allowlist(),
+            args.updater_opts.domain_opts.external_users,
+        )
+        .with_value_format(args.updater_opts.value_format)
+        .with_compression(args.updater_opts.compress_values_over);
+    #[cfg(feature = "transform")]
+    let redis_server = redis_server.with_transform(
+        args.updater_opts
+            .transform_script
+            .as_deref()
+            .map(Transform::load)
+            .transpose()?,
+    );
+
+    // Both are checked before taking the lock, so a broken dependency doesn't hold the lock
+    // until it expires, keeping healthy replicas from syncing.
+    redis_server
+        .ping()
+        .await
+        .map_err(|e| CliErrors::DependencyUnavailable {
+            dependency: "Redis".to_owned(),
+            source: anyhow::anyhow!(e),
+        })?;
+    let primary = match args.updater_opts.source {
+        SourceKind::Slack => {
+            let slack_api = build_slack_api(args, usage.clone()).await?;
+            slack_api
+                .check_auth()
+                .await
+                .map_err(|e| CliErrors::DependencyUnavailable {
+                    dependency: "Slack".to_owned(),
+                    source: anyhow::anyhow!(e),
+                })?;
+            Primary::Slack(Arc::new(slack_api.with_progress(progress.clone())))
+        }
+        kind => Primary::Other(build_source(kind, args, usage).await?),
     };
-    info!("Fetched {} users to save into redis", slack_users.len());
 
-    debug!("Saving Users to Redis");
-    redis_server.insert_users(&slack_users).await?;
-    info!("{} users saved", slack_users.len());
+    sync_from(args, force, progress, redis_server, primary, usage).await
+}
+
+/// Syncs `primary` into `redis_server`, once no other updater is syncing. Gives up without
+/// syncing when another one holds the lock.
+async fn sync_from(
+    args: &UpdateRedisArgs,
+    force: bool,
+    progress: &Progress,
+    redis_server: RedisServer,
+    primary: Primary,
+    usage: &Arc<ApiUsage>,
+) -> Result<(), CliErrors> {
+    // The leader lease already keeps other replicas from syncing.
+    if !args.updater_opts.leader_election {
+        match args.updater_opts.kubernetes_opts.lock_backend {
+            LockBackend::Redis => {
+                debug!("Getting server lock");
+                let acquired = redis_server
+                    .acquire_lock(&args.updater_opts.server_id)
+                    .await?;
+                if args.updater_opts.ignore_lock {
+                    warn!("Ignoring existing lock (if it exists). Be careful!");
+                } else if !acquired {
+                    info!("Another server has the lock. Giving up");
+                    return Ok(());
+                }
+                debug!("Server lock acquired");
+            }
+            LockBackend::K8s => {
+                let lease = &args.updater_opts.kubernetes_opts.lock_lease_name;
+                debug!("Getting sync lease {}", lease);
+                let acquired = KubernetesClient::in_cluster()?
+                    .acquire_lease(lease, &args.updater_opts.server_id, SYNC_LEASE_DURATION)
+                    .await?;
+                if args.updater_opts.ignore_lock {
+                    warn!("Ignoring existing lease (if it's held). Be careful!");
+                } else if !acquired {
+                    info!("Another server holds lease {}. Giving up", lease);
+                    return Ok(());
+                }
+                debug!("Sync lease acquired");
+            }
+        }
+    }
+
+    let redis_server = match &args.updater_opts.github_opts.github_logins {
+        Some(source) => {
+            let github_logins = github::load_logins(
+                source,
+                args.updater_opts.github_opts.github_token.as_deref(),
+                args.updater_opts.github_opts.slack_scim_token.as_deref(),
+            )
+            .await?;
+            redis_server.with_github_logins(Some(github_logins))
+        }
+        None => redis_server,
+    };
+
+    let extra_sources = build_extra_sources(args, usage).await?;
+
+    let home_users: Vec<&str> = args
+        .updater_opts
+        .app_home_users
+        .as_deref()
+        .map(|users| {
+            users
+                .split(',')
+                .map(str::trim)
+                .filter(|user| !user.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    let compare_with_cache = !home_users.is_empty() || args.updater_opts.publish_changes;
+    let previous_users = if !compare_with_cache {
+        Vec::new()
+    } else {
+        cached(redis_server.get_all_users().await)
+    };
 
+    // Users are written a page at a time. They're only all kept in memory for the steps that
+    // look at the whole workspace at once.
+    let keep_users = compare_with_cache
+        || !args.updater_opts.email_alias_rules.is_empty()
+        || !extra_sources.is_empty()
+        || args.updater_opts.backfill_emails.is_some();
+
+    // Groups come first, so users can be filtered by the groups they're in.
     debug!("Getting user groups");
-    let slack_user_groups = match slack_api.list_all_user_groups().await {
-        None => return Err(CliErrors::Slack(SlackErrors::UnableToFetch)),
-        Some(users) => users,
+    let slack_user_groups = match &primary {
+        Primary::Slack(slack_api) => {
+            let fetched = match redis_server.get_fetched_groups().await {
+                Ok(fetched) => fetched,
+                Err(e) => {
+                    warn!(
+                        "Unable to read group members from the last sync, fetching them all. Error: {}",
+                        e
+                    );
+                    FetchedGroups::new()
+                }
+            };
+            let (groups, fetched) = slack_api.list_user_groups_since(&fetched).await?;
+            if let Err(e) = redis_server.set_fetched_groups(&fetched).await {
+                warn!("Unable to save fetched group members. Error: {}", e);
+            }
+            groups
+        }
+        Primary::Other(source) => source.list_groups().await?,
     };
     info!(
         "Fetched {} user groups to save into redis",
         slack_user_groups.len()
     );
+    let membership = MembershipFilter::new(
+        &slack_user_groups,
+        &args.updater_opts.only_groups,
+        &args.updater_opts.exclude_groups,
+    )
+    .map_err(|name| CliErrors::UnknownGroup { name })?;
+    let mut slack_user_groups = redis_server.screen_groups(slack_user_groups);
+
+    systemd::alive(systemd::Component::Updater);
+    let baseline = shrink_baseline(args, &redis_server, force).await;
+    debug!("Getting user profiles");
+    let crawl = match &primary {
+        Primary::Slack(slack_api) => {
+            sync_users(
+                args,
+                slack_api,
+                &redis_server,
+                &membership,
+                baseline.as_ref(),
+                keep_users,
+                progress,
+            )
+            .await?
+        }
+        Primary::Other(source) => {
+            sync_source_users(
+                args,
+                source.as_ref(),
+                &redis_server,
+                &membership,
+                baseline.as_ref(),
+            )
+            .await?
+        }
+    };
+    info!("{} users saved", crawl.fetched);
+    let mut user_counts = crawl.counts;
+    let mut slack_users = crawl.users;
+    for source in &extra_sources {
+        merge_source(
+            args,
+            source.as_ref(),
+            &redis_server,
+            &mut slack_users,
+            &mut slack_user_groups,
+            &mut user_counts,
+        )
+        .await?;
+    }
+    if let Some(path) = &args.updater_opts.backfill_emails {
+        backfill_users(
+            args,
+            path,
+            &primary,
+            &redis_server,
+            &membership,
+            &mut slack_users,
+            &mut user_counts,
+        )
+        .await?;
+    }
+    if args.updater_opts.staged_sync {
+        commit_staged_sync(args, &redis_server, &slack_user_groups, baseline.as_ref()).await?;
+    }
+    match redis_server.get_synthetic_groups().await {
+        Ok(synthetic) => {
+            if args.updater_opts.write_back_groups {
+                write_back_groups(
+                    args,
+                    &primary,
+                    &redis_server,
+                    &synthetic,
+                    &slack_user_groups,
+                )
+                .await;
+            }
+            let mirrors = match redis_server.get_synthetic_group_mirrors().await {
+                Ok(mirrors) => mirrors,
+                Err(e) => {
+                    warn!(
+                        "Unable to read the Slack groups written back for synthetic groups. Error: {}",
+                        e
+                    );
+                    Mirrors::new()
+                }
+            };
+            let merged = synthetic::merge(&mut slack_user_groups, synthetic, &mirrors);
+            debug!("Added {} synthetic groups", merged);
+        }
+        Err(e) => warn!("Unable to read synthetic groups. Error: {}", e),
+    }
 
+    let watchers = match redis_server.get_all_group_watchers().await {
+        Ok(watchers) => watchers,
+        Err(e) => {
+            warn!("Unable to read group watchers. Error: {}", e);
+            GroupWatchers::new()
+        }
+    };
+    let previous_groups = if !compare_with_cache && watchers.is_empty() {
+        Vec::new()
+    } else {
+        cached(redis_server.get_all_user_groups().await)
+    };
+
+    systemd::alive(systemd::Component::Updater);
     debug!("Saving User Groups to Redis");
     redis_server.insert_user_groups(&slack_user_groups).await?;
     info!("{} user groups saved", slack_user_groups.len());
 
+    debug!("Expanding User Groups");
+    if let Err(e) = redis_server.expand_user_groups(&slack_user_groups).await {
+        warn!(
+            "Unable to store the members of user groups, they'll be looked up when asked for. Error: {}",
+            e
+        );
+    }
+
+    let compressed = redis_server.compressed_totals();
+    if let Some(ratio) = compressed.ratio() {
+        info!(
+            "Compressed {} values from {} to {} bytes ({:.1}x)",
+            compressed.values, compressed.original_bytes, compressed.compressed_bytes, ratio
+        );
+    }
+
+    let finished_at = unix_now();
+
+    if args.updater_opts.membership_history > 0 {
+        debug!("Recording group membership history");
+        redis_server
+            .record_group_history(
+                &slack_user_groups,
+                finished_at,
+                args.updater_opts.membership_history,
+            )
+            .await?;
+    }
+
+    if args.updater_opts.publish_changes {
+        publish_changes(
+            &redis_server,
+            &previous_users,
+            &slack_users,
+            &previous_groups,
+            &slack_user_groups,
+        )
+        .await?;
+    }
+
+    redis_server.set_last_sync(finished_at).await?;
+    match redis_server.prune_user_updates().await {
+        Ok(0) => {}
+        Ok(pruned) => debug!("Dropped {} expired users from the update index", pruned),
+        Err(e) => warn!("Unable to prune the user update index. Error: {}", e),
+    }
+    match redis_server.prune_name_indexes().await {
+        Ok(0) => {}
+        Ok(pruned) => debug!("Dropped {} expired entries from the name indexes", pruned),
+        Err(e) => warn!("Unable to prune the name indexes. Error: {}", e),
+    }
+
+    let summary = DirectorySummary::new(&user_counts, &slack_user_groups, finished_at);
+    if let Err(e) = redis_server.set_directory_summary(&summary).await {
+        warn!("Unable to save the directory summary. Error: {}", e);
+    }
+    if let Err(e) = redis_server.set_cache_settings().await {
+        warn!(
+            "Unable to save the options the cache was synced with. Error: {}",
+            e
+        );
+    }
+    // Users and groups that expired rather than being removed were never counted down.
+    if let Err(e) = redis_server.set_counts(summary.users, summary.groups).await {
+        warn!(
+            "Unable to save how many users and groups are cached. Error: {}",
+            e
+        );
+    }
+
+    let membership_changes =
+        watchers::membership_changes(&watchers, &previous_groups, &slack_user_groups);
+    if !membership_changes.is_empty() {
+        let slack_api: Arc<dyn SlackDirectory> = match &primary {
+            Primary::Slack(slack_api) => slack_api.clone(),
+            Primary::Other(_) => Arc::new(build_slack_api(args, usage.clone()).await?),
+        };
+        for change in membership_changes {
+            notify_watchers(slack_api.as_ref(), &watchers[&change.group.id], &change).await;
+        }
+    }
+
+    if !home_users.is_empty() {
+        let report = SyncReport::new(
+            &previous_users,
+            &slack_users,
+            &previous_groups,
+            &slack_user_groups,
+        );
+        let view = blocks::home_view(&report);
+        let slack_api: Arc<dyn SlackDirectory> = match &primary {
+            Primary::Slack(slack_api) => slack_api.clone(),
+            Primary::Other(_) => Arc::new(build_slack_api(args, usage.clone()).await?),
+        };
+        for user in home_users {
+            if let Err(e) = slack_api.publish_home(user, &view).await {
+                warn!("Unable to update App Home of {}. Error: {}", user, e);
+            }
+        }
+    }
+
+    if let Err(e) = redis_server.set_last_sync_api_usage(&usage.summary()).await {
+        warn!("Unable to save the sync's Slack API usage. Error: {}", e);
+    }
+
+    Ok(())
+}
+
+/// Where a sync's users and groups come from. Slack users are crawled a page at a time, while
+/// other directories are listed in one go.
+enum Primary {
+    Slack(Arc<dyn SlackDirectory>),
+    Other(Box<dyn DirectorySource>),
+}
+
+/// The last sync's summary, for this one to be failed against when it fetches fewer than
+/// `--min-user-ratio` of the users that one cached. `None` when the check is off, `force` skips
+/// it, or there's nothing to check against.
+async fn shrink_baseline(
+    args: &UpdateRedisArgs,
+    redis_server: &RedisServer,
+    force: bool,
+) -> Option<DirectorySummary> {
+    if args.updater_opts.min_user_ratio <= 0.0 {
+        return None;
+    }
+    if force {
+        warn!("Not checking how many users this sync fetches, as --force was given");
+        return None;
+    }
+    match redis_server.get_directory_summary().await {
+        Ok(previous) => previous,
+        Err(e) => {
+            warn!(
+                "Unable to read the last sync's counts, not checking this one's. Error: {}",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Fails a sync that fetched far fewer users than the `baseline` one. It runs before the sync's
+/// groups, counts and summary are written, so the last sync's stay in place and the next sync is
+/// checked against them too. The users missing from it are left to expire rather than being
+/// replaced, and there's time to look into it.
+fn check_user_count(
+    baseline: Option<&DirectorySummary>,
+    ratio: f64,
+    fetched: u64,
+) -> Result<(), CliErrors> {
+    match baseline {
+        Some(previous) if summary::shrank(previous, fetched, ratio) => {
+            Err(CliErrors::UsersShrank {
+                fetched,
+                previous: previous.users,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Makes the users a staged sync collected live once they and the sync's groups pass
+/// validation. Groups are only written after this, so they're held back along with the users.
+/// When they don't pass, the users and groups of the last sync stay cached and the failure is
+/// recorded until a later sync is committed.
+async fn commit_staged_sync(
+    args: &UpdateRedisArgs,
+    redis_server: &RedisServer,
+    groups: &BTreeSet<SlackUserGroup>,
+    baseline: Option<&DirectorySummary>,
+) -> Result<(), CliErrors> {
+    let users = redis_server.get_staged_users().await?;
+    if let Err(reason) =
+        staging::validate(&users, groups, baseline, args.updater_opts.min_user_ratio)
+    {
+        let failure = SyncFailure {
+            at: unix_now(),
+            users: users.len() as u64,
+            groups: groups.len() as u64,
+            reason: reason.clone(),
+        };
+        if let Err(e) = redis_server.set_sync_failure(&failure).await {
+            warn!("Unable to record the failed sync. Error: {}", e);
+        }
+        if let Err(e) = redis_server.clear_staged_users().await {
+            warn!("Unable to clear staged users. Error: {}", e);
+        }
+        return Err(CliErrors::InvalidStagedSync { reason });
+    }
+
+    info!("Committing {} staged users", users.len());
+    redis_server.insert_users(&users).await?;
+    if let Some(retention) = args.updater_opts.user_history_retention {
+        redis_server
+            .record_user_history(&users, unix_now(), retention)
+            .await?;
+    }
+    redis_server.clear_staged_users().await?;
+    if let Err(e) = redis_server.clear_sync_failure().await {
+        warn!("Unable to clear the last failed sync. Error: {}", e);
+    }
+    Ok(())
+}
+
+/// Caches `users` straight away, or with `--staged-sync` stages them to be committed once the
+/// sync is done.
+async fn save_users(
+    args: &UpdateRedisArgs,
+    redis_server: &RedisServer,
+    users: &BTreeSet<SlackUser>,
+) -> Result<(), RedisErrors> {
+    if args.updater_opts.staged_sync {
+        redis_server.stage_users(users).await
+    } else {
+        redis_server.insert_users(users).await
+    }
+}
+
+/// Crawls users.list, writing each page to Redis as it arrives. The users are only collected
+/// when `keep_users` is set. With `--resume-sync-within`, progress is saved after every page and
+/// a recent enough save from an interrupted sync is carried on from, as long as it kept users
+/// the same way. With a `baseline`, pages are held back until enough users have been fetched to
+/// pass `--min-user-ratio`, and the sync fails without writing them if that never happens.
+async fn sync_users(
+    args: &UpdateRedisArgs,
+    slack_api: &Arc<dyn SlackDirectory>,
+    redis_server: &RedisServer,
+    membership: &MembershipFilter,
+    baseline: Option<&DirectorySummary>,
+    keep_users: bool,
+    progress: &Progress,
+) -> Result<UsersCrawl, CliErrors> {
+    let start_over = || UsersCrawl {
+        keep_users,
+        ..UsersCrawl::default()
+    };
+    let mut crawl = match args.updater_opts.resume_sync_within {
+        None => start_over(),
+        Some(_) => match redis_server.get_sync_cursor().await {
+            // The users fetched before the interruption would be missing from the ones kept.
+            Ok(Some(crawl)) if crawl.keep_users != keep_users => {
+                info!("Starting over, as the interrupted sync kept users differently");
+                start_over()
+            }
+            Ok(Some(crawl)) => {
+                info!(
+                    "Resuming interrupted sync from page {} with {} users",
+                    crawl.cursor.page(),
+                    crawl.fetched
+                );
+                crawl
+            }
+            Ok(None) => start_over(),
+            Err(e) => {
+                warn!(
+                    "Unable to read saved sync progress, starting over. Error: {}",
+                    e
+                );
+                start_over()
+            }
+        },
+    };
+
+    // users.list doesn't say how many users there are, so the last sync's count stands in.
+    let estimate = if progress.is_drawn() {
+        match redis_server.scan_keys("user:id:*").await {
+            Ok(keys) if !keys.is_empty() => Some(keys.len().max(crawl.fetched) as u64),
+            _ => None,
+        }
+    } else {
+        None
+    };
+    // Users staged by an interrupted sync are only kept when carrying on from it.
+    if args.updater_opts.staged_sync && crawl.cursor.page() == 0 {
+        redis_server.clear_staged_users().await?;
+    }
+
+    let bar = progress.start("users", estimate);
+    bar.set_position(crawl.fetched as u64);
+
+    // Staged users are checked when they're committed instead.
+    let baseline = baseline.filter(|_| !args.updater_opts.staged_sync);
+
+    let mut pages = slack_api.clone().prefetch_users(crawl.cursor.clone());
+    while let Some(page) = pages.recv().await {
+        systemd::alive(systemd::Component::Updater);
+        let mut page = page?;
+        let fetched = page.users.len();
+        page.users = redis_server.screen_users(page.users);
+        if !membership.is_empty() {
+            page.users = page
+                .users
+                .into_iter()
+                .filter(|user| membership.allows(&user.id))
+                .collect();
+        }
+        if page.users.len() < fetched {
+            debug!(
+                "Left out {} users by email domain or group membership",
+                fetched - page.users.len()
+            );
+        }
+        let kept: BTreeSet<&UserId> = page.users.iter().map(|user| &user.id).collect();
+        page.availability = std::mem::take(&mut page.availability)
+            .into_iter()
+            .filter(|(id, _)| kept.contains(id))
+            .collect();
+
+        crawl.cursor = page.cursor.clone();
+        crawl.fetched += page.users.len();
+        for user in &page.users {
+            crawl.counts.add(user);
+        }
+        bar.inc(page.users.len() as u64);
+        write_users_page(args, slack_api, redis_server, &mut page).await?;
+        if keep_users {
+            crawl.users.extend(page.users);
+        }
+        if let Some(window) = args.updater_opts.resume_sync_within {
+            if let Err(e) = redis_server.save_sync_cursor(&crawl, window).await {
+                warn!("Unable to save sync progress. Error: {}", e);
+            }
+        }
+    }
+    // Pages are written as they arrive, rather than held until there are enough users to pass, so
+    // a big workspace is never all in memory. The groups, counts, aliases and summary still wait.
+    check_user_count(
+        baseline,
+        args.updater_opts.min_user_ratio,
+        crawl.fetched as u64,
+    )?;
+
+    if !args.updater_opts.staged_sync {
+        redis_server.insert_email_aliases(&crawl.users).await?;
+    }
+    if args.updater_opts.resume_sync_within.is_some() {
+        if let Err(e) = redis_server.clear_sync_cursor().await {
+            warn!("Unable to clear saved sync progress. Error: {}", e);
+        }
+    }
+
+    Ok(crawl)
+}
+
+/// Writes a page of users from users.list, staged or live, along with their availability and
+/// history.
+async fn write_users_page(
+    args: &UpdateRedisArgs,
+    slack_api: &Arc<dyn SlackDirectory>,
+    redis_server: &RedisServer,
+    page: &mut UsersPage,
+) -> Result<(), CliErrors> {
+    if args.updater_opts.staged_sync {
+        redis_server.stage_users(&page.users).await?;
+    } else {
+        redis_server.insert_user_records(&page.users).await?;
+    }
+    if let Some(ttl) = args.updater_opts.dnd_ttl {
+        match slack_api.add_dnd_schedules(&mut page.availability).await {
+            Ok(()) => {
+                redis_server
+                    .insert_availability(&page.availability, ttl)
+                    .await?
+            }
+            Err(e) => warn!("Unable to fetch do-not-disturb schedules. Error: {}", e),
+        }
+    }
+    // Staged users' history is recorded when they're committed.
+    if let Some(retention) = args
+        .updater_opts
+        .user_history_retention
+        .filter(|_| !args.updater_opts.staged_sync)
+    {
+        redis_server
+            .record_user_history(&page.users, unix_now(), retention)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Lists every user in `source` and writes the ones the filters keep, for directories that are
+/// the primary source instead of Slack.
+async fn sync_source_users(
+    args: &UpdateRedisArgs,
+    source: &dyn DirectorySource,
+    redis_server: &RedisServer,
+    membership: &MembershipFilter,
+    baseline: Option<&DirectorySummary>,
+) -> Result<UsersCrawl, CliErrors> {
+    let users: BTreeSet<SlackUser> = redis_server
+        .screen_users(source.list_users().await?)
+        .into_iter()
+        .filter(|user| membership.allows(&user.id))
+        .collect();
+
+    if args.updater_opts.staged_sync {
+        redis_server.clear_staged_users().await?;
+        redis_server.stage_users(&users).await?;
+    } else {
+        check_user_count(
+            baseline,
+            args.updater_opts.min_user_ratio,
+            users.len() as u64,
+        )?;
+        redis_server.insert_users(&users).await?;
+        if let Some(retention) = args.updater_opts.user_history_retention {
+            redis_server
+                .record_user_history(&users, unix_now(), retention)
+                .await?;
+        }
+    }
+
+    let mut counts = UserCounts::default();
+    for user in &users {
+        counts.add(user);
+    }
+
+    Ok(UsersCrawl {
+        fetched: users.len(),
+        counts,
+        users,
+        ..UsersCrawl::default()
+    })
+}
+
+/// Lists the users and groups in `source` and caches them alongside `users` and `groups`, which
+/// get the merged records too. Group membership filters only apply to the primary source. Users
+/// that weren't already in `users` are added to `counts`.
+async fn merge_source(
+    args: &UpdateRedisArgs,
+    source: &dyn DirectorySource,
+    redis_server: &RedisServer,
+    users: &mut BTreeSet<SlackUser>,
+    groups: &mut BTreeSet<SlackUserGroup>,
+    counts: &mut UserCounts,
+) -> Result<(), CliErrors> {
+    debug!("Getting users and groups from {}", source.kind());
+    let listed_users = redis_server.screen_users(source.list_users().await?);
+    let listed_groups = redis_server.screen_groups(source.list_groups().await?);
+
+    let merged = directory::merge(users, groups, listed_users, listed_groups);
+    info!(
+        "Merged {} users and {} user groups from {}",
+        merged.users.len(),
+        merged.groups.len(),
+        source.kind()
+    );
+    save_users(args, redis_server, &merged.users).await?;
+
+    for user in merged.users {
+        if !users.contains(&user) {
+            counts.add(&user);
+        }
+        users.replace(user);
+    }
+    groups.extend(merged.groups);
+    Ok(())
+}
+
+async fn build_source(
+    kind: SourceKind,
+    args: &UpdateRedisArgs,
+    usage: &Arc<ApiUsage>,
+) -> Result<Box<dyn DirectorySource>, CliErrors> {
+    match kind {
+        SourceKind::Slack => Ok(Box::new(build_slack_api(args, usage.clone()).await?)),
+        SourceKind::Google => Ok(Box::new(args.updater_opts.google_opts.directory()?)),
+    }
+}
+
+async fn build_extra_sources(
+    args: &UpdateRedisArgs,
+    usage: &Arc<ApiUsage>,
+) -> Result<Vec<Box<dyn DirectorySource>>, CliErrors> {
+    let mut sources = Vec::new();
+    for kind in &args.updater_opts.extra_sources {
+        if *kind == args.updater_opts.source {
+            return Err(DirectoryErrors::AlreadySynced {
+                directory: kind.to_string(),
+            }
+            .into());
+        }
+        sources.push(build_source(*kind, args, usage).await?);
+    }
+
+    Ok(sources)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// A Slack client counting its calls against `usage`.
+async fn build_slack_api(
+    args: &UpdateRedisArgs,
+    usage: Arc<ApiUsage>,
+) -> Result<SlackApi, CliErrors> {
+    let rotation = match (
+        &args.updater_opts.slack_refresh_token,
+        &args.updater_opts.slack_client_id,
+        &args.updater_opts.slack_client_secret,
+    ) {
+        (Some(refresh_token), Some(client_id), Some(client_secret)) => Some(TokenRotation {
+            client_id: client_id.clone(),
+            client_secret: client_secret.clone(),
+            refresh_token: refresh_token.clone(),
+            store: args.updater_opts.slack_token_store.clone(),
+        }),
+        _ => None,
+    };
+
+    let slack_token = resolve_slack_token(args).await?;
+    let slack_api = SlackApi::new(
+        &slack_token,
+        &SlackClientConfig {
+            connect_timeout: Duration::from_secs(args.shared_opts.slack_connect_timeout),
+            request_timeout: Duration::from_secs(args.shared_opts.slack_request_timeout),
+            tcp_keepalive: Duration::from_secs(args.shared_opts.slack_tcp_keepalive),
+            rotation,
+            usage,
+        },
+    )?;
+
+    Ok(slack_api)
+}
+
+/// Posts the error chain of a failed sync to `channel`. Problems sending it are only logged, so
+/// the original error is what gets reported.
+async fn send_failure_alert(args: &UpdateRedisArgs, channel: &str, error: &CliErrors) {
+    // Failure alerts are sent even when a sync was stopped for running out of API calls.
+    let slack_api = match build_slack_api(args, Arc::default()).await {
+        Ok(slack_api) => slack_api,
+        Err(e) => {
+            warn!(
+                "Unable to alert {} about the failed sync. Error: {}",
+                channel, e
+            );
+            return;
+        }
+    };
+
+    let title = format!(
+        "Slack user cache sync failed on {}",
+        args.updater_opts.server_id
+    );
+    let causes = error.chain();
+    let text = format!("{}: {}", title, causes.join(": "));
+    if let Err(e) = slack_api
+        .post_message(channel, &text, &blocks::failure_alert(&title, &causes))
+        .await
+    {
+        warn!(
+            "Unable to alert {} about the failed sync. Error: {}",
+            channel, e
+        );
+    }
+}
+
+/// Looks up the users listed in `path` that users.list didn't return, caching the ones Slack
+/// finds. Only Slack can be asked. Emails Slack recently had no user for are skipped.
+async fn backfill_users(
+    args: &UpdateRedisArgs,
+    path: &Path,
+    primary: &Primary,
+    redis_server: &RedisServer,
+    membership: &MembershipFilter,
+    users: &mut BTreeSet<SlackUser>,
+    counts: &mut UserCounts,
+) -> Result<(), CliErrors> {
+    let slack_api = match primary {
+        Primary::Slack(slack_api) => slack_api,
+        Primary::Other(source) => {
+            warn!(
+                "Not backfilling users, as {} can't look them up by email",
+                source.kind()
+            );
+            return Ok(());
+        }
+    };
+
+    let emails = backfill::load_emails(path)?;
+    let mut missing = backfill::missing_emails(&emails, users);
+    match redis_server.get_backfill_misses(&missing).await {
+        Ok(misses) if !misses.is_empty() => {
+            debug!(
+                "Skipping {} emails Slack recently had no user for",
+                misses.len()
+            );
+            missing = missing.difference(&misses).cloned().collect();
+        }
+        Ok(_) => {}
+        Err(e) => warn!(
+            "Unable to read the emails Slack had no user for, looking them all up. Error: {}",
+            e
+        ),
+    }
+
+    let backfill = backfill::missing_users(
+        slack_api.as_ref(),
+        &missing,
+        args.updater_opts.backfill_max_lookups,
+    )
+    .await?;
+    if let Err(e) = redis_server
+        .add_backfill_misses(&backfill.not_found, args.updater_opts.backfill_miss_ttl)
+        .await
+    {
+        warn!(
+            "Unable to save the emails Slack had no user for. Error: {}",
+            e
+        );
+    }
+    let found: BTreeSet<SlackUser> = redis_server
+        .screen_users(backfill.found)
+        .into_iter()
+        .filter(|user| membership.allows(&user.id))
+        .collect();
+    save_users(args, redis_server, &found).await?;
+    info!("Backfilled {} users missing from users.list", found.len());
+
+    for user in found {
+        counts.add(&user);
+        users.insert(user);
+    }
+    Ok(())
+}
+
+/// Creates or updates a Slack group for each synthetic group whose members Slack doesn't have
+/// yet. Slack's own groups are only known when Slack is the primary source.
+async fn write_back_groups(
+    args: &UpdateRedisArgs,
+    primary: &Primary,
+    redis_server: &RedisServer,
+    synthetic: &[SlackUserGroup],
+    fetched: &BTreeSet<SlackUserGroup>,
+) {
+    let slack_api = match primary {
+        Primary::Slack(slack_api) => slack_api,
+        Primary::Other(source) => {
+            warn!(
+                "Not writing back synthetic groups, as Slack groups aren't fetched from {}",
+                source.kind()
+            );
+            return;
+        }
+    };
+    let mirrors = match redis_server.get_synthetic_group_mirrors().await {
+        Ok(mirrors) => mirrors,
+        Err(e) => {
+            // Without them every synthetic group would be created in Slack again.
+            warn!("Not writing back synthetic groups. Error: {}", e);
+            return;
+        }
+    };
+
+    let plan = write_back::plan(synthetic, fetched, &mirrors);
+    if plan.is_empty() {
+        debug!("Slack groups already match the synthetic groups");
+        return;
+    }
+    write_back::apply(
+        slack_api.as_ref(),
+        redis_server,
+        synthetic,
+        &plan,
+        args.updater_opts.write_back_dry_run,
+    )
+    .await;
+}
+
+/// Posts who joined and left a group to each channel watching it. Problems posting are only
+/// logged, as the sync itself went fine.
+async fn notify_watchers(
+    slack_api: &dyn SlackDirectory,
+    channels: &BTreeSet<String>,
+    change: &MembershipChange<'_>,
+) {
+    let group = change.group;
+    let text = format!(
+        "Membership of {} changed: {} added, {} removed",
+        group.name,
+        change.added.len(),
+        change.removed.len()
+    );
+    let message = blocks::membership_change(group, &change.added, &change.removed);
+    for channel in channels {
+        if let Err(e) = slack_api.post_message(channel, &text, &message).await {
+            warn!(
+                "Unable to tell {} about changes to group {}. Error: {}",
+                channel, group.id, e
+            );
+        }
+    }
+}
+
+/// Adds a record to the changes stream for every user and group that differs from the cache.
+async fn publish_changes(
+    redis_server: &RedisServer,
+    previous_users: &[SlackUser],
+    users: &BTreeSet<SlackUser>,
+    previous_groups: &[SlackUserGroup],
+    groups: &BTreeSet<SlackUserGroup>,
+) -> Result<(), RedisErrors> {
+    let users: Vec<SlackUser> = users
+        .iter()
+        .map(|user| redis_server.stored_user(user))
+        .collect();
+    let groups: Vec<SlackUserGroup> = groups.iter().cloned().collect();
+
+    let mut records = changes::user_changes(previous_users, &users);
+    records.extend(changes::group_changes(previous_groups, &groups));
+    info!("Publishing {} changes", records.len());
+
+    for record in &records {
+        redis_server
+            .append_to_stream(
+                CHANGES_STREAM_KEY,
+                &[("data", serde_json::to_string(record).unwrap())],
+                Some(CHANGES_STREAM_MAX_LEN),
+            )
+            .await?;
+    }
+
     Ok(())
 }
+
+/// What Redis held before this sync, used to report changes. Empty if it can't be read.
+fn cached<T>(response: RedisResponse<Vec<T>, RedisErrors>) -> Vec<T> {
+    match response {
+        RedisResponse::Ok(values) => values,
+        RedisResponse::Missing => Vec::new(),
+        RedisResponse::Err(e) => {
+            warn!(
+                "Unable to read cached entries to compare against. Error: {}",
+                e
+            );
+            Vec::new()
+        }
+    }
+}
+
+async fn resolve_slack_token(args: &UpdateRedisArgs) -> Result<String, SecretErrors> {
+    if let Some(path) = &args.shared_opts.slack_token_file {
+        return secrets::read_secret_file(path);
+    }
+
+    #[cfg(feature = "vault")]
+    {
+        if let Some(reference) = &args.updater_opts.slack_token_vault {
+            return secrets::read_vault_secret(reference).await;
+        }
+    }
+
+    args.shared_opts
+        .slack_token
+        .clone()
+        .ok_or_else(|| SecretErrors::Empty {
+            name: "SLACK_BOT_TOKEN".to_owned(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::Mutex;
+
+    use clap::Clap;
+    use futures::future::{self, FutureExt};
+    use serde_json::{json, Value};
+    use tokio::sync::mpsc;
+    use warp::Filter;
+
+    use super::*;
+    use crate::commands::fake_redis::FakeRedis;
+    use crate::error::SlackErrors;
+    use crate::libs::directory::SlackResult;
+    use crate::libs::email::{DomainAllowlist, Email, EmailHasher, ExternalUsers};
+    use crate::libs::slack::{Cursor, GroupId, UserAvailability, UsersPage};
+
+    /// Serves whatever `usergroups` holds at the time from usergroups.list, each with member
+    /// U1, at the URL returned.
+    fn fake_slack(usergroups: Arc<Mutex<Value>>) -> String {
+        let usergroups_list = warp::path("usergroups.list").map(move || {
+            let usergroups = usergroups.lock().unwrap().clone();
+            warp::reply::json(&json!({"ok": true, "usergroups": usergroups}))
+        });
+        let usergroups_users_list = warp::path("usergroups.users.list")
+            .and(warp::body::form())
+            .map(|_: HashMap<String, String>| {
+                warp::reply::json(&json!({"ok": true, "users": ["U1"]}))
+            });
+
+        let (address, server) = warp::serve(usergroups_list.or(usergroups_users_list))
+            .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("http://{}", address)
+    }
+
+    fn slack_api(api_url: &str) -> SlackApi {
+        let config = SlackClientConfig {
+            connect_timeout: Duration::from_secs(1),
+            request_timeout: Duration::from_secs(5),
+            tcp_keepalive: Duration::from_secs(60),
+            rotation: None,
+            usage: Arc::default(),
+        };
+        SlackApi::new("xoxb-test", &config)
+            .unwrap()
+            .with_api_url(api_url)
+    }
+
+    fn usergroup(id: &str, name: &str, date_update: u64, date_delete: u64) -> Value {
+        json!({
+            "id": id, "name": name, "handle": name,
+            "date_update": date_update, "date_delete": date_delete
+        })
+    }
+
+    /// Fetches the groups from Slack and saves them, as a sync does.
+    async fn sync_groups(slack_api: &SlackApi, redis_server: &RedisServer) {
+        let (groups, _) = slack_api
+            .list_user_groups_since(&FetchedGroups::new())
+            .await
+            .unwrap();
+        redis_server.insert_user_groups(&groups).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn renamed_groups_are_found_by_their_old_name_and_deleted_ones_are_not_saved() {
+        let usergroups = Arc::new(Mutex::new(json!([
+            usergroup("S1", "eng", 10, 0),
+            usergroup("S2", "gone", 10, 20),
+        ])));
+        let slack_api = slack_api(&fake_slack(usergroups.clone()));
+        let redis = FakeRedis::start().await;
+        let redis_server = RedisServer::new(&[redis.address()]).await.unwrap();
+
+        sync_groups(&slack_api, &redis_server).await;
+        assert!(matches!(
+            redis_server.get_user_group_by_name("eng".to_owned()).await,
+            RedisResponse::Ok(group) if group.id.as_str() == "S1"
+        ));
+        assert!(matches!(
+            redis_server.get_user_group_by_name("gone".to_owned()).await,
+            RedisResponse::Missing
+        ));
+
+        *usergroups.lock().unwrap() = json!([usergroup("S1", "platform", 11, 0)]);
+        sync_groups(&slack_api, &redis_server).await;
+        assert!(matches!(
+            redis_server.get_user_group_by_name("eng".to_owned()).await,
+            RedisResponse::Missing
+        ));
+        match redis_server.get_user_group_by_previous_name("eng").await {
+            RedisResponse::Ok(group) => {
+                assert_eq!(group.name, "platform");
+                assert!(group.previous_names.contains("eng"));
+            }
+            other => panic!("expected the renamed group, got {:?}", other),
+        }
+    }
+
+    /// Stands in for Slack with a single page of `users`, which can also be looked up by email,
+    /// and no groups. Nothing else a sync asks of Slack is expected of it.
+    struct FakeDirectory {
+        users: BTreeSet<SlackUser>,
+    }
+
+    impl SlackDirectory for FakeDirectory {
+        fn prefetch_users(
+            self: Arc<Self>,
+            _cursor: Cursor,
+        ) -> mpsc::Receiver<Result<UsersPage, SlackErrors>> {
+            let (sender, receiver) = mpsc::channel(1);
+            tokio::spawn(async move {
+                let page = UsersPage {
+                    users: self.users.clone(),
+                    availability: BTreeMap::new(),
+                    cursor: Cursor::default(),
+                };
+                let _ = sender.send(Ok(page)).await;
+            });
+            receiver
+        }
+
+        fn list_user_groups_since<'a>(
+            &'a self,
+            _fetched: &'a FetchedGroups,
+        ) -> SlackResult<'a, (BTreeSet<SlackUserGroup>, FetchedGroups)> {
+            future::ready(Ok((BTreeSet::new(), FetchedGroups::new()))).boxed()
+        }
+
+        fn add_dnd_schedules<'a>(
+            &'a self,
+            _availability: &'a mut BTreeMap<UserId, UserAvailability>,
+        ) -> SlackResult<'a, ()> {
+            unreachable!("no sync in these tests calls add_dnd_schedules")
+        }
+
+        fn lookup_user<'a>(&'a self, email: &'a Email) -> SlackResult<'a, Option<SlackUser>> {
+            let user = self
+                .users
+                .iter()
+                .find(|user| user.email.normalized() == email.normalized())
+                .cloned();
+            future::ready(Ok(user)).boxed()
+        }
+
+        fn create_user_group<'a>(&'a self, _name: &'a str) -> SlackResult<'a, GroupId> {
+            unreachable!("no sync in these tests calls create_user_group")
+        }
+
+        fn set_user_group_members<'a>(
+            &'a self,
+            _id: &'a GroupId,
+            _users: &'a [&'a UserId],
+        ) -> SlackResult<'a, ()> {
+            unreachable!("no sync in these tests calls set_user_group_members")
+        }
+
+        fn post_message<'a>(
+            &'a self,
+            _channel: &'a str,
+            _text: &'a str,
+            _blocks: &'a [Value],
+        ) -> SlackResult<'a, ()> {
+            unreachable!("no sync in these tests calls post_message")
+        }
+
+        fn publish_home<'a>(&'a self, _user_id: &'a str, _view: &'a Value) -> SlackResult<'a, ()> {
+            unreachable!("no sync in these tests calls publish_home")
+        }
+    }
+
+    fn user(id: &str, email: &str) -> SlackUser {
+        serde_json::from_value(json!({"id": id, "name": id, "email": email})).unwrap()
+    }
+
+    /// Syncs the users `ids`, with emails at example.com, from the fake directory into `redis`.
+    async fn sync_fake(
+        args: &UpdateRedisArgs,
+        force: bool,
+        redis: &FakeRedis,
+        ids: &[&str],
+    ) -> Result<(), CliErrors> {
+        let users = ids
+            .iter()
+            .map(|id| user(id, &format!("{}@example.com", id)))
+            .collect();
+        let primary = Primary::Slack(Arc::new(FakeDirectory { users }));
+        let redis_server = RedisServer::new(&[redis.address()]).await.unwrap();
+        let usage = Arc::new(ApiUsage::new(None));
+        sync_from(
+            args,
+            force,
+            &Progress::default(),
+            redis_server,
+            primary,
+            &usage,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn sync_users_keeps_only_allowed_domains_in_the_chosen_groups() {
+        let args = UpdateRedisArgs::parse_from(&["update-redis", "--server-id", "test"]);
+        let slack_api: Arc<dyn SlackDirectory> = Arc::new(FakeDirectory {
+            users: vec![
+                user("U1", "member@example.com"),
+                user("U2", "member@elsewhere.com"),
+                user("U3", "outsider@example.com"),
+            ]
+            .into_iter()
+            .collect(),
+        });
+        let redis = FakeRedis::start().await;
+        let redis_server = RedisServer::new(&[redis.address()])
+            .await
+            .unwrap()
+            .with_domain_allowlist(
+                DomainAllowlist::new(&["example.com".to_owned()]),
+                ExternalUsers::Exclude,
+            );
+        let groups: BTreeSet<SlackUserGroup> = vec![serde_json::from_value(json!({
+            "id": "S1", "name": "eng", "users": [{"id": "U1"}, {"id": "U2"}]
+        }))
+        .unwrap()]
+        .into_iter()
+        .collect();
+        let membership = MembershipFilter::new(&groups, &["eng".to_owned()], &[]).unwrap();
+
+        let crawl = sync_users(
+            &args,
+            &slack_api,
+            &redis_server,
+            &membership,
+            None,
+            true,
+            &Progress::default(),
+        )
+        .await
+        .unwrap();
+
+        let kept: Vec<&str> = crawl.users.iter().map(|user| user.id.as_str()).collect();
+        assert_eq!(kept, vec!["U1"]);
+        for (id, cached) in &[("U1", true), ("U2", false), ("U3", false)] {
+            let response = redis_server.get_user_by_id(&id.parse().unwrap()).await;
+            assert_eq!(matches!(response, RedisResponse::Ok(_)), *cached, "{}", id);
+        }
+    }
+
+    #[tokio::test]
+    async fn backfilled_emails_without_a_slack_user_are_remembered() {
+        let args = UpdateRedisArgs::parse_from(&["update-redis", "--server-id", "test"]);
+        let slack_api: Arc<dyn SlackDirectory> = Arc::new(FakeDirectory {
+            users: vec![user("U1", "ann@example.com")].into_iter().collect(),
+        });
+        let redis = FakeRedis::start().await;
+        let redis_server = RedisServer::new(&[redis.address()]).await.unwrap();
+        let path =
+            std::env::temp_dir().join(format!("slack-user-cache-backfill-{}", std::process::id()));
+        std::fs::write(&path, "ann@example.com\nnobody@example.com\n").unwrap();
+
+        let mut users = BTreeSet::new();
+        backfill_users(
+            &args,
+            &path,
+            &Primary::Slack(slack_api),
+            &redis_server,
+            &MembershipFilter::default(),
+            &mut users,
+            &mut UserCounts::default(),
+        )
+        .await
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let found: Vec<&str> = users.iter().map(|user| user.id.as_str()).collect();
+        assert_eq!(found, vec!["U1"]);
+        let emails: BTreeSet<Email> = vec!["ann@example.com", "nobody@example.com"]
+            .into_iter()
+            .map(|email| email.parse().unwrap())
+            .collect();
+        let misses = redis_server.get_backfill_misses(&emails).await.unwrap();
+        let misses: Vec<String> = misses.iter().map(ToString::to_string).collect();
+        assert_eq!(misses, vec!["nobody@example.com"]);
+    }
+
+    #[tokio::test]
+    async fn committed_staged_syncs_clear_the_last_failure() {
+        let mut args = UpdateRedisArgs::parse_from(&["update-redis", "--server-id", "test"]);
+        args.updater_opts.staged_sync = true;
+        let redis = FakeRedis::start().await;
+        let redis_server = RedisServer::new(&[redis.address()]).await.unwrap();
+        let groups = BTreeSet::new();
+
+        let rejected = commit_staged_sync(&args, &redis_server, &groups, None).await;
+        assert!(matches!(rejected, Err(CliErrors::InvalidStagedSync { .. })));
+        assert!(redis_server.get_sync_failure().await.unwrap().is_some());
+
+        let users = vec![user("U1", "ann@example.com")].into_iter().collect();
+        redis_server.stage_users(&users).await.unwrap();
+        commit_staged_sync(&args, &redis_server, &groups, None)
+            .await
+            .unwrap();
+        assert!(redis_server.get_sync_failure().await.unwrap().is_none());
+        assert!(matches!(
+            redis_server.get_user_by_id(&"U1".parse().unwrap()).await,
+            RedisResponse::Ok(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn syncs_with_far_fewer_users_than_the_last_fail_before_their_summary_is_written() {
+        let redis = FakeRedis::start().await;
+        let args =
+            UpdateRedisArgs::parse_from(&["update-redis", "--server-id", "test", "--ignore-lock"]);
+        let sync = |force: bool| sync_fake(&args, force, &redis, &["U1"]);
+        let redis_server = RedisServer::new(&[redis.address()]).await.unwrap();
+        let previous = DirectorySummary {
+            users: 10,
+            ..DirectorySummary::default()
+        };
+        redis_server.set_directory_summary(&previous).await.unwrap();
+        let summarized = || async {
+            let summary = redis_server.get_directory_summary().await.unwrap();
+            summary.map(|summary| summary.users)
+        };
+
+        let shrank = sync(false).await;
+        assert!(matches!(
+            shrank,
+            Err(CliErrors::UsersShrank {
+                fetched: 1,
+                previous: 10
+            })
+        ));
+        // Its users were written as they were fetched, but the next sync is checked against the
+        // last one's summary still.
+        assert!(matches!(
+            redis_server.get_user_by_id(&"U1".parse().unwrap()).await,
+            RedisResponse::Ok(_)
+        ));
+        assert_eq!(summarized().await, Some(10));
+
+        sync(true).await.unwrap();
+        assert_eq!(summarized().await, Some(1));
+    }
+
+    #[tokio::test]
+    async fn interrupted_syncs_that_kept_users_differently_are_started_over() {
+        let args = UpdateRedisArgs::parse_from(&[
+            "update-redis",
+            "--server-id",
+            "test",
+            "--resume-sync-within",
+            "1h",
+        ]);
+        let slack_api: Arc<dyn SlackDirectory> = Arc::new(FakeDirectory {
+            users: vec![user("U1", "ann@example.com")].into_iter().collect(),
+        });
+        let redis = FakeRedis::start().await;
+        let redis_server = RedisServer::new(&[redis.address()]).await.unwrap();
+        let interrupted = UsersCrawl {
+            fetched: 5,
+            ..UsersCrawl::default()
+        };
+        redis_server
+            .save_sync_cursor(&interrupted, Duration::from_secs(3600))
+            .await
+            .unwrap();
+
+        let crawl = sync_users(
+            &args,
+            &slack_api,
+            &redis_server,
+            &MembershipFilter::default(),
+            None,
+            true,
+            &Progress::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(crawl.fetched, 1);
+        assert_eq!(crawl.users.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn changed_users_drop_the_expanded_members_of_their_groups() {
+        let redis = FakeRedis::start().await;
+        let redis_server = RedisServer::new(&[redis.address()]).await.unwrap();
+        let named = |name: &str| -> BTreeSet<SlackUser> {
+            let user = json!({"id": "U1", "name": name, "email": "ann@example.com"});
+            vec![serde_json::from_value(user).unwrap()]
+                .into_iter()
+                .collect()
+        };
+        let group: SlackUserGroup =
+            serde_json::from_value(json!({"id": "S1", "name": "eng", "users": [{"id": "U1"}]}))
+                .unwrap();
+        let groups: BTreeSet<SlackUserGroup> = vec![group.clone()].into_iter().collect();
+
+        redis_server.insert_users(&named("Ann")).await.unwrap();
+        redis_server.insert_user_groups(&groups).await.unwrap();
+        redis_server.expand_user_groups(&groups).await.unwrap();
+        redis_server.insert_users(&named("Annie")).await.unwrap();
+
+        match redis_server.get_expanded_members(&group).await {
+            RedisResponse::Ok(members) => assert_eq!(members[0].name, "Annie"),
+            other => panic!("expected the group's members, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn users_are_only_counted_once_a_sync_has_set_the_count() {
+        let redis = FakeRedis::start().await;
+        let redis_server = RedisServer::new(&[redis.address()]).await.unwrap();
+        let users = |id: &str| -> BTreeSet<SlackUser> {
+            vec![user(id, &format!("{}@example.com", id))]
+                .into_iter()
+                .collect()
+        };
+
+        redis_server.insert_users(&users("U1")).await.unwrap();
+        assert_eq!(redis_server.get_user_count().await.unwrap(), None);
+
+        redis_server.set_counts(1, 0).await.unwrap();
+        redis_server.insert_users(&users("U2")).await.unwrap();
+        assert_eq!(redis_server.get_user_count().await.unwrap(), Some(2));
+    }
+
+    #[tokio::test]
+    async fn typeahead_fills_its_limit_past_emails_the_caller_may_not_see() {
+        let redis = FakeRedis::start().await;
+        let redis_server = RedisServer::new(&[redis.address()]).await.unwrap();
+        // The emails of the first eight sort before Anna's name.
+        let mut users: BTreeSet<SlackUser> = (1..=8)
+            .map(|n| user(&format!("U{}", n), &format!("ann{}@example.com", n)))
+            .collect();
+        users.insert(
+            serde_json::from_value(json!({"id": "U9", "name": "Anna", "email": "z@example.com"}))
+                .unwrap(),
+        );
+        redis_server.insert_users(&users).await.unwrap();
+
+        match redis_server.get_users_by_prefix("ann", 1, false).await {
+            RedisResponse::Ok(found) => {
+                let ids: Vec<&str> = found.iter().map(|user| user.id.as_str()).collect();
+                assert_eq!(ids, vec!["U9"]);
+            }
+            other => panic!("expected users, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn users_found_by_email_carry_the_address_even_when_emails_are_hashed() {
+        let redis = FakeRedis::start().await;
+        let redis_server = RedisServer::new(&[redis.address()])
+            .await
+            .unwrap()
+            .with_email_hasher(Some(EmailHasher::new("salt")));
+        let users = vec![user("U1", "ann@example.com")].into_iter().collect();
+        redis_server.insert_users(&users).await.unwrap();
+
+        match redis_server
+            .get_user_by_email(&"Ann@Example.com".parse().unwrap())
+            .await
+        {
+            RedisResponse::Ok(user) => assert_eq!(user.email.to_string(), "ann@example.com"),
+            other => panic!("expected the user, got {:?}", other),
+        }
+        match redis_server.get_user_by_id(&"U1".parse().unwrap()).await {
+            RedisResponse::Ok(user) => assert_ne!(user.email.to_string(), "ann@example.com"),
+            other => panic!("expected the user, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn updaters_back_off_while_another_holds_the_lock() {
+        let redis = FakeRedis::start().await;
+        let args = |server_id: &str| {
+            UpdateRedisArgs::parse_from(&["update-redis", "--server-id", server_id])
+        };
+
+        sync_fake(&args("first"), false, &redis, &["U1"])
+            .await
+            .unwrap();
+        sync_fake(&args("second"), false, &redis, &["U2"])
+            .await
+            .unwrap();
+
+        let redis_server = RedisServer::new(&[redis.address()]).await.unwrap();
+        for (id, synced) in &[("U1", true), ("U2", false)] {
+            let response = redis_server.get_user_by_id(&id.parse().unwrap()).await;
+            assert_eq!(matches!(response, RedisResponse::Ok(_)), *synced, "{}", id);
+        }
+    }
+}
@@ -1,168 +1,4047 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::convert::Infallible;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use serde_json::json;
-use warp::http::StatusCode;
-use warp::Filter;
+use anyhow::anyhow;
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use warp::filters::BoxedFilter;
+use warp::http::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+use warp::http::{StatusCode, Version};
+use warp::hyper::server::conn::AddrStream;
+use warp::hyper::service::{make_service_fn, service_fn, Service};
+use warp::hyper::{Body, Request};
+use warp::{reject, Filter, Reply};
 
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 type Db = Arc<RedisServer>;
+type Tokens = Arc<ApiTokens>;
+type AllowedFields = Option<Arc<BTreeSet<String>>>;
+type Oncall = Arc<OncallClient>;
+type Latency = Arc<LatencyHistograms>;
+type Shadow = Option<Arc<ShadowLookups>>;
 
-use crate::error::CliErrors;
-use crate::libs::RedisServer;
+/// Version of the response envelope, also the prefix the API is served under.
+const API_VERSION: &str = "v1";
+
+/// How often the OIDC issuer's signing keys are re-read, so rotated keys are picked up.
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const REPLICA_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Slack requests signed further from now than this are rejected as possible replays.
+const SLACK_MAX_TIMESTAMP_SKEW: Duration = Duration::from_secs(5 * 60);
+const SLACK_BODY_LIMIT: u64 = 1024 * 1024;
+
+/// Longest path parameter, once percent decoded, the routes accept. Emails, ids, logins and
+/// group names are all well under it.
+const MAX_PATH_PARAM_LENGTH: usize = 256;
+
+/// Longest annotation name `/admin/user/id/{id}/annotations` accepts.
+const MAX_ANNOTATION_NAME_LENGTH: usize = 64;
+
+/// RFC 7807 media type, sent instead of the envelope to clients that ask for it.
+const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Media type of OpenMetrics, which `/metrics` answers with exemplars to scrapers that ask for
+/// it.
+const OPEN_METRICS: &str = "application/openmetrics-text";
+const OPEN_METRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// How often `/slack/changes/stream` checks for new change records, and how many it reads at once.
+const CHANGES_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const CHANGES_BATCH_SIZE: usize = 100;
+
+/// Seconds clients turned away by the concurrency limits are asked to wait.
+const OVERLOADED_RETRY_AFTER_SECONDS: u64 = 1;
+
+/// Longest `X-Request-Id` taken from a caller before a new id is used instead.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+use crate::error::{CliErrors, SecretErrors};
+use crate::libs::auth::Principal;
+use crate::libs::concurrency::ConcurrencyLimits;
+use crate::libs::email::{self, Email};
+use crate::libs::health::Components;
+use crate::libs::latency::LatencyHistograms;
+use crate::libs::oncall::{self, OncallClient};
+use crate::libs::shadow::ShadowLookups;
+use crate::libs::slack::{GroupId, UserId};
+use crate::libs::{redact, secrets, systemd};
+use crate::libs::{
+    AccessLog, ApiTokens, AuditLog, OidcValidator, RedisPools, RedisServer, SlackApi,
+    SlackClientConfig,
+};
 use crate::WebArgs;
 
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+#[derive(Debug)]
+struct Forbidden;
+
+impl warp::reject::Reject for Forbidden {}
+
+#[derive(Debug)]
+struct Overloaded;
+
+impl warp::reject::Reject for Overloaded {}
+
+/// A caller that has used up its quota in one of its windows.
+#[derive(Debug, Clone, Copy)]
+struct QuotaExceeded {
+    usage: QuotaUsage,
+}
+
+impl warp::reject::Reject for QuotaExceeded {}
+
+/// A path or query parameter that isn't what the route expects.
+#[derive(Debug)]
+struct InvalidParameter {
+    message: String,
+}
+
+impl warp::reject::Reject for InvalidParameter {}
+
+#[derive(Debug)]
+struct Maintenance {
+    message: String,
+}
+
+impl warp::reject::Reject for Maintenance {}
+
+#[derive(Debug)]
+struct InvalidSignature;
+
+impl warp::reject::Reject for InvalidSignature {}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    since: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CacheStatsQuery {
+    largest: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    limit: Option<usize>,
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsersQuery {
+    tz: Option<String>,
+    /// Only users that changed at or after this time.
+    updated_since: Option<String>,
+    /// Only users that belong to this workspace, like `T12345`.
+    team: Option<String>,
+}
+
+/// Query of `/slack/users/typeahead` and `/slack/users/fuzzy`.
+#[derive(Debug, Deserialize)]
+pub struct NameSearchQuery {
+    q: Option<String>,
+    limit: Option<usize>,
+}
+
+impl NameSearchQuery {
+    /// What to look up and how many users to return at most.
+    fn parse(&self) -> Result<(&str, usize), String> {
+        let q = match self.q.as_deref().map(str::trim) {
+            Some(q) if !q.is_empty() => q,
+            _ => return Err("q can't be empty".to_owned()),
+        };
+        let limit = match self.limit {
+            Some(0) => return Err("limit must be at least 1".to_owned()),
+            Some(limit) => limit.min(MAX_NAME_SEARCH_LIMIT),
+            None => DEFAULT_NAME_SEARCH_LIMIT,
+        };
+        Ok((q, limit))
+    }
+}
+
+/// Users a name search returns without `?limit=`.
+const DEFAULT_NAME_SEARCH_LIMIT: usize = 10;
+
+/// Most users a name search returns, whatever `?limit=` asks for.
+const MAX_NAME_SEARCH_LIMIT: usize = 50;
+
+#[derive(Debug, Deserialize)]
+pub struct OnlineNowQuery {
+    window: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AvatarQuery {
+    size: Option<u32>,
+}
+
+/// Width of the profile photo `/slack/user/id/{id}/avatar` redirects to without `?size=`.
+const DEFAULT_AVATAR_SIZE: u32 = 192;
+
+#[derive(Debug, Deserialize)]
+pub struct AsOfQuery {
+    as_of: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExpandQuery {
+    expand: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FieldsQuery {
+    fields: Option<String>,
+    name_form: Option<String>,
+}
+
+/// Which of a user's names `name` holds, picked with `?name_form=`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NameForm {
+    Real,
+    Display,
+    Normalized,
+}
+
+impl NameForm {
+    /// The field the name is taken from, or `None` for `name` itself.
+    fn field(self) -> Option<&'static str> {
+        match self {
+            NameForm::Real => None,
+            NameForm::Display => Some("display-name"),
+            NameForm::Normalized => Some("real-name-normalized"),
+        }
+    }
+}
+
+impl FromStr for NameForm {
+    type Err = String;
+
+    fn from_str(form: &str) -> Result<Self, Self::Err> {
+        match form {
+            "real" => Ok(NameForm::Real),
+            "display" => Ok(NameForm::Display),
+            "normalized" => Ok(NameForm::Normalized),
+            _ => Err(format!(
+                "`{}` isn't a name form, expected `display`, `real` or `normalized`",
+                form
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SlashCommand {
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    user_id: String,
+}
+
+/// What `/whois` was asked about.
+#[derive(Debug, PartialEq)]
+enum WhoisQuery {
+    Email(Email),
+    UserId(UserId),
+    GroupId(GroupId),
+    GroupName(String),
+}
+
+/// Understands plain emails, ids and group names as well as the `<@U123|name>`,
+/// `<!subteam^S123|@handle>` and `<mailto:...>` forms Slack escapes them into.
+fn parse_whois(text: &str) -> Option<WhoisQuery> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+
+    if text.starts_with('<') && text.ends_with('>') {
+        let inner = &text[1..text.len() - 1];
+        let target = inner.splitn(2, '|').next().unwrap_or_default();
+        if let Some(id) = target.strip_prefix('@') {
+            return id.parse().ok().map(WhoisQuery::UserId);
+        }
+        if let Some(id) = target.strip_prefix("!subteam^") {
+            return id.parse().ok().map(WhoisQuery::GroupId);
+        }
+        if let Some(email) = target.strip_prefix("mailto:") {
+            return email.parse().ok().map(WhoisQuery::Email);
+        }
+    }
+
+    if text.contains('@') && !text.starts_with('@') {
+        return text.parse().ok().map(WhoisQuery::Email);
+    }
+
+    if let Ok(id) = text.parse() {
+        return Some(WhoisQuery::UserId(id));
+    }
+
+    Some(WhoisQuery::GroupName(
+        text.trim_start_matches('@').to_owned(),
+    ))
+}
+
+/// Percent decodes a path `segment`, which warp hands over raw, and reads it with `parse`.
+/// Overly long segments are turned away before being parsed, and without being echoed back.
+fn parse_path_param<T>(segment: &str, parse: fn(&str) -> Result<T, String>) -> Result<T, String> {
+    let decoded = percent_decode_str(segment).decode_utf8_lossy();
+    if decoded.len() > MAX_PATH_PARAM_LENGTH {
+        return Err(format!(
+            "path parameters can be at most {} characters",
+            MAX_PATH_PARAM_LENGTH
+        ));
+    }
+
+    parse(&decoded)
+}
+
+/// A domain as users are indexed by it, lowercased and without a leading `@`.
+fn parse_domain(domain: &str) -> Result<String, String> {
+    let domain = email::normalize(domain.trim_start_matches('@'));
+    if domain.is_empty() || domain.contains('@') {
+        Err(format!("{} isn't an email domain", domain))
+    } else {
+        Ok(domain)
+    }
+}
+
+/// A Redis Stream entry id, `<milliseconds>-<sequence>`, as clients send back in `Last-Event-ID`.
+fn parse_stream_id(id: &str) -> Result<String, String> {
+    let mut parts = id.splitn(2, '-');
+    let valid = match (parts.next(), parts.next()) {
+        (Some(millis), Some(sequence)) => {
+            millis.parse::<u64>().is_ok() && sequence.parse::<u64>().is_ok()
+        }
+        _ => false,
+    };
+    if valid {
+        Ok(id.to_owned())
+    } else {
+        Err("Last-Event-ID must be a change id like `1526919030474-55`".to_owned())
+    }
+}
+
+fn parse_group_name(name: &str) -> Result<String, String> {
+    if name.trim().is_empty() {
+        Err("group name can't be empty".to_owned())
+    } else {
+        Ok(name.to_owned())
+    }
+}
+
+/// Seconds since the last successful sync finished.
+fn cache_age(last_sync: u64) -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+        .saturating_sub(last_sync)
+}
+
+/// How much of its quota a caller has left in a window.
+#[derive(Debug, Clone, Copy)]
+struct QuotaUsage {
+    limit: u64,
+    remaining: u64,
+    /// Seconds until the window ends and the quota starts over.
+    reset_seconds: u64,
+}
+
+impl QuotaUsage {
+    fn add_headers(&self, headers: &mut HeaderMap) {
+        headers.insert("x-ratelimit-limit", HeaderValue::from(self.limit));
+        headers.insert("x-ratelimit-remaining", HeaderValue::from(self.remaining));
+        headers.insert("x-ratelimit-reset", HeaderValue::from(self.reset_seconds));
+    }
+}
+
+/// Counts a request against each window of `principal`'s quota, returning the usage of the one
+/// closest to running out, or rejecting the request once one is used up. Requests aren't
+/// counted when Redis can't be reached, so an outage doesn't turn every caller away.
+async fn check_quota(
+    db: &RedisServer,
+    principal: &Principal,
+) -> Result<Option<QuotaUsage>, QuotaExceeded> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    let mut tightest: Option<QuotaUsage> = None;
+    for (window, limit) in principal.quota().limits() {
+        let seconds = window.seconds();
+        let start = now - now % seconds;
+        let count = match db
+            .count_request(&principal.id, window.name(), start, seconds)
+            .await
+        {
+            Ok(count) => count,
+            Err(e) => {
+                warn!(
+                    "Not counting a request by {} against its quota. Error: {}",
+                    principal.id, e
+                );
+                return Ok(None);
+            }
+        };
+
+        let usage = QuotaUsage {
+            limit,
+            remaining: limit.saturating_sub(count),
+            reset_seconds: start + seconds - now,
+        };
+        if count > limit {
+            return Err(QuotaExceeded { usage });
+        }
+        if tightest.map_or(true, |tightest| usage.remaining < tightest.remaining) {
+            tightest = Some(usage);
+        }
+    }
+    Ok(tightest)
+}
+
+/// What counting a request against its caller's quota came to: how much of it is left, if it
+/// has one, or that it's used up.
+type QuotaCheck = Result<Option<QuotaUsage>, QuotaExceeded>;
+
+/// Where a request stands against its caller's quota. It's added to every request as an
+/// extension, so the route that matches it can count it once it has authenticated the caller,
+/// and `with_quota` can then say how much of the quota is left.
+#[derive(Clone)]
+pub struct QuotaSlot {
+    db: Db,
+    checked: Arc<tokio::sync::Mutex<Option<QuotaCheck>>>,
+}
+
+impl QuotaSlot {
+    fn new(db: Db) -> Self {
+        QuotaSlot {
+            db,
+            checked: Arc::default(),
+        }
+    }
+
+    /// Counts the request against `principal`'s quota, unless it already has been, and rejects
+    /// it when the quota is used up.
+    async fn count(&self, principal: &Principal) -> Result<(), warp::Rejection> {
+        let mut checked = self.checked.lock().await;
+        if checked.is_none() {
+            *checked = Some(check_quota(&self.db, principal).await);
+        }
+        match *checked {
+            Some(Err(exceeded)) => Err(warp::reject::custom(exceeded)),
+            _ => Ok(()),
+        }
+    }
+
+    async fn usage(&self) -> Option<QuotaUsage> {
+        match *self.checked.lock().await {
+            Some(Ok(usage)) => usage,
+            _ => None,
+        }
+    }
+}
+
+/// `seconds` since the epoch as an HTTP date, as `Last-Modified` takes it.
+fn http_date(seconds: u64) -> String {
+    Utc.timestamp(seconds as i64, 0)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+/// Whether a record that last changed at the HTTP date `last_modified` is unchanged since the
+/// HTTP date `if_modified_since`. Dates that can't be read count as changed.
+fn is_unmodified_since(last_modified: &str, if_modified_since: &str) -> bool {
+    match (
+        DateTime::parse_from_rfc2822(last_modified),
+        DateTime::parse_from_rfc2822(if_modified_since),
+    ) {
+        (Ok(last_modified), Ok(since)) => last_modified <= since,
+        _ => false,
+    }
+}
+
+/// The connection a request came in on. warp only hands it to filters when it runs the server
+/// itself, so it's passed along as a request extension instead.
+#[derive(Debug, Clone, Copy)]
+pub struct Client {
+    remote_addr: SocketAddr,
+    version: Version,
+}
+
+/// What `/admin/debug` reports besides live pool statistics.
+#[derive(Debug)]
+pub struct DebugInfo {
+    started_at: Instant,
+    config: Value,
+}
+
+/// The server's configuration with secrets reduced to whether they are set.
+fn effective_config(args: &WebArgs) -> Value {
+    let secret = |value: Option<&str>| value.map(|_| redact::REDACTED);
+
+    json!({
+        "redis-address": args
+            .shared_opts
+            .redis_opts
+            .redis_address
+            .iter()
+            .map(|address| redact::url_password(address))
+            .collect::<Vec<_>>(),
+        "redis-read-address": args
+            .web_opts
+            .redis_read_address
+            .iter()
+            .map(|address| redact::url_password(address))
+            .collect::<Vec<_>>(),
+        "key-prefix": args.shared_opts.key_opts.key_prefix,
+        "dual-write-legacy-keys": args.shared_opts.key_opts.dual_write_legacy_keys,
+        "group-name-folding": args.shared_opts.key_opts.group_name_folding,
+        "listen-server": args.web_opts.listen_server,
+        "api-tokens-file": args.web_opts.api_tokens_file,
+        "oidc-issuer": args.web_opts.oidc_issuer,
+        "oidc-audience": args.web_opts.oidc_audience,
+        "oidc-jwks-url": args.web_opts.oidc_jwks_url,
+        "allowed-fields": args.web_opts.allowed_fields,
+        "max-staleness": args.web_opts.max_staleness,
+        "cache-age-header": args.web_opts.cache_age_header,
+        "cache-control": args.web_opts.cache_control.to_str().ok(),
+        "max-concurrent-requests": args.web_opts.max_concurrent_requests,
+        "route-concurrency-limits": args
+            .web_opts
+            .route_concurrency_limits
+            .iter()
+            .map(|route| format!("{}={}", route.endpoint, route.limit))
+            .collect::<Vec<_>>(),
+        "max-queued-requests": args.web_opts.max_queued_requests,
+        "tcp-keepalive": args.web_opts.tcp_keepalive,
+        "disable-http1-keepalive": args.web_opts.disable_http1_keepalive,
+        "http2-keepalive-interval": args.web_opts.http2_keepalive_interval,
+        "http2-keepalive-timeout": args.web_opts.http2_keepalive_timeout,
+        "http2-max-concurrent-streams": args.web_opts.http2_max_concurrent_streams,
+        "max-body-size": args.web_opts.max_body_size,
+        "max-list-entries": args.web_opts.max_list_entries,
+        "maintenance-message": args.web_opts.maintenance_message,
+        "slack-signing-secret": secret(args.web_opts.slack_signing_secret.as_deref()),
+        "slack-signing-secret-file": args.web_opts.slack_signing_secret_file,
+        "shadow-lookup-rate": args.web_opts.shadow_lookup_rate,
+        "slack-token": secret(args.shared_opts.slack_token.as_deref()),
+        "slack-token-file": args.shared_opts.slack_token_file,
+        "slack-connect-timeout": args.shared_opts.slack_connect_timeout,
+        "slack-request-timeout": args.shared_opts.slack_request_timeout,
+        "slack-tcp-keepalive": args.shared_opts.slack_tcp_keepalive,
+        "audit-log": args.web_opts.audit_log,
+        "access-log": args.web_opts.access_log,
+        "access-log-format": args.web_opts.access_log_format.to_string(),
+        "oncall-schedules-file": args.web_opts.oncall_schedules_file,
+        "pagerduty-api-token": secret(args.web_opts.pagerduty_api_token.as_deref()),
+        "opsgenie-api-key": secret(args.shared_opts.opsgenie_api_key.as_deref()),
+        "email-hash-salt": secret(args.shared_opts.privacy_opts.email_hash_salt.as_deref()),
+        "redact-pii": redact::is_redacting_pii(),
+    })
+}
+
+/// The browse UI file at `path` and its content type, in builds with the `ui` feature.
+#[cfg(feature = "ui")]
+fn ui_asset(path: &str) -> Option<(Cow<'static, [u8]>, String)> {
+    crate::libs::ui::asset(path)
+}
+
+#[cfg(not(feature = "ui"))]
+fn ui_asset(_path: &str) -> Option<(Cow<'static, [u8]>, String)> {
+    None
+}
+
+/// The shadow lookups `--shadow-lookup-rate` asks for, if any.
+fn build_shadow_lookups(args: &WebArgs) -> Result<Shadow, CliErrors> {
+    if args.web_opts.shadow_lookup_rate <= 0.0 {
+        return Ok(None);
+    }
+
+    let slack_token = match &args.shared_opts.slack_token_file {
+        Some(path) => secrets::read_secret_file(path)?,
+        None => args
+            .shared_opts
+            .slack_token
+            .clone()
+            .ok_or_else(|| SecretErrors::Empty {
+                name: "SLACK_BOT_TOKEN".to_owned(),
+            })?,
+    };
+    let slack_api = SlackApi::new(
+        &slack_token,
+        &SlackClientConfig {
+            connect_timeout: Duration::from_secs(args.shared_opts.slack_connect_timeout),
+            request_timeout: Duration::from_secs(args.shared_opts.slack_request_timeout),
+            tcp_keepalive: Duration::from_secs(args.shared_opts.slack_tcp_keepalive),
+            rotation: None,
+            usage: Arc::default(),
+        },
+    )?;
+
+    Ok(Some(Arc::new(ShadowLookups::new(
+        slack_api,
+        args.web_opts.shadow_lookup_rate,
+    ))))
+}
+
+fn resolve_signing_secret(args: &WebArgs) -> Result<Option<String>, SecretErrors> {
+    match &args.web_opts.slack_signing_secret_file {
+        Some(path) => secrets::read_secret_file(path).map(Some),
+        None => Ok(args.web_opts.slack_signing_secret.clone()),
+    }
+}
+
+/// Fields holding email addresses, which only callers whose token can read emails see.
+const EMAIL_FIELDS: &[&str] = &["email", "previous-emails"];
+
+/// Drops fields from users and groups that the client didn't ask for, the operator didn't allow,
+/// or the caller's token may not see. Users are given the name the client asked for first, falling
+/// back to their real name when they don't have that one.
+#[derive(Debug, Clone)]
+pub struct FieldFilter {
+    requested: Option<BTreeSet<String>>,
+    allowed: AllowedFields,
+    read_emails: bool,
+    name_form: NameForm,
+}
+
+impl FieldFilter {
+    fn apply<T>(&self, value: &T) -> Value
+    where
+        T: serde::Serialize,
+    {
+        let mut value = serde_json::to_value(value).unwrap_or(Value::Null);
+        self.filter(&mut value);
+        value
+    }
+
+    fn filter(&self, value: &mut Value) {
+        match value {
+            Value::Array(items) => items.iter_mut().for_each(|item| self.filter(item)),
+            Value::Object(map) => {
+                // Only from a field the caller could see anyway, so `name` doesn't leak it.
+                let source = self.name_form.field().filter(|field| self.allows(field));
+                if let Some(name) = source.and_then(|field| map.get(field)) {
+                    let name = name.clone();
+                    map.insert("name".to_owned(), name);
+                }
+                *map = std::mem::take(map)
+                    .into_iter()
+                    .filter(|(field, _)| self.keep(field))
+                    .collect();
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether the caller may see emails at all, whatever fields they asked for, so whether users
+    /// can be looked up by them.
+    fn shows_emails(&self) -> bool {
+        self.allows("email")
+    }
+
+    fn keep(&self, field: &str) -> bool {
+        let requested = match &self.requested {
+            Some(requested) => requested.contains(field),
+            None => true,
+        };
+
+        requested && self.allows(field)
+    }
+
+    /// Whether the operator and the caller's token let the caller see `field`, whether or not
+    /// they asked for it.
+    fn allows(&self, field: &str) -> bool {
+        if EMAIL_FIELDS.contains(&field) && !self.read_emails {
+            return false;
+        }
+
+        match &self.allowed {
+            Some(allowed) => allowed.contains(field),
+            None => true,
+        }
+    }
+}
+
+fn parse_fields(list: &str) -> BTreeSet<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
 enum Response<T>
 where
     T: serde::Serialize,
 {
-    Result { result: T },
-    Error { message: String },
+    Result {
+        result: T,
+    },
+    /// One page of a list, with where the next one starts.
+    Page {
+        result: T,
+        next_cursor: Option<String>,
+        truncated: bool,
+    },
+    Renamed {
+        result: T,
+        renamed_to: String,
+    },
+    /// Found under an email the user has since changed.
+    Moved {
+        result: T,
+        moved_to: String,
+    },
+    Error {
+        message: String,
+    },
+    BadRequest {
+        message: String,
+    },
     NotFound,
+    MethodNotAllowed,
+    PayloadTooLarge,
+    Unauthorized,
+    Forbidden,
+    Unavailable {
+        message: String,
+    },
+    TooManyRequests {
+        message: String,
+    },
 }
 
 impl<T> Response<T>
 where
     T: serde::Serialize,
 {
-    fn into_response(self) -> warp::reply::WithStatus<warp::reply::Json> {
-        match self {
+    fn into_response(self) -> warp::reply::Response {
+        let (status, kind, message) = match self {
             Response::Result { result } => {
                 let obj = json!({
+                    "api_version": API_VERSION,
                     "code": 200,
                     "success": true,
                     "result": result
                 });
 
-                warp::reply::with_status(warp::reply::json(&obj), StatusCode::OK)
+                return warp::reply::with_status(warp::reply::json(&obj), StatusCode::OK)
+                    .into_response();
             }
-            Response::Error { message } => {
+            Response::Page {
+                result,
+                next_cursor,
+                truncated,
+            } => {
+                let mut obj = json!({
+                    "api_version": API_VERSION,
+                    "code": 200,
+                    "success": true,
+                    "result": result
+                });
+                if truncated {
+                    obj["truncated"] = json!(true);
+                }
+                if let Some(next_cursor) = next_cursor {
+                    obj["next_cursor"] = json!(next_cursor);
+                }
+
+                return warp::reply::with_status(warp::reply::json(&obj), StatusCode::OK)
+                    .into_response();
+            }
+            Response::Renamed { result, renamed_to } => {
                 let obj = json!({
-                    "code": 501,
-                    "success": false,
-                    "message": message
+                    "api_version": API_VERSION,
+                    "code": 200,
+                    "success": true,
+                    "renamed_to": renamed_to,
+                    "result": result
                 });
 
-                warp::reply::with_status(warp::reply::json(&obj), StatusCode::INTERNAL_SERVER_ERROR)
+                return warp::reply::with_status(warp::reply::json(&obj), StatusCode::OK)
+                    .into_response();
             }
-            Response::NotFound => {
+            Response::Moved { result, moved_to } => {
                 let obj = json!({
-                    "code": 404,
+                    "api_version": API_VERSION,
+                    "code": 200,
                     "success": true,
-                    "message": "not found"
+                    "moved_to": moved_to,
+                    "result": result
                 });
 
-                warp::reply::with_status(warp::reply::json(&obj), StatusCode::NOT_FOUND)
+                return warp::reply::with_status(warp::reply::json(&obj), StatusCode::OK)
+                    .into_response();
+            }
+            Response::Error { message } => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "internal_error", message)
+            }
+            Response::BadRequest { message } => (StatusCode::BAD_REQUEST, "bad_request", message),
+            Response::NotFound => (StatusCode::NOT_FOUND, "not_found", "not found".to_owned()),
+            Response::MethodNotAllowed => (
+                StatusCode::METHOD_NOT_ALLOWED,
+                "method_not_allowed",
+                "method not allowed".to_owned(),
+            ),
+            Response::PayloadTooLarge => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload_too_large",
+                "payload too large".to_owned(),
+            ),
+            Response::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+                "unauthorized".to_owned(),
+            ),
+            Response::Forbidden => (StatusCode::FORBIDDEN, "forbidden", "forbidden".to_owned()),
+            Response::Unavailable { message } => {
+                (StatusCode::SERVICE_UNAVAILABLE, "unavailable", message)
+            }
+            Response::TooManyRequests { message } => {
+                (StatusCode::TOO_MANY_REQUESTS, "too_many_requests", message)
+            }
+        };
+
+        let problem = Problem {
+            status,
+            kind,
+            detail: message,
+        };
+        let mut response =
+            warp::reply::with_status(warp::reply::json(&problem.to_envelope(None)), status)
+                .into_response();
+        response.extensions_mut().insert(problem);
+        response
+    }
+}
+
+/// The failure behind an error response, kept so it can be rendered again once the request id and
+/// the format the client asked for are known.
+#[derive(Debug, Clone)]
+struct Problem {
+    status: StatusCode,
+    kind: &'static str,
+    detail: String,
+}
+
+impl Problem {
+    fn to_envelope(&self, request_id: Option<&str>) -> Value {
+        let mut obj = json!({
+            "api_version": API_VERSION,
+            "code": self.status.as_u16(),
+            "success": false,
+            "message": self.detail,
+            "error": {
+                "kind": self.kind,
+                "message": self.detail
             }
+        });
+        if let Some(request_id) = request_id {
+            obj["request_id"] = json!(request_id);
         }
+        obj
+    }
+
+    fn to_problem_json(&self, instance: &str, request_id: &str) -> Value {
+        json!({
+            "type": "about:blank",
+            "title": self.status.canonical_reason().unwrap_or("Error"),
+            "status": self.status.as_u16(),
+            "detail": self.detail,
+            "instance": instance,
+            "kind": self.kind,
+            "request_id": request_id
+        })
     }
 }
 
-pub async fn web_server(args: &WebArgs) -> Result<(), CliErrors> {
-    use std::net::SocketAddr;
+fn accepts_problem_json(accept: &str) -> bool {
+    accepts(accept, PROBLEM_JSON)
+}
+
+fn accepts_open_metrics(accept: &str) -> bool {
+    accepts(accept, OPEN_METRICS)
+}
+
+/// Whether the `Accept` header `accept` lists `media_type`, whatever its parameters.
+fn accepts(accept: &str, media_type: &str) -> bool {
+    accept.split(',').any(|accepted| {
+        accepted
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .eq_ignore_ascii_case(media_type)
+    })
+}
+
+/// The trace id of a W3C `traceparent` header, `<version>-<trace id>-<parent id>-<flags>`.
+fn trace_id(traceparent: Option<&str>) -> Option<String> {
+    let trace_id = traceparent?.trim().split('-').nth(1)?;
+    let valid = trace_id.len() == 32
+        && trace_id.chars().all(|c| c.is_ascii_hexdigit())
+        && trace_id.chars().any(|c| c != '0');
+    if valid {
+        Some(trace_id.to_lowercase())
+    } else {
+        None
+    }
+}
 
-    let redis_server = match RedisServer::new(&args.redis_address).await {
-        Ok(redis_server) => redis_server,
-        Err(e) => return Err(CliErrors::Redis(e)),
+/// `path` with the ids, emails, logins, names, domains and channels in it replaced by
+/// placeholders, so each endpoint is a single label value, e.g. `/slack/user/id/{id}` or
+/// `/admin/groups/{id}/members/{user}`.
+fn endpoint(path: &str) -> String {
+    let prefix = format!("/{}/", API_VERSION);
+    let path = if path.starts_with(&prefix) {
+        &path[prefix.len() - 1..]
+    } else {
+        path
     };
 
-    debug!("Redis client create");
+    let mut segments: Vec<String> = Vec::new();
+    for segment in path.split('/') {
+        // Placeholders are pushed in place of values, so a value never reads as the segment
+        // naming the one after it.
+        let before = |back: usize| {
+            segments
+                .len()
+                .checked_sub(back)
+                .map_or("", |index| segments[index].as_str())
+        };
+        let placeholder = match (before(2), before(1), segment) {
+            (_, name, _) if matches!(name, "id" | "email" | "github" | "name" | "domain") => {
+                Some(name)
+            }
+            ("admin", "groups", "changes") => None,
+            ("admin", "groups", _) => Some("id"),
+            ("groups", "changes", _) => Some("change"),
+            (_, "members", _) => Some("user"),
+            (_, "watchers", _) => Some("channel"),
+            _ => None,
+        };
+        let placeholder = placeholder.map(|name| format!("{{{}}}", name));
+        segments.push(placeholder.unwrap_or_else(|| segment.to_owned()));
+    }
+    segments.join("/")
+}
 
-    let db = Arc::new(redis_server);
+/// Uses the caller's `X-Request-Id` when it's a reasonable one, otherwise makes up a new id.
+fn request_id(header: Option<&str>) -> String {
+    match header.map(str::trim) {
+        Some(id)
+            if !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LENGTH
+                && id.chars().all(|c| c.is_ascii_graphic()) =>
+        {
+            id.to_owned()
+        }
+        _ => format!("{:016x}", rand::random::<u64>()),
+    }
+}
 
-    let api = filters::get_all_users(db.clone())
-        .or(filters::get_user_by_id(db.clone()))
-        .or(filters::get_user_by_email(db.clone()))
-        .or(filters::get_all_user_groups(db.clone()))
-        .or(filters::status());
+async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, warp::Rejection> {
+    if err.find::<Unauthorized>().is_some() {
+        return Ok(Response::<()>::Unauthorized.into_response());
+    }
 
-    let listen_server: SocketAddr = args
-        .listen_server
-        .parse()
-        .expect("Unable to parse listen_server");
+    if err.find::<Forbidden>().is_some() {
+        return Ok(Response::<()>::Forbidden.into_response());
+    }
 
-    info!("Listing on {}", listen_server);
+    if err.find::<InvalidSignature>().is_some() {
+        return Ok(Response::<()>::Unauthorized.into_response());
+    }
+
+    if let Some(e) = err.find::<InvalidParameter>() {
+        let message = e.message.clone();
+        return Ok(Response::<()>::BadRequest { message }.into_response());
+    }
+
+    if let Some(e) = err.find::<Maintenance>() {
+        let message = e.message.clone();
+        return Ok(Response::<()>::Unavailable { message }.into_response());
+    }
+
+    if let Some(e) = err.find::<QuotaExceeded>() {
+        let mut response = Response::<()>::TooManyRequests {
+            message: "quota exhausted, try again once it resets".to_owned(),
+        }
+        .into_response();
+        e.usage.add_headers(response.headers_mut());
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(e.usage.reset_seconds));
+        return Ok(response);
+    }
+
+    if err.find::<Overloaded>().is_some() {
+        let mut response = Response::<()>::Unavailable {
+            message: "too many requests in flight, try again shortly".to_owned(),
+        }
+        .into_response();
+        response.headers_mut().insert(
+            RETRY_AFTER,
+            HeaderValue::from(OVERLOADED_RETRY_AFTER_SECONDS),
+        );
+        return Ok(response);
+    }
+
+    Err(err)
+}
+
+/// Answers whatever no route took, so those responses also use the JSON envelope.
+async fn handle_unmatched(err: warp::Rejection) -> Result<impl warp::Reply, Infallible> {
+    let response = if err.is_not_found() {
+        Response::<()>::NotFound
+    } else if err.find::<reject::MethodNotAllowed>().is_some() {
+        Response::MethodNotAllowed
+    } else if err.find::<reject::PayloadTooLarge>().is_some() {
+        Response::PayloadTooLarge
+    } else if let Some(e) = err.find::<reject::InvalidQuery>() {
+        Response::BadRequest {
+            message: e.to_string(),
+        }
+    } else if let Some(e) = err.find::<reject::InvalidHeader>() {
+        Response::BadRequest {
+            message: e.to_string(),
+        }
+    } else if let Some(e) = err.find::<reject::MissingHeader>() {
+        Response::BadRequest {
+            message: e.to_string(),
+        }
+    } else if let Some(e) = err.find::<reject::LengthRequired>() {
+        Response::BadRequest {
+            message: e.to_string(),
+        }
+    } else if let Some(e) = err.find::<reject::UnsupportedMediaType>() {
+        Response::BadRequest {
+            message: e.to_string(),
+        }
+    } else if let Some(e) = err.find::<warp::body::BodyDeserializeError>() {
+        Response::BadRequest {
+            message: e.to_string(),
+        }
+    } else {
+        warn!("Unhandled rejection: {:?}", err);
+        Response::Error {
+            message: "internal error".to_owned(),
+        }
+    };
+
+    Ok(response.into_response())
+}
+
+/// Checks a request against Slack's `v0` signature: an HMAC-SHA256 of `v0:<timestamp>:<body>`
+/// keyed with the app's signing secret.
+fn is_valid_slack_signature(secret: &str, timestamp: &str, signature: &str, body: &[u8]) -> bool {
+    let sent_at: u64 = match timestamp.parse() {
+        Ok(sent_at) => sent_at,
+        Err(_) => return false,
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    if now.max(sent_at) - now.min(sent_at) > SLACK_MAX_TIMESTAMP_SKEW.as_secs() {
+        debug!("Rejecting Slack request signed at {}, now {}", sent_at, now);
+        return false;
+    }
+
+    let signature = match signature
+        .strip_prefix("v0=")
+        .and_then(|signature| hex::decode(signature).ok())
+    {
+        Some(signature) => signature,
+        None => return false,
+    };
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(b"v0:");
+    mac.update(timestamp.as_bytes());
+    mac.update(b":");
+    mac.update(body);
+    mac.verify(&signature).is_ok()
+}
+
+async fn reload_tokens_on_hangup(tokens: Tokens, path: PathBuf) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!(
+                "Unable to listen for SIGHUP, API tokens will not be reloaded. Error: {}",
+                e
+            );
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading API tokens");
+        if let Err(e) = tokens.reload(&path) {
+            warn!("Keeping previous API tokens. Error: {}", e);
+        }
+    }
+}
+
+async fn refresh_signing_keys(oidc: Arc<OidcValidator>) {
+    let mut interval = tokio::time::interval(JWKS_REFRESH_INTERVAL);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = oidc.refresh().await {
+            warn!("Keeping previous OIDC signing keys. Error: {}", e);
+        }
+    }
+}
+
+async fn check_read_replicas(redis_server: Db) {
+    let mut interval = tokio::time::interval(REPLICA_CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        redis_server.check_read_replicas().await;
+    }
+}
+
+pub async fn web_server(args: &WebArgs) -> Result<(), CliErrors> {
+    let pools = RedisPools::connect(&args.shared_opts.redis_opts.redis_address)?;
+    serve(args, &pools, &Components::default()).await
+}
+
+/// Serves the API with connections from `pools`, reporting as the `web` component of
+/// `components`.
+pub async fn serve(
+    args: &WebArgs,
+    pools: &RedisPools,
+    components: &Components,
+) -> Result<(), CliErrors> {
+    let health = components.register("web");
+    let debug_info = Arc::new(DebugInfo {
+        started_at: Instant::now(),
+        config: effective_config(args),
+    });
+
+    let redis_server = RedisServer::from_pools(pools)
+        .with_key_layout(args.shared_opts.key_opts.layout())
+        .with_email_hasher(args.shared_opts.privacy_opts.email_hasher())
+        .with_read_replicas(&args.web_opts.redis_read_address)?;
+
+    debug!("Redis client create");
+
+    let db = Arc::new(redis_server);
+    if !args.web_opts.redis_read_address.is_empty() {
+        tokio::spawn(check_read_replicas(db.clone()));
+    }
+
+    let oidc = match (&args.web_opts.oidc_issuer, &args.web_opts.oidc_audience) {
+        (Some(issuer), Some(audience)) => {
+            let oidc = Arc::new(
+                OidcValidator::new(issuer, audience, args.web_opts.oidc_jwks_url.as_deref())
+                    .await?,
+            );
+            tokio::spawn(refresh_signing_keys(oidc.clone()));
+            Some(oidc)
+        }
+        _ => None,
+    };
+
+    let tokens: Tokens = match &args.web_opts.api_tokens_file {
+        Some(path) => {
+            let tokens = Arc::new(ApiTokens::load(path)?.with_oidc(oidc));
+            tokio::spawn(reload_tokens_on_hangup(tokens.clone(), path.clone()));
+            tokens
+        }
+        None => Arc::new(ApiTokens::default().with_oidc(oidc)),
+    };
+    if !tokens.is_enabled() {
+        warn!("No API tokens configured, the API is open to anyone who can reach it");
+    }
+
+    let allowed_fields: AllowedFields = args
+        .web_opts
+        .allowed_fields
+        .as_deref()
+        .map(|fields| Arc::new(parse_fields(fields)));
+    if let Some(fields) = &allowed_fields {
+        info!("Only returning fields {:?}", fields);
+    }
+
+    let audit_log = match &args.web_opts.audit_log {
+        Some(target) => AuditLog::start(target, db.clone())?,
+        None => AuditLog::default(),
+    };
+    let access_log = match &args.web_opts.access_log {
+        Some(target) => AccessLog::start(target, args.web_opts.access_log_format)?,
+        None => AccessLog::default(),
+    };
+
+    if let Some(path) = &args.web_opts.oncall_schedules_file {
+        for (id, schedule) in oncall::load_schedules(path)? {
+            db.set_oncall_schedule(&id, &schedule).await?;
+        }
+    }
+    let shadow = build_shadow_lookups(args)?;
+    if shadow.is_some() {
+        info!(
+            "Comparing {} of email lookups with Slack",
+            args.web_opts.shadow_lookup_rate
+        );
+    }
+    let route_config = RouteConfig {
+        db: db.clone(),
+        tokens: tokens.clone(),
+        allowed_fields: allowed_fields.clone(),
+        shadow: shadow.clone(),
+        oncall: Arc::new(OncallClient::new(
+            args.web_opts.pagerduty_api_token.clone(),
+            args.shared_opts.opsgenie_api_key.clone(),
+        )?),
+        debug_info,
+        max_list_entries: args.web_opts.max_list_entries,
+        max_body_size: args.web_opts.max_body_size,
+    };
+
+    let latency: Latency = Arc::new(LatencyHistograms::new(
+        "http_request_duration_seconds",
+        "Time taken to answer requests, by endpoint",
+        "endpoint",
+    ));
+    let maintenance = args.web_opts.maintenance_message.clone().map(Arc::new);
+    if let Some(message) = &maintenance {
+        warn!("In maintenance, answering /slack routes with: {}", message);
+    }
+    let routes = filters::timed(
+        latency.clone(),
+        filters::in_maintenance(maintenance)
+            .and(slack_routes(&route_config))
+            .or(admin_routes(&route_config)),
+    );
+
+    let limits = Arc::new(ConcurrencyLimits::new(
+        args.web_opts.max_concurrent_requests,
+        &args.web_opts.route_concurrency_limits,
+        args.web_opts.max_queued_requests,
+    ));
+    if limits.is_enabled() {
+        info!(
+            "Limiting requests in flight, with up to {} queued",
+            args.web_opts.max_queued_requests
+        );
+    }
+
+    // Served under `/v1` and, for clients written before it existed, without a prefix.
+    let data = filters::with_quota(filters::limited(
+        limits,
+        warp::path(API_VERSION).and(routes.clone()).or(routes),
+    ))
+    .recover(handle_rejection);
+
+    let signing_secret = resolve_signing_secret(args)?.map(Arc::new);
+    if signing_secret.is_some() {
+        info!("Slash commands enabled at /slack/command");
+    }
+
+    let data = filters::with_chaos_delay(data);
+    let data = filters::with_conditional_get(args.web_opts.cache_control.clone(), data);
+    let data = filters::with_cache_age(db.clone(), args.web_opts.cache_age_header, data);
+
+    #[cfg(feature = "ui")]
+    let serve_ui = args.web_opts.serve_ui;
+    #[cfg(not(feature = "ui"))]
+    let serve_ui = false;
+    if serve_ui {
+        info!("Serving the browse UI at /ui");
+    }
+
+    let api = filters::audited(tokens, audit_log.clone(), data)
+        .or(filters::slack_command(
+            db.clone(),
+            signing_secret,
+            allowed_fields,
+            audit_log,
+        ))
+        .or(filters::status())
+        .or(filters::component_health(components.clone()))
+        .or(filters::version())
+        .or(filters::ui(serve_ui))
+        .or(filters::ready(db.clone(), args.web_opts.max_staleness))
+        .or(filters::metrics(db.clone(), latency, shadow))
+        .recover(handle_rejection)
+        .recover(handle_unmatched);
+    let api = filters::with_error_details(api);
+    let api = filters::with_access_log(access_log, api);
+
+    let listen_server: SocketAddr = args
+        .web_opts
+        .listen_server
+        .parse()
+        .expect("Unable to parse listen_server");
+
+    info!("Listing on {}", listen_server);
+
+    let serve_error = |e: warp::hyper::Error| CliErrors::Serve {
+        address: listen_server.to_string(),
+        source: anyhow!(e),
+    };
+    let server = warp::hyper::Server::try_bind(&listen_server).map_err(serve_error)?;
+    health.healthy(&format!("listening on {}", listen_server));
+    systemd::ready();
+    if let Some(interval) = systemd::start_watchdog(systemd::Component::Web) {
+        spawn_liveness_probe(listen_server, interval);
+    }
+
+    server
+        .tcp_keepalive(args.web_opts.tcp_keepalive.map(Duration::from_secs))
+        .http1_keepalive(!args.web_opts.disable_http1_keepalive)
+        .http2_keep_alive_interval(
+            args.web_opts
+                .http2_keepalive_interval
+                .map(Duration::from_secs),
+        )
+        .http2_keep_alive_timeout(Duration::from_secs(args.web_opts.http2_keepalive_timeout))
+        .http2_max_concurrent_streams(args.web_opts.http2_max_concurrent_streams)
+        .serve(make_service_fn(move |conn: &AddrStream| {
+            let remote_addr = conn.remote_addr();
+            let mut service = warp::service(api.clone());
+            let db = db.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |mut request: Request<Body>| {
+                    let client = Client {
+                        remote_addr,
+                        version: request.version(),
+                    };
+                    request.extensions_mut().insert(client);
+                    request.extensions_mut().insert(QuotaSlot::new(db.clone()));
+                    service.call(request)
+                }))
+            }
+        }))
+        .await
+        .map_err(serve_error)
+}
+
+/// Asks the server at `address` for `/healthz` every `interval`, telling the systemd watchdog the
+/// server is alive whenever it answers at all, so one that stops answering gets restarted.
+fn spawn_liveness_probe(mut address: SocketAddr, interval: Duration) {
+    if address.ip().is_unspecified() {
+        let loopback: IpAddr = match address {
+            SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+            SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+        };
+        address.set_ip(loopback);
+    }
+    let url = format!("http://{}/healthz", address);
+
+    tokio::spawn(async move {
+        let client = match reqwest::Client::builder().timeout(interval).build() {
+            Ok(client) => client,
+            Err(e) => {
+                warn!("Unable to probe the server for the watchdog. Error: {}", e);
+                return;
+            }
+        };
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            match client.get(&url).send().await {
+                Ok(_) => systemd::alive(systemd::Component::Web),
+                Err(e) => warn!(
+                    "The server didn't answer the watchdog's probe. Error: {}",
+                    e
+                ),
+            }
+        }
+    });
+}
+
+/// What the `/slack` and `/admin` routes answer from, shared by the server and its tests so both
+/// answer with the same routes.
+struct RouteConfig {
+    db: Db,
+    tokens: Tokens,
+    allowed_fields: AllowedFields,
+    shadow: Shadow,
+    oncall: Oncall,
+    debug_info: Arc<DebugInfo>,
+    max_list_entries: usize,
+    max_body_size: u64,
+}
+
+// Routes are boxed in groups. Chaining them all with `or` nests their types deeply enough to slow
+// compiling down to minutes.
+
+/// The routes under `/slack`, before they're timed and limited.
+fn slack_routes(config: &RouteConfig) -> BoxedFilter<(warp::reply::Response,)> {
+    let RouteConfig {
+        db,
+        tokens,
+        allowed_fields,
+        shadow,
+        ..
+    } = config;
+    let user_routes = filters::get_all_users(
+        db.clone(),
+        tokens.clone(),
+        allowed_fields.clone(),
+        config.max_list_entries,
+    )
+    .or(filters::get_users_online_now(
+        db.clone(),
+        tokens.clone(),
+        allowed_fields.clone(),
+        config.max_list_entries,
+    ))
+    .or(filters::get_user_count(db.clone(), tokens.clone()))
+    .or(filters::get_users_by_domain(
+        db.clone(),
+        tokens.clone(),
+        allowed_fields.clone(),
+        config.max_list_entries,
+    ))
+    .or(filters::get_users_typeahead(
+        db.clone(),
+        tokens.clone(),
+        allowed_fields.clone(),
+    ))
+    .or(filters::get_users_fuzzy(
+        db.clone(),
+        tokens.clone(),
+        allowed_fields.clone(),
+    ))
+    .or(filters::get_user_by_id(
+        db.clone(),
+        tokens.clone(),
+        allowed_fields.clone(),
+    ))
+    .or(filters::get_user_avatar(db.clone(), tokens.clone()))
+    .or(filters::get_user_dnd(db.clone(), tokens.clone()))
+    .or(filters::user_email_exists(db.clone(), tokens.clone()))
+    .or(filters::get_user_by_email(
+        db.clone(),
+        tokens.clone(),
+        allowed_fields.clone(),
+        shadow.clone(),
+    ))
+    .or(filters::get_user_by_github(
+        db.clone(),
+        tokens.clone(),
+        allowed_fields.clone(),
+    ))
+    .map(Reply::into_response)
+    .boxed();
+
+    let group_routes = filters::get_all_user_groups(
+        db.clone(),
+        tokens.clone(),
+        allowed_fields.clone(),
+        config.max_list_entries,
+    )
+    .or(filters::get_user_group_count(db.clone(), tokens.clone()))
+    .or(filters::get_user_group_by_name(
+        db.clone(),
+        tokens.clone(),
+        allowed_fields.clone(),
+    ))
+    .or(filters::get_user_group_history(db.clone(), tokens.clone()))
+    .or(filters::get_user_group_oncall(
+        db.clone(),
+        tokens.clone(),
+        allowed_fields.clone(),
+        config.oncall.clone(),
+    ))
+    .or(filters::changes_stream(
+        db.clone(),
+        tokens.clone(),
+        allowed_fields.clone(),
+    ))
+    .or(filters::directory_stats(db.clone(), tokens.clone()))
+    .map(Reply::into_response)
+    .boxed();
+
+    user_routes.or(group_routes).unify().boxed()
+}
+
+/// The routes under `/admin`, before they're timed and limited.
+fn admin_routes(config: &RouteConfig) -> BoxedFilter<(warp::reply::Response,)> {
+    let RouteConfig { db, tokens, .. } = config;
+    filters::admin_debug(db.clone(), tokens.clone(), config.debug_info.clone())
+        .or(filters::admin_cache_stats(db.clone(), tokens.clone()))
+        .or(filters::admin_user_annotations(
+            db.clone(),
+            tokens.clone(),
+            config.max_body_size,
+        ))
+        .or(filters::admin_set_oncall_schedule(
+            db.clone(),
+            tokens.clone(),
+            config.max_body_size,
+        ))
+        .or(filters::admin_remove_oncall_schedule(
+            db.clone(),
+            tokens.clone(),
+        ))
+        .or(filters::admin_group_changes(db.clone(), tokens.clone()))
+        .or(filters::admin_approve_group_change(
+            db.clone(),
+            tokens.clone(),
+        ))
+        .or(filters::admin_reject_group_change(
+            db.clone(),
+            tokens.clone(),
+        ))
+        .or(filters::admin_create_synthetic_group(
+            db.clone(),
+            tokens.clone(),
+            config.max_body_size,
+        ))
+        .or(filters::admin_delete_synthetic_group(
+            db.clone(),
+            tokens.clone(),
+        ))
+        .or(filters::admin_synthetic_group_member(
+            db.clone(),
+            tokens.clone(),
+        ))
+        .or(filters::admin_get_group_watchers(
+            db.clone(),
+            tokens.clone(),
+        ))
+        .or(filters::admin_add_group_watcher(
+            db.clone(),
+            tokens.clone(),
+            config.max_body_size,
+        ))
+        .or(filters::admin_remove_group_watcher(
+            db.clone(),
+            tokens.clone(),
+        ))
+        .map(Reply::into_response)
+        .boxed()
+}
+
+mod filters {
+    use super::{
+        accepts_problem_json, cache_age, endpoint, handlers, is_unmodified_since,
+        is_valid_slack_signature, parse_domain, parse_fields, parse_group_name, parse_path_param,
+        request_id, trace_id, ui_asset, AllowedFields, AsOfQuery, AvatarQuery, CacheStatsQuery,
+        Client, Db, DebugInfo, ExpandQuery, FieldFilter, FieldsQuery, Forbidden, HistoryQuery,
+        InvalidParameter, InvalidSignature, Latency, Maintenance, NameForm, NameSearchQuery,
+        Oncall, OnlineNowQuery, Overloaded, PageQuery, Problem, QuotaSlot, Shadow, Tokens,
+        Unauthorized, UsersQuery, PROBLEM_JSON, SLACK_BODY_LIMIT,
+    };
+    use crate::libs::auth::{Permission, Principal};
+    use crate::libs::chaos;
+    use crate::libs::concurrency::{ConcurrencyLimits, Permits};
+    use crate::libs::email::Email;
+    use crate::libs::github;
+    use crate::libs::health::Components;
+    use crate::libs::paging::Paging;
+    use crate::libs::slack::{GroupId, UserId};
+    use crate::libs::watchers;
+    use crate::libs::{build_info, stats, AccessEntry, AccessLog, AuditEntry, AuditLog};
+    use chrono::Utc;
+    use futures::future::{self, Ready};
+    use std::convert::Infallible;
+    use std::sync::Arc;
+    use std::time::Instant;
+    use warp::http::header::{
+        HeaderMap, HeaderValue, CACHE_CONTROL, CONTENT_TYPE, IF_MODIFIED_SINCE, LAST_MODIFIED,
+        REFERER, USER_AGENT,
+    };
+    use warp::http::{Method, StatusCode};
+    use warp::hyper::body::Bytes;
+    use warp::hyper::body::HttpBody;
+    use warp::hyper::Body;
+    use warp::path::{FullPath, Peek, Tail};
+    use warp::Filter;
+
+    /// Records each request that `route` answers, along with the status it was answered with.
+    pub fn audited<F, R>(
+        tokens: Tokens,
+        audit_log: AuditLog,
+        route: F,
+    ) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone
+    where
+        F: Filter<Extract = (R,), Error = warp::Rejection> + Clone + Send + Sync + 'static,
+        R: warp::Reply,
+    {
+        warp::header::optional::<String>("authorization")
+            .and(warp::method())
+            .and(warp::path::full())
+            .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+            .and(route)
+            .map(
+                move |header: Option<String>,
+                      method: Method,
+                      path: FullPath,
+                      query: String,
+                      reply: R| {
+                    let response = reply.into_response();
+                    if audit_log.is_enabled() {
+                        let token_id = tokens
+                            .authenticate(header.as_deref())
+                            .map(|principal| principal.id)
+                            .unwrap_or_else(|| "unauthenticated".to_owned());
+                        audit_log.record(AuditEntry::new(
+                            token_id,
+                            method.as_str(),
+                            path.as_str(),
+                            &query,
+                            response.status().as_u16(),
+                        ));
+                    }
+                    response
+                },
+            )
+    }
+
+    pub fn get_all_users(
+        db: Db,
+        tokens: Tokens,
+        allowed_fields: AllowedFields,
+        max_entries: usize,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "users")
+            .and(warp::get())
+            .and(warp::query::<UsersQuery>())
+            .and(with_paging(max_entries))
+            .and(with_db(db))
+            .and(with_fields(
+                tokens,
+                &[Permission::ReadUsers],
+                allowed_fields,
+            ))
+            .and_then(handlers::get_all_users)
+    }
+
+    pub fn get_users_online_now(
+        db: Db,
+        tokens: Tokens,
+        allowed_fields: AllowedFields,
+        max_entries: usize,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "users" / "online_now")
+            .and(warp::get())
+            .and(warp::query::<OnlineNowQuery>())
+            .and(with_paging(max_entries))
+            .and(with_db(db))
+            .and(with_fields(
+                tokens,
+                &[Permission::ReadUsers],
+                allowed_fields,
+            ))
+            .and_then(handlers::get_users_online_now)
+    }
+
+    /// How many users are cached, for callers that only want the number.
+    pub fn get_user_count(
+        db: Db,
+        tokens: Tokens,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "users" / "count")
+            .and(warp::get())
+            .and(
+                with_principal(tokens, &[Permission::ReadUsers])
+                    .map(|_| ())
+                    .untuple_one(),
+            )
+            .and(with_db(db))
+            .and_then(handlers::get_user_count)
+    }
+
+    /// How many groups are cached.
+    pub fn get_user_group_count(
+        db: Db,
+        tokens: Tokens,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "user_groups" / "count")
+            .and(warp::get())
+            .and(
+                with_principal(tokens, &[Permission::ReadGroups])
+                    .map(|_| ())
+                    .untuple_one(),
+            )
+            .and(with_db(db))
+            .and_then(handlers::get_user_group_count)
+    }
+
+    /// Needs permission to read emails, as it tells which users have one in the domain.
+    pub fn get_users_by_domain(
+        db: Db,
+        tokens: Tokens,
+        allowed_fields: AllowedFields,
+        max_entries: usize,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "users" / "domain" / String)
+            .and(warp::get())
+            .and_then(path_param(parse_domain))
+            .and(with_paging(max_entries))
+            .and(with_db(db))
+            .and(with_fields(
+                tokens,
+                &[Permission::ReadUsers, Permission::ReadEmails],
+                allowed_fields,
+            ))
+            .and_then(handlers::get_users_by_domain)
+    }
+
+    pub fn get_users_typeahead(
+        db: Db,
+        tokens: Tokens,
+        allowed_fields: AllowedFields,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "users" / "typeahead")
+            .and(warp::get())
+            .and(warp::query::<NameSearchQuery>())
+            .and(with_db(db))
+            .and(with_fields(
+                tokens,
+                &[Permission::ReadUsers],
+                allowed_fields,
+            ))
+            .and_then(handlers::get_users_typeahead)
+    }
+
+    pub fn get_users_fuzzy(
+        db: Db,
+        tokens: Tokens,
+        allowed_fields: AllowedFields,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "users" / "fuzzy")
+            .and(warp::get())
+            .and(warp::query::<NameSearchQuery>())
+            .and(with_db(db))
+            .and(with_fields(
+                tokens,
+                &[Permission::ReadUsers],
+                allowed_fields,
+            ))
+            .and_then(handlers::get_users_fuzzy)
+    }
+
+    pub fn get_user_by_id(
+        db: Db,
+        tokens: Tokens,
+        allowed_fields: AllowedFields,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "user" / "id" / String)
+            .and(warp::get())
+            .and_then(path_param(str::parse::<UserId>))
+            .and(warp::query::<AsOfQuery>())
+            .and(with_db(db))
+            .and(with_fields(
+                tokens,
+                &[Permission::ReadUsers],
+                allowed_fields,
+            ))
+            .and_then(handlers::get_user_by_id)
+    }
+
+    pub fn get_user_avatar(
+        db: Db,
+        tokens: Tokens,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "user" / "id" / String / "avatar")
+            .and(warp::get())
+            .and_then(path_param(str::parse::<UserId>))
+            .and(warp::query::<AvatarQuery>())
+            .and(with_db(db))
+            .and(
+                with_principal(tokens, &[Permission::ReadUsers])
+                    .map(|_| ())
+                    .untuple_one(),
+            )
+            .and_then(handlers::get_user_avatar)
+    }
+
+    pub fn get_user_dnd(
+        db: Db,
+        tokens: Tokens,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "user" / "id" / String / "dnd")
+            .and(warp::get())
+            .and_then(path_param(str::parse::<UserId>))
+            .and(with_db(db))
+            .and(
+                with_principal(tokens, &[Permission::ReadUsers])
+                    .map(|_| ())
+                    .untuple_one(),
+            )
+            .and_then(handlers::get_user_dnd)
+    }
+
+    /// `HEAD` of a user by email, answered with only 200 or 404.
+    pub fn user_email_exists(
+        db: Db,
+        tokens: Tokens,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "user" / "email" / String)
+            .and(warp::head())
+            .and_then(path_param(str::parse::<Email>))
+            .and(
+                with_principal(tokens, &[Permission::ReadUsers, Permission::ReadEmails])
+                    .map(|_| ())
+                    .untuple_one(),
+            )
+            .and(with_db(db))
+            .and_then(handlers::user_email_exists)
+    }
+
+    pub fn get_user_by_email(
+        db: Db,
+        tokens: Tokens,
+        allowed_fields: AllowedFields,
+        shadow: Shadow,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "user" / "email" / String)
+            .and(warp::get())
+            .and_then(path_param(str::parse::<Email>))
+            .and(with_db(db))
+            .and(with_fields(
+                tokens,
+                &[Permission::ReadUsers, Permission::ReadEmails],
+                allowed_fields,
+            ))
+            .and(warp::any().map(move || shadow.clone()))
+            .and_then(handlers::get_user_by_email)
+    }
+
+    pub fn get_user_by_github(
+        db: Db,
+        tokens: Tokens,
+        allowed_fields: AllowedFields,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "user" / "github" / String)
+            .and(warp::get())
+            .and_then(path_param(github::parse_login))
+            .and(with_db(db))
+            .and(with_fields(
+                tokens,
+                &[Permission::ReadUsers],
+                allowed_fields,
+            ))
+            .and_then(handlers::get_user_by_github)
+    }
+
+    pub fn get_all_user_groups(
+        db: Db,
+        tokens: Tokens,
+        allowed_fields: AllowedFields,
+        max_entries: usize,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "user_groups")
+            .and(warp::get())
+            .and(with_paging(max_entries))
+            .and(with_db(db))
+            .and(with_fields(
+                tokens,
+                &[Permission::ReadGroups],
+                allowed_fields,
+            ))
+            .and_then(handlers::get_all_user_groups)
+    }
+
+    pub fn get_user_group_by_name(
+        db: Db,
+        tokens: Tokens,
+        allowed_fields: AllowedFields,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "user_group" / "name" / String)
+            .and(warp::get())
+            .and_then(path_param(parse_group_name))
+            .and(with_db(db))
+            .and(with_fields(
+                tokens.clone(),
+                &[Permission::ReadGroups],
+                allowed_fields,
+            ))
+            .and(with_expand_members(tokens))
+            .and_then(handlers::get_user_group_by_name)
+    }
+
+    pub fn changes_stream(
+        db: Db,
+        tokens: Tokens,
+        allowed_fields: AllowedFields,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "changes" / "stream")
+            .and(warp::get())
+            .and(warp::sse::last_event_id::<String>())
+            .and(with_db(db))
+            .and(with_fields(
+                tokens,
+                &[Permission::ReadUsers, Permission::ReadGroups],
+                allowed_fields,
+            ))
+            .and_then(handlers::changes_stream)
+    }
+
+    /// The headline numbers of the cache, as of the last sync.
+    pub fn directory_stats(
+        db: Db,
+        tokens: Tokens,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "stats")
+            .and(warp::get())
+            .and(
+                with_principal(tokens, &[Permission::ReadUsers, Permission::ReadGroups])
+                    .map(|_| ())
+                    .untuple_one(),
+            )
+            .and(with_db(db))
+            .and_then(handlers::directory_stats)
+    }
+
+    pub fn get_user_group_history(
+        db: Db,
+        tokens: Tokens,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "user_group" / "id" / String / "history")
+            .and(warp::get())
+            .and_then(path_param(str::parse::<GroupId>))
+            .and(warp::query::<HistoryQuery>())
+            .and(with_db(db))
+            .and(
+                with_principal(tokens, &[Permission::ReadGroups])
+                    .map(|_| ())
+                    .untuple_one(),
+            )
+            .and_then(handlers::get_user_group_history)
+    }
+
+    /// Who is on call for the group now, according to its registered schedule, among the
+    /// group's members.
+    pub fn get_user_group_oncall(
+        db: Db,
+        tokens: Tokens,
+        allowed_fields: AllowedFields,
+        oncall: Oncall,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("slack" / "user_group" / "id" / String / "oncall")
+            .and(warp::get())
+            .and_then(path_param(str::parse::<GroupId>))
+            .and(with_db(db))
+            .and(warp::any().map(move || oncall.clone()))
+            .and(with_fields(
+                tokens,
+                &[Permission::ReadGroups, Permission::ReadUsers],
+                allowed_fields,
+            ))
+            .and_then(handlers::get_user_group_oncall)
+    }
+
+    pub fn admin_debug(
+        db: Db,
+        tokens: Tokens,
+        debug_info: Arc<DebugInfo>,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("admin" / "debug")
+            .and(warp::get())
+            .and(
+                with_principal(tokens, &[Permission::Admin])
+                    .map(|_| ())
+                    .untuple_one(),
+            )
+            .and(with_db(db))
+            .and(warp::any().map(move || debug_info.clone()))
+            .and_then(handlers::admin_debug)
+    }
+
+    pub fn admin_cache_stats(
+        db: Db,
+        tokens: Tokens,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("admin" / "cache_stats")
+            .and(warp::get())
+            .and(
+                warp::query::<CacheStatsQuery>().and_then(|query: CacheStatsQuery| {
+                    future::ready(match query.largest {
+                        Some(largest) if largest > stats::MAX_LARGEST => {
+                            Err(warp::reject::custom(InvalidParameter {
+                                message: format!("largest must be at most {}", stats::MAX_LARGEST),
+                            }))
+                        }
+                        largest => Ok(largest.unwrap_or(stats::DEFAULT_LARGEST)),
+                    })
+                }),
+            )
+            .and(
+                with_principal(tokens, &[Permission::Admin])
+                    .map(|_| ())
+                    .untuple_one(),
+            )
+            .and(with_db(db))
+            .and_then(handlers::admin_cache_stats)
+    }
+
+    /// Registers the on-call schedule in the body, like
+    /// `{"provider": "pagerduty", "schedule-id": "P123ABC"}`, for a group.
+    pub fn admin_set_oncall_schedule(
+        db: Db,
+        tokens: Tokens,
+        max_body_size: u64,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("admin" / "user_group" / "id" / String / "oncall")
+            .and(warp::put())
+            .and_then(path_param(str::parse::<GroupId>))
+            .and(
+                with_principal(tokens, &[Permission::Admin])
+                    .map(|_| ())
+                    .untuple_one(),
+            )
+            .and(warp::body::content_length_limit(max_body_size))
+            .and(warp::body::json())
+            .and(with_db(db))
+            .and_then(handlers::admin_set_oncall_schedule)
+    }
+
+    pub fn admin_remove_oncall_schedule(
+        db: Db,
+        tokens: Tokens,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("admin" / "user_group" / "id" / String / "oncall")
+            .and(warp::delete())
+            .and_then(path_param(str::parse::<GroupId>))
+            .and(
+                with_principal(tokens, &[Permission::Admin])
+                    .map(|_| ())
+                    .untuple_one(),
+            )
+            .and(with_db(db))
+            .and_then(handlers::admin_remove_oncall_schedule)
+    }
+
+    /// Asks for a synthetic group, like `{"name": "db-admins", "users": ["U0123ABCD"]}`, to be
+    /// made. Another admin has to approve it.
+    pub fn admin_create_synthetic_group(
+        db: Db,
+        tokens: Tokens,
+        max_body_size: u64,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("admin" / "groups")
+            .and(warp::post())
+            .and(with_principal(tokens, &[Permission::Admin]))
+            .and(warp::body::content_length_limit(max_body_size))
+            .and(warp::body::json())
+            .and(with_db(db))
+            .and_then(handlers::admin_create_synthetic_group)
+    }
+
+    pub fn admin_delete_synthetic_group(
+        db: Db,
+        tokens: Tokens,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("admin" / "groups" / String)
+            .and(warp::delete())
+            .and_then(path_param(str::parse::<GroupId>))
+            .and(with_principal(tokens, &[Permission::Admin]))
+            .and(with_db(db))
+            .and_then(handlers::admin_delete_synthetic_group)
+    }
+
+    /// Adds a user to a synthetic group with `PUT`, or removes them with `DELETE`.
+    pub fn admin_synthetic_group_member(
+        db: Db,
+        tokens: Tokens,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path("admin")
+            .and(warp::path("groups"))
+            .and(warp::path::param())
+            .and(warp::path("members"))
+            .and(warp::path::param())
+            .and(warp::path::end())
+            .and(
+                warp::put()
+                    .map(|| true)
+                    .or(warp::delete().map(|| false))
+                    .unify(),
+            )
+            .and_then(|group: String, user: String, add: bool| {
+                future::ready(
+                    path_params(&group, str::parse::<GroupId>, &user, str::parse::<UserId>)
+                        .map(|(group, user)| (group, user, add)),
+                )
+            })
+            .untuple_one()
+            .and(with_principal(tokens, &[Permission::Admin]))
+            .and(with_db(db))
+            .and_then(handlers::admin_synthetic_group_member)
+    }
+
+    /// Lists the changes to synthetic groups waiting for approval.
+    pub fn admin_group_changes(
+        db: Db,
+        tokens: Tokens,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("admin" / "groups" / "changes")
+            .and(warp::get())
+            .and(
+                with_principal(tokens, &[Permission::Admin])
+                    .map(|_| ())
+                    .untuple_one(),
+            )
+            .and(with_db(db))
+            .and_then(handlers::admin_group_changes)
+    }
+
+    /// Makes a change another admin asked for.
+    pub fn admin_approve_group_change(
+        db: Db,
+        tokens: Tokens,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("admin" / "groups" / "changes" / String / "approve")
+            .and(warp::post())
+            .and(with_principal(tokens, &[Permission::Admin]))
+            .and(with_db(db))
+            .and_then(handlers::admin_approve_group_change)
+    }
+
+    /// Drops a change without making it, whoever asked for it.
+    pub fn admin_reject_group_change(
+        db: Db,
+        tokens: Tokens,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("admin" / "groups" / "changes" / String)
+            .and(warp::delete())
+            .and(
+                with_principal(tokens, &[Permission::Admin])
+                    .map(|_| ())
+                    .untuple_one(),
+            )
+            .and(with_db(db))
+            .and_then(handlers::admin_reject_group_change)
+    }
+
+    /// Lists the channels told about membership changes of a group.
+    pub fn admin_get_group_watchers(
+        db: Db,
+        tokens: Tokens,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("admin" / "user_group" / "id" / String / "watchers")
+            .and(warp::get())
+            .and_then(path_param(str::parse::<GroupId>))
+            .and(
+                with_principal(tokens, &[Permission::Admin])
+                    .map(|_| ())
+                    .untuple_one(),
+            )
+            .and(with_db(db))
+            .and_then(handlers::admin_get_group_watchers)
+    }
+
+    /// Has the channel in the body, like `{"channel": "C0123ABCD"}`, told about membership
+    /// changes of a group after each sync.
+    pub fn admin_add_group_watcher(
+        db: Db,
+        tokens: Tokens,
+        max_body_size: u64,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("admin" / "user_group" / "id" / String / "watchers")
+            .and(warp::put())
+            .and_then(path_param(str::parse::<GroupId>))
+            .and(
+                with_principal(tokens, &[Permission::Admin])
+                    .map(|_| ())
+                    .untuple_one(),
+            )
+            .and(warp::body::content_length_limit(max_body_size))
+            .and(warp::body::json())
+            .and(with_db(db))
+            .and_then(handlers::admin_add_group_watcher)
+    }
+
+    pub fn admin_remove_group_watcher(
+        db: Db,
+        tokens: Tokens,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path("admin")
+            .and(warp::path("user_group"))
+            .and(warp::path("id"))
+            .and(warp::path::param())
+            .and(warp::path("watchers"))
+            .and(warp::path::param())
+            .and(warp::path::end())
+            .and(warp::delete())
+            .and_then(|group: String, channel: String| {
+                future::ready(path_params(
+                    &group,
+                    str::parse::<GroupId>,
+                    &channel,
+                    watchers::parse_channel,
+                ))
+            })
+            .untuple_one()
+            .and(
+                with_principal(tokens, &[Permission::Admin])
+                    .map(|_| ())
+                    .untuple_one(),
+            )
+            .and(with_db(db))
+            .and_then(handlers::admin_remove_group_watcher)
+    }
+
+    /// Merges the JSON object in the body into a user's annotations. `null` removes one.
+    pub fn admin_user_annotations(
+        db: Db,
+        tokens: Tokens,
+        max_body_size: u64,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("admin" / "user" / "id" / String / "annotations")
+            .and(warp::put())
+            .and_then(path_param(str::parse::<UserId>))
+            .and(
+                with_principal(tokens, &[Permission::Admin])
+                    .map(|_| ())
+                    .untuple_one(),
+            )
+            .and(warp::body::content_length_limit(max_body_size))
+            .and(warp::body::json())
+            .and(with_db(db))
+            .and_then(handlers::admin_user_annotations)
+    }
+
+    /// Ready once a sync has completed, and while it's no older than `max_staleness` seconds.
+    pub fn ready(
+        db: Db,
+        max_staleness: Option<u64>,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("readyz")
+            .and(with_db(db))
+            .and(warp::any().map(move || max_staleness))
+            .and_then(handlers::ready)
+    }
+
+    pub fn metrics(
+        db: Db,
+        latency: Latency,
+        shadow: Shadow,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("metrics")
+            .and(warp::header::optional::<String>("accept"))
+            .and(with_db(db))
+            .and(warp::any().map(move || latency.clone()))
+            .and(warp::any().map(move || shadow.clone()))
+            .and_then(handlers::metrics)
+    }
+
+    /// Tags each response from `route` with an `X-Request-Id`, and renders its errors with that
+    /// id: as RFC 7807 problem details for clients that send `Accept: application/problem+json`,
+    /// and as the usual envelope for everyone else.
+    pub fn with_error_details<F, R>(
+        route: F,
+    ) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone
+    where
+        F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+        R: warp::Reply,
+    {
+        warp::header::headers_cloned()
+            .and(warp::path::full())
+            .and(route)
+            .map(|headers: HeaderMap, path: FullPath, reply: R| {
+                let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+                let mut response = reply.into_response();
+                // Routes that are timed have given the response its id already.
+                let request_id = match response
+                    .headers()
+                    .get("x-request-id")
+                    .and_then(|value| value.to_str().ok())
+                {
+                    Some(id) => id.to_owned(),
+                    None => request_id(header("x-request-id")),
+                };
+
+                if let Some(problem) = response.extensions_mut().remove::<Problem>() {
+                    let (body, content_type) =
+                        if header("accept").map_or(false, accepts_problem_json) {
+                            (
+                                problem.to_problem_json(path.as_str(), &request_id),
+                                PROBLEM_JSON,
+                            )
+                        } else {
+                            (problem.to_envelope(Some(&request_id)), "application/json")
+                        };
+                    *response.body_mut() = Body::from(body.to_string());
+                    response
+                        .headers_mut()
+                        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+                }
+
+                if let Ok(value) = HeaderValue::from_str(&request_id) {
+                    response.headers_mut().insert("x-request-id", value);
+                }
+                response
+            })
+    }
+
+    /// Writes each request that `route` answers to the access log, once it has been answered.
+    pub fn with_access_log<F, R>(
+        access_log: AccessLog,
+        route: F,
+    ) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone
+    where
+        F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+        R: warp::Reply,
+    {
+        warp::any()
+            .map(Instant::now)
+            .and(warp::ext::optional::<Client>())
+            .and(warp::method())
+            .and(warp::path::full())
+            .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+            .and(warp::header::headers_cloned())
+            .and(route)
+            .map(
+                move |started: Instant,
+                      client: Option<Client>,
+                      method: Method,
+                      path: FullPath,
+                      query: String,
+                      headers: HeaderMap,
+                      reply: R| {
+                    let response = reply.into_response();
+                    if access_log.is_enabled() {
+                        let header = |name| {
+                            headers
+                                .get(name)
+                                .and_then(|value: &HeaderValue| value.to_str().ok())
+                                .map(str::to_owned)
+                        };
+                        let target = if query.is_empty() {
+                            path.as_str().to_owned()
+                        } else {
+                            format!("{}?{}", path.as_str(), query)
+                        };
+                        access_log.record(AccessEntry {
+                            time: Utc::now(),
+                            remote_addr: client.map(|client| client.remote_addr),
+                            protocol: client.map_or_else(
+                                || "HTTP/1.1".to_owned(),
+                                |client| format!("{:?}", client.version),
+                            ),
+                            method: method.to_string(),
+                            target,
+                            status: response.status().as_u16(),
+                            bytes: HttpBody::size_hint(response.body()).exact(),
+                            referer: header(REFERER),
+                            user_agent: header(USER_AGENT),
+                            elapsed: started.elapsed(),
+                        });
+                    }
+                    response
+                },
+            )
+    }
+
+    /// Times how long `route` takes to answer the requests it matches, by endpoint. The time is
+    /// linked to the caller's `traceparent` trace, or else to the request id, which is why
+    /// responses get their `X-Request-Id` here.
+    pub fn timed<F, R>(
+        latency: Latency,
+        route: F,
+    ) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone
+    where
+        F: Filter<Extract = (R,), Error = warp::Rejection> + Clone + Send + Sync + 'static,
+        R: warp::Reply,
+    {
+        warp::any()
+            .map(Instant::now)
+            .and(warp::path::full())
+            .and(warp::header::headers_cloned())
+            .and(route)
+            .map(
+                move |started: Instant, path: FullPath, headers: HeaderMap, reply: R| {
+                    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+                    let request_id = request_id(header("x-request-id"));
+                    let trace_id =
+                        trace_id(header("traceparent")).unwrap_or_else(|| request_id.clone());
+
+                    let mut response = reply.into_response();
+                    latency.observe(&endpoint(path.as_str()), started.elapsed(), Some(&trace_id));
+                    if let Ok(value) = HeaderValue::from_str(&request_id) {
+                        response.headers_mut().insert("x-request-id", value);
+                    }
+                    response
+                },
+            )
+    }
+
+    /// Waits for a turn under `limits` before letting `route` answer, and rejects the request as
+    /// overloaded when too many are already waiting.
+    pub fn limited<F, R>(
+        limits: Arc<ConcurrencyLimits>,
+        route: F,
+    ) -> impl Filter<Extract = (R,), Error = warp::Rejection> + Clone
+    where
+        F: Filter<Extract = (R,), Error = warp::Rejection> + Clone + Send + Sync + 'static,
+        R: warp::Reply,
+    {
+        warp::path::full()
+            .and_then(move |path: FullPath| {
+                let limits = limits.clone();
+                async move {
+                    limits
+                        .acquire(&endpoint(path.as_str()))
+                        .await
+                        .ok_or_else(|| warp::reject::custom(Overloaded))
+                }
+            })
+            .and(route)
+            .map(|_permits: Permits, reply: R| reply)
+    }
+
+    /// Adds how much of the caller's quota is left to what `route` answers. Requests are counted
+    /// against it by `with_principal`, once a route has matched them and authenticated the
+    /// caller, so ones that match no route, or are turned away as overloaded, don't use it up.
+    pub fn with_quota<F, R>(
+        route: F,
+    ) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone
+    where
+        F: Filter<Extract = (R,), Error = warp::Rejection> + Clone + Send + Sync + 'static,
+        R: warp::Reply,
+    {
+        warp::ext::optional::<QuotaSlot>().and(route).and_then(
+            |slot: Option<QuotaSlot>, reply: R| async move {
+                let usage = match slot {
+                    Some(slot) => slot.usage().await,
+                    None => None,
+                };
+                let mut response = reply.into_response();
+                if let Some(usage) = usage {
+                    usage.add_headers(response.headers_mut());
+                }
+                Ok::<_, warp::Rejection>(response)
+            },
+        )
+    }
+
+    /// Turns requests under `/slack` away while `message` is set, with a 503 carrying it.
+    pub fn in_maintenance(
+        message: Option<Arc<String>>,
+    ) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+        warp::path::peek()
+            .and_then(move |peek: Peek| {
+                let result = match &message {
+                    Some(message) if peek.segments().next() == Some("slack") => {
+                        Err(warp::reject::custom(Maintenance {
+                            message: message.to_string(),
+                        }))
+                    }
+                    _ => Ok(()),
+                };
+                future::ready(result)
+            })
+            .untuple_one()
+    }
+
+    /// Holds requests up before `route` sees them, when `--chaos` injects slow responses.
+    pub fn with_chaos_delay<F, R>(
+        route: F,
+    ) -> impl Filter<Extract = (R,), Error = warp::Rejection> + Clone
+    where
+        F: Filter<Extract = (R,), Error = warp::Rejection> + Clone + Send + Sync + 'static,
+        R: warp::Reply,
+    {
+        warp::any()
+            .and_then(|| async {
+                if let Some(delay) = chaos::slow_response() {
+                    tokio::time::sleep(delay).await;
+                }
+                Ok::<_, warp::Rejection>(())
+            })
+            .untuple_one()
+            .and(route)
+    }
+
+    /// Adds `X-Cache-Age` to the responses of `route` when `enabled`.
+    pub fn with_cache_age<F, R>(
+        db: Db,
+        enabled: bool,
+        route: F,
+    ) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone
+    where
+        F: Filter<Extract = (R,), Error = warp::Rejection> + Clone + Send + Sync + 'static,
+        R: warp::Reply + Send,
+    {
+        route
+            .and(with_db(db))
+            .and_then(move |reply: R, db: Db| async move {
+                let mut response = reply.into_response();
+                if enabled {
+                    if let Ok(Some(last_sync)) = db.get_last_sync().await {
+                        response
+                            .headers_mut()
+                            .insert("x-cache-age", HeaderValue::from(cache_age(last_sync)));
+                    }
+                }
+                Ok::<_, warp::Rejection>(response)
+            })
+    }
+
+    /// Adds `cache_control` to the responses of `route` that carry `Last-Modified`, and turns
+    /// them into `304 Not Modified` when the record is unchanged since `If-Modified-Since`.
+    pub fn with_conditional_get<F, R>(
+        cache_control: HeaderValue,
+        route: F,
+    ) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone
+    where
+        F: Filter<Extract = (R,), Error = warp::Rejection> + Clone + Send + Sync + 'static,
+        R: warp::Reply,
+    {
+        warp::header::headers_cloned()
+            .and(route)
+            .map(move |headers: HeaderMap, reply: R| {
+                let mut response = reply.into_response();
+                let last_modified = match response
+                    .headers()
+                    .get(LAST_MODIFIED)
+                    .and_then(|value| value.to_str().ok())
+                {
+                    Some(last_modified) => last_modified.to_owned(),
+                    None => return response,
+                };
+                response
+                    .headers_mut()
+                    .insert(CACHE_CONTROL, cache_control.clone());
+
+                let unmodified = headers
+                    .get(IF_MODIFIED_SINCE)
+                    .and_then(|value| value.to_str().ok())
+                    .map_or(false, |since| is_unmodified_since(&last_modified, since));
+                if response.status() == StatusCode::OK && unmodified {
+                    *response.status_mut() = StatusCode::NOT_MODIFIED;
+                    *response.body_mut() = Body::empty();
+                    response.headers_mut().remove(CONTENT_TYPE);
+                }
+                response
+            })
+    }
+
+    pub fn version() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("version").and(warp::get()).map(|| {
+            super::Response::Result {
+                result: build_info::to_json(),
+            }
+            .into_response()
+        })
+    }
+
+    /// Serves the browse UI's files under `/ui` when `enabled`. They're static, so unlike the
+    /// JSON routes the page calls they don't need a token.
+    pub fn ui(
+        enabled: bool,
+    ) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone {
+        warp::path("ui")
+            .and(warp::get())
+            .and(warp::path::tail())
+            .and_then(move |tail: Tail| {
+                let asset = if enabled {
+                    ui_asset(tail.as_str())
+                } else {
+                    None
+                };
+                future::ready(match asset {
+                    Some((contents, content_type)) => {
+                        let mut response = warp::reply::Response::new(Body::from(contents));
+                        if let Ok(content_type) = HeaderValue::from_str(&content_type) {
+                            response.headers_mut().insert(CONTENT_TYPE, content_type);
+                        }
+                        Ok(response)
+                    }
+                    None => Err(warp::reject::not_found()),
+                })
+            })
+    }
+
+    pub fn status() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("healthz").map(|| {
+            super::Response::Result {
+                result: "OK".to_owned(),
+            }
+            .into_response()
+        })
+    }
+
+    /// Reports one component of the process, `/healthz/web` or, under `all-in-one`,
+    /// `/healthz/updater`, as unavailable while it's unhealthy.
+    pub fn component_health(
+        components: Components,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("healthz" / String).map(move |name: String| match components.get(&name) {
+            Some(status) if status.healthy => {
+                super::Response::Result { result: status }.into_response()
+            }
+            Some(status) => super::Response::<()>::Unavailable {
+                message: status.message,
+            }
+            .into_response(),
+            None => super::Response::<()>::NotFound.into_response(),
+        })
+    }
+
+    /// Answers `/whois`. Only routed when a signing secret is configured.
+    pub fn slack_command(
+        db: Db,
+        signing_secret: Option<Arc<String>>,
+        allowed_fields: AllowedFields,
+        audit_log: AuditLog,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        let enabled = signing_secret.is_some();
+        warp::path!("slack" / "command")
+            .and(warp::post())
+            .and(
+                warp::any()
+                    .and_then(move || async move {
+                        if enabled {
+                            Ok(())
+                        } else {
+                            Err(warp::reject::not_found())
+                        }
+                    })
+                    .untuple_one(),
+            )
+            .and(with_slack_signature(signing_secret.unwrap_or_default()))
+            .and(with_db(db))
+            .and(warp::any().map(move || allowed_fields.clone()))
+            .and(warp::any().map(move || audit_log.clone()))
+            .and_then(handlers::slack_command)
+    }
+
+    /// Passes on the raw body of requests signed with the Slack app's signing secret and rejects
+    /// everything else, including requests signed too long ago.
+    pub fn with_slack_signature(
+        signing_secret: Arc<String>,
+    ) -> impl Filter<Extract = (Bytes,), Error = warp::Rejection> + Clone {
+        warp::header::optional::<String>("x-slack-request-timestamp")
+            .and(warp::header::optional::<String>("x-slack-signature"))
+            .and(warp::body::content_length_limit(SLACK_BODY_LIMIT))
+            .and(warp::body::bytes())
+            .and_then(
+                move |timestamp: Option<String>, signature: Option<String>, body: Bytes| {
+                    let signing_secret = signing_secret.clone();
+                    async move {
+                        match (timestamp, signature) {
+                            (Some(timestamp), Some(signature))
+                                if is_valid_slack_signature(
+                                    &signing_secret,
+                                    &timestamp,
+                                    &signature,
+                                    &body,
+                                ) =>
+                            {
+                                Ok(body)
+                            }
+                            _ => Err(warp::reject::custom(InvalidSignature)),
+                        }
+                    }
+                },
+            )
+    }
+
+    /// Reads the path parameter a route extracted with `parse`, so handlers are handed one that
+    /// was checked. Parameters that don't parse are answered with a 400.
+    fn path_param<T>(
+        parse: fn(&str) -> Result<T, String>,
+    ) -> impl Fn(String) -> Ready<Result<T, warp::Rejection>> + Clone {
+        move |segment: String| {
+            future::ready(
+                parse_path_param(&segment, parse)
+                    .map_err(|message| warp::reject::custom(InvalidParameter { message })),
+            )
+        }
+    }
+
+    /// Reads both path parameters of a route, the first that doesn't parse answering with a 400.
+    fn path_params<A, B>(
+        first: &str,
+        parse_first: fn(&str) -> Result<A, String>,
+        second: &str,
+        parse_second: fn(&str) -> Result<B, String>,
+    ) -> Result<(A, B), warp::Rejection> {
+        let first = parse_path_param(first, parse_first);
+        let second = parse_path_param(second, parse_second);
+        first
+            .and_then(|first| second.map(|second| (first, second)))
+            .map_err(|message| warp::reject::custom(InvalidParameter { message }))
+    }
+
+    fn with_db(db: Db) -> impl Filter<Extract = (Db,), Error = Infallible> + Clone {
+        warp::any().map(move || db.clone())
+    }
+
+    /// Rejects requests without a valid token (401) or without every `required` permission (403).
+    fn with_principal(
+        tokens: Tokens,
+        required: &'static [Permission],
+    ) -> impl Filter<Extract = (Principal,), Error = warp::Rejection> + Clone {
+        warp::header::optional::<String>("authorization")
+            .and(warp::any().map(move || tokens.clone()))
+            .and(warp::ext::optional::<QuotaSlot>())
+            .and_then(
+                move |header: Option<String>, tokens: Tokens, slot: Option<QuotaSlot>| async move {
+                    let principal = tokens
+                        .authenticate(header.as_deref())
+                        .ok_or_else(|| warp::reject::custom(Unauthorized))?;
+                    if let Some(slot) = slot {
+                        slot.count(&principal).await?;
+                    }
+
+                    if required.iter().all(|permission| principal.has(*permission)) {
+                        Ok(principal)
+                    } else if !tokens.is_enabled() {
+                        // No token could give the caller more, such as admin access.
+                        Err(warp::reject::custom(Unauthorized))
+                    } else {
+                        Err(warp::reject::custom(Forbidden))
+                    }
+                },
+            )
+    }
+
+    /// Reads `?expand=members`, which needs permission to read users as well.
+    fn with_expand_members(
+        tokens: Tokens,
+    ) -> impl Filter<Extract = (bool,), Error = warp::Rejection> + Clone {
+        warp::query::<ExpandQuery>()
+            .and(with_principal(tokens, &[]))
+            .and_then(|query: ExpandQuery, principal: Principal| {
+                future::ready(match query.expand.as_deref() {
+                    None => Ok(false),
+                    Some("members") if principal.has(Permission::ReadUsers) => Ok(true),
+                    Some("members") => Err(warp::reject::custom(Forbidden)),
+                    Some(other) => Err(warp::reject::custom(InvalidParameter {
+                        message: format!("unknown expand {}, expected members", other),
+                    })),
+                })
+            })
+    }
+
+    /// Reads `limit` and `cursor`, rejecting ones that can't be used.
+    fn with_paging(
+        max_entries: usize,
+    ) -> impl Filter<Extract = (Paging,), Error = warp::Rejection> + Clone {
+        warp::query::<PageQuery>().and_then(move |query: PageQuery| {
+            future::ready(
+                Paging::new(query.limit, query.cursor, max_entries)
+                    .map_err(|message| warp::reject::custom(InvalidParameter { message })),
+            )
+        })
+    }
+
+    fn with_fields(
+        tokens: Tokens,
+        required: &'static [Permission],
+        allowed_fields: AllowedFields,
+    ) -> impl Filter<Extract = (FieldFilter,), Error = warp::Rejection> + Clone {
+        with_principal(tokens, required)
+            .and(warp::query::<FieldsQuery>())
+            .and_then(move |principal: Principal, query: FieldsQuery| {
+                let name_form = match query.name_form.as_deref().map(str::parse) {
+                    None => Ok(NameForm::Real),
+                    Some(parsed) => {
+                        parsed.map_err(|message| warp::reject::custom(InvalidParameter { message }))
+                    }
+                };
+                future::ready(name_form.map(|name_form| FieldFilter {
+                    requested: query.fields.as_deref().map(parse_fields),
+                    allowed: allowed_fields.clone(),
+                    read_emails: principal.has(Permission::ReadEmails),
+                    name_form,
+                }))
+            })
+    }
+}
+
+mod handlers {
+    use super::{
+        accepts_open_metrics, cache_age, http_date, parse_stream_id, parse_whois, AllowedFields,
+        AsOfQuery, AvatarQuery, Db, DebugInfo, FieldFilter, HistoryQuery, Latency, NameForm,
+        NameSearchQuery, Oncall, OnlineNowQuery, Response, Shadow, SlashCommand, UsersQuery,
+        WhoisQuery, CHANGES_BATCH_SIZE, CHANGES_POLL_INTERVAL, DEFAULT_AVATAR_SIZE,
+        MAX_ANNOTATION_NAME_LENGTH, OPEN_METRICS_CONTENT_TYPE,
+    };
+    use crate::error::{RedisErrors, SyntheticGroupErrors};
+    use crate::libs::auth::{Permission, Principal};
+    use crate::libs::changes::{ChangeRecord, CHANGES_STREAM_KEY};
+    use crate::libs::email::Email;
+    use crate::libs::local_time::HourWindow;
+    use crate::libs::oncall::OncallSchedule;
+    use crate::libs::paging::{Page, Paging};
+    use crate::libs::slack::blocks;
+    use crate::libs::slack::{GroupId, UserId};
+    use crate::libs::synthetic::{self, CreateGroupRequest, GroupAction, GroupChange};
+    use crate::libs::watchers::{self, WatcherRequest};
+    use crate::libs::{
+        build_info, history, stats, AuditEntry, AuditLog, RedisResponse, SlackUser, SlackUserGroup,
+    };
+    use chrono::Utc;
+    use chrono_tz::Tz;
+    use futures::stream::{self, StreamExt};
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+    use std::convert::Infallible;
+    use std::sync::Arc;
+    use tracing::warn;
+    use warp::http::header::{HeaderValue, LAST_MODIFIED, RETRY_AFTER};
+    use warp::http::StatusCode;
+    use warp::hyper::body::Bytes;
+    use warp::sse::Event;
+    use warp::Reply;
+
+    pub async fn admin_debug(
+        redis_server: Db,
+        debug_info: Arc<DebugInfo>,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let pools: Vec<Value> = redis_server
+            .pool_state()
+            .await
+            .into_iter()
+            .map(|(address, pool)| {
+                json!({
+                    "address": address,
+                    "max-open": pool.max_open,
+                    "connections": pool.connections,
+                    "in-use": pool.in_use,
+                    "idle": pool.idle,
+                    "wait-count": pool.wait_count,
+                    "wait-duration-ms": pool.wait_duration.as_millis() as u64,
+                    "max-idle-closed": pool.max_idle_closed,
+                    "max-lifetime-closed": pool.max_lifetime_closed,
+                })
+            })
+            .collect();
+        let result = json!({
+            "build": build_info::to_json(),
+            "uptime-seconds": debug_info.started_at.elapsed().as_secs(),
+            "redis-pools": pools,
+            "read-replicas": redis_server
+                .read_replica_health()
+                .into_iter()
+                .map(|(address, healthy)| json!({ "address": address, "healthy": healthy }))
+                .collect::<Vec<_>>(),
+            "last-sync-api-usage": redis_server.get_last_sync_api_usage().await.ok().flatten(),
+            "last-sync-failure": redis_server.get_sync_failure().await.ok().flatten(),
+            "config": debug_info.config,
+        });
+
+        Ok(Response::Result { result }.into_response())
+    }
+
+    /// Scans the cache for its size, at most once per `stats::SCAN_INTERVAL` across replicas.
+    pub async fn admin_cache_stats(
+        largest: usize,
+        redis_server: Db,
+    ) -> Result<impl warp::Reply, Infallible> {
+        match redis_server.claim_stats_scan(stats::SCAN_INTERVAL).await {
+            Ok(true) => {}
+            Ok(false) => {
+                let mut response = Response::<()>::TooManyRequests {
+                    message: format!(
+                        "the cache is scanned at most every {}s, try again later",
+                        stats::SCAN_INTERVAL.as_secs()
+                    ),
+                }
+                .into_response();
+                response.headers_mut().insert(
+                    RETRY_AFTER,
+                    HeaderValue::from(stats::SCAN_INTERVAL.as_secs()),
+                );
+                return Ok(response);
+            }
+            Err(e) => {
+                let message = format!("{}", e);
+                return Ok(Response::<()>::Error { message }.into_response());
+            }
+        }
+
+        let result = match redis_server.cache_stats(largest).await {
+            Ok(stats) => Response::Result { result: stats },
+            Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+        };
+
+        Ok(result.into_response())
+    }
+
+    pub async fn admin_user_annotations(
+        id: UserId,
+        changes: BTreeMap<String, Option<String>>,
+        redis_server: Db,
+    ) -> Result<impl warp::Reply, Infallible> {
+        if let Some(name) = changes
+            .keys()
+            .find(|name| name.is_empty() || name.len() > MAX_ANNOTATION_NAME_LENGTH)
+        {
+            let message = format!(
+                "annotation names must be 1 to {} characters, got `{}`",
+                MAX_ANNOTATION_NAME_LENGTH, name
+            );
+            return Ok(Response::<()>::BadRequest { message }.into_response());
+        }
+
+        match redis_server.get_user_by_id(&id).await {
+            RedisResponse::Ok(_) => {}
+            RedisResponse::Missing => return Ok(Response::<()>::NotFound.into_response()),
+            RedisResponse::Err(e) => {
+                let message = format!("{}", e);
+                return Ok(Response::<()>::Error { message }.into_response());
+            }
+        }
+
+        let result = match redis_server.update_annotations(&id, &changes).await {
+            Ok(annotations) => Response::Result {
+                result: annotations,
+            },
+            Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+        };
+
+        Ok(result.into_response())
+    }
+
+    pub async fn admin_set_oncall_schedule(
+        id: GroupId,
+        schedule: OncallSchedule,
+        redis_server: Db,
+    ) -> Result<impl warp::Reply, Infallible> {
+        if let Err(message) = schedule.validate() {
+            return Ok(Response::<()>::BadRequest { message }.into_response());
+        }
+
+        let result = match redis_server.set_oncall_schedule(&id, &schedule).await {
+            Ok(()) => Response::Result { result: schedule },
+            Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+        };
+
+        Ok(result.into_response())
+    }
+
+    pub async fn admin_remove_oncall_schedule(
+        id: GroupId,
+        redis_server: Db,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let result = match redis_server.remove_oncall_schedule(&id).await {
+            Ok(true) => Response::Result {
+                result: "OK".to_owned(),
+            },
+            Ok(false) => Response::NotFound,
+            Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+        };
+
+        Ok(result.into_response())
+    }
+
+    pub async fn admin_create_synthetic_group(
+        principal: Principal,
+        request: CreateGroupRequest,
+        redis_server: Db,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let name = match synthetic::parse_name(&request.name) {
+            Ok(name) => name,
+            Err(message) => return Ok(Response::<()>::BadRequest { message }.into_response()),
+        };
+        match synthetic::name_taken(&redis_server, &name).await {
+            Ok(None) => {}
+            Ok(Some(taken)) => {
+                let message = format!("a group is already named {}", taken);
+                return Ok(Response::<()>::BadRequest { message }.into_response());
+            }
+            Err(e) => return Ok(synthetic_group_error(e)),
+        }
+        for user in &request.users {
+            if let Some(response) = unknown_user(&redis_server, user).await {
+                return Ok(response);
+            }
+        }
+
+        let action = GroupAction::Create {
+            group: synthetic::new_group_id(),
+            name,
+            users: request.users,
+        };
+        Ok(propose(&principal, action, &redis_server).await)
+    }
+
+    pub async fn admin_delete_synthetic_group(
+        id: GroupId,
+        principal: Principal,
+        redis_server: Db,
+    ) -> Result<impl warp::Reply, Infallible> {
+        if let Some(response) = unknown_synthetic_group(&redis_server, &id).await {
+            return Ok(response);
+        }
+
+        let action = GroupAction::Delete { group: id };
+        Ok(propose(&principal, action, &redis_server).await)
+    }
+
+    pub async fn admin_synthetic_group_member(
+        id: GroupId,
+        user: UserId,
+        add: bool,
+        principal: Principal,
+        redis_server: Db,
+    ) -> Result<impl warp::Reply, Infallible> {
+        if let Some(response) = unknown_synthetic_group(&redis_server, &id).await {
+            return Ok(response);
+        }
+
+        let action = if add {
+            if let Some(response) = unknown_user(&redis_server, &user).await {
+                return Ok(response);
+            }
+            GroupAction::AddMember { group: id, user }
+        } else {
+            GroupAction::RemoveMember { group: id, user }
+        };
+        Ok(propose(&principal, action, &redis_server).await)
+    }
+
+    pub async fn admin_group_changes(redis_server: Db) -> Result<impl warp::Reply, Infallible> {
+        let result = match redis_server.get_group_changes().await {
+            Ok(changes) => Response::Result { result: changes },
+            Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+        };
+
+        Ok(result.into_response())
+    }
+
+    pub async fn admin_approve_group_change(
+        id: String,
+        principal: Principal,
+        redis_server: Db,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let change = match redis_server.get_group_change(&id).await {
+            Ok(Some(change)) => change,
+            Ok(None) => return Ok(Response::<()>::NotFound.into_response()),
+            Err(e) => {
+                let message = format!("{}", e);
+                return Ok(Response::<()>::Error { message }.into_response());
+            }
+        };
+        if change.requested_by == principal.id {
+            let message = "changes have to be approved by another admin".to_owned();
+            return Ok(Response::<()>::BadRequest { message }.into_response());
+        }
+
+        match redis_server.remove_group_change(&id).await {
+            Ok(true) => {}
+            Ok(false) => return Ok(Response::<()>::NotFound.into_response()),
+            Err(e) => {
+                let message = format!("{}", e);
+                return Ok(Response::<()>::Error { message }.into_response());
+            }
+        }
+        Ok(apply(&change.action, &redis_server).await)
+    }
+
+    pub async fn admin_reject_group_change(
+        id: String,
+        redis_server: Db,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let result = match redis_server.remove_group_change(&id).await {
+            Ok(true) => Response::Result {
+                result: "OK".to_owned(),
+            },
+            Ok(false) => Response::NotFound,
+            Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+        };
+
+        Ok(result.into_response())
+    }
+
+    /// Saves `action` for another admin to approve.
+    async fn propose(
+        principal: &Principal,
+        action: GroupAction,
+        redis_server: &Db,
+    ) -> warp::reply::Response {
+        let change = GroupChange::new(&principal.id, Utc::now().timestamp() as u64, action);
+        let result = match redis_server.add_group_change(&change).await {
+            Ok(()) => Response::Result {
+                result: json!({ "status": "pending", "change": change }),
+            },
+            Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+        };
+
+        result.into_response()
+    }
+
+    async fn apply(action: &GroupAction, redis_server: &Db) -> warp::reply::Response {
+        match synthetic::apply(redis_server, action).await {
+            Ok(group) => Response::Result {
+                result: json!({ "status": "applied", "group": group }),
+            }
+            .into_response(),
+            Err(e) => synthetic_group_error(e),
+        }
+    }
+
+    fn synthetic_group_error(error: SyntheticGroupErrors) -> warp::reply::Response {
+        match error {
+            SyntheticGroupErrors::UnknownGroup { .. } => Response::<()>::NotFound,
+            SyntheticGroupErrors::NameTaken { .. } => Response::BadRequest {
+                message: format!("{}", error),
+            },
+            SyntheticGroupErrors::Redis(e) => Response::Error {
+                message: format!("{}", e),
+            },
+        }
+        .into_response()
+    }
+
+    /// The response to send when `id` isn't a synthetic group.
+    async fn unknown_synthetic_group(
+        redis_server: &Db,
+        id: &GroupId,
+    ) -> Option<warp::reply::Response> {
+        match redis_server.get_synthetic_group(id).await {
+            Ok(Some(_)) => None,
+            Ok(None) => Some(Response::<()>::NotFound.into_response()),
+            Err(e) => Some(
+                Response::<()>::Error {
+                    message: format!("{}", e),
+                }
+                .into_response(),
+            ),
+        }
+    }
+
+    /// The response to send when `id` isn't a cached user.
+    async fn unknown_user(redis_server: &Db, id: &UserId) -> Option<warp::reply::Response> {
+        match redis_server.get_user_by_id(id).await {
+            RedisResponse::Ok(_) => None,
+            RedisResponse::Missing => Some(
+                Response::<()>::BadRequest {
+                    message: format!("no user has id {}", id),
+                }
+                .into_response(),
+            ),
+            RedisResponse::Err(e) => Some(
+                Response::<()>::Error {
+                    message: format!("{}", e),
+                }
+                .into_response(),
+            ),
+        }
+    }
+
+    pub async fn admin_get_group_watchers(
+        id: GroupId,
+        redis_server: Db,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let result = match redis_server.get_all_group_watchers().await {
+            Ok(mut watchers) => Response::Result {
+                result: watchers.remove(&id).unwrap_or_default(),
+            },
+            Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+        };
+
+        Ok(result.into_response())
+    }
+
+    pub async fn admin_add_group_watcher(
+        id: GroupId,
+        request: WatcherRequest,
+        redis_server: Db,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let channel = match watchers::parse_channel(&request.channel) {
+            Ok(channel) => channel,
+            Err(message) => return Ok(Response::<()>::BadRequest { message }.into_response()),
+        };
+
+        let result = match redis_server.add_group_watcher(&id, &channel).await {
+            Ok(channels) => Response::Result { result: channels },
+            Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+        };
+
+        Ok(result.into_response())
+    }
+
+    pub async fn admin_remove_group_watcher(
+        id: GroupId,
+        channel: String,
+        redis_server: Db,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let result = match redis_server.remove_group_watcher(&id, &channel).await {
+            Ok(true) => Response::Result {
+                result: "OK".to_owned(),
+            },
+            Ok(false) => Response::NotFound,
+            Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+        };
+
+        Ok(result.into_response())
+    }
+
+    /// The page of the users `keep` keeps, cut before they're annotated so only the users
+    /// returned are.
+    fn paged<F>(
+        response: RedisResponse<Vec<SlackUser>, RedisErrors>,
+        paging: &Paging,
+        keep: F,
+    ) -> RedisResponse<Page<SlackUser>, RedisErrors>
+    where
+        F: Fn(&SlackUser) -> bool,
+    {
+        match response {
+            RedisResponse::Ok(mut users) => {
+                users.retain(|user| keep(user));
+                RedisResponse::Ok(paging.page(users, |user| user.id.as_str()))
+            }
+            RedisResponse::Err(e) => RedisResponse::Err(e),
+            RedisResponse::Missing => RedisResponse::Missing,
+        }
+    }
+
+    /// Adds the annotations set through the admin API to users that were looked up.
+    async fn annotated<T>(
+        redis_server: &Db,
+        response: RedisResponse<T, RedisErrors>,
+        users: impl Fn(&mut T) -> &mut [SlackUser],
+    ) -> RedisResponse<T, RedisErrors> {
+        match response {
+            RedisResponse::Ok(mut found) => match redis_server.annotate(users(&mut found)).await {
+                Ok(()) => RedisResponse::Ok(found),
+                Err(e) => RedisResponse::Err(e),
+            },
+            other => other,
+        }
+    }
+
+    pub async fn ready(
+        redis_server: Db,
+        max_staleness: Option<u64>,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let result = match redis_server.get_last_sync().await {
+            Ok(Some(last_sync)) => {
+                let age = cache_age(last_sync);
+                match max_staleness {
+                    Some(max_staleness) if age > max_staleness => Response::Unavailable {
+                        message: format!("last sync finished {}s ago", age),
+                    },
+                    _ => Response::Result {
+                        result: json!({ "cache-age-seconds": age }),
+                    },
+                }
+            }
+            Ok(None) => Response::Unavailable {
+                message: "no sync has finished yet".to_owned(),
+            },
+            Err(e) => Response::Unavailable {
+                message: format!("{}", e),
+            },
+        };
+
+        Ok(result.into_response())
+    }
+
+    /// Prometheus text exposition of the cache's gauges and latencies, or OpenMetrics with
+    /// exemplars linking latencies to traces for scrapers that ask for it.
+    pub async fn metrics(
+        accept: Option<String>,
+        redis_server: Db,
+        latency: Latency,
+        shadow: Shadow,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let open_metrics = accept.map_or(false, |accept| accepts_open_metrics(&accept));
+        // OpenMetrics names counter families without the `_total` their samples end in.
+        let counter = |body: &mut String, name: &str, help: &str, value: u64| {
+            let family = if open_metrics {
+                name.trim_end_matches("_total")
+            } else {
+                name
+            };
+            body.push_str(&format!("# HELP {} {}\n", family, help));
+            body.push_str(&format!("# TYPE {} counter\n", family));
+            body.push_str(&format!("{} {}\n", name, value));
+        };
+
+        let mut body = String::new();
+        match redis_server.get_last_sync().await {
+            Ok(Some(last_sync)) => {
+                body.push_str(
+                    "# HELP cache_age_seconds Seconds since the last successful sync finished\n",
+                );
+                body.push_str("# TYPE cache_age_seconds gauge\n");
+                body.push_str(&format!("cache_age_seconds {}\n", cache_age(last_sync)));
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Unable to read last sync time. Error: {}", e),
+        }
+        match redis_server.get_last_sync_api_usage().await {
+            Ok(Some(usage)) => {
+                body.push_str("# HELP last_sync_slack_api_calls Slack API calls the last successful sync made, by method\n");
+                body.push_str("# TYPE last_sync_slack_api_calls gauge\n");
+                for (method, calls) in &usage.methods {
+                    body.push_str(&format!(
+                        "last_sync_slack_api_calls{{method=\"{}\"}} {}\n",
+                        method, calls
+                    ));
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Unable to read last sync's Slack API usage. Error: {}", e),
+        }
+
+        let decompressed = redis_server.decompressed_totals();
+        counter(
+            &mut body,
+            "cache_decompressed_values_total",
+            "Compressed values read from Redis",
+            decompressed.values,
+        );
+        counter(
+            &mut body,
+            "cache_decompressed_bytes_total",
+            "Size of the compressed values read, once decompressed",
+            decompressed.original_bytes,
+        );
+        counter(
+            &mut body,
+            "cache_compressed_bytes_total",
+            "Size of the compressed values read, as stored",
+            decompressed.compressed_bytes,
+        );
+        if let Some(ratio) = decompressed.ratio() {
+            body.push_str(
+                "# HELP cache_compression_ratio Decompressed over stored size of the compressed values read\n",
+            );
+            body.push_str("# TYPE cache_compression_ratio gauge\n");
+            body.push_str(&format!("cache_compression_ratio {}\n", ratio));
+        }
+
+        if let Some(shadow) = &shadow {
+            let family = if open_metrics {
+                "shadow_lookups"
+            } else {
+                "shadow_lookups_total"
+            };
+            body.push_str(&format!(
+                "# HELP {} Email lookups compared with Slack's users.lookupByEmail, by outcome\n",
+                family
+            ));
+            body.push_str(&format!("# TYPE {} counter\n", family));
+            for (outcome, count) in shadow.outcomes() {
+                body.push_str(&format!(
+                    "shadow_lookups_total{{outcome=\"{}\"}} {}\n",
+                    outcome.label(),
+                    count
+                ));
+            }
+        }
+
+        latency.render(&mut body, open_metrics);
+        redis_server.latency().render(&mut body, open_metrics);
+
+        let content_type = if open_metrics {
+            body.push_str("# EOF\n");
+            OPEN_METRICS_CONTENT_TYPE
+        } else {
+            "text/plain; version=0.0.4"
+        };
+        Ok(warp::reply::with_header(body, "content-type", content_type))
+    }
+
+    const WHOIS_USAGE: &str = "Usage: `/whois <email | @user | user id | group name>`";
+
+    /// Answers `/whois` with what the caller could see through the web API with a token listed
+    /// without permissions, with fields filtered by `--allowed-fields`. Emails are neither shown
+    /// nor looked up by unless they may be seen.
+    pub async fn slack_command(
+        body: Bytes,
+        redis_server: Db,
+        allowed_fields: AllowedFields,
+        audit_log: AuditLog,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let command: SlashCommand = match serde_urlencoded::from_bytes(&body) {
+            Ok(command) => command,
+            Err(e) => {
+                warn!("Unable to parse slash command. Error: {}", e);
+                return Ok(warp::reply::json(&blocks::ephemeral_message(
+                    WHOIS_USAGE,
+                    vec![blocks::section(WHOIS_USAGE)],
+                )));
+            }
+        };
+
+        let principal = Principal::slack_user(&command.user_id);
+        let fields = FieldFilter {
+            requested: None,
+            allowed: allowed_fields,
+            read_emails: principal.has(Permission::ReadEmails),
+            name_form: NameForm::Real,
+        };
+        let user_card = |user: &SlackUser| blocks::user_card(&user.id, &fields.apply(user));
+
+        let text = command.text.trim();
+        let message = match parse_whois(text) {
+            None => blocks::ephemeral_message(WHOIS_USAGE, vec![blocks::section(WHOIS_USAGE)]),
+            Some(WhoisQuery::Email(_)) if !fields.shows_emails() => {
+                let text = "Looking users up by email isn't allowed";
+                blocks::ephemeral_message(text, vec![blocks::section(text)])
+            }
+            Some(WhoisQuery::Email(email)) => whois_reply(
+                text,
+                redis_server.get_user_by_email(&email).await,
+                user_card,
+            ),
+            Some(WhoisQuery::UserId(id)) => {
+                whois_reply(text, redis_server.get_user_by_id(&id).await, user_card)
+            }
+            Some(WhoisQuery::GroupId(id)) => whois_reply(
+                text,
+                redis_server.get_user_group_by_id(&id).await,
+                blocks::group_card,
+            ),
+            Some(WhoisQuery::GroupName(name)) => whois_reply(
+                text,
+                redis_server.get_user_group_by_name(name).await,
+                blocks::group_card,
+            ),
+        };
+
+        audit_log.record(AuditEntry::new(
+            principal.id,
+            "POST",
+            "/slack/command",
+            text,
+            StatusCode::OK.as_u16(),
+        ));
+        Ok(warp::reply::json(&message))
+    }
+
+    fn whois_reply<T>(
+        query: &str,
+        response: RedisResponse<T, RedisErrors>,
+        card: impl Fn(&T) -> Vec<Value>,
+    ) -> Value {
+        match response {
+            RedisResponse::Ok(found) => {
+                blocks::ephemeral_message(&format!("Found {}", query), card(&found))
+            }
+            RedisResponse::Missing => {
+                let text = format!("Nobody in the cache matches {}", query);
+                blocks::ephemeral_message(&text, vec![blocks::section(&text)])
+            }
+            RedisResponse::Err(e) => {
+                warn!("Unable to answer /whois. Error: {}", e);
+                let text = "Unable to look that up right now, try again later";
+                blocks::ephemeral_message(text, vec![blocks::section(text)])
+            }
+        }
+    }
+
+    pub async fn get_all_user_groups(
+        paging: Paging,
+        redis_server: Db,
+        fields: FieldFilter,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let result = match redis_server.get_all_user_groups().await {
+            RedisResponse::Ok(results) => {
+                let page = paging.page(results, |group| group.id.as_str());
+                Response::Page {
+                    result: fields.apply(&page.items),
+                    next_cursor: page.next_cursor,
+                    truncated: page.truncated,
+                }
+            }
+            RedisResponse::Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+            RedisResponse::Missing => Response::NotFound,
+        };
+
+        Ok(result.into_response())
+    }
+
+    /// Read from the counter kept as users are added and removed, rather than counting them.
+    pub async fn get_user_count(redis_server: Db) -> Result<impl warp::Reply, Infallible> {
+        Ok(count_response(redis_server.get_user_count().await).into_response())
+    }
+
+    pub async fn get_user_group_count(redis_server: Db) -> Result<impl warp::Reply, Infallible> {
+        Ok(count_response(redis_server.get_user_group_count().await).into_response())
+    }
+
+    /// Not found until the updater has counted anything.
+    fn count_response(count: Result<Option<u64>, RedisErrors>) -> Response<Value> {
+        match count {
+            Ok(Some(count)) => Response::Result {
+                result: json!({ "count": count }),
+            },
+            Ok(None) => Response::NotFound,
+            Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+        }
+    }
+
+    /// Counts the updater worked out at the end of the last sync. Until one has finished there's
+    /// nothing to report.
+    pub async fn directory_stats(redis_server: Db) -> Result<impl warp::Reply, Infallible> {
+        let result = match redis_server.get_directory_summary().await {
+            Ok(Some(summary)) => Response::Result { result: summary },
+            Ok(None) => Response::NotFound,
+            Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+        };
+
+        Ok(result.into_response())
+    }
+
+    /// Follows the change records the updater publishes, as Server-Sent Events. Clients that
+    /// reconnect with `Last-Event-ID` resume after it, new ones start with the next change.
+    pub async fn changes_stream(
+        last_event_id: Option<String>,
+        redis_server: Db,
+        fields: FieldFilter,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let after = match last_event_id.as_deref().map(parse_stream_id) {
+            Some(Ok(id)) => id,
+            Some(Err(message)) => return Ok(Response::<()>::BadRequest { message }.into_response()),
+            None => match redis_server.last_stream_id(CHANGES_STREAM_KEY).await {
+                Ok(id) => id.unwrap_or_else(|| "0-0".to_owned()),
+                Err(e) => {
+                    return Ok(Response::<()>::Error {
+                        message: format!("{}", e),
+                    }
+                    .into_response())
+                }
+            },
+        };
+
+        let fields = Arc::new(fields);
+        let events = stream::unfold((redis_server, after), move |(redis_server, after)| {
+            let fields = fields.clone();
+            async move {
+                loop {
+                    match redis_server
+                        .read_stream(CHANGES_STREAM_KEY, "data", &after, CHANGES_BATCH_SIZE)
+                        .await
+                    {
+                        Ok(entries) if !entries.is_empty() => {
+                            let last = entries[entries.len() - 1].0.clone();
+                            let events: Vec<Result<Event, Infallible>> = entries
+                                .into_iter()
+                                .filter_map(|(id, data)| change_event(&fields, id, &data))
+                                .map(Ok)
+                                .collect();
+                            return Some((stream::iter(events), (redis_server, last)));
+                        }
+                        Ok(_) => {}
+                        Err(e) if e.is_transient() => {
+                            warn!("Unable to read changes. Error: {}", e)
+                        }
+                        Err(e) => {
+                            warn!("Unable to read changes, ending the stream. Error: {}", e);
+                            return None;
+                        }
+                    }
+                    tokio::time::sleep(CHANGES_POLL_INTERVAL).await;
+                }
+            }
+        })
+        .flatten();
+
+        Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)).into_response())
+    }
+
+    fn change_event(fields: &FieldFilter, id: String, data: &str) -> Option<Event> {
+        let mut change: ChangeRecord = match serde_json::from_str(data) {
+            Ok(change) => change,
+            Err(e) => {
+                warn!("Skipping unreadable change {}. Error: {}", id, e);
+                return None;
+            }
+        };
+        change.record = fields.apply(&change.record);
+
+        Some(
+            Event::default()
+                .id(id)
+                .event(change.entity.name())
+                .data(serde_json::to_string(&change).unwrap()),
+        )
+    }
+
+    /// Changes to a group's members, starting from the snapshot that was current at `since`.
+    pub async fn get_user_group_history(
+        id: GroupId,
+        query: HistoryQuery,
+        redis_server: Db,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let since = match query.since.as_deref().map(history::parse_timestamp) {
+            None => None,
+            Some(Ok(since)) => Some(since),
+            Some(Err(message)) => {
+                return Ok(Response::<()>::BadRequest { message }.into_response());
+            }
+        };
+
+        let result = match redis_server.get_group_history(&id).await {
+            RedisResponse::Ok(snapshots) => Response::Result {
+                result: json!({
+                    "id": id,
+                    "changes": history::changes(&snapshots, since),
+                }),
+            },
+            RedisResponse::Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+            RedisResponse::Missing => Response::NotFound,
+        };
+
+        Ok(result.into_response())
+    }
+
+    /// Asks the group's on-call provider who is on call, and returns the ones among the group's
+    /// members. People on call who aren't members, or aren't cached, are left out.
+    pub async fn get_user_group_oncall(
+        id: GroupId,
+        redis_server: Db,
+        oncall: Oncall,
+        fields: FieldFilter,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let schedule = match redis_server.get_oncall_schedule(&id).await {
+            RedisResponse::Ok(schedule) => schedule,
+            RedisResponse::Missing => return Ok(Response::<()>::NotFound.into_response()),
+            RedisResponse::Err(e) => {
+                let message = format!("{}", e);
+                return Ok(Response::<()>::Error { message }.into_response());
+            }
+        };
+        let group = match redis_server.get_user_group_by_id(&id).await {
+            RedisResponse::Ok(group) => group,
+            RedisResponse::Missing => return Ok(Response::<()>::NotFound.into_response()),
+            RedisResponse::Err(e) => {
+                let message = format!("{}", e);
+                return Ok(Response::<()>::Error { message }.into_response());
+            }
+        };
+
+        let emails = match oncall.on_call(&schedule).await {
+            Ok(emails) => emails,
+            Err(e) => {
+                warn!("Unable to find who is on call for {}. Error: {}", id, e);
+                let message = format!("{}", e);
+                return Ok(Response::<()>::Unavailable { message }.into_response());
+            }
+        };
+
+        let mut on_call = Vec::new();
+        for email in emails {
+            match redis_server.get_user_by_email(&email).await {
+                RedisResponse::Ok(user) => {
+                    if group.users.iter().any(|member| member.id == user.id) {
+                        on_call.push(user);
+                    }
+                }
+                RedisResponse::Missing => {}
+                RedisResponse::Err(e) => {
+                    let message = format!("{}", e);
+                    return Ok(Response::<()>::Error { message }.into_response());
+                }
+            }
+        }
+
+        let result = Response::Result {
+            result: json!({
+                "id": id,
+                "schedule": schedule,
+                "on-call": fields.apply(&on_call),
+            }),
+        };
+
+        Ok(result.into_response())
+    }
+
+    /// Groups renamed since are still found by their old name, along with the name they have now.
+    /// With `expand_members`, the group's members are included in full.
+    pub async fn get_user_group_by_name(
+        name: String,
+        redis_server: Db,
+        fields: FieldFilter,
+        expand_members: bool,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let mut modified = None;
+        let result = match redis_server.get_user_group_by_name(name.clone()).await {
+            RedisResponse::Ok(results) => {
+                modified = redis_server
+                    .get_group_modified(&results.id)
+                    .await
+                    .ok()
+                    .flatten();
+                match group_result(&redis_server, &results, &fields, expand_members).await {
+                    Ok(result) => Response::Result { result },
+                    Err(e) => Response::Error {
+                        message: format!("{}", e),
+                    },
+                }
+            }
+            RedisResponse::Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+            RedisResponse::Missing => {
+                match redis_server.get_user_group_by_previous_name(&name).await {
+                    RedisResponse::Ok(results) => {
+                        modified = redis_server
+                            .get_group_modified(&results.id)
+                            .await
+                            .ok()
+                            .flatten();
+                        match group_result(&redis_server, &results, &fields, expand_members).await {
+                            Ok(result) => Response::Renamed {
+                                renamed_to: results.name.clone(),
+                                result,
+                            },
+                            Err(e) => Response::Error {
+                                message: format!("{}", e),
+                            },
+                        }
+                    }
+                    RedisResponse::Err(e) => Response::Error {
+                        message: format!("{}", e),
+                    },
+                    RedisResponse::Missing => Response::NotFound,
+                }
+            }
+        };
+
+        // Members can change without the group changing, so expanded groups aren't cached by
+        // when the group last changed.
+        if expand_members {
+            modified = None;
+        }
+        Ok(with_last_modified(result.into_response(), modified))
+    }
+
+    /// `group` as served, with its members under `members` when `expand_members`.
+    async fn group_result(
+        redis_server: &Db,
+        group: &SlackUserGroup,
+        fields: &FieldFilter,
+        expand_members: bool,
+    ) -> Result<Value, RedisErrors> {
+        let mut result = fields.apply(group);
+        if !expand_members {
+            return Ok(result);
+        }
+
+        let members = annotated(
+            redis_server,
+            redis_server.get_expanded_members(group).await,
+            Vec::as_mut_slice,
+        )
+        .await;
+        result["members"] = match members {
+            RedisResponse::Ok(members) => fields.apply(&members),
+            RedisResponse::Missing => json!([]),
+            RedisResponse::Err(e) => return Err(e),
+        };
+        Ok(result)
+    }
+
+    /// With `tz`, only the users in that timezone, and with `team`, only the ones in that
+    /// workspace.
+    pub async fn get_all_users(
+        query: UsersQuery,
+        paging: Paging,
+        redis_server: Db,
+        fields: FieldFilter,
+    ) -> Result<impl warp::Reply, Infallible> {
+        if let Some(tz) = &query.tz {
+            if let Err(message) = tz.parse::<Tz>() {
+                return Ok(Response::<()>::BadRequest { message }.into_response());
+            }
+        }
+        let updated_since = match query.updated_since.as_deref().map(history::parse_timestamp) {
+            None => None,
+            Some(Ok(since)) => Some(since),
+            Some(Err(message)) => {
+                return Ok(Response::<()>::BadRequest { message }.into_response());
+            }
+        };
+
+        let team = query.team.as_deref().map(str::trim);
+        if team == Some("") {
+            let message = "team can't be empty".to_owned();
+            return Ok(Response::<()>::BadRequest { message }.into_response());
+        }
+
+        let users = match (updated_since, team) {
+            (Some(since), _) => redis_server.get_users_updated_since(since).await,
+            (None, Some(team)) => redis_server.get_users_by_team(team).await,
+            (None, None) => redis_server.get_all_users().await,
+        };
+        let users = paged(users, &paging, |user| {
+            let in_tz = match &query.tz {
+                Some(tz) => user.tz.as_deref() == Some(tz.as_str()),
+                None => true,
+            };
+            in_tz && team.map_or(true, |team| user.in_team(team))
+        });
+        let response = annotated(&redis_server, users, |page| page.items.as_mut_slice()).await;
+        let result = match response {
+            RedisResponse::Ok(page) => Response::Page {
+                result: fields.apply(&page.items),
+                next_cursor: page.next_cursor,
+                truncated: page.truncated,
+            },
+            RedisResponse::Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+            RedisResponse::Missing => Response::NotFound,
+        };
+
+        Ok(result.into_response())
+    }
+
+    /// Users whose name, or email when the caller may see emails, starts with `q`, for
+    /// autocomplete.
+    pub async fn get_users_typeahead(
+        query: NameSearchQuery,
+        redis_server: Db,
+        fields: FieldFilter,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let (q, limit) = match query.parse() {
+            Ok(parsed) => parsed,
+            Err(message) => return Ok(Response::<()>::BadRequest { message }.into_response()),
+        };
+
+        let response = annotated(
+            &redis_server,
+            redis_server
+                .get_users_by_prefix(q, limit, fields.shows_emails())
+                .await,
+            Vec::as_mut_slice,
+        )
+        .await;
+        let result = match response {
+            RedisResponse::Ok(users) => Response::Result {
+                result: fields.apply(&users),
+            },
+            RedisResponse::Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+            RedisResponse::Missing => Response::NotFound,
+        };
 
-    warp::serve(api).run(listen_server).await;
+        Ok(result.into_response())
+    }
 
-    Ok(())
-}
+    /// Users whose name is close to `q`, best first, each with a score from 0 to 1 saying how
+    /// close.
+    pub async fn get_users_fuzzy(
+        query: NameSearchQuery,
+        redis_server: Db,
+        fields: FieldFilter,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let (q, limit) = match query.parse() {
+            Ok(parsed) => parsed,
+            Err(message) => return Ok(Response::<()>::BadRequest { message }.into_response()),
+        };
 
-mod filters {
-    use super::{handlers, Db};
-    use std::convert::Infallible;
-    use warp::Filter;
+        let (scores, users): (Vec<f64>, Vec<SlackUser>) =
+            match redis_server.get_users_by_similarity(q, limit).await {
+                RedisResponse::Ok(matches) => matches
+                    .into_iter()
+                    .map(|found| (found.score, found.user))
+                    .unzip(),
+                RedisResponse::Missing => (Vec::new(), Vec::new()),
+                RedisResponse::Err(e) => {
+                    let message = format!("{}", e);
+                    return Ok(Response::<()>::Error { message }.into_response());
+                }
+            };
+        let result =
+            match annotated(&redis_server, RedisResponse::Ok(users), Vec::as_mut_slice).await {
+                RedisResponse::Ok(users) => Response::Result {
+                    result: scores
+                        .iter()
+                        .zip(&users)
+                        .map(|(score, user)| {
+                            json!({
+                                "score": (score * 1000.0).round() / 1000.0,
+                                "user": fields.apply(user),
+                            })
+                        })
+                        .collect::<Vec<_>>(),
+                },
+                RedisResponse::Err(e) => Response::Error {
+                    message: format!("{}", e),
+                },
+                RedisResponse::Missing => Response::NotFound,
+            };
 
-    pub fn get_all_users(
-        db: Db,
-    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-        warp::path!("slack" / "users")
-            .and(warp::get())
-            .and(with_db(db))
-            .and_then(handlers::get_all_users)
+        Ok(result.into_response())
     }
 
-    pub fn get_user_by_id(
-        db: Db,
-    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-        warp::path!("slack" / "user" / "id" / String)
-            .and(warp::get())
-            .and(with_db(db))
-            .and_then(handlers::get_user_by_id)
-    }
+    /// Users whose local time is within `window`, 9 to 5 by default. Users without a timezone
+    /// are left out.
+    pub async fn get_users_online_now(
+        query: OnlineNowQuery,
+        paging: Paging,
+        redis_server: Db,
+        fields: FieldFilter,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let window: HourWindow = match query.window.as_deref().map(str::parse).transpose() {
+            Ok(window) => window.unwrap_or_default(),
+            Err(message) => return Ok(Response::<()>::BadRequest { message }.into_response()),
+        };
 
-    pub fn get_user_by_email(
-        db: Db,
-    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-        warp::path!("slack" / "user" / "email" / String)
-            .and(warp::get())
-            .and(with_db(db))
-            .and_then(handlers::get_user_by_email)
-    }
+        let now = Utc::now();
+        let users = paged(
+            redis_server.get_all_users().await,
+            &paging,
+            |user| match &user.tz {
+                Some(tz) => window.contains_in(tz, now),
+                None => false,
+            },
+        );
+        let response = annotated(&redis_server, users, |page| page.items.as_mut_slice()).await;
+        let result = match response {
+            RedisResponse::Ok(page) => Response::Page {
+                result: fields.apply(&page.items),
+                next_cursor: page.next_cursor,
+                truncated: page.truncated,
+            },
+            RedisResponse::Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+            RedisResponse::Missing => Response::NotFound,
+        };
 
-    pub fn get_all_user_groups(
-        db: Db,
-    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-        warp::path!("slack" / "user_groups")
-            .and(warp::get())
-            .and(with_db(db))
-            .and_then(handlers::get_all_user_groups)
+        Ok(result.into_response())
     }
 
-    pub fn status() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-        warp::path!("healthz").map(|| {
-            super::Response::Result {
-                result: "OK".to_owned(),
-            }
-            .into_response()
-        })
-    }
+    /// Users with an email in `domain`, like the guests from a partner company. Users aren't
+    /// indexed by domain when emails are hashed.
+    pub async fn get_users_by_domain(
+        domain: String,
+        paging: Paging,
+        redis_server: Db,
+        fields: FieldFilter,
+    ) -> Result<impl warp::Reply, Infallible> {
+        if !redis_server.indexes_domains() {
+            let message = "users aren't indexed by domain while emails are hashed".to_owned();
+            return Ok(Response::<()>::BadRequest { message }.into_response());
+        }
 
-    fn with_db(db: Db) -> impl Filter<Extract = (Db,), Error = Infallible> + Clone {
-        warp::any().map(move || db.clone())
+        let users = paged(
+            redis_server.get_users_by_domain(&domain).await,
+            &paging,
+            |_| true,
+        );
+        let response = annotated(&redis_server, users, |page| page.items.as_mut_slice()).await;
+        let result = match response {
+            RedisResponse::Ok(page) => Response::Page {
+                result: fields.apply(&page.items),
+                next_cursor: page.next_cursor,
+                truncated: page.truncated,
+            },
+            RedisResponse::Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+            RedisResponse::Missing => Response::NotFound,
+        };
+
+        Ok(result.into_response())
     }
-}
 
-mod handlers {
-    use super::{Db, Response};
-    use crate::libs::RedisResponse;
-    use std::convert::Infallible;
+    /// With `as_of`, the user as they were at that time, from the history the updater keeps.
+    pub async fn get_user_by_id(
+        id: UserId,
+        query: AsOfQuery,
+        redis_server: Db,
+        fields: FieldFilter,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let response = match query.as_of.as_deref().map(history::parse_timestamp) {
+            None => redis_server.get_user_by_id(&id).await,
+            Some(Ok(as_of)) => redis_server.get_user_as_of(&id, as_of).await,
+            Some(Err(message)) => {
+                return Ok(Response::<()>::BadRequest { message }.into_response());
+            }
+        };
 
-    pub async fn get_all_user_groups(redis_server: Db) -> Result<impl warp::Reply, Infallible> {
-        let result = match redis_server.get_all_user_groups().await {
-            RedisResponse::Ok(results) => Response::Result { result: results },
+        let response = annotated(&redis_server, response, std::slice::from_mut).await;
+        // Past versions of a user aren't what `Last-Modified` describes.
+        let modified = match (&response, &query.as_of) {
+            (RedisResponse::Ok(_), None) => {
+                redis_server.get_user_modified(&id).await.ok().flatten()
+            }
+            _ => None,
+        };
+        let result = match response {
+            RedisResponse::Ok(results) => Response::Result {
+                result: fields.apply(&results),
+            },
             RedisResponse::Err(e) => Response::Error {
                 message: format!("{}", e),
             },
             RedisResponse::Missing => Response::NotFound,
         };
 
-        Ok(result.into_response())
+        Ok(with_last_modified(result.into_response(), modified))
     }
 
-    pub async fn get_all_users(redis_server: Db) -> Result<impl warp::Reply, Infallible> {
-        let result = match redis_server.get_all_users().await {
-            RedisResponse::Ok(results) => Response::Result { result: results },
+    /// Redirects to the user's profile photo, so it can be embedded without a Slack token.
+    pub async fn get_user_avatar(
+        id: UserId,
+        query: AvatarQuery,
+        redis_server: Db,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let result = match redis_server.get_user_by_id(&id).await {
+            RedisResponse::Ok(user) => {
+                match user.avatar(query.size.unwrap_or(DEFAULT_AVATAR_SIZE)) {
+                    Some(url) => {
+                        return Ok(warp::reply::with_header(StatusCode::FOUND, "location", url)
+                            .into_response())
+                    }
+                    None => Response::<()>::NotFound,
+                }
+            }
             RedisResponse::Err(e) => Response::Error {
                 message: format!("{}", e),
             },
@@ -172,12 +4051,18 @@ mod handlers {
         Ok(result.into_response())
     }
 
-    pub async fn get_user_by_id(
-        id: String,
+    /// The user's do-not-disturb schedule and status as of the last sync, and whether they're in
+    /// do-not-disturb now. Missing when the updater doesn't run with `--dnd-ttl`.
+    pub async fn get_user_dnd(
+        id: UserId,
         redis_server: Db,
     ) -> Result<impl warp::Reply, Infallible> {
-        let result = match redis_server.get_user_by_id(id).await {
-            RedisResponse::Ok(results) => Response::Result { result: results },
+        let result = match redis_server.get_availability(&id).await {
+            RedisResponse::Ok(availability) => {
+                let mut result = serde_json::to_value(&availability).unwrap();
+                result["in-dnd"] = json!(availability.in_dnd_at(Utc::now().timestamp()));
+                Response::Result { result }
+            }
             RedisResponse::Err(e) => Response::Error {
                 message: format!("{}", e),
             },
@@ -187,18 +4072,793 @@ mod handlers {
         Ok(result.into_response())
     }
 
+    /// Tells whether `email` maps to a user without reading them. An email a user has changed
+    /// away from still counts while it redirects to a user who's cached, as it does for `GET`.
+    pub async fn user_email_exists(
+        email: Email,
+        redis_server: Db,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let status = match redis_server.user_email_exists(&email).await {
+            Ok(true) => StatusCode::OK,
+            Ok(false) => StatusCode::NOT_FOUND,
+            Err(e) => {
+                return Ok(Response::<()>::Error {
+                    message: format!("{}", e),
+                }
+                .into_response())
+            }
+        };
+
+        Ok(warp::reply::with_status(warp::reply(), status).into_response())
+    }
+
+    /// With `--shadow-lookup-rate`, a sample of the answers are checked against Slack's. Users
+    /// whose email changed are still found by the old one for a while, along with the one they
+    /// have now.
     pub async fn get_user_by_email(
-        email: String,
+        email: Email,
         redis_server: Db,
+        fields: FieldFilter,
+        shadow: Shadow,
     ) -> Result<impl warp::Reply, Infallible> {
-        let result = match redis_server.get_user_by_email(email).await {
-            RedisResponse::Ok(results) => Response::Result { result: results },
+        let (response, moved) = find_by_email(&redis_server, &email).await;
+        if let Some(shadow) = &shadow {
+            match &response {
+                _ if moved => shadow.sample(&email.to_string(), None),
+                RedisResponse::Ok(user) => shadow.sample(&email.to_string(), Some(user.id.clone())),
+                RedisResponse::Missing | RedisResponse::Err(_) => {}
+            }
+        }
+        let response = annotated(&redis_server, response, std::slice::from_mut).await;
+        let modified = match &response {
+            RedisResponse::Ok(user) => redis_server
+                .get_user_modified(&user.id)
+                .await
+                .ok()
+                .flatten(),
+            _ => None,
+        };
+        let result = match response {
+            RedisResponse::Ok(results) if moved => Response::Moved {
+                moved_to: results.email.to_string(),
+                result: fields.apply(&results),
+            },
+            RedisResponse::Ok(results) => Response::Result {
+                result: fields.apply(&results),
+            },
             RedisResponse::Err(e) => Response::Error {
                 message: format!("{}", e),
             },
             RedisResponse::Missing => Response::NotFound,
         };
 
-        Ok(result.into_response())
+        Ok(with_last_modified(result.into_response(), modified))
+    }
+
+    /// The user with `email`, or else the one who had it before changing it, along with whether
+    /// `email` was only found that way.
+    async fn find_by_email(
+        redis_server: &Db,
+        email: &Email,
+    ) -> (RedisResponse<SlackUser, RedisErrors>, bool) {
+        match redis_server.get_user_by_email(email).await {
+            RedisResponse::Missing => (redis_server.get_user_by_previous_email(email).await, true),
+            response => (response, false),
+        }
+    }
+
+    /// The user with GitHub `login`, for tools that only know who opened a pull request. Only
+    /// users whose login `update-redis --github-logins` found can be looked up.
+    pub async fn get_user_by_github(
+        login: String,
+        redis_server: Db,
+        fields: FieldFilter,
+    ) -> Result<impl warp::Reply, Infallible> {
+        let response = annotated(
+            &redis_server,
+            redis_server.get_user_by_github(&login).await,
+            std::slice::from_mut,
+        )
+        .await;
+        let modified = match &response {
+            RedisResponse::Ok(user) => redis_server
+                .get_user_modified(&user.id)
+                .await
+                .ok()
+                .flatten(),
+            _ => None,
+        };
+        let result = match response {
+            RedisResponse::Ok(results) => Response::Result {
+                result: fields.apply(&results),
+            },
+            RedisResponse::Err(e) => Response::Error {
+                message: format!("{}", e),
+            },
+            RedisResponse::Missing => Response::NotFound,
+        };
+
+        Ok(with_last_modified(result.into_response(), modified))
+    }
+
+    /// `response` with `Last-Modified` set to when the record it holds last changed, if known.
+    fn with_last_modified(
+        mut response: warp::reply::Response,
+        modified: Option<u64>,
+    ) -> warp::reply::Response {
+        let value = modified.and_then(|modified| HeaderValue::from_str(&http_date(modified)).ok());
+        if let (StatusCode::OK, Some(value)) = (response.status(), value) {
+            response.headers_mut().insert(LAST_MODIFIED, value);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use serde::Serialize;
+
+    use super::*;
+    use crate::commands::fake_redis::FakeRedis;
+    use crate::libs::{RedisResponse, SlackUser, SlackUserGroup};
+
+    /// Some routes behind the same rejection handling and envelopes as the server's. Redis is
+    /// only connected to once a handler uses it, which none of the requests here get to.
+    async fn api(
+        tokens: ApiTokens,
+    ) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone {
+        let db: Db = Arc::new(
+            RedisServer::new(&["redis://127.0.0.1:1".to_owned()])
+                .await
+                .unwrap(),
+        );
+        let tokens: Tokens = Arc::new(tokens);
+        let debug_info = Arc::new(DebugInfo {
+            started_at: Instant::now(),
+            config: Value::Null,
+        });
+        let routes = filters::get_user_by_id(db.clone(), tokens.clone(), None)
+            .or(filters::admin_debug(db.clone(), tokens.clone(), debug_info))
+            .or(filters::admin_remove_group_watcher(db, tokens))
+            .recover(handle_rejection)
+            .recover(handle_unmatched);
+        filters::with_error_details(routes)
+    }
+
+    /// Loads `line` as a tokens file.
+    fn tokens_file(line: &str) -> ApiTokens {
+        static FILES: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "slack-user-cache-tokens-{}-{}",
+            std::process::id(),
+            FILES.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::write(&path, format!("{}\n", line)).unwrap();
+        let tokens = ApiTokens::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        tokens
+    }
+
+    /// Accepts only the token `secret`.
+    fn required_tokens() -> ApiTokens {
+        tokens_file("test secret")
+    }
+
+    fn envelope(code: u16, kind: &str, message: &str) -> Value {
+        json!({
+            "api_version": API_VERSION,
+            "code": code,
+            "success": false,
+            "message": message,
+            "error": {
+                "kind": kind,
+                "message": message
+            },
+            "request_id": "test-request"
+        })
+    }
+
+    async fn answer(tokens: ApiTokens, method: &str, path: &str) -> (StatusCode, Value) {
+        let response = warp::test::request()
+            .method(method)
+            .path(path)
+            .header("x-request-id", "test-request")
+            .reply(&api(tokens).await)
+            .await;
+        let body = serde_json::from_slice(response.body()).unwrap();
+        (response.status(), body)
+    }
+
+    #[tokio::test]
+    async fn unknown_paths_are_not_found() {
+        let (status, body) = answer(ApiTokens::default(), "GET", "/slack/nope").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, envelope(404, "not_found", "not found"));
+    }
+
+    #[tokio::test]
+    async fn wrong_methods_are_not_allowed() {
+        let expected = envelope(405, "method_not_allowed", "method not allowed");
+
+        let (status, body) = answer(ApiTokens::default(), "POST", "/slack/user/id/U123").await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(body, expected);
+
+        let (status, body) = answer(ApiTokens::default(), "POST", "/slack/user/id/bad").await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(body, expected);
+    }
+
+    #[tokio::test]
+    async fn malformed_parameters_are_bad_requests() {
+        let (status, body) = answer(ApiTokens::default(), "GET", "/slack/user/id/bad").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body,
+            envelope(400, "bad_request", "`bad` is not a Slack user id")
+        );
+
+        let (status, body) = answer(
+            ApiTokens::default(),
+            "DELETE",
+            "/admin/user_group/id/S123/watchers/%20",
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, envelope(400, "bad_request", "channel can't be empty"));
+    }
+
+    #[tokio::test]
+    async fn missing_tokens_are_unauthorized() {
+        let (status, body) = answer(required_tokens(), "GET", "/slack/user/id/U123").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body, envelope(401, "unauthorized", "unauthorized"));
+    }
+
+    #[tokio::test]
+    async fn admin_routes_are_unauthorized_without_tokens() {
+        let (status, body) = answer(ApiTokens::default(), "GET", "/admin/debug").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body, envelope(401, "unauthorized", "unauthorized"));
+    }
+
+    #[tokio::test]
+    async fn requests_over_quota_are_refused_before_they_are_answered() {
+        let redis = FakeRedis::start().await;
+        let db: Db = Arc::new(RedisServer::new(&[redis.address()]).await.unwrap());
+        let tokens: Tokens = Arc::new(tokens_file("test secret admin hour=1"));
+        let routes = filters::admin_set_oncall_schedule(db.clone(), tokens.clone(), 1024).or(
+            filters::admin_remove_oncall_schedule(db.clone(), tokens.clone()),
+        );
+        let routes = filters::with_quota(warp::path(API_VERSION).and(routes.clone()).or(routes))
+            .recover(handle_rejection);
+        let request = |method: &str, path: &str| {
+            warp::test::request()
+                .method(method)
+                .path(path)
+                .header("authorization", "Bearer secret")
+                .extension(QuotaSlot::new(db.clone()))
+        };
+        let set_schedule = |group: &str| {
+            request("PUT", &format!("/v1/admin/user_group/id/{}/oncall", group))
+                .json(&json!({"provider": "pagerduty", "schedule-id": "P123ABC"}))
+        };
+
+        // Neither an unknown route nor the wrong method for a known one counts.
+        let response = request("GET", "/v1/admin/nowhere").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = request("POST", "/v1/admin/user_group/id/S1/oncall")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let response = set_schedule("S1").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-ratelimit-remaining"], "0");
+
+        let response = set_schedule("S2").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let group = "S2".parse().unwrap();
+        assert!(matches!(
+            db.get_oncall_schedule(&group).await,
+            RedisResponse::Missing
+        ));
+    }
+
+    #[tokio::test]
+    async fn head_and_get_agree_on_emails_redirecting_to_removed_users() {
+        let redis = FakeRedis::start().await;
+        let db: Db = Arc::new(RedisServer::new(&[redis.address()]).await.unwrap());
+        let tokens: Tokens = Arc::new(ApiTokens::default());
+        let routes = filters::user_email_exists(db.clone(), tokens.clone())
+            .or(filters::get_user_by_email(db.clone(), tokens, None, None));
+        let status = |method: &str| {
+            let request = warp::test::request()
+                .method(method)
+                .path("/slack/user/email/old@example.com");
+            let routes = routes.clone();
+            async move { request.reply(&routes).await.status() }
+        };
+
+        // The user moves to a new email, leaving the old one redirecting to them.
+        for email in &["old@example.com", "new@example.com"] {
+            let user: SlackUser =
+                serde_json::from_value(json!({"id": "U123", "name": "Ann", "email": email}))
+                    .unwrap();
+            db.insert_users(&vec![user].into_iter().collect())
+                .await
+                .unwrap();
+        }
+        assert_eq!(status("HEAD").await, StatusCode::OK);
+        assert_eq!(status("GET").await, StatusCode::OK);
+
+        db.remove_user(&"U123".parse().unwrap()).await.unwrap();
+        assert_eq!(status("HEAD").await, StatusCode::NOT_FOUND);
+        assert_eq!(status("GET").await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn typeahead_only_matches_emails_for_callers_that_may_see_them() {
+        let redis = FakeRedis::start().await;
+        let db: Db = Arc::new(RedisServer::new(&[redis.address()]).await.unwrap());
+        let user: SlackUser =
+            serde_json::from_value(json!({"id": "U123", "name": "Ann", "email": "ann.lee@x.com"}))
+                .unwrap();
+        db.insert_users(&vec![user].into_iter().collect())
+            .await
+            .unwrap();
+        let found = |permissions: &str| {
+            let tokens: Tokens = Arc::new(tokens_file(&format!("test secret {}", permissions)));
+            let routes = filters::get_users_typeahead(db.clone(), tokens, None);
+            async move {
+                let response = warp::test::request()
+                    .path("/slack/users/typeahead?q=ann.lee")
+                    .header("authorization", "Bearer secret")
+                    .reply(&routes)
+                    .await;
+                let body: Value = serde_json::from_slice(response.body()).unwrap();
+                body["result"].as_array().unwrap().len()
+            }
+        };
+
+        assert_eq!(found("read-users").await, 0);
+        assert_eq!(found("read-users,read-emails").await, 1);
+    }
+
+    #[test]
+    fn names_are_only_swapped_in_from_fields_the_caller_may_see() {
+        let user = json!({"id": "U1", "name": "Ann Lee", "display-name": "ann"});
+        let filter = |allowed: &[&str], requested: Option<&[&str]>| FieldFilter {
+            requested: requested.map(|fields| fields.iter().map(|f| f.to_string()).collect()),
+            allowed: Some(Arc::new(allowed.iter().map(|f| f.to_string()).collect())),
+            read_emails: false,
+            name_form: NameForm::Display,
+        };
+
+        assert_eq!(
+            filter(&["id", "name"], None).apply(&user),
+            json!({"id": "U1", "name": "Ann Lee"})
+        );
+        assert_eq!(
+            filter(&["id", "name", "display-name"], Some(&["name"])).apply(&user),
+            json!({"name": "ann"})
+        );
+    }
+
+    #[tokio::test]
+    async fn whois_only_shows_allowed_fields() {
+        let redis = FakeRedis::start().await;
+        let db: Db = Arc::new(RedisServer::new(&[redis.address()]).await.unwrap());
+        let user: SlackUser =
+            serde_json::from_value(json!({"id": "U123", "name": "Ann", "email": "ann@x.com"}))
+                .unwrap();
+        db.insert_users(&vec![user].into_iter().collect())
+            .await
+            .unwrap();
+        let allowed: AllowedFields = Some(Arc::new(
+            vec!["id".to_owned(), "name".to_owned()]
+                .into_iter()
+                .collect(),
+        ));
+        let whois = |text: &str| {
+            let body = format!("user_id=U9&text={}", text);
+            let reply = handlers::slack_command(
+                body.into(),
+                db.clone(),
+                allowed.clone(),
+                AuditLog::default(),
+            );
+            async move {
+                let response = reply.await.unwrap().into_response();
+                let body = warp::hyper::body::to_bytes(response.into_body())
+                    .await
+                    .unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+
+        let card = whois("U123").await;
+        assert!(card.contains("Ann"));
+        assert!(!card.contains("ann@x.com"));
+        assert!(whois("ann@x.com").await.contains("isn't allowed"));
+    }
+
+    /// The server's `/slack` and `/admin` routes, with and without `/v1`, over an in-memory store
+    /// holding three users and a group. Callers can use the tokens `admin-secret` and
+    /// `reader-secret`, which can only read users. Lists are cut at two entries.
+    async fn golden_api(
+    ) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone {
+        let redis = FakeRedis::start().await;
+        let db: Db = Arc::new(RedisServer::new(&[redis.address()]).await.unwrap());
+
+        let user = |value: Value| -> SlackUser { serde_json::from_value(value).unwrap() };
+        let mut ann = user(json!({
+            "id": "U1",
+            "name": "Ann Lee",
+            "display-name": "ann",
+            "email": "ann@example.com",
+            "tz": "Europe/Berlin",
+            "github-login": "annlee"
+        }));
+        ann.avatars
+            .insert(192, "https://avatars.example.com/ann-192.png".to_owned());
+        let bob =
+            user(json!({"id": "U2", "name": "Bob", "email": "bob@partner.com", "guest": true}));
+        let cat = |email: &str| user(json!({"id": "U3", "name": "Cat", "email": email}));
+        db.insert_users(&vec![ann, bob, cat("cat@old.com")].into_iter().collect())
+            .await
+            .unwrap();
+        // Leaves Cat's old email redirecting to the new one.
+        db.insert_users(&vec![cat("cat@example.com")].into_iter().collect())
+            .await
+            .unwrap();
+
+        // The group is renamed from `eng` as Bob joins it.
+        let group = |name: &str, users: &[&str]| -> SlackUserGroup {
+            let users: Vec<Value> = users.iter().map(|id| json!({ "id": id })).collect();
+            serde_json::from_value(json!({"name": name, "id": "S1", "users": users})).unwrap()
+        };
+        for &(name, users, timestamp) in &[
+            ("eng", &["U1"][..], 1_600_000_000),
+            ("engineering", &["U1", "U2"][..], 1_600_086_400),
+        ] {
+            let groups = vec![group(name, users)].into_iter().collect();
+            db.insert_user_groups(&groups).await.unwrap();
+            db.record_group_history(&groups, timestamp, 10)
+                .await
+                .unwrap();
+        }
+        db.set_counts(3, 1).await.unwrap();
+
+        let availability = json!({
+            "dnd-enabled": true,
+            "next-dnd-start": 1_600_000_000,
+            "next-dnd-end": 1_600_003_600,
+            "status-text": "Lunch",
+            "status-emoji": ":taco:"
+        });
+        db.insert_availability(
+            &vec![(
+                "U1".parse().unwrap(),
+                serde_json::from_value(availability).unwrap(),
+            )]
+            .into_iter()
+            .collect(),
+            Duration::from_secs(60),
+        )
+        .await
+        .unwrap();
+        let summary = json!({
+            "users": 3,
+            "groups": 1,
+            "users-by-type": {"members": 2, "guests": 1, "external": 0},
+            "group-sizes": [{"min": 2, "max": 5, "groups": 1}],
+            "largest-group": 2,
+            "mean-group-size": 2.0,
+            "as-of": 1_600_086_400
+        });
+        db.set_directory_summary(&serde_json::from_value(summary).unwrap())
+            .await
+            .unwrap();
+
+        let config = RouteConfig {
+            db,
+            tokens: Arc::new(tokens_file(
+                "admin admin-secret admin\nreader reader-secret read-users",
+            )),
+            allowed_fields: None,
+            shadow: None,
+            oncall: Arc::new(OncallClient::new(None, None).unwrap()),
+            debug_info: Arc::new(DebugInfo {
+                started_at: Instant::now(),
+                config: Value::Null,
+            }),
+            max_list_entries: 2,
+            max_body_size: 1024,
+        };
+        let routes = slack_routes(&config).or(admin_routes(&config)).unify();
+        let routes = warp::path(API_VERSION)
+            .and(routes.clone())
+            .or(routes)
+            .unify()
+            .recover(handle_rejection)
+            .recover(handle_unmatched);
+        filters::with_error_details(routes)
+    }
+
+    /// A request of a golden file, along with the response it's expected to get.
+    #[derive(Debug, Deserialize, Serialize)]
+    struct GoldenCase {
+        /// Method and path, like `GET /v1/slack/users?limit=1`.
+        request: String,
+        /// `admin` or `reader`, sent as the bearer token `{token}-secret`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        headers: BTreeMap<String, String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        json: Option<Value>,
+        /// JSON pointers into the response body of values that differ from run to run.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        redact: Vec<String>,
+        /// The status, the body or `null` when it's empty, and the `Location` if there's one.
+        response: Value,
+    }
+
+    async fn golden_response<F>(case: &GoldenCase, api: &F) -> Value
+    where
+        F: Filter<Extract = (warp::reply::Response,), Error = Infallible> + 'static,
+    {
+        let mut parts = case.request.splitn(2, ' ');
+        let (method, path) = (parts.next().unwrap(), parts.next().unwrap());
+        let mut request = warp::test::request()
+            .method(method)
+            .path(path)
+            .header("x-request-id", "golden");
+        if let Some(token) = &case.token {
+            request = request.header("authorization", format!("Bearer {}-secret", token));
+        }
+        for (name, value) in &case.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        if let Some(body) = &case.json {
+            request = request.json(body);
+        }
+        let response = request.reply(api).await;
+
+        let mut body = if response.body().is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(response.body()).unwrap()
+        };
+        for pointer in &case.redact {
+            if let Some(value) = body.pointer_mut(pointer) {
+                *value = json!("<redacted>");
+            }
+        }
+        let mut result = json!({ "status": response.status().as_u16(), "body": body });
+        if let Some(location) = response.headers().get("location") {
+            result["location"] = json!(location.to_str().unwrap());
+        }
+        result
+    }
+
+    /// Sends the requests in `tests/golden/{name}.json` to a new `golden_api`, in order, and
+    /// compares the responses with the ones recorded there. With `UPDATE_GOLDEN` set the file is
+    /// rewritten with the responses instead, so changes to them can be reviewed as a diff.
+    async fn check_golden(name: &str) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(format!("{}.json", name));
+        let mut cases: Vec<GoldenCase> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+
+        let api = golden_api().await;
+        for case in &mut cases {
+            let response = golden_response(case, &api).await;
+            if update {
+                case.response = response;
+            } else {
+                assert_eq!(response, case.response, "{} in {}", case.request, name);
+            }
+        }
+
+        if update {
+            let cases = serde_json::to_string_pretty(&cases).unwrap();
+            std::fs::write(&path, format!("{}\n", cases)).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn users_match_golden_responses() {
+        check_golden("users").await;
+    }
+
+    #[tokio::test]
+    async fn users_online_now_match_golden_responses() {
+        check_golden("online_now").await;
+    }
+
+    #[tokio::test]
+    async fn users_by_domain_match_golden_responses() {
+        check_golden("domain").await;
+    }
+
+    #[tokio::test]
+    async fn name_searches_match_golden_responses() {
+        check_golden("name_search").await;
+    }
+
+    #[tokio::test]
+    async fn users_by_id_match_golden_responses() {
+        check_golden("user").await;
+    }
+
+    #[tokio::test]
+    async fn users_by_email_match_golden_responses() {
+        check_golden("email").await;
+    }
+
+    #[tokio::test]
+    async fn users_by_github_login_match_golden_responses() {
+        check_golden("github").await;
+    }
+
+    #[tokio::test]
+    async fn groups_match_golden_responses() {
+        check_golden("groups").await;
+    }
+
+    #[tokio::test]
+    async fn group_history_matches_golden_responses() {
+        check_golden("history").await;
+    }
+
+    #[tokio::test]
+    async fn oncall_matches_golden_responses() {
+        check_golden("oncall").await;
+    }
+
+    #[tokio::test]
+    async fn changes_stream_matches_golden_responses() {
+        check_golden("changes_stream").await;
+    }
+
+    #[tokio::test]
+    async fn stats_match_golden_responses() {
+        check_golden("stats").await;
+    }
+
+    #[tokio::test]
+    async fn admin_debug_matches_golden_responses() {
+        check_golden("debug").await;
+    }
+
+    #[tokio::test]
+    async fn cache_stats_match_golden_responses() {
+        check_golden("cache_stats").await;
+    }
+
+    #[tokio::test]
+    async fn annotations_match_golden_responses() {
+        check_golden("annotations").await;
+    }
+
+    #[tokio::test]
+    async fn synthetic_groups_match_golden_responses() {
+        check_golden("synthetic_groups").await;
+    }
+
+    #[tokio::test]
+    async fn watchers_match_golden_responses() {
+        check_golden("watchers").await;
+    }
+
+    #[test]
+    fn endpoints_have_placeholders_for_path_params() {
+        for (path, expected) in &[
+            ("/v1/slack/user/id/U1/avatar", "/slack/user/id/{id}/avatar"),
+            (
+                "/slack/user/email/ann@example.com",
+                "/slack/user/email/{email}",
+            ),
+            ("/slack/user/id/id/dnd", "/slack/user/id/{id}/dnd"),
+            ("/slack/changes/stream", "/slack/changes/stream"),
+            ("/admin/groups", "/admin/groups"),
+            ("/admin/groups/X1", "/admin/groups/{id}"),
+            (
+                "/admin/groups/X1/members/U1",
+                "/admin/groups/{id}/members/{user}",
+            ),
+            ("/admin/groups/changes", "/admin/groups/changes"),
+            (
+                "/admin/groups/changes/ab12",
+                "/admin/groups/changes/{change}",
+            ),
+            (
+                "/v1/admin/groups/changes/ab12/approve",
+                "/admin/groups/changes/{change}/approve",
+            ),
+            (
+                "/admin/user_group/id/S1/watchers",
+                "/admin/user_group/id/{id}/watchers",
+            ),
+            (
+                "/admin/user_group/id/S1/watchers/C1",
+                "/admin/user_group/id/{id}/watchers/{channel}",
+            ),
+        ] {
+            assert_eq!(endpoint(path), *expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn stream_ids_need_milliseconds_and_a_sequence() {
+        assert_eq!(
+            parse_stream_id("1526919030474-55").unwrap(),
+            "1526919030474-55"
+        );
+        assert_eq!(parse_stream_id("0-0").unwrap(), "0-0");
+        for id in &[
+            "",
+            "1526919030474",
+            "1526919030474-",
+            "-55",
+            "abc-1",
+            "1-2-3",
+            "$",
+        ] {
+            assert!(parse_stream_id(id).is_err(), "{}", id);
+        }
+    }
+
+    #[test]
+    fn path_params_are_percent_decoded() {
+        let email = parse_path_param("foo%2Bbar@x.com", str::parse::<Email>).unwrap();
+        assert_eq!(&*email, "foo+bar@x.com");
+        let email = parse_path_param("j%C3%B6rg@x.com", str::parse::<Email>).unwrap();
+        assert_eq!(&*email, "jörg@x.com");
+    }
+
+    #[test]
+    fn plus_in_path_params_is_kept() {
+        let email = parse_path_param("foo+bar@x.com", str::parse::<Email>).unwrap();
+        assert_eq!(&*email, "foo+bar@x.com");
+    }
+
+    #[test]
+    fn long_path_params_are_not_echoed() {
+        let segment = "a".repeat(MAX_PATH_PARAM_LENGTH + 1);
+        let message = parse_path_param(&segment, str::parse::<Email>).unwrap_err();
+        assert!(!message.contains(&segment));
+    }
+
+    #[tokio::test]
+    async fn a_lone_user_or_group_is_listed() {
+        let redis = FakeRedis::start().await;
+        let db = RedisServer::new(&[redis.address()]).await.unwrap();
+        let user: SlackUser =
+            serde_json::from_value(json!({"id": "U1", "name": "Ann", "email": "ann@x.com"}))
+                .unwrap();
+        db.insert_users(&vec![user].into_iter().collect())
+            .await
+            .unwrap();
+        let group: SlackUserGroup =
+            serde_json::from_value(json!({"name": "eng", "id": "S1", "users": [{"id": "U1"}]}))
+                .unwrap();
+        db.insert_user_groups(&vec![group].into_iter().collect())
+            .await
+            .unwrap();
+
+        assert!(matches!(db.get_all_users().await, RedisResponse::Ok(users) if users.len() == 1));
+        assert!(matches!(
+            db.get_all_user_groups().await,
+            RedisResponse::Ok(groups) if groups.len() == 1
+        ));
     }
 }
@@ -0,0 +1,189 @@
+use anyhow::anyhow;
+use chrono::{TimeZone, Utc};
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+
+use crate::error::{CliErrors, RedisErrors};
+use crate::libs::shell::{ShellCommand, Table, HELP};
+use crate::libs::{RedisResponse, RedisServer, SlackUser, SlackUserGroup};
+use crate::ShellArgs;
+
+/// Most users `search` lists.
+const SEARCH_LIMIT: usize = 25;
+
+/// Reads commands from the terminal and answers them straight from Redis, until `exit` or
+/// Ctrl-D. Errors from Redis are printed rather than ending the shell.
+pub async fn shell(args: &ShellArgs) -> Result<(), CliErrors> {
+    let redis_server = RedisServer::new(&args.redis_opts.redis_address)
+        .await?
+        .with_key_layout(args.key_opts.layout())
+        .with_email_hasher(args.privacy_opts.email_hasher());
+
+    let mut editor = Editor::<()>::new();
+    println!("Type `help` to list the commands");
+    loop {
+        // Reading blocks until a line is entered, so it's kept off the runtime's other tasks.
+        let line = match tokio::task::block_in_place(|| editor.readline("> ")) {
+            Ok(line) => line,
+            // Ctrl-C drops the line being typed, like in a shell.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(CliErrors::Shell { source: anyhow!(e) }),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str());
+
+        let command = match line.parse() {
+            Ok(ShellCommand::Exit) => break,
+            Ok(command) => command,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
+        if let Err(e) = run(&redis_server, command).await {
+            println!("Error: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+async fn run(redis_server: &RedisServer, command: ShellCommand) -> Result<(), RedisErrors> {
+    match command {
+        ShellCommand::UserByEmail(email) => {
+            print_users(found(redis_server.get_user_by_email(&email).await)?)
+        }
+        ShellCommand::UserById(id) => print_users(found(redis_server.get_user_by_id(&id).await)?),
+        ShellCommand::UserByGithub(login) => {
+            print_users(found(redis_server.get_user_by_github(&login).await)?)
+        }
+        ShellCommand::Search(query) => print_users(
+            found(
+                redis_server
+                    .get_users_by_prefix(&query, SEARCH_LIMIT, true)
+                    .await,
+            )?
+            .unwrap_or_default(),
+        ),
+        ShellCommand::GroupByName(name) => {
+            let group = found(redis_server.get_user_group_by_name(name).await)?;
+            print_group(redis_server, group).await?
+        }
+        ShellCommand::GroupById(id) => {
+            let group = found(redis_server.get_user_group_by_id(&id).await)?;
+            print_group(redis_server, group).await?
+        }
+        ShellCommand::Stats => print_stats(redis_server).await?,
+        ShellCommand::Help => println!("{}", HELP),
+        ShellCommand::Exit => {}
+    }
+
+    Ok(())
+}
+
+fn found<T>(response: RedisResponse<T, RedisErrors>) -> Result<Option<T>, RedisErrors> {
+    match response {
+        RedisResponse::Ok(value) => Ok(Some(value)),
+        RedisResponse::Missing => Ok(None),
+        RedisResponse::Err(e) => Err(e),
+    }
+}
+
+fn print_users<I: IntoIterator<Item = SlackUser>>(users: I) {
+    let mut table = Table::new(&["Id", "Name", "Display name", "Email", "Timezone"]);
+    let mut count = 0;
+    for user in users {
+        table.add_row(vec![
+            user.id.to_string(),
+            user.name,
+            user.display_name.unwrap_or_default(),
+            user.email.to_string(),
+            user.tz.unwrap_or_default(),
+        ]);
+        count += 1;
+    }
+
+    match count {
+        0 => println!("No users found"),
+        _ => print!("{}", table),
+    }
+}
+
+/// Prints the group with its members, looking each up so they're listed by name. Members the
+/// cache doesn't have are listed by id alone.
+async fn print_group(
+    redis_server: &RedisServer,
+    group: Option<SlackUserGroup>,
+) -> Result<(), RedisErrors> {
+    let group = match group {
+        Some(group) => group,
+        None => {
+            println!("No user group found");
+            return Ok(());
+        }
+    };
+
+    println!(
+        "{} ({}), {} members",
+        group.name,
+        group.id,
+        group.users.len()
+    );
+    let mut table = Table::new(&["Id", "Name", "Email"]);
+    for member in &group.users {
+        let row = match found(redis_server.get_user_by_id(&member.id).await)? {
+            Some(user) => vec![user.id.to_string(), user.name, user.email.to_string()],
+            None => vec![
+                member.id.to_string(),
+                "(not cached)".to_owned(),
+                String::new(),
+            ],
+        };
+        table.add_row(row);
+    }
+    print!("{}", table);
+    Ok(())
+}
+
+async fn print_stats(redis_server: &RedisServer) -> Result<(), RedisErrors> {
+    match redis_server.get_last_sync().await? {
+        Some(finished_at) => println!(
+            "Last synced {}",
+            Utc.timestamp(finished_at as i64, 0).to_rfc3339()
+        ),
+        None => println!("Not synced yet"),
+    }
+
+    let stats = redis_server.cache_stats(0).await?;
+    let mut table = Table::new(&[
+        "Prefix",
+        "Keys",
+        "Memory (bytes)",
+        "Expiring",
+        "Average TTL",
+    ]);
+    for (prefix, prefix_stats) in &stats.prefixes {
+        table.add_row(vec![
+            prefix.clone(),
+            prefix_stats.keys.to_string(),
+            prefix_stats.memory_bytes.to_string(),
+            prefix_stats.expiring_keys.to_string(),
+            prefix_stats
+                .average_ttl_seconds
+                .map(|seconds| format!("{}s", seconds))
+                .unwrap_or_default(),
+        ]);
+    }
+    table.add_row(vec![
+        "total".to_owned(),
+        stats.keys.to_string(),
+        stats.memory_bytes.to_string(),
+        String::new(),
+        String::new(),
+    ]);
+    print!("{}", table);
+    Ok(())
+}
@@ -0,0 +1,206 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::Value;
+use tracing::{debug, info, warn};
+
+use crate::error::{CliErrors, RedisErrors, SecretErrors};
+use crate::SocketListenerArgs;
+
+use crate::libs::membership::MembershipFilter;
+use crate::libs::secrets;
+use crate::libs::slack::{CacheEvent, GroupId, SlackUserId, UserId};
+#[cfg(feature = "transform")]
+use crate::libs::transform::Transform;
+use crate::libs::{
+    RedisResponse, RedisServer, SlackClientConfig, SlackUser, SlackUserGroup, SocketModeClient,
+};
+
+pub async fn socket_listener(args: &SocketListenerArgs) -> Result<(), CliErrors> {
+    let redis_server = RedisServer::new(&args.redis_opts.redis_address)
+        .await?
+        .with_key_layout(args.key_opts.layout())
+        .with_email_hasher(args.privacy_opts.email_hasher())
+        .with_email_aliases(args.email_alias_rules.clone())
+        .with_domain_allowlist(
+            args.domain_opts.allowlist(),
+            args.domain_opts.external_users,
+        )
+        .with_value_format(args.value_format)
+        .with_compression(args.compress_values_over);
+    #[cfg(feature = "transform")]
+    let redis_server = redis_server.with_transform(
+        args.transform_script
+            .as_deref()
+            .map(Transform::load)
+            .transpose()?,
+    );
+
+    let app_token = resolve_app_token(args)?;
+    let client = SocketModeClient::new(
+        &app_token,
+        &SlackClientConfig {
+            connect_timeout: Duration::from_secs(args.slack_connect_timeout),
+            request_timeout: Duration::from_secs(args.slack_request_timeout),
+            tcp_keepalive: Duration::from_secs(args.slack_tcp_keepalive),
+            rotation: None,
+            usage: Arc::default(),
+        },
+    )?;
+
+    info!("Listening for Slack events");
+    client
+        .run(|event| apply_event(&redis_server, args, event))
+        .await?;
+
+    Ok(())
+}
+
+async fn apply_event(redis_server: &RedisServer, args: &SocketListenerArgs, event: Value) {
+    let event = match CacheEvent::from_event(&event) {
+        Some(event) => event,
+        None => return,
+    };
+    debug!("Applying Slack event {:?}", event);
+
+    let result = match event {
+        CacheEvent::UserChanged(user) => update_user(redis_server, args, &user).await,
+        CacheEvent::UserRemoved(id) => redis_server.remove_user(&id).await,
+        CacheEvent::GroupChanged { id, name, users } => {
+            update_group(redis_server, id, name, users).await
+        }
+        CacheEvent::GroupRemoved(id) => redis_server.remove_user_group(&id).await,
+        CacheEvent::GroupMembersChanged { id, added, removed } => {
+            update_group_members(redis_server, id, added, removed).await
+        }
+    };
+
+    if let Err(e) = result {
+        warn!("Unable to apply Slack event. Error: {}", e);
+    }
+}
+
+/// Writes a changed user, or removes them when they aren't in the groups users are cached for.
+/// Membership is taken from the cached groups.
+async fn update_user(
+    redis_server: &RedisServer,
+    args: &SocketListenerArgs,
+    user: &SlackUser,
+) -> Result<(), RedisErrors> {
+    if args.only_groups.is_empty() && args.exclude_groups.is_empty() {
+        return redis_server.update_user(user).await;
+    }
+
+    let groups: BTreeSet<SlackUserGroup> = match redis_server.get_all_user_groups().await {
+        RedisResponse::Ok(groups) => groups.into_iter().collect(),
+        RedisResponse::Missing => BTreeSet::new(),
+        RedisResponse::Err(e) => return Err(e),
+    };
+    match MembershipFilter::new(&groups, &args.only_groups, &args.exclude_groups) {
+        Ok(membership) if membership.allows(&user.id) => redis_server.update_user(user).await,
+        Ok(_) => redis_server.remove_user(&user.id).await,
+        Err(name) => {
+            debug!(
+                "Leaving user {} for the next sync, group {} isn't cached",
+                user.id, name
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Writes a created or updated group. Events that don't list the members keep the cached ones.
+async fn update_group(
+    redis_server: &RedisServer,
+    id: GroupId,
+    name: String,
+    users: Option<BTreeSet<SlackUserId>>,
+) -> Result<(), RedisErrors> {
+    let users = match users {
+        Some(users) => users,
+        None => match cached_group(redis_server, &id).await? {
+            Some(group) => group.users,
+            None => {
+                debug!(
+                    "Leaving group {} for the next sync, its members are unknown",
+                    id
+                );
+                return Ok(());
+            }
+        },
+    };
+
+    let groups = redis_server.screen_groups(single_group(id.clone(), name, users));
+    if groups.is_empty() {
+        return redis_server.remove_user_group(&id).await;
+    }
+    redis_server.insert_user_groups(&groups).await
+}
+
+async fn update_group_members(
+    redis_server: &RedisServer,
+    id: GroupId,
+    added: Vec<UserId>,
+    removed: Vec<UserId>,
+) -> Result<(), RedisErrors> {
+    let group = match cached_group(redis_server, &id).await? {
+        Some(group) => group,
+        None => {
+            debug!(
+                "Leaving group {} for the next sync, it isn't cached yet",
+                id
+            );
+            return Ok(());
+        }
+    };
+
+    let mut users = group.users;
+    for id in removed {
+        users.remove(&SlackUserId { id });
+    }
+    users.extend(added.into_iter().map(|id| SlackUserId { id }));
+
+    // The cached group was screened when it was written, so it's not screened again.
+    let groups = single_group(group.id, group.name, users);
+    redis_server.insert_user_groups(&groups).await
+}
+
+async fn cached_group(
+    redis_server: &RedisServer,
+    id: &GroupId,
+) -> Result<Option<SlackUserGroup>, RedisErrors> {
+    match redis_server.get_user_group_by_id(id).await {
+        RedisResponse::Ok(group) => Ok(Some(group)),
+        RedisResponse::Missing => Ok(None),
+        RedisResponse::Err(e) => Err(e),
+    }
+}
+
+fn single_group(
+    id: GroupId,
+    name: String,
+    users: BTreeSet<SlackUserId>,
+) -> BTreeSet<SlackUserGroup> {
+    let mut groups = BTreeSet::new();
+    groups.insert(SlackUserGroup {
+        id,
+        name,
+        users,
+        previous_names: BTreeSet::new(),
+        synthetic: false,
+    });
+    groups
+}
+
+fn resolve_app_token(args: &SocketListenerArgs) -> Result<String, SecretErrors> {
+    if let Some(path) = &args.slack_app_token_file {
+        return secrets::read_secret_file(path);
+    }
+
+    args.slack_app_token
+        .clone()
+        .ok_or_else(|| SecretErrors::Empty {
+            name: "SLACK_APP_TOKEN".to_owned(),
+        })
+}
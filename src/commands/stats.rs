@@ -0,0 +1,16 @@
+use crate::error::CliErrors;
+use crate::libs::RedisServer;
+use crate::StatsArgs;
+
+pub async fn cache_stats(args: &StatsArgs) -> Result<(), CliErrors> {
+    let redis_server = RedisServer::new(&args.redis_opts.redis_address)
+        .await?
+        .with_key_layout(args.key_opts.layout());
+    let stats = redis_server.cache_stats(args.largest).await?;
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&stats).expect("cache stats are always serializable")
+    );
+    Ok(())
+}
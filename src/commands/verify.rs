@@ -0,0 +1,90 @@
+use tracing::info;
+
+use crate::error::CliErrors;
+use crate::libs::RedisServer;
+use crate::VerifyArgs;
+
+pub async fn verify(args: &VerifyArgs) -> Result<(), CliErrors> {
+    let redis_server = RedisServer::new(&args.redis_opts.redis_address)
+        .await?
+        .with_key_layout(args.key_opts.layout())
+        .with_email_hasher(args.privacy_opts.email_hasher())
+        .with_email_aliases(args.email_alias_rules.clone())
+        .with_value_format(args.value_format);
+
+    // Keys are only right or wrong for the options they were written with, so repairing with
+    // any others would delete and rewrite good ones.
+    if args.repair {
+        let synced = redis_server
+            .get_cache_settings()
+            .await?
+            .ok_or(CliErrors::NoCacheSettings)?;
+        let differences = redis_server.cache_settings().differences(&synced);
+        if !differences.is_empty() {
+            return Err(CliErrors::CacheSettingsMismatch {
+                options: differences.join(", "),
+            });
+        }
+    }
+
+    let report = redis_server.verify_email_keys(args.repair).await?;
+    let problems =
+        report.orphaned.len() + report.stale.len() + report.mismatched.len() + report.missing.len();
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).expect("reports are always serializable")
+    );
+    if args.repair {
+        info!("Repaired {} email keys", problems);
+    } else if problems > 0 {
+        info!(
+            "Found {} inconsistent email keys. Run again with --repair to fix them",
+            problems
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Clap;
+
+    use super::*;
+    use crate::commands::fake_redis::FakeRedis;
+    use crate::libs::EmailHasher;
+
+    #[tokio::test]
+    async fn repairs_are_refused_unless_the_options_match_the_last_sync() {
+        let redis = FakeRedis::start().await;
+        let address = redis.address();
+        let args = |options: &[&str]| {
+            let base = ["verify", "--redis-address", address.as_str(), "--repair"];
+            let argv = base.iter().chain(options).map(|arg| arg.to_string());
+            VerifyArgs::parse_from(argv)
+        };
+
+        let unsynced = verify(&args(&["--email-hash-salt", "salt"])).await;
+        assert!(matches!(unsynced, Err(CliErrors::NoCacheSettings)));
+
+        RedisServer::new(&[address.clone()])
+            .await
+            .unwrap()
+            .with_email_hasher(Some(EmailHasher::new("salt")))
+            .set_cache_settings()
+            .await
+            .unwrap();
+
+        let unhashed = verify(&args(&[])).await;
+        assert!(matches!(
+            unhashed,
+            Err(CliErrors::CacheSettingsMismatch { options }) if options == "--email-hash-salt"
+        ));
+        let other_salt = verify(&args(&["--email-hash-salt", "pepper"])).await;
+        assert!(matches!(
+            other_salt,
+            Err(CliErrors::CacheSettingsMismatch { .. })
+        ));
+        verify(&args(&["--email-hash-salt", "salt"])).await.unwrap();
+    }
+}
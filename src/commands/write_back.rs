@@ -0,0 +1,124 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tracing::{info, warn};
+
+use crate::error::{CliErrors, SecretErrors};
+use crate::libs::secrets;
+use crate::libs::slack::FetchedGroups;
+use crate::libs::write_back::{self, PlannedChange, WriteBackPlan};
+use crate::libs::{RedisServer, SlackApi, SlackClientConfig, SlackUserGroup};
+use crate::WriteBackArgs;
+
+/// Works out what writing back synthetic groups would change in Slack, prints it and saves it
+/// for `apply`, replacing any plan saved before.
+pub async fn plan_write_back(args: &WriteBackArgs) -> Result<(), CliErrors> {
+    let (redis_server, slack_api) = connect(args).await?;
+    let (_, changes) = current_plan(&redis_server, &slack_api).await?;
+    if changes.is_empty() {
+        redis_server.remove_write_back_plan().await?;
+        println!("Slack already matches the synthetic groups, there's nothing to apply");
+        return Ok(());
+    }
+
+    let created_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time went backwards")
+        .as_secs();
+    let plan = WriteBackPlan::new(created_at, changes);
+    redis_server.set_write_back_plan(&plan).await?;
+
+    println!("Plan {} makes {} changes:", plan.id, plan.changes.len());
+    for change in &plan.changes {
+        println!("{}", change.describe());
+    }
+    println!("Run `apply` to make them");
+    Ok(())
+}
+
+/// Makes the changes of the saved plan, as long as planning again comes up with the same ones.
+pub async fn apply_write_back(args: &WriteBackArgs) -> Result<(), CliErrors> {
+    let (redis_server, slack_api) = connect(args).await?;
+    let saved = redis_server
+        .get_write_back_plan()
+        .await?
+        .ok_or(CliErrors::NoWriteBackPlan)?;
+
+    let (synthetic, changes) = current_plan(&redis_server, &slack_api).await?;
+    if changes != saved.changes {
+        return Err(CliErrors::StaleWriteBackPlan { id: saved.id });
+    }
+    // Another `apply` got to it first.
+    if !redis_server.remove_write_back_plan().await? {
+        return Err(CliErrors::NoWriteBackPlan);
+    }
+
+    info!(
+        "Applying plan {} of {} changes",
+        saved.id,
+        saved.changes.len()
+    );
+    let failed =
+        write_back::apply(&slack_api, &redis_server, &synthetic, &saved.changes, false).await;
+    if failed > 0 {
+        return Err(CliErrors::WriteBackFailed {
+            failed,
+            total: saved.changes.len(),
+        });
+    }
+
+    info!("Applied plan {}", saved.id);
+    Ok(())
+}
+
+async fn connect(args: &WriteBackArgs) -> Result<(RedisServer, SlackApi), CliErrors> {
+    let redis_server = RedisServer::new(&args.redis_opts.redis_address)
+        .await?
+        .with_key_layout(args.key_opts.layout());
+
+    let slack_token = match &args.slack_token_file {
+        Some(path) => secrets::read_secret_file(path)?,
+        None => args
+            .slack_token
+            .clone()
+            .ok_or_else(|| SecretErrors::Empty {
+                name: "SLACK_BOT_TOKEN".to_owned(),
+            })?,
+    };
+    let slack_api = SlackApi::new(
+        &slack_token,
+        &SlackClientConfig {
+            connect_timeout: Duration::from_secs(args.slack_connect_timeout),
+            request_timeout: Duration::from_secs(args.slack_request_timeout),
+            tcp_keepalive: Duration::from_secs(args.slack_tcp_keepalive),
+            rotation: None,
+            usage: Arc::default(),
+        },
+    )?;
+
+    Ok((redis_server, slack_api))
+}
+
+/// The synthetic groups, along with the changes to Slack that would write them back as things
+/// stand. Members saved by the last sync are reused for groups that haven't changed since.
+async fn current_plan(
+    redis_server: &RedisServer,
+    slack_api: &SlackApi,
+) -> Result<(Vec<SlackUserGroup>, Vec<PlannedChange>), CliErrors> {
+    let synthetic = redis_server.get_synthetic_groups().await?;
+    let mirrors = redis_server.get_synthetic_group_mirrors().await?;
+    let fetched = match redis_server.get_fetched_groups().await {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            warn!(
+                "Unable to read group members from the last sync, fetching them all. Error: {}",
+                e
+            );
+            FetchedGroups::new()
+        }
+    };
+    let (groups, _) = slack_api.list_user_groups_since(&fetched).await?;
+
+    let changes = write_back::plan(&synthetic, &groups, &mirrors);
+    Ok((synthetic, changes))
+}
@@ -1,4 +1,5 @@
 use anyhow::Error as AnyhowError;
+use mobc_redis::redis::RedisError;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -8,16 +9,400 @@ pub enum CliErrors {
 
     #[error(transparent)]
     Slack(#[from] SlackErrors),
+
+    #[error(transparent)]
+    Secret(#[from] SecretErrors),
+
+    #[error(transparent)]
+    Audit(#[from] AuditErrors),
+
+    #[error(transparent)]
+    AccessLog(#[from] AccessLogErrors),
+
+    #[error(transparent)]
+    Oidc(#[from] OidcErrors),
+
+    #[error(transparent)]
+    Oncall(#[from] OncallErrors),
+
+    #[cfg(feature = "transform")]
+    #[error(transparent)]
+    Transform(#[from] TransformErrors),
+
+    #[error(transparent)]
+    Directory(#[from] DirectoryErrors),
+
+    #[error(transparent)]
+    Github(#[from] GithubErrors),
+
+    #[error(transparent)]
+    Backfill(#[from] BackfillErrors),
+
+    #[error(transparent)]
+    Kubernetes(#[from] KubernetesErrors),
+
+    #[error("No user group is named {name}")]
+    UnknownGroup { name: String },
+
+    #[error(
+        "Only fetched {fetched} users, down from {previous} last sync. Check the Slack app's scopes, or pass --force to sync anyway"
+    )]
+    UsersShrank { fetched: u64, previous: u64 },
+
+    #[error("all-in-one needs --schedule or --interval, so the updater keeps running")]
+    UnscheduledUpdater,
+
+    #[error("Staged sync failed validation: {reason}")]
+    InvalidStagedSync { reason: String },
+
+    #[error("{dependency} failed its startup check, not syncing")]
+    DependencyUnavailable {
+        dependency: String,
+        #[source]
+        source: AnyhowError,
+    },
+
+    #[error("Unable to write export to {path}")]
+    UnableToWriteExport {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("No sync has saved the options the cache was written with, run `update-redis` first")]
+    NoCacheSettings,
+
+    #[error("Not repairing, as the cache was synced with a different {options}")]
+    CacheSettingsMismatch { options: String },
+
+    #[error("No write-back plan is saved, run `plan` first")]
+    NoWriteBackPlan,
+
+    #[error("Slack or the synthetic groups changed since plan {id} was made, run `plan` again")]
+    StaleWriteBackPlan { id: String },
+
+    #[error("Unable to write back {failed} of {total} synthetic groups")]
+    WriteBackFailed { failed: usize, total: usize },
+
+    #[error("Unable to read from the terminal")]
+    Shell {
+        #[source]
+        source: AnyhowError,
+    },
+
+    #[error("Unable to serve on {address}")]
+    Serve {
+        address: String,
+        #[source]
+        source: AnyhowError,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum DirectoryErrors {
+    #[error("Unable to list users and groups from {directory}")]
+    UnableToList {
+        directory: String,
+        #[source]
+        source: AnyhowError,
+    },
+    #[error("{directory} is already the primary source, it can't be an extra source")]
+    AlreadySynced { directory: String },
+    #[error("Unable to read credentials from {path}")]
+    UnableToReadCredentials {
+        path: String,
+        #[source]
+        source: AnyhowError,
+    },
+    #[error("No value provided for {name}, which {directory} needs")]
+    NotConfigured { directory: String, name: String },
+    #[error("Unable to build the HTTP client for {directory}")]
+    UnableToBuildClient {
+        directory: String,
+        #[source]
+        source: reqwest::Error,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum OidcErrors {
+    #[error("Unable to fetch {url}")]
+    UnableToFetch {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("Unable to parse response from {url}")]
+    MalformedResponse {
+        url: String,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+#[cfg(feature = "transform")]
+#[derive(Debug, Error)]
+pub enum TransformErrors {
+    #[error("Unable to load transform script {path}: {message}")]
+    UnableToCompile { path: String, message: String },
+}
+
+#[derive(Debug, Error)]
+pub enum GithubErrors {
+    #[error("Unable to read GitHub logins from {path}")]
+    UnableToRead {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Invalid entry on line {line} of {path}: {message}")]
+    Malformed {
+        path: String,
+        line: usize,
+        message: String,
+    },
+    #[error("No value provided for {name}")]
+    NotConfigured { name: String },
+    #[error("Unable to build the HTTP client for GitHub logins")]
+    UnableToBuildClient {
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("Unable to fetch GitHub logins from {service}")]
+    UnableToFetch {
+        service: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("Unable to parse response from {service}")]
+    MalformedResponse {
+        service: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Unexpected response from {service}: {message}")]
+    UnexpectedResponse { service: String, message: String },
+}
+
+#[derive(Debug, Error)]
+pub enum BackfillErrors {
+    #[error("Unable to read emails to backfill from {path}")]
+    UnableToRead {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Invalid entry on line {line} of {path}: {message}")]
+    Malformed {
+        path: String,
+        line: usize,
+        message: String,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum OncallErrors {
+    #[error("Unable to read on-call schedules from {path}")]
+    UnableToRead {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Invalid entry on line {line} of {path}: {message}")]
+    Malformed {
+        path: String,
+        line: usize,
+        message: String,
+    },
+    #[error("No API key configured for {provider}")]
+    NotConfigured { provider: String },
+    #[error("Unable to build the on-call HTTP client")]
+    UnableToBuildClient {
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("Unable to ask {provider} who is on call")]
+    UnableToFetch {
+        provider: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("Unable to parse response from {provider}")]
+    MalformedResponse {
+        provider: String,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum SyntheticGroupErrors {
+    #[error("No synthetic group has id {id}")]
+    UnknownGroup { id: String },
+    #[error("A group is already named {name}")]
+    NameTaken { name: String },
+    #[error(transparent)]
+    Redis(#[from] RedisErrors),
+}
+
+impl CliErrors {
+    /// The error followed by each of its sources, outermost first.
+    pub fn chain(&self) -> Vec<String> {
+        let mut chain = vec![self.to_string()];
+        let mut source = std::error::Error::source(self);
+        while let Some(cause) = source {
+            chain.push(cause.to_string());
+            source = cause.source();
+        }
+        chain
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum AlertErrors {
+    #[error("Unable to send alert to {notifier}")]
+    UnableToSend {
+        notifier: String,
+        #[source]
+        source: reqwest::Error,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum KubernetesErrors {
+    #[error("Not running in Kubernetes, KUBERNETES_SERVICE_HOST isn't set")]
+    NotInCluster,
+    #[error("Unable to read {path} from the pod's service account")]
+    UnableToRead {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Unable to load the cluster's CA from {path}")]
+    InvalidCertificate {
+        path: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("Unable to set up a client for the Kubernetes API")]
+    UnableToConnect {
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("Kubernetes API request for {resource} failed")]
+    Request {
+        resource: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("Unable to parse {resource} from the Kubernetes API")]
+    MalformedResponse {
+        resource: String,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum AuditErrors {
+    #[error("Unable to open audit log {path}")]
+    UnableToOpen {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum AccessLogErrors {
+    #[error("Unable to open access log {path}")]
+    UnableToOpen {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum SecretErrors {
+    #[error("Unable to read secret from {path}")]
+    UnableToRead {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("No value provided for {name}")]
+    Empty { name: String },
+    #[error("Invalid entry on line {line} of {path}: {message}")]
+    Malformed {
+        path: String,
+        line: usize,
+        message: String,
+    },
+    #[cfg(feature = "vault")]
+    #[error("Invalid secret reference {reference}, expected <path>#<field>")]
+    InvalidReference { reference: String },
+    #[cfg(feature = "vault")]
+    #[error("Unable to fetch secret {reference}")]
+    UnableToFetch {
+        reference: String,
+        #[source]
+        source: AnyhowError,
+    },
 }
 
 #[derive(Debug, Error)]
 pub enum SlackErrors {
-    #[error("Unable to fetch from Slack")]
-    UnableToFetch,
+    #[error("Timed out waiting for Slack to respond to {method}")]
+    Timeout { method: String },
+    #[error("Slack rate limited {method}, retry after {retry_after}s")]
+    RateLimited { method: String, retry_after: u64 },
+    #[error("Slack rejected {method}: {error}")]
+    Api { method: String, error: String },
+    #[error("Made the {budget} Slack API calls allowed, stopping before calling {method}")]
+    BudgetExceeded { method: String, budget: u64 },
+    #[error("Unable to call Slack {method}")]
+    Request {
+        method: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("Unable to parse Slack response to {method}")]
+    MalformedResponse {
+        method: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Parsing the Slack response to {method} didn't finish")]
+    UnableToParse {
+        method: String,
+        #[source]
+        source: tokio::task::JoinError,
+    },
+    #[error("Unable to store Slack token in {path}")]
+    UnableToStoreToken {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Unable to build Slack HTTP client")]
+    UnableToBuildClient {
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("Slack Socket Mode connection failed")]
+    SocketMode {
+        #[source]
+        source: AnyhowError,
+    },
 }
 
 #[derive(Debug, Error)]
 pub enum RedisErrors {
+    #[error("No Redis address was given")]
+    NoServers,
+    #[error("Read replicas can't be used while keys are sharded across several Redis servers")]
+    ReplicasWithShards,
     #[error("Unable to connect to {address}")]
     UnableToConnect {
         address: String,
@@ -36,6 +421,12 @@ pub enum RedisErrors {
         #[source]
         source: AnyhowError,
     },
+    #[error("Unable to delete {key} from redis")]
+    UnableToDelete {
+        key: String,
+        #[source]
+        source: AnyhowError,
+    },
     #[error("Unable to set {key} to expire")]
     UnableToExpire {
         key: String,
@@ -55,3 +446,27 @@ pub enum RedisErrors {
         source: AnyhowError,
     },
 }
+
+impl RedisErrors {
+    /// Whether trying again could work, as Redis couldn't be reached rather than turning down
+    /// what it was asked.
+    pub fn is_transient(&self) -> bool {
+        let source = match self {
+            RedisErrors::UnableToConnect { .. } => return true,
+            RedisErrors::UnableToSet { source, .. }
+            | RedisErrors::UnableToGet { source, .. }
+            | RedisErrors::UnableToDelete { source, .. }
+            | RedisErrors::UnableToExpire { source, .. } => source,
+            _ => return false,
+        };
+        match source.downcast_ref::<RedisError>() {
+            Some(e) => {
+                e.is_io_error()
+                    || e.is_timeout()
+                    || e.is_connection_dropped()
+                    || e.is_connection_refusal()
+            }
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::fmt::{self, Write as _};
+use std::fs::OpenOptions;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::error::AccessLogErrors;
+
+/// Target that sends the access log to stdout rather than a file.
+const STDOUT_TARGET: &str = "-";
+
+/// Layouts the access log can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// Common Log Format, `host ident user [time] "request" status bytes`.
+    Common,
+    /// Combined Log Format, Common with the quoted referer and user agent after it.
+    Combined,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "common" => Ok(AccessLogFormat::Common),
+            "combined" => Ok(AccessLogFormat::Combined),
+            other => Err(format!(
+                "unknown access log format {}, expected common or combined",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AccessLogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessLogFormat::Common => f.write_str("common"),
+            AccessLogFormat::Combined => f.write_str("combined"),
+        }
+    }
+}
+
+/// One request as the access log describes it.
+#[derive(Debug, Clone)]
+pub struct AccessEntry {
+    pub time: DateTime<Utc>,
+    pub remote_addr: Option<SocketAddr>,
+    pub method: String,
+    /// HTTP version, like `HTTP/1.1` or `HTTP/2.0`.
+    pub protocol: String,
+    /// Path along with the query string, if there was one.
+    pub target: String,
+    pub status: u16,
+    /// Body size, when it's known up front. Streamed responses have none.
+    pub bytes: Option<u64>,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    pub elapsed: Duration,
+}
+
+impl AccessEntry {
+    /// The entry as a line in `format`, with the time taken in microseconds on the end, as
+    /// Apache's `%D` writes it.
+    pub fn to_line(&self, format: AccessLogFormat) -> String {
+        let mut line = format!(
+            "{} - - [{}] \"{} {} {}\" {} {}",
+            self.remote_addr
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_else(|| "-".to_owned()),
+            self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            escape(&self.method),
+            escape(&self.target),
+            self.protocol,
+            self.status,
+            self.bytes
+                .map(|bytes| bytes.to_string())
+                .unwrap_or_else(|| "-".to_owned()),
+        );
+        if format == AccessLogFormat::Combined {
+            let quoted = |value: &Option<String>| value.as_deref().map_or("-".to_owned(), escape);
+            let _ = write!(
+                line,
+                " \"{}\" \"{}\"",
+                quoted(&self.referer),
+                quoted(&self.user_agent)
+            );
+        }
+        let _ = write!(line, " {}", self.elapsed.as_micros());
+        line
+    }
+}
+
+/// Escapes quotes, backslashes and control characters the way Apache does, so a header can't
+/// break a line apart.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\x{:02x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Apache-style access log, kept apart from the tracing output so tools that read access logs
+/// get nothing else. Like the audit log, lines are written in the background and a failed write
+/// is logged and dropped.
+#[derive(Debug, Clone, Default)]
+pub struct AccessLog {
+    sender: Option<mpsc::UnboundedSender<AccessEntry>>,
+}
+
+impl AccessLog {
+    /// Starts writing to `target`, a file lines are appended to, or `-` for stdout.
+    pub fn start(target: &str, format: AccessLogFormat) -> Result<Self, AccessLogErrors> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        if target == STDOUT_TARGET {
+            info!("Writing access log to stdout");
+            tokio::spawn(write_entries(tokio::io::stdout(), format, receiver));
+        } else {
+            let file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(target)
+                .map_err(|e| AccessLogErrors::UnableToOpen {
+                    path: target.to_owned(),
+                    source: e,
+                })?;
+            info!("Writing access log to {}", target);
+            tokio::spawn(write_entries(
+                tokio::fs::File::from_std(file),
+                format,
+                receiver,
+            ));
+        }
+
+        Ok(Self {
+            sender: Some(sender),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    pub fn record(&self, entry: AccessEntry) {
+        if let Some(sender) = &self.sender {
+            if sender.send(entry).is_err() {
+                warn!("Access log writer has stopped, dropping entry");
+            }
+        }
+    }
+}
+
+async fn write_entries<W>(
+    mut sink: W,
+    format: AccessLogFormat,
+    mut receiver: mpsc::UnboundedReceiver<AccessEntry>,
+) where
+    W: AsyncWrite + Unpin,
+{
+    while let Some(entry) = receiver.recv().await {
+        let mut line = entry.to_line(format);
+        line.push('\n');
+        let result = match sink.write_all(line.as_bytes()).await {
+            Ok(()) => sink.flush().await,
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = result {
+            warn!("Unable to write access log entry. Error: {}", e);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use derivative::Derivative;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::error::AlertErrors;
+
+const PAGERDUTY_EVENTS_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+const OPSGENIE_ALERTS_URL: &str = "https://api.opsgenie.com/v2/alerts";
+
+/// Somewhere incidents about failing syncs are opened and closed.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub enum Notifier {
+    /// PagerDuty Events API v2.
+    PagerDuty {
+        #[derivative(Debug = "ignore")]
+        routing_key: String,
+        client: reqwest::Client,
+    },
+    /// Opsgenie Alert API, with the dedup key as the alert's alias.
+    Opsgenie {
+        #[derivative(Debug = "ignore")]
+        api_key: String,
+        client: reqwest::Client,
+    },
+}
+
+impl Notifier {
+    pub fn pagerduty(routing_key: &str) -> Self {
+        Notifier::PagerDuty {
+            routing_key: routing_key.to_owned(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn opsgenie(api_key: &str) -> Self {
+        Notifier::Opsgenie {
+            api_key: api_key.to_owned(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Notifier::PagerDuty { .. } => "PagerDuty",
+            Notifier::Opsgenie { .. } => "Opsgenie",
+        }
+    }
+
+    /// Opens an incident, or updates the open one with the same `dedup_key`.
+    pub async fn trigger(
+        &self,
+        dedup_key: &str,
+        summary: &str,
+        source: &str,
+        details: &[String],
+    ) -> Result<(), AlertErrors> {
+        match self {
+            Notifier::PagerDuty {
+                routing_key,
+                client,
+            } => {
+                let event = json!({
+                    "routing_key": routing_key,
+                    "event_action": "trigger",
+                    "dedup_key": dedup_key,
+                    "payload": {
+                        "summary": summary,
+                        "source": source,
+                        "severity": "error",
+                        "custom_details": { "errors": details }
+                    }
+                });
+                self.send_pagerduty(client, &event).await
+            }
+            Notifier::Opsgenie { api_key, client } => {
+                let alert = json!({
+                    "message": summary,
+                    "alias": dedup_key,
+                    "source": source,
+                    "description": details.join("\n"),
+                    "priority": "P2"
+                });
+                self.send_opsgenie(client, api_key, OPSGENIE_ALERTS_URL, &alert)
+                    .await
+            }
+        }
+    }
+
+    pub async fn resolve(&self, dedup_key: &str) -> Result<(), AlertErrors> {
+        match self {
+            Notifier::PagerDuty {
+                routing_key,
+                client,
+            } => {
+                let event = json!({
+                    "routing_key": routing_key,
+                    "event_action": "resolve",
+                    "dedup_key": dedup_key
+                });
+                self.send_pagerduty(client, &event).await
+            }
+            Notifier::Opsgenie { api_key, client } => {
+                let url = format!(
+                    "{}/{}/close?identifierType=alias",
+                    OPSGENIE_ALERTS_URL,
+                    utf8_percent_encode(dedup_key, NON_ALPHANUMERIC)
+                );
+                self.send_opsgenie(client, api_key, &url, &json!({})).await
+            }
+        }
+    }
+
+    async fn send_pagerduty(
+        &self,
+        client: &reqwest::Client,
+        event: &serde_json::Value,
+    ) -> Result<(), AlertErrors> {
+        client
+            .post(PAGERDUTY_EVENTS_URL)
+            .header(CONTENT_TYPE, "application/json")
+            .body(event.to_string())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| AlertErrors::UnableToSend {
+                notifier: self.name().to_owned(),
+                source: e,
+            })?;
+
+        Ok(())
+    }
+
+    async fn send_opsgenie(
+        &self,
+        client: &reqwest::Client,
+        api_key: &str,
+        url: &str,
+        body: &serde_json::Value,
+    ) -> Result<(), AlertErrors> {
+        client
+            .post(url)
+            .header(AUTHORIZATION, format!("GenieKey {}", api_key))
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| AlertErrors::UnableToSend {
+                notifier: self.name().to_owned(),
+                source: e,
+            })?;
+
+        Ok(())
+    }
+}
+
+/// Counts consecutive failed syncs, opening an incident once there are `threshold` in a row and
+/// resolving it after the next successful sync.
+#[derive(Debug)]
+pub struct FailureTracker {
+    notifiers: Vec<Notifier>,
+    threshold: u32,
+    source: String,
+    consecutive_failures: u32,
+    triggered: bool,
+}
+
+impl FailureTracker {
+    pub fn new(notifiers: Vec<Notifier>, threshold: u32, source: &str) -> Self {
+        Self {
+            notifiers,
+            threshold,
+            source: source.to_owned(),
+            consecutive_failures: 0,
+            triggered: false,
+        }
+    }
+
+    fn dedup_key(&self) -> String {
+        format!("slack-user-cache/{}", self.source)
+    }
+
+    pub async fn record_failure(&mut self, causes: &[String]) {
+        self.consecutive_failures += 1;
+        if self.consecutive_failures < self.threshold || self.notifiers.is_empty() {
+            return;
+        }
+
+        let summary = format!(
+            "Slack user cache sync failed {} times in a row on {}",
+            self.consecutive_failures, self.source
+        );
+        for notifier in &self.notifiers {
+            match notifier
+                .trigger(&self.dedup_key(), &summary, &self.source, causes)
+                .await
+            {
+                Ok(()) => info!("Opened {} incident: {}", notifier.name(), summary),
+                Err(e) => warn!("Unable to open incident. Error: {}", e),
+            }
+        }
+        self.triggered = true;
+    }
+
+    pub async fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        if !self.triggered {
+            return;
+        }
+
+        for notifier in &self.notifiers {
+            match notifier.resolve(&self.dedup_key()).await {
+                Ok(()) => info!("Resolved {} incident", notifier.name()),
+                Err(e) => warn!("Unable to resolve incident. Error: {}", e),
+            }
+        }
+        self.triggered = false;
+    }
+}
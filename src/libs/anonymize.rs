@@ -0,0 +1,98 @@
+use std::collections::BTreeSet;
+
+use derivative::Derivative;
+use hmac::{Hmac, Mac, NewMac};
+use serde::Serialize;
+use sha2::Sha256;
+
+use super::slack::{GroupId, SlackUser, SlackUserGroup, UserId};
+
+/// Hex digits kept of each HMAC. 64 bits keeps collisions out of reach for any workspace.
+const PSEUDONYM_LENGTH: usize = 16;
+
+/// Domain of the emails users are given in place of their own. `.invalid` is reserved, so mail
+/// sent to them can't reach anyone.
+const PSEUDONYM_EMAIL_DOMAIN: &str = "anonymized.invalid";
+
+/// Stands users in for pseudonyms: HMACs of their ids under a key. The same key gives the same
+/// pseudonyms export after export, so exports can be compared, while without the key they can't
+/// be traced back to anyone.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct Pseudonymizer {
+    #[derivative(Debug = "ignore")]
+    key: Vec<u8>,
+}
+
+/// A user with everything that could identify them replaced or left out. Only what says where
+/// they sit in the organization is kept.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Serialize)]
+pub struct AnonymousUser {
+    pub id: String,
+    pub name: String,
+    pub email: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tz: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub external: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub guest: bool,
+}
+
+/// A group with its members given by their pseudonyms.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Serialize)]
+pub struct AnonymousGroup {
+    pub id: GroupId,
+    pub name: String,
+    pub users: BTreeSet<String>,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub previous_names: BTreeSet<String>,
+}
+
+impl Pseudonymizer {
+    pub fn new(key: &str) -> Self {
+        Self {
+            key: key.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn pseudonym(&self, id: &UserId) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(id.as_str().as_bytes());
+        let mut pseudonym = hex::encode(mac.finalize().into_bytes());
+        pseudonym.truncate(PSEUDONYM_LENGTH);
+        pseudonym
+    }
+
+    /// `user` under their pseudonym, which also stands in for their name and email. Avatars,
+    /// annotations and GitHub logins are left out.
+    pub fn user(&self, user: &SlackUser) -> AnonymousUser {
+        let pseudonym = self.pseudonym(&user.id);
+        AnonymousUser {
+            name: format!("user-{}", pseudonym),
+            email: format!("{}@{}", pseudonym, PSEUDONYM_EMAIL_DOMAIN),
+            id: pseudonym,
+            tz: user.tz.clone(),
+            external: user.external,
+            guest: user.guest,
+        }
+    }
+
+    /// `group` with its members under their pseudonyms, so who is in which group still matches
+    /// up with the users.
+    pub fn group(&self, group: &SlackUserGroup) -> AnonymousGroup {
+        AnonymousGroup {
+            id: group.id.clone(),
+            name: group.name.clone(),
+            users: group
+                .users
+                .iter()
+                .map(|user| self.pseudonym(&user.id))
+                .collect(),
+            previous_names: group.previous_names.clone(),
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::fs::OpenOptions;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use super::RedisServer;
+use crate::error::AuditErrors;
+
+/// Redis Stream that audit entries are appended to when the target is `redis`.
+const AUDIT_STREAM_KEY: &str = "audit_log";
+
+/// One API request: who made it, what they asked for and what they got back.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub token_id: String,
+    pub method: String,
+    pub route: String,
+    pub query: String,
+    pub status: u16,
+}
+
+impl AuditEntry {
+    pub fn new(token_id: String, method: &str, route: &str, query: &str, status: u16) -> Self {
+        Self {
+            timestamp: now(),
+            token_id,
+            method: method.to_owned(),
+            route: route.to_owned(),
+            query: query.to_owned(),
+            status,
+        }
+    }
+
+    fn fields(&self) -> Vec<(&str, String)> {
+        vec![
+            ("timestamp", self.timestamp.to_string()),
+            ("token-id", self.token_id.clone()),
+            ("method", self.method.clone()),
+            ("route", self.route.clone()),
+            ("query", self.query.clone()),
+            ("status", self.status.to_string()),
+        ]
+    }
+}
+
+enum AuditSink {
+    File(tokio::fs::File),
+    Redis(Arc<RedisServer>),
+}
+
+/// Append-only record of API access. Entries are written in the background so requests never
+/// wait on the sink; a failed write is logged and dropped.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog {
+    sender: Option<mpsc::UnboundedSender<AuditEntry>>,
+}
+
+impl AuditLog {
+    /// Starts writing to `target`: a file that entries are appended to as JSON lines, or `redis`
+    /// for the `audit_log` stream on `redis_server`.
+    pub fn start(target: &str, redis_server: Arc<RedisServer>) -> Result<Self, AuditErrors> {
+        let sink = if target == "redis" {
+            info!("Writing audit log to Redis stream {}", AUDIT_STREAM_KEY);
+            AuditSink::Redis(redis_server)
+        } else {
+            let file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(target)
+                .map_err(|e| AuditErrors::UnableToOpen {
+                    path: target.to_owned(),
+                    source: e,
+                })?;
+            info!("Writing audit log to {}", target);
+            AuditSink::File(tokio::fs::File::from_std(file))
+        };
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(write_entries(sink, receiver));
+
+        Ok(Self {
+            sender: Some(sender),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    pub fn record(&self, entry: AuditEntry) {
+        if let Some(sender) = &self.sender {
+            if sender.send(entry).is_err() {
+                warn!("Audit log writer has stopped, dropping entry");
+            }
+        }
+    }
+}
+
+async fn write_entries(mut sink: AuditSink, mut receiver: mpsc::UnboundedReceiver<AuditEntry>) {
+    while let Some(entry) = receiver.recv().await {
+        let result = match &mut sink {
+            AuditSink::File(file) => {
+                let mut line = serde_json::to_vec(&entry).unwrap();
+                line.push(b'\n');
+                match file.write_all(&line).await {
+                    Ok(()) => file.flush().await.map_err(anyhow::Error::from),
+                    Err(e) => Err(anyhow::Error::from(e)),
+                }
+            }
+            AuditSink::Redis(redis_server) => redis_server
+                .append_to_stream(AUDIT_STREAM_KEY, &entry.fields(), None)
+                .await
+                .map_err(anyhow::Error::from),
+        };
+
+        if let Err(e) = result {
+            warn!("Unable to write audit log entry. Error: {}", e);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,298 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use derivative::Derivative;
+use tracing::info;
+
+use super::OidcValidator;
+use crate::error::SecretErrors;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    ReadUsers,
+    ReadEmails,
+    ReadGroups,
+    Admin,
+}
+
+impl FromStr for Permission {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read-users" => Ok(Permission::ReadUsers),
+            "read-emails" => Ok(Permission::ReadEmails),
+            "read-groups" => Ok(Permission::ReadGroups),
+            "admin" => Ok(Permission::Admin),
+            other => Err(format!("unknown permission {}", other)),
+        }
+    }
+}
+
+/// Permissions of tokens listed without any, so plain token files keep working.
+const DEFAULT_PERMISSIONS: &[Permission] = &[
+    Permission::ReadUsers,
+    Permission::ReadEmails,
+    Permission::ReadGroups,
+];
+
+/// A window requests are counted over for quotas. Windows are aligned to the epoch, so every
+/// server counts the same hour and day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QuotaWindow {
+    Hour,
+    Day,
+}
+
+impl QuotaWindow {
+    pub fn name(self) -> &'static str {
+        match self {
+            QuotaWindow::Hour => "hour",
+            QuotaWindow::Day => "day",
+        }
+    }
+
+    pub fn seconds(self) -> u64 {
+        match self {
+            QuotaWindow::Hour => 60 * 60,
+            QuotaWindow::Day => 24 * 60 * 60,
+        }
+    }
+}
+
+impl FromStr for QuotaWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hour" => Ok(QuotaWindow::Hour),
+            "day" => Ok(QuotaWindow::Day),
+            other => Err(format!(
+                "unknown quota window {}, expected hour or day",
+                other
+            )),
+        }
+    }
+}
+
+/// Most requests a token may make in each window, written as `hour=1000,day=20000`. Windows
+/// without a limit aren't counted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Quota {
+    limits: BTreeMap<QuotaWindow, u64>,
+}
+
+impl Quota {
+    pub fn limits(&self) -> impl Iterator<Item = (QuotaWindow, u64)> + '_ {
+        self.limits.iter().map(|(window, limit)| (*window, *limit))
+    }
+}
+
+impl FromStr for Quota {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut limits = BTreeMap::new();
+        for limit in s
+            .split(',')
+            .map(str::trim)
+            .filter(|limit| !limit.is_empty())
+        {
+            let mut parts = limit.splitn(2, '=');
+            let window: QuotaWindow = parts.next().unwrap_or_default().trim().parse()?;
+            let requests = parts
+                .next()
+                .and_then(|requests| requests.trim().parse::<u64>().ok())
+                .ok_or_else(|| format!("expected `{}=<requests>`", window.name()))?;
+            limits.insert(window, requests);
+        }
+        Ok(Quota { limits })
+    }
+}
+
+/// The caller behind a request, identified by the id of its API token.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub id: String,
+    permissions: BTreeSet<Permission>,
+    quota: Quota,
+}
+
+impl Principal {
+    pub fn new(id: String, permissions: BTreeSet<Permission>) -> Self {
+        Self {
+            id,
+            permissions,
+            quota: Quota::default(),
+        }
+    }
+
+    /// Someone in the workspace using a slash command, who may see what a token listed without
+    /// permissions can.
+    pub fn slack_user(user_id: &str) -> Self {
+        Self::new(
+            format!("slack:{}", user_id),
+            DEFAULT_PERMISSIONS.iter().copied().collect(),
+        )
+    }
+
+    /// Used when authentication is disabled. It can read everything but isn't an admin, as
+    /// admin routes always need a token.
+    pub fn anonymous() -> Self {
+        Self::new(
+            "anonymous".to_owned(),
+            DEFAULT_PERMISSIONS.iter().copied().collect(),
+        )
+    }
+
+    pub fn has(&self, permission: Permission) -> bool {
+        self.permissions.contains(&Permission::Admin) || self.permissions.contains(&permission)
+    }
+
+    /// How many requests the caller may make. Only tokens from the tokens file can have one.
+    pub fn quota(&self) -> &Quota {
+        &self.quota
+    }
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct ApiToken {
+    #[derivative(Debug = "ignore")]
+    token: String,
+    principal: Principal,
+}
+
+/// Bearer tokens accepted by the web server, either listed in a file or issued by an OIDC
+/// provider. With neither configured authentication is disabled.
+#[derive(Debug, Default)]
+pub struct ApiTokens {
+    tokens: RwLock<Vec<ApiToken>>,
+    oidc: Option<Arc<OidcValidator>>,
+}
+
+impl ApiTokens {
+    /// Reads one token per line as `<id> <token> <permission>,... <quota>`, skipping blank lines
+    /// and `#` comments. A line with only a token gets read access to users, emails and groups,
+    /// and one without a quota isn't limited.
+    pub fn load(path: &Path) -> Result<Self, SecretErrors> {
+        let tokens = Self::default();
+        tokens.reload(path)?;
+        Ok(tokens)
+    }
+
+    /// Also accept JWTs validated by `oidc`.
+    pub fn with_oidc(mut self, oidc: Option<Arc<OidcValidator>>) -> Self {
+        self.oidc = oidc;
+        self
+    }
+
+    pub fn reload(&self, path: &Path) -> Result<(), SecretErrors> {
+        let contents = fs::read_to_string(path).map_err(|e| SecretErrors::UnableToRead {
+            path: path.display().to_string(),
+            source: e,
+        })?;
+
+        let mut tokens = Vec::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let token = parse_line(index + 1, line).map_err(|message| SecretErrors::Malformed {
+                path: path.display().to_string(),
+                line: index + 1,
+                message,
+            })?;
+            tokens.push(token);
+        }
+
+        if tokens.is_empty() {
+            return Err(SecretErrors::Empty {
+                name: path.display().to_string(),
+            });
+        }
+
+        info!("Loaded {} API tokens from {}", tokens.len(), path.display());
+        *self.tokens.write().unwrap() = tokens;
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.oidc.is_some() || !self.tokens.read().unwrap().is_empty()
+    }
+
+    /// Finds the caller for an `Authorization` header value.
+    pub fn authenticate(&self, header: Option<&str>) -> Option<Principal> {
+        if !self.is_enabled() {
+            return Some(Principal::anonymous());
+        }
+
+        let presented = header
+            .and_then(|value| value.strip_prefix("Bearer "))?
+            .trim();
+
+        if let Some(principal) = self.oidc.as_ref().and_then(|oidc| oidc.validate(presented)) {
+            return Some(principal);
+        }
+
+        self.tokens
+            .read()
+            .unwrap()
+            .iter()
+            .find(|token| constant_time_eq(token.token.as_bytes(), presented.as_bytes()))
+            .map(|token| token.principal.clone())
+    }
+}
+
+fn parse_line(line_number: usize, line: &str) -> Result<ApiToken, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let (id, token, permissions, quota) = match fields.as_slice() {
+        [token] => (format!("token-{}", line_number), *token, None, None),
+        [id, token] => ((*id).to_owned(), *token, None, None),
+        [id, token, permissions] => ((*id).to_owned(), *token, Some(*permissions), None),
+        [id, token, permissions, quota] => {
+            ((*id).to_owned(), *token, Some(*permissions), Some(*quota))
+        }
+        _ => return Err("expected `<id> <token> <permission>,... <quota>`".to_owned()),
+    };
+
+    let permissions = match permissions {
+        None => DEFAULT_PERMISSIONS.iter().copied().collect(),
+        Some(permissions) => permissions
+            .split(',')
+            .map(str::trim)
+            .filter(|permission| !permission.is_empty())
+            .map(Permission::from_str)
+            .collect::<Result<BTreeSet<_>, _>>()?,
+    };
+
+    let quota = match quota {
+        None => Quota::default(),
+        Some(quota) => quota.parse()?,
+    };
+
+    Ok(ApiToken {
+        token: token.to_owned(),
+        principal: Principal {
+            id,
+            permissions,
+            quota,
+        },
+    })
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
@@ -0,0 +1,105 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use rand::seq::SliceRandom;
+use tracing::{info, warn};
+
+use super::directory::SlackDirectory;
+use super::email::Email;
+use super::redact;
+use super::slack::SlackUser;
+use crate::error::{BackfillErrors, SlackErrors};
+
+/// Reads the emails of users expected to be in Slack, one per line. Blank lines and lines
+/// starting with `#` are skipped, so the file can be an export from LDAP with a header.
+pub fn load_emails(path: &Path) -> Result<Vec<Email>, BackfillErrors> {
+    let contents = fs::read_to_string(path).map_err(|e| BackfillErrors::UnableToRead {
+        path: path.display().to_string(),
+        source: e,
+    })?;
+
+    let mut emails = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let email: Email = line.parse().map_err(|message| BackfillErrors::Malformed {
+            path: path.display().to_string(),
+            line: index + 1,
+            message,
+        })?;
+        emails.push(email);
+    }
+
+    info!(
+        "Loaded {} emails to backfill from {}",
+        emails.len(),
+        path.display()
+    );
+    Ok(emails)
+}
+
+/// The users a backfill found, and the emails Slack had no user for.
+#[derive(Debug, Default)]
+pub struct Backfill {
+    pub found: BTreeSet<SlackUser>,
+    pub not_found: Vec<Email>,
+}
+
+/// The emails of `emails` that none of `users` has, and so are for backfilling to look up.
+pub fn missing_emails(emails: &[Email], users: &BTreeSet<SlackUser>) -> BTreeSet<Email> {
+    let known: BTreeSet<Email> = users.iter().map(|user| user.email.normalized()).collect();
+    emails
+        .iter()
+        .map(Email::normalized)
+        .filter(|email| !known.contains(email))
+        .collect()
+}
+
+/// Looks up `missing` one at a time, up to `max_lookups` of them, and returns the users found
+/// marked as backfilled. The ones looked up are picked at random, so when there are more than
+/// `max_lookups` the rest get their turn on later syncs. Users Slack can't find are skipped;
+/// running out of API calls stops the backfill.
+pub async fn missing_users(
+    slack_api: &dyn SlackDirectory,
+    missing: &BTreeSet<Email>,
+    max_lookups: usize,
+) -> Result<Backfill, SlackErrors> {
+    let mut missing: Vec<&Email> = missing.iter().collect();
+    if missing.len() > max_lookups {
+        missing.shuffle(&mut rand::thread_rng());
+        info!(
+            "Looking up {} of the {} users missing from users.list, leaving the rest for later syncs",
+            max_lookups,
+            missing.len()
+        );
+        missing.truncate(max_lookups);
+    } else if !missing.is_empty() {
+        info!(
+            "Looking up {} users missing from users.list by email",
+            missing.len()
+        );
+    }
+
+    let mut backfill = Backfill::default();
+    for email in missing {
+        match slack_api.lookup_user(email).await {
+            Ok(Some(mut user)) => {
+                user.backfilled = true;
+                backfill.found.insert(user);
+            }
+            Ok(None) => backfill.not_found.push(email.clone()),
+            Err(e @ SlackErrors::BudgetExceeded { .. }) => return Err(e),
+            Err(e) => warn!(
+                "Unable to look up {} in Slack. Error: {}",
+                redact::pii(email),
+                e
+            ),
+        }
+    }
+
+    Ok(backfill)
+}
@@ -0,0 +1,22 @@
+use serde_json::{json, Value};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_SHA: &str = env!("GIT_SHA");
+pub const BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP");
+
+/// Printed by `--version`; `-V` prints only the version.
+pub const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    "\ncommit: ",
+    env!("GIT_SHA"),
+    "\nbuilt: ",
+    env!("BUILD_TIMESTAMP")
+);
+
+pub fn to_json() -> Value {
+    json!({
+        "version": VERSION,
+        "git-sha": GIT_SHA,
+        "build-timestamp": BUILD_TIMESTAMP,
+    })
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{SlackUser, SlackUserGroup};
+
+/// Redis Stream the updater publishes change records to.
+pub const CHANGES_STREAM_KEY: &str = "changes";
+
+/// Roughly how many change records the stream keeps before dropping the oldest.
+pub const CHANGES_STREAM_MAX_LEN: usize = 10_000;
+
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Entity {
+    User,
+    Group,
+}
+
+impl Entity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Entity::User => "user",
+            Entity::Group => "group",
+        }
+    }
+}
+
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+    Added,
+    Updated,
+    Removed,
+}
+
+/// One user or group that a sync added, changed or removed.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeRecord {
+    pub entity: Entity,
+    pub action: Action,
+    pub id: String,
+    /// The record as it is now cached, or as it was before being removed.
+    pub record: Value,
+}
+
+/// What changed between the users cached before a sync and the ones it wrote. Both are expected
+/// in the form they're stored in, so hashed emails compare equal. Emails a user had before don't
+/// count as a change.
+pub fn user_changes(previous: &[SlackUser], current: &[SlackUser]) -> Vec<ChangeRecord> {
+    diff(
+        Entity::User,
+        previous.iter().map(|user| (user.id.as_str(), user)),
+        current.iter().map(|user| (user.id.as_str(), user)),
+        |previous, current| {
+            *previous
+                == SlackUser {
+                    previous_emails: previous.previous_emails.clone(),
+                    ..current.clone()
+                }
+        },
+    )
+}
+
+/// What changed between the groups cached before a sync and the ones it wrote. Names a group had
+/// before don't count as a change.
+pub fn group_changes(previous: &[SlackUserGroup], current: &[SlackUserGroup]) -> Vec<ChangeRecord> {
+    diff(
+        Entity::Group,
+        previous.iter().map(|group| (group.id.as_str(), group)),
+        current.iter().map(|group| (group.id.as_str(), group)),
+        |previous, current| previous.name == current.name && previous.users == current.users,
+    )
+}
+
+fn diff<'a, T>(
+    entity: Entity,
+    previous: impl Iterator<Item = (&'a str, &'a T)>,
+    current: impl Iterator<Item = (&'a str, &'a T)>,
+    unchanged: impl Fn(&T, &T) -> bool,
+) -> Vec<ChangeRecord>
+where
+    T: Serialize + 'a,
+{
+    let record = |action, id: &str, value: &T| ChangeRecord {
+        entity,
+        action,
+        id: id.to_owned(),
+        record: serde_json::to_value(value).unwrap_or(Value::Null),
+    };
+
+    let mut previous: BTreeMap<&str, &T> = previous.collect();
+    let mut changes = Vec::new();
+    for (id, value) in current {
+        match previous.remove(id) {
+            None => changes.push(record(Action::Added, id, value)),
+            Some(before) if !unchanged(before, value) => {
+                changes.push(record(Action::Updated, id, value))
+            }
+            Some(_) => {}
+        }
+    }
+    for (id, value) in previous {
+        changes.push(record(Action::Removed, id, value));
+    }
+
+    changes
+}
@@ -0,0 +1,115 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use tracing::warn;
+
+/// Seconds injected Slack rate limits ask to wait, short so a staging sync isn't held up for the
+/// minute Slack usually asks for.
+pub const RATE_LIMIT_RETRY_AFTER_SECONDS: u64 = 1;
+
+const DEFAULT_SLOW_DELAY: Duration = Duration::from_secs(2);
+
+// Probabilities are kept as the bits of an `f64`, zero while chaos is off.
+static REDIS_TIMEOUT: AtomicU64 = AtomicU64::new(0);
+static SLACK_RATE_LIMIT: AtomicU64 = AtomicU64::new(0);
+static SLOW_RESPONSE: AtomicU64 = AtomicU64::new(0);
+static SLOW_DELAY_MILLIS: AtomicU64 = AtomicU64::new(0);
+
+/// Failures to inject, as `--chaos redis-timeout=0.1,slack-429=0.05,slow=0.2,slow-delay=2s`.
+/// Each probability is between 0 and 1, and failures left out aren't injected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChaosConfig {
+    pub redis_timeout: f64,
+    pub slack_rate_limit: f64,
+    pub slow_response: f64,
+    pub slow_delay: Duration,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            redis_timeout: 0.0,
+            slack_rate_limit: 0.0,
+            slow_response: 0.0,
+            slow_delay: DEFAULT_SLOW_DELAY,
+        }
+    }
+}
+
+impl FromStr for ChaosConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = ChaosConfig::default();
+        for setting in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let mut parts = setting.splitn(2, '=');
+            let (name, value) = match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => (name.trim(), value.trim()),
+                _ => return Err(format!("`{}` is not a `<failure>=<value>` pair", setting)),
+            };
+
+            match name {
+                "redis-timeout" => config.redis_timeout = parse_probability(name, value)?,
+                "slack-429" => config.slack_rate_limit = parse_probability(name, value)?,
+                "slow" => config.slow_response = parse_probability(name, value)?,
+                "slow-delay" => {
+                    config.slow_delay = humantime::parse_duration(value)
+                        .map_err(|e| format!("slow-delay `{}` is not a duration: {}", value, e))?
+                }
+                other => {
+                    return Err(format!(
+                        "unknown failure {}, expected redis-timeout, slack-429, slow or slow-delay",
+                        other
+                    ))
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn parse_probability(name: &str, value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(probability) if (0.0..=1.0).contains(&probability) => Ok(probability),
+        _ => Err(format!("{} `{}` is not between 0 and 1", name, value)),
+    }
+}
+
+/// Starts injecting the failures in `config` for the rest of the process.
+pub fn enable(config: &ChaosConfig) {
+    warn!(
+        "Chaos mode is on: Redis timeouts {}, Slack rate limits {}, slow responses {} of {:?}",
+        config.redis_timeout, config.slack_rate_limit, config.slow_response, config.slow_delay
+    );
+    REDIS_TIMEOUT.store(config.redis_timeout.to_bits(), Ordering::Relaxed);
+    SLACK_RATE_LIMIT.store(config.slack_rate_limit.to_bits(), Ordering::Relaxed);
+    SLOW_RESPONSE.store(config.slow_response.to_bits(), Ordering::Relaxed);
+    SLOW_DELAY_MILLIS.store(config.slow_delay.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Whether this Redis connection should time out.
+pub fn redis_timeout() -> bool {
+    roll(&REDIS_TIMEOUT)
+}
+
+/// Whether this Slack request should be answered with a 429.
+pub fn slack_rate_limited() -> bool {
+    roll(&SLACK_RATE_LIMIT)
+}
+
+/// How long to hold this web request up for, if at all.
+pub fn slow_response() -> Option<Duration> {
+    if roll(&SLOW_RESPONSE) {
+        Some(Duration::from_millis(
+            SLOW_DELAY_MILLIS.load(Ordering::Relaxed),
+        ))
+    } else {
+        None
+    }
+}
+
+fn roll(probability: &AtomicU64) -> bool {
+    let probability = f64::from_bits(probability.load(Ordering::Relaxed));
+    probability > 0.0 && rand::random::<f64>() < probability
+}
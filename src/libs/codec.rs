@@ -0,0 +1,53 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// First byte of values written as MessagePack. JSON values are written as they are and always
+/// start with `{`, so caches written before there was a choice stay readable.
+const MSGPACK_PREFIX: u8 = 0x01;
+
+/// How users and groups are encoded in Redis. Readers detect either, so the format can be
+/// switched by restarting the updater.
+#[serde(rename_all = "lowercase")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ValueFormat {
+    Json,
+    Msgpack,
+}
+
+impl FromStr for ValueFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ValueFormat::Json),
+            "msgpack" => Ok(ValueFormat::Msgpack),
+            other => Err(format!(
+                "unknown value format {}, expected json or msgpack",
+                other
+            )),
+        }
+    }
+}
+
+impl ValueFormat {
+    pub fn encode(self, record: &Value) -> Vec<u8> {
+        match self {
+            ValueFormat::Json => record.to_string().into_bytes(),
+            ValueFormat::Msgpack => {
+                let mut bytes = vec![MSGPACK_PREFIX];
+                bytes.extend(rmp_serde::to_vec(record).expect("JSON is always valid MessagePack"));
+                bytes
+            }
+        }
+    }
+}
+
+/// Reads a value written in either format.
+pub fn decode(bytes: &[u8]) -> anyhow::Result<Value> {
+    match bytes.split_first() {
+        Some((&MSGPACK_PREFIX, rest)) => Ok(rmp_serde::from_slice(rest)?),
+        _ => Ok(serde_json::from_slice(bytes)?),
+    }
+}
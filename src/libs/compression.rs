@@ -0,0 +1,79 @@
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// First byte of values stored zstd compressed. It can't start JSON, MessagePack written by
+/// `codec`, or any of the plain text values, so uncompressed values are read as they are.
+const ZSTD_PREFIX: u8 = 0x02;
+
+const ZSTD_LEVEL: i32 = 3;
+
+/// Running totals of the values compressed or decompressed by one process.
+#[derive(Debug, Default)]
+pub struct CompressionStats {
+    values: AtomicU64,
+    original_bytes: AtomicU64,
+    compressed_bytes: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompressionTotals {
+    pub values: u64,
+    pub original_bytes: u64,
+    pub compressed_bytes: u64,
+}
+
+impl CompressionStats {
+    fn record(&self, original_bytes: usize, compressed_bytes: usize) {
+        self.values.fetch_add(1, Ordering::Relaxed);
+        self.original_bytes
+            .fetch_add(original_bytes as u64, Ordering::Relaxed);
+        self.compressed_bytes
+            .fetch_add(compressed_bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn totals(&self) -> CompressionTotals {
+        CompressionTotals {
+            values: self.values.load(Ordering::Relaxed),
+            original_bytes: self.original_bytes.load(Ordering::Relaxed),
+            compressed_bytes: self.compressed_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl CompressionTotals {
+    /// How many times smaller the values got, or `None` before any were compressed.
+    pub fn ratio(&self) -> Option<f64> {
+        if self.compressed_bytes == 0 {
+            return None;
+        }
+        Some(self.original_bytes as f64 / self.compressed_bytes as f64)
+    }
+}
+
+/// `value` compressed and tagged, when it's at least `threshold` bytes and compressing shrinks it.
+pub fn compress(value: &[u8], threshold: usize, stats: &CompressionStats) -> Option<Vec<u8>> {
+    if value.len() < threshold {
+        return None;
+    }
+
+    let mut compressed = vec![ZSTD_PREFIX];
+    compressed.extend(zstd::stream::encode_all(value, ZSTD_LEVEL).ok()?);
+    if compressed.len() >= value.len() {
+        return None;
+    }
+
+    stats.record(value.len(), compressed.len());
+    Some(compressed)
+}
+
+/// The original of a value `compress` tagged, or `None` if it wasn't compressed.
+pub fn decompress(value: &[u8], stats: &CompressionStats) -> io::Result<Option<Vec<u8>>> {
+    let compressed = match value.split_first() {
+        Some((&ZSTD_PREFIX, compressed)) => compressed,
+        _ => return Ok(None),
+    };
+
+    let original = zstd::stream::decode_all(compressed)?;
+    stats.record(original.len(), value.len());
+    Ok(Some(original))
+}
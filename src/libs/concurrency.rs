@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// A cap on how many requests run at once, with room for `queue_depth` more to wait for a turn.
+/// Requests beyond both are turned away straight away rather than piling up on the Redis pool.
+#[derive(Debug)]
+struct Limit {
+    running: Arc<Semaphore>,
+    queue: Arc<Semaphore>,
+}
+
+impl Limit {
+    fn new(max_running: usize, queue_depth: usize) -> Self {
+        Self {
+            running: Arc::new(Semaphore::new(max_running)),
+            queue: Arc::new(Semaphore::new(queue_depth)),
+        }
+    }
+
+    async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        if let Ok(permit) = self.running.clone().try_acquire_owned() {
+            return Some(permit);
+        }
+
+        let _queued = self.queue.clone().try_acquire_owned().ok()?;
+        self.running.clone().acquire_owned().await.ok()
+    }
+}
+
+/// How many requests one endpoint may run at once, as `<endpoint>=<limit>`, e.g.
+/// `/slack/users=4`. Endpoints are written with placeholders, like `/slack/user/id/{id}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteLimit {
+    pub endpoint: String,
+    pub limit: usize,
+}
+
+impl FromStr for RouteLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.rsplitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(limit), Some(endpoint)) if endpoint.starts_with('/') => {
+                match limit.trim().parse::<usize>() {
+                    Ok(limit) if limit > 0 => Ok(RouteLimit {
+                        endpoint: endpoint.trim().to_owned(),
+                        limit,
+                    }),
+                    _ => Err(format!("limit of {} must be a positive number", endpoint)),
+                }
+            }
+            _ => Err(format!(
+                "`{}` is not a route limit, expected <endpoint>=<limit>",
+                s
+            )),
+        }
+    }
+}
+
+/// Parses `--max-concurrent-requests`. Under a limit of 0 no request would ever get a turn.
+pub fn parse_max_requests(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_owned()),
+        Ok(max_requests) => Ok(max_requests),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Permits a request holds while it runs, released when dropped.
+#[derive(Debug)]
+pub struct Permits {
+    _global: Option<OwnedSemaphorePermit>,
+    _route: Option<OwnedSemaphorePermit>,
+}
+
+/// The limits on requests in flight, for the whole server and for single endpoints.
+#[derive(Debug, Default)]
+pub struct ConcurrencyLimits {
+    global: Option<Limit>,
+    routes: BTreeMap<String, Limit>,
+}
+
+impl ConcurrencyLimits {
+    pub fn new(max_requests: Option<usize>, routes: &[RouteLimit], queue_depth: usize) -> Self {
+        Self {
+            global: max_requests.map(|limit| Limit::new(limit, queue_depth)),
+            routes: routes
+                .iter()
+                .map(|route| (route.endpoint.clone(), Limit::new(route.limit, queue_depth)))
+                .collect(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.global.is_some() || !self.routes.is_empty()
+    }
+
+    /// Waits for a turn to answer a request to `endpoint`, or `None` when too many are already
+    /// waiting. The endpoint's own limit is taken first, so requests queued on a busy endpoint
+    /// don't hold up the rest of the server.
+    pub async fn acquire(&self, endpoint: &str) -> Option<Permits> {
+        let route = match self.routes.get(endpoint) {
+            Some(limit) => Some(limit.acquire().await?),
+            None => None,
+        };
+        let global = match &self.global {
+            Some(limit) => Some(limit.acquire().await?),
+            None => None,
+        };
+
+        Some(Permits {
+            _global: global,
+            _route: route,
+        })
+    }
+}
@@ -0,0 +1,24 @@
+use serde::Serialize;
+
+/// What `verify` found wrong with the keys users are looked up by email under. Keys are listed
+/// as they'd appear in logs, so redacted under `--redact-pii`.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConsistencyReport {
+    pub users: usize,
+    pub email_keys: usize,
+    /// Email keys left pointing to a user whose email changed.
+    pub redirects: usize,
+    /// Email keys holding a user that no longer has an id record.
+    pub orphaned: Vec<String>,
+    /// Email keys a user was under before their email changed.
+    pub stale: Vec<String>,
+    /// Email keys holding a different version of the user than their id record.
+    pub mismatched: Vec<String>,
+    /// Email keys users should be under but aren't.
+    pub missing: Vec<String>,
+    /// Keys that couldn't be read. They're left for the next sync to overwrite or expire.
+    pub unreadable: Vec<String>,
+    /// Whether the problems were fixed, rather than only reported.
+    pub repaired: bool,
+}
@@ -0,0 +1,270 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tracing::debug;
+
+use super::email::{self, Email};
+use super::slack::{
+    Cursor, FetchedGroups, GroupId, SlackUserId, UserAvailability, UserId, UsersPage,
+};
+use super::{SlackApi, SlackUser, SlackUserGroup};
+use crate::error::{DirectoryErrors, SlackErrors};
+
+pub type SourceResult<'a, T> = BoxFuture<'a, Result<T, DirectoryErrors>>;
+
+pub type SlackResult<'a, T> = BoxFuture<'a, Result<T, SlackErrors>>;
+
+/// Somewhere people and their groups are listed. A sync starts from one source, Slack unless
+/// `--source` says otherwise, and users from other sources are merged in by email, so the web
+/// API looks the same whichever directory a user came from.
+pub trait DirectorySource: Send + Sync {
+    fn kind(&self) -> SourceKind;
+
+    fn list_users(&self) -> SourceResult<'_, BTreeSet<SlackUser>>;
+
+    fn list_groups(&self) -> SourceResult<'_, BTreeSet<SlackUserGroup>>;
+}
+
+/// Everything a sync asks of Slack itself: crawling users a page at a time, reusing group
+/// members that haven't changed, and the lookups and writes around them. `SlackApi` is the only
+/// one, but the sync only goes through this, so another can stand in for it.
+pub trait SlackDirectory: Send + Sync {
+    /// Pages of users from `cursor` on, fetched ahead of the receiver. The channel closes after
+    /// the last page or an error.
+    fn prefetch_users(
+        self: Arc<Self>,
+        cursor: Cursor,
+    ) -> mpsc::Receiver<Result<UsersPage, SlackErrors>>;
+
+    /// Every group, reusing the members in `fetched` of groups that haven't changed. Returns the
+    /// groups along with the members to reuse next time.
+    fn list_user_groups_since<'a>(
+        &'a self,
+        fetched: &'a FetchedGroups,
+    ) -> SlackResult<'a, (BTreeSet<SlackUserGroup>, FetchedGroups)>;
+
+    fn add_dnd_schedules<'a>(
+        &'a self,
+        availability: &'a mut BTreeMap<UserId, UserAvailability>,
+    ) -> SlackResult<'a, ()>;
+
+    fn lookup_user<'a>(&'a self, email: &'a Email) -> SlackResult<'a, Option<SlackUser>>;
+
+    fn create_user_group<'a>(&'a self, name: &'a str) -> SlackResult<'a, GroupId>;
+
+    fn set_user_group_members<'a>(
+        &'a self,
+        id: &'a GroupId,
+        users: &'a [&'a UserId],
+    ) -> SlackResult<'a, ()>;
+
+    fn post_message<'a>(
+        &'a self,
+        channel: &'a str,
+        text: &'a str,
+        blocks: &'a [Value],
+    ) -> SlackResult<'a, ()>;
+
+    fn publish_home<'a>(&'a self, user_id: &'a str, view: &'a Value) -> SlackResult<'a, ()>;
+}
+
+/// The directories a sync can read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    Slack,
+    Google,
+}
+
+impl FromStr for SourceKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "slack" => Ok(SourceKind::Slack),
+            "google" => Ok(SourceKind::Google),
+            other => Err(format!(
+                "unknown directory source {}, expected slack or google",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceKind::Slack => f.write_str("Slack"),
+            SourceKind::Google => f.write_str("Google Workspace"),
+        }
+    }
+}
+
+impl DirectorySource for SlackApi {
+    fn kind(&self) -> SourceKind {
+        SourceKind::Slack
+    }
+
+    fn list_users(&self) -> SourceResult<'_, BTreeSet<SlackUser>> {
+        async move {
+            self.list_all_users()
+                .await
+                .map_err(|e| DirectoryErrors::UnableToList {
+                    directory: SourceKind::Slack.to_string(),
+                    source: anyhow::anyhow!(e),
+                })
+        }
+        .boxed()
+    }
+
+    fn list_groups(&self) -> SourceResult<'_, BTreeSet<SlackUserGroup>> {
+        async move {
+            self.list_all_user_groups()
+                .await
+                .map_err(|e| DirectoryErrors::UnableToList {
+                    directory: SourceKind::Slack.to_string(),
+                    source: anyhow::anyhow!(e),
+                })
+        }
+        .boxed()
+    }
+}
+
+impl SlackDirectory for SlackApi {
+    fn prefetch_users(
+        self: Arc<Self>,
+        cursor: Cursor,
+    ) -> mpsc::Receiver<Result<UsersPage, SlackErrors>> {
+        SlackApi::prefetch_users(self, cursor)
+    }
+
+    fn list_user_groups_since<'a>(
+        &'a self,
+        fetched: &'a FetchedGroups,
+    ) -> SlackResult<'a, (BTreeSet<SlackUserGroup>, FetchedGroups)> {
+        SlackApi::list_user_groups_since(self, fetched).boxed()
+    }
+
+    fn add_dnd_schedules<'a>(
+        &'a self,
+        availability: &'a mut BTreeMap<UserId, UserAvailability>,
+    ) -> SlackResult<'a, ()> {
+        SlackApi::add_dnd_schedules(self, availability).boxed()
+    }
+
+    fn lookup_user<'a>(&'a self, email: &'a Email) -> SlackResult<'a, Option<SlackUser>> {
+        SlackApi::lookup_user(self, email).boxed()
+    }
+
+    fn create_user_group<'a>(&'a self, name: &'a str) -> SlackResult<'a, GroupId> {
+        SlackApi::create_user_group(self, name).boxed()
+    }
+
+    fn set_user_group_members<'a>(
+        &'a self,
+        id: &'a GroupId,
+        users: &'a [&'a UserId],
+    ) -> SlackResult<'a, ()> {
+        SlackApi::set_user_group_members(self, id, users).boxed()
+    }
+
+    fn post_message<'a>(
+        &'a self,
+        channel: &'a str,
+        text: &'a str,
+        blocks: &'a [Value],
+    ) -> SlackResult<'a, ()> {
+        SlackApi::post_message(self, channel, text, blocks).boxed()
+    }
+
+    fn publish_home<'a>(&'a self, user_id: &'a str, view: &'a Value) -> SlackResult<'a, ()> {
+        SlackApi::publish_home(self, user_id, view).boxed()
+    }
+}
+
+/// Users and groups from another source, ready to be cached next to the ones already synced.
+#[derive(Debug, Default)]
+pub struct Merged {
+    /// Users that weren't cached before, and cached users with details filled in.
+    pub users: BTreeSet<SlackUser>,
+    /// Groups whose names aren't taken yet, with members under their cached ids.
+    pub groups: BTreeSet<SlackUserGroup>,
+}
+
+/// Merges `users` and `groups` from another source into `cached_users` and `cached_groups`.
+///
+/// Users are matched by normalized email. A matched user keeps their cached record, only
+/// getting the avatars and timezone it's missing, and is known by the cached id in the other
+/// source's groups. The rest are added as they are. Groups are added unless one with the same
+/// name is already cached.
+pub fn merge(
+    cached_users: &BTreeSet<SlackUser>,
+    cached_groups: &BTreeSet<SlackUserGroup>,
+    users: BTreeSet<SlackUser>,
+    groups: BTreeSet<SlackUserGroup>,
+) -> Merged {
+    let by_email: BTreeMap<String, &SlackUser> = cached_users
+        .iter()
+        .map(|user| (email::normalize(&user.email), user))
+        .collect();
+
+    let mut merged = Merged::default();
+    let mut ids: BTreeMap<UserId, UserId> = BTreeMap::new();
+    for user in users {
+        match by_email.get(&email::normalize(&user.email)) {
+            Some(cached) => {
+                ids.insert(user.id.clone(), cached.id.clone());
+                if let Some(filled) = fill_in(cached, &user) {
+                    merged.users.insert(filled);
+                }
+            }
+            None => {
+                merged.users.insert(user);
+            }
+        }
+    }
+
+    let taken: BTreeSet<String> = cached_groups
+        .iter()
+        .map(|group| group.name.to_lowercase())
+        .collect();
+    for mut group in groups {
+        if taken.contains(&group.name.to_lowercase()) {
+            debug!("Leaving out group {}, its name is taken", group.name);
+            continue;
+        }
+        group.users = group
+            .users
+            .into_iter()
+            .map(|user| SlackUserId {
+                id: ids.get(&user.id).cloned().unwrap_or(user.id),
+            })
+            .collect();
+        merged.groups.insert(group);
+    }
+
+    merged
+}
+
+/// `cached` with the details it's missing taken from `other`, or `None` when it has them all.
+fn fill_in(cached: &SlackUser, other: &SlackUser) -> Option<SlackUser> {
+    let missing_avatars = cached.avatars.is_empty() && !other.avatars.is_empty();
+    let missing_tz = cached.tz.is_none() && other.tz.is_some();
+    if !missing_avatars && !missing_tz {
+        return None;
+    }
+
+    let mut filled = cached.clone();
+    if missing_avatars {
+        filled.avatars = other.avatars.clone();
+    }
+    if missing_tz {
+        filled.tz = other.tz.clone();
+    }
+    Some(filled)
+}
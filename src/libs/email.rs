@@ -0,0 +1,281 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use derivative::Derivative;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Longest address SMTP can deliver to, per RFC 5321.
+const MAX_EMAIL_LENGTH: usize = 254;
+
+/// An email address. Ones given by callers are checked with `parse`, while ones read from Slack
+/// or Redis are taken as they are. That includes the hashes stored in their place when hashing is
+/// on.
+#[serde(transparent)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Email(String);
+
+impl Email {
+    /// Wraps a value that didn't come from a caller, like an email hash, without checking it.
+    pub fn unchecked(value: String) -> Self {
+        Email(value)
+    }
+
+    /// This address in the form it's keyed by, see `normalize`.
+    pub fn normalized(&self) -> Email {
+        Email(normalize(&self.0))
+    }
+}
+
+impl FromStr for Email {
+    type Err = String;
+
+    /// Only checks the basic shape, `<local>@<domain>` without spaces, and the length.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if value.len() > MAX_EMAIL_LENGTH {
+            return Err(format!(
+                "email addresses can be at most {} characters",
+                MAX_EMAIL_LENGTH
+            ));
+        }
+
+        let mut parts = value.split('@');
+        let valid = match (parts.next(), parts.next(), parts.next()) {
+            (Some(local), Some(domain), None) => {
+                !local.is_empty() && !domain.is_empty() && !value.contains(char::is_whitespace)
+            }
+            _ => false,
+        };
+
+        if valid {
+            Ok(Email(value.to_owned()))
+        } else {
+            Err(format!("`{}` is not an email address", value))
+        }
+    }
+}
+
+impl Deref for Email {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Email {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The form emails are keyed by: trimmed and lowercased, so `Foo@X.com ` and `foo@x.com` are the
+/// same user.
+pub fn normalize(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// The domain of a normalized `email`, if it has one.
+pub fn domain(email: &str) -> Option<&str> {
+    let at = email.rfind('@')?;
+    Some(&email[at + 1..]).filter(|domain| !domain.is_empty())
+}
+
+/// A rule deriving another address a user gets mail at from their Slack email.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AliasRule {
+    /// `jane.doe@x.com` is also `jdoe@x.com`.
+    FirstInitialLast,
+    /// `jane@<from>` is also `jane@<to>`.
+    Domain { from: String, to: String },
+}
+
+impl AliasRule {
+    /// The alias a normalized `email` has under this rule, if the rule applies to it.
+    pub fn apply(&self, email: &str) -> Option<String> {
+        let at = email.rfind('@')?;
+        let (local, domain) = (&email[..at], &email[at + 1..]);
+
+        match self {
+            AliasRule::FirstInitialLast => {
+                let mut names = local.split('.');
+                let initial = names.next()?.chars().next()?;
+                let last = names.next().filter(|last| !last.is_empty())?;
+                if names.next().is_some() {
+                    return None;
+                }
+                Some(format!("{}{}@{}", initial, last, domain))
+            }
+            AliasRule::Domain { from, to } if from == domain => Some(format!("{}@{}", local, to)),
+            AliasRule::Domain { .. } => None,
+        }
+    }
+}
+
+impl FromStr for AliasRule {
+    type Err = String;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let rule = rule.trim();
+        if rule == "first-initial-last" {
+            return Ok(AliasRule::FirstInitialLast);
+        }
+
+        if let Some(domains) = rule.strip_prefix("domain:") {
+            let mut domains = domains.splitn(2, '=');
+            return match (domains.next(), domains.next()) {
+                (Some(from), Some(to)) if !from.trim().is_empty() && !to.trim().is_empty() => {
+                    Ok(AliasRule::Domain {
+                        from: normalize(from),
+                        to: normalize(to),
+                    })
+                }
+                _ => Err(format!("expected `domain:<from>=<to>`, got `{}`", rule)),
+            };
+        }
+
+        Err(format!(
+            "unknown alias rule `{}`, expected `first-initial-last` or `domain:<from>=<to>`",
+            rule
+        ))
+    }
+}
+
+/// Every alias `rules` give a normalized `email`, other than the email itself.
+pub fn aliases(rules: &[AliasRule], email: &str) -> Vec<String> {
+    let mut aliases: Vec<String> = rules
+        .iter()
+        .filter_map(|rule| rule.apply(email))
+        .filter(|alias| alias != email)
+        .collect();
+    aliases.sort();
+    aliases.dedup();
+    aliases
+}
+
+/// What happens to users whose email isn't in an allowed domain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExternalUsers {
+    /// They aren't cached.
+    Exclude,
+    /// They're cached with `external` set.
+    Flag,
+}
+
+impl FromStr for ExternalUsers {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exclude" => Ok(ExternalUsers::Exclude),
+            "flag" => Ok(ExternalUsers::Flag),
+            other => Err(format!(
+                "unknown external users handling {}, expected exclude or flag",
+                other
+            )),
+        }
+    }
+}
+
+/// Email domains users are cached for. Without any, every domain is.
+#[derive(Debug, Clone, Default)]
+pub struct DomainAllowlist {
+    domains: BTreeSet<String>,
+}
+
+impl DomainAllowlist {
+    pub fn new(domains: &[String]) -> Self {
+        Self {
+            domains: domains
+                .iter()
+                .map(|domain| normalize(domain.trim_start_matches('@')))
+                .filter(|domain| !domain.is_empty())
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    /// Whether a normalized `email` is in one of the domains. Subdomains have to be listed too.
+    pub fn allows(&self, email: &str) -> bool {
+        self.domains.is_empty()
+            || domain(email).map_or(false, |domain| self.domains.contains(domain))
+    }
+}
+
+/// Turns email addresses into salted SHA-256 digests, so Redis never holds them in plaintext.
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct EmailHasher {
+    #[derivative(Debug = "ignore")]
+    salt: String,
+}
+
+impl EmailHasher {
+    pub fn new(salt: &str) -> Self {
+        Self {
+            salt: salt.to_owned(),
+        }
+    }
+
+    pub fn hash(&self, email: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt.as_bytes());
+        hasher.update(email.as_bytes());
+        hex::encode(hasher.finalize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_checks_the_shape() {
+        assert!("jane@x.com".parse::<Email>().is_ok());
+        assert!(" jane@x.com ".parse::<Email>().is_ok());
+        assert!("jane".parse::<Email>().is_err());
+        assert!("@x.com".parse::<Email>().is_err());
+        assert!("jane@".parse::<Email>().is_err());
+        assert!("jane@doe@x.com".parse::<Email>().is_err());
+        assert!("jane doe@x.com".parse::<Email>().is_err());
+        assert!(format!("{}@x.com", "a".repeat(MAX_EMAIL_LENGTH))
+            .parse::<Email>()
+            .is_err());
+    }
+
+    #[test]
+    fn plus_is_kept() {
+        let email: Email = "jane+slack@x.com".parse().unwrap();
+        assert_eq!(&*email, "jane+slack@x.com");
+        assert_eq!(&*email.normalized(), "jane+slack@x.com");
+    }
+
+    #[test]
+    fn case_is_folded_when_normalized() {
+        let email: Email = " Jane.Doe@X.COM ".parse().unwrap();
+        assert_eq!(&*email, "Jane.Doe@X.COM");
+        assert_eq!(&*email.normalized(), "jane.doe@x.com");
+        assert_eq!(normalize("JANE@x.com"), normalize("jane@X.com"));
+    }
+
+    #[test]
+    fn non_ascii_local_parts_are_allowed() {
+        let email: Email = "JÖRG.Müller@x.com".parse().unwrap();
+        assert_eq!(&*email.normalized(), "jörg.müller@x.com");
+        assert_eq!(domain(&email.normalized()), Some("x.com"));
+    }
+
+    #[test]
+    fn domain_of_an_email() {
+        assert_eq!(domain("jane@x.com"), Some("x.com"));
+        assert_eq!(domain("jane@"), None);
+        assert_eq!(domain("jane"), None);
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::BTreeSet;
+
+use super::slack::SlackUser;
+use super::typeahead;
+
+/// Lowest score a user can match with. Half the characters of the closest part of their name can
+/// be wrong, so `jhon smth` still finds `John Smith`.
+pub const MIN_SCORE: f64 = 0.5;
+
+/// A user found by `fuzzy` lookup, with how closely their name matched from 0 to 1.
+#[derive(Debug, Clone)]
+pub struct FuzzyMatch {
+    pub score: f64,
+    pub user: SlackUser,
+}
+
+/// The trigrams of each word in the folded `text`, padded the way PostgreSQL's `pg_trgm` pads
+/// them, so the starts and ends of words count for more.
+pub fn trigrams(text: &str) -> BTreeSet<String> {
+    let mut trigrams = BTreeSet::new();
+    for word in text.split_whitespace() {
+        let padded: Vec<char> = format!("  {} ", word).chars().collect();
+        for window in padded.windows(3) {
+            trigrams.insert(window.iter().collect());
+        }
+    }
+    trigrams
+}
+
+/// The trigrams the user is indexed under: those of their real and display names.
+pub fn user_trigrams(user: &SlackUser) -> BTreeSet<String> {
+    typeahead::names(user)
+        .iter()
+        .flat_map(|name| trigrams(name))
+        .collect()
+}
+
+/// How closely the folded `query` matches the user's closest name. Each run of words in a name is
+/// compared, so a misspelt surname alone still matches well.
+pub fn score(query: &str, user: &SlackUser) -> f64 {
+    let query: Vec<char> = query.chars().collect();
+    let mut best: f64 = 0.0;
+    for name in typeahead::names(user) {
+        let words: Vec<&str> = name.split(' ').collect();
+        for start in 0..words.len() {
+            for end in start + 1..=words.len() {
+                let span: Vec<char> = words[start..end].join(" ").chars().collect();
+                let longest = query.len().max(span.len());
+                let distance = edit_distance(&query, &span);
+                best = best.max(1.0 - distance as f64 / longest as f64);
+            }
+        }
+    }
+    best
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, a_char) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == b_char { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
@@ -0,0 +1,301 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, USER_AGENT};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{debug, info};
+
+use super::email;
+use crate::error::GithubErrors;
+
+const GITHUB_GRAPHQL_URL: &str = "https://api.github.com/graphql";
+const SLACK_SCIM_USERS_URL: &str = "https://api.slack.com/scim/v1/Users";
+const SCIM_PAGE_SIZE: usize = 1000;
+/// How long to wait for GitHub or Slack SCIM, the same as the defaults for Slack.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// The longest login GitHub allows.
+const MAX_LOGIN_LENGTH: usize = 39;
+
+const SAML_IDENTITIES_QUERY: &str = "
+query($org: String!, $cursor: String) {
+  organization(login: $org) {
+    samlIdentityProvider {
+      externalIdentities(first: 100, after: $cursor) {
+        pageInfo { hasNextPage endCursor }
+        nodes { samlIdentity { nameId } user { login } }
+      }
+    }
+  }
+}";
+
+/// Where the GitHub logins of users are found, matched up by email.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoginSource {
+    /// `csv:<path>`, a file of `<email>,<login>` lines.
+    Csv(PathBuf),
+    /// `saml:<org>`, the SAML SSO identities linked in a GitHub organization, whose name ids are
+    /// emails.
+    Saml { org: String },
+    /// `scim:<pointer>`, a JSON pointer to a custom field of Slack SCIM users, e.g.
+    /// `/urn:scim:schemas:extension:enterprise:1.0/githubLogin`.
+    Scim { pointer: String },
+}
+
+impl FromStr for LoginSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("csv"), Some(path)) if !path.is_empty() => Ok(LoginSource::Csv(path.into())),
+            (Some("saml"), Some(org)) if !org.is_empty() => Ok(LoginSource::Saml {
+                org: org.to_owned(),
+            }),
+            (Some("scim"), Some(pointer)) if pointer.starts_with('/') => Ok(LoginSource::Scim {
+                pointer: pointer.to_owned(),
+            }),
+            _ => Err(format!(
+                "`{}` is not a GitHub login source, expected csv:<path>, saml:<org> or scim:<pointer>",
+                s
+            )),
+        }
+    }
+}
+
+/// A GitHub login, lowercased as logins are case insensitive.
+pub fn parse_login(login: &str) -> Result<String, String> {
+    let valid = !login.is_empty()
+        && login.len() <= MAX_LOGIN_LENGTH
+        && !login.starts_with('-')
+        && login.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if valid {
+        Ok(login.to_lowercase())
+    } else {
+        Err(format!("`{}` is not a GitHub login", login))
+    }
+}
+
+/// Reads the GitHub logins from `source`, keyed by normalized email.
+pub async fn load_logins(
+    source: &LoginSource,
+    github_token: Option<&str>,
+    scim_token: Option<&str>,
+) -> Result<BTreeMap<String, String>, GithubErrors> {
+    let client = reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(|e| GithubErrors::UnableToBuildClient { source: e })?;
+    let logins = match source {
+        LoginSource::Csv(path) => read_csv(path)?,
+        LoginSource::Saml { org } => {
+            let token = github_token.ok_or(GithubErrors::NotConfigured {
+                name: "GITHUB_TOKEN".to_owned(),
+            })?;
+            fetch_saml_identities(&client, org, token).await?
+        }
+        LoginSource::Scim { pointer } => {
+            let token = scim_token.ok_or(GithubErrors::NotConfigured {
+                name: "SLACK_SCIM_TOKEN".to_owned(),
+            })?;
+            fetch_scim_field(&client, pointer, token).await?
+        }
+    };
+
+    info!("Loaded {} GitHub logins", logins.len());
+    Ok(logins)
+}
+
+fn read_csv(path: &Path) -> Result<BTreeMap<String, String>, GithubErrors> {
+    let contents = fs::read_to_string(path).map_err(|e| GithubErrors::UnableToRead {
+        path: path.display().to_string(),
+        source: e,
+    })?;
+
+    let mut logins = BTreeMap::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (index == 0 && line.starts_with("email")) {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let (address, login) = match fields.as_slice() {
+            [address, login] => (*address, parse_login(login)),
+            _ => (line, Err("expected `<email>,<login>`".to_owned())),
+        };
+        let login = login.map_err(|message| GithubErrors::Malformed {
+            path: path.display().to_string(),
+            line: index + 1,
+            message,
+        })?;
+        logins.insert(email::normalize(address), login);
+    }
+
+    Ok(logins)
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Deserialize)]
+struct SamlIdentities {
+    page_info: PageInfo,
+    nodes: Vec<ExternalIdentity>,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Deserialize)]
+struct PageInfo {
+    has_next_page: bool,
+    end_cursor: Option<String>,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Deserialize)]
+struct ExternalIdentity {
+    saml_identity: Option<SamlIdentity>,
+    user: Option<GithubUser>,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Deserialize)]
+struct SamlIdentity {
+    name_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubUser {
+    login: String,
+}
+
+async fn fetch_saml_identities(
+    client: &reqwest::Client,
+    org: &str,
+    token: &str,
+) -> Result<BTreeMap<String, String>, GithubErrors> {
+    let mut logins = BTreeMap::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        debug!("Fetching SAML identities of {}", org);
+        let request = client
+            .post(GITHUB_GRAPHQL_URL)
+            .header(AUTHORIZATION, format!("bearer {}", token))
+            .header(USER_AGENT, "slack-user-cache")
+            .header(CONTENT_TYPE, "application/json")
+            .body(
+                json!({
+                    "query": SAML_IDENTITIES_QUERY,
+                    "variables": { "org": org, "cursor": cursor },
+                })
+                .to_string(),
+            );
+        let response: Value = fetch("GitHub", request).await?;
+        let identities: SamlIdentities = response
+            .pointer("/data/organization/samlIdentityProvider/externalIdentities")
+            .cloned()
+            .ok_or_else(|| GithubErrors::UnexpectedResponse {
+                service: "GitHub".to_owned(),
+                message: format!("no SAML identities for {}", org),
+            })
+            .and_then(|identities| parse("GitHub", identities))?;
+
+        for identity in identities.nodes {
+            let name_id = identity.saml_identity.and_then(|saml| saml.name_id);
+            if let (Some(address), Some(user)) = (name_id, identity.user) {
+                logins.insert(email::normalize(&address), user.login.to_lowercase());
+            }
+        }
+
+        match identities.page_info {
+            PageInfo {
+                has_next_page: true,
+                end_cursor: Some(end_cursor),
+            } => cursor = Some(end_cursor),
+            _ => break,
+        }
+    }
+
+    Ok(logins)
+}
+
+async fn fetch_scim_field(
+    client: &reqwest::Client,
+    pointer: &str,
+    token: &str,
+) -> Result<BTreeMap<String, String>, GithubErrors> {
+    let mut logins = BTreeMap::new();
+    let mut start_index = 1;
+    loop {
+        debug!("Fetching Slack SCIM users from {}", start_index);
+        let request = client
+            .get(SLACK_SCIM_USERS_URL)
+            .query(&[("startIndex", start_index), ("count", SCIM_PAGE_SIZE)])
+            .header(AUTHORIZATION, format!("Bearer {}", token));
+        let page: Value = fetch("Slack SCIM", request).await?;
+
+        let resources = page["Resources"].as_array().cloned().unwrap_or_default();
+        for user in &resources {
+            let address = primary_email(user);
+            let login = user.pointer(pointer).and_then(Value::as_str);
+            if let (Some(address), Some(Ok(login))) = (address, login.map(parse_login)) {
+                logins.insert(email::normalize(address), login);
+            }
+        }
+
+        let total = page["totalResults"].as_u64().unwrap_or_default() as usize;
+        start_index += resources.len();
+        if resources.is_empty() || start_index > total {
+            break;
+        }
+    }
+
+    Ok(logins)
+}
+
+/// The email a SCIM user has marked primary, or their first one.
+fn primary_email(user: &Value) -> Option<&str> {
+    let emails = user["emails"].as_array()?;
+    emails
+        .iter()
+        .find(|address| address["primary"].as_bool() == Some(true))
+        .or_else(|| emails.first())
+        .and_then(|address| address["value"].as_str())
+}
+
+async fn fetch<T>(service: &str, request: reqwest::RequestBuilder) -> Result<T, GithubErrors>
+where
+    T: DeserializeOwned,
+{
+    let to_error = |e: reqwest::Error| GithubErrors::UnableToFetch {
+        service: service.to_owned(),
+        source: e,
+    };
+    let body = request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(to_error)?
+        .text()
+        .await
+        .map_err(to_error)?;
+
+    serde_json::from_str(&body).map_err(|e| GithubErrors::MalformedResponse {
+        service: service.to_owned(),
+        source: e,
+    })
+}
+
+fn parse<T>(service: &str, value: Value) -> Result<T, GithubErrors>
+where
+    T: DeserializeOwned,
+{
+    serde_json::from_value(value).map_err(|e| GithubErrors::MalformedResponse {
+        service: service.to_owned(),
+        source: e,
+    })
+}
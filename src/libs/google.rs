@@ -0,0 +1,379 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use derivative::Derivative;
+use futures::FutureExt;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::header::AUTHORIZATION;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use super::directory::{DirectorySource, SourceKind, SourceResult};
+use super::email::Email;
+use super::slack::{GroupId, SlackUserId, UserId};
+use super::{SlackUser, SlackUserGroup};
+use crate::error::DirectoryErrors;
+
+const DIRECTORY_API_URL: &str = "https://admin.googleapis.com/admin/directory/v1";
+const DIRECTORY_SCOPES: &str = "https://www.googleapis.com/auth/admin.directory.user.readonly \
+    https://www.googleapis.com/auth/admin.directory.group.readonly";
+const JWT_BEARER_GRANT: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+/// How long the signed assertion exchanged for an access token is good for, the most Google
+/// allows.
+const ASSERTION_LIFETIME_SECONDS: u64 = 60 * 60;
+/// How long to wait for Google, the same as the defaults for Slack.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const USERS_PAGE_SIZE: &str = "500";
+const MEMBERS_PAGE_SIZE: &str = "200";
+
+/// The parts of a service account key file that are needed to sign in as it.
+#[derive(Derivative, Deserialize)]
+#[derivative(Debug)]
+struct ServiceAccount {
+    client_email: String,
+    #[derivative(Debug = "ignore")]
+    private_key: String,
+    token_uri: String,
+}
+
+#[derive(Debug, Serialize)]
+struct AssertionClaims<'a> {
+    iss: &'a str,
+    sub: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: u64,
+    exp: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Deserialize)]
+struct UsersResponse {
+    #[serde(default)]
+    users: Vec<GoogleUser>,
+    next_page_token: Option<String>,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Deserialize)]
+struct GoogleUser {
+    id: String,
+    primary_email: String,
+    name: Option<GoogleUserName>,
+    #[serde(default)]
+    suspended: bool,
+    #[serde(default)]
+    archived: bool,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Deserialize)]
+struct GoogleUserName {
+    full_name: Option<String>,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Deserialize)]
+struct GroupsResponse {
+    #[serde(default)]
+    groups: Vec<GoogleGroup>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleGroup {
+    id: String,
+    name: String,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Deserialize)]
+struct MembersResponse {
+    #[serde(default)]
+    members: Vec<GoogleMember>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GoogleMember {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// Lists users and groups through the Google Admin SDK Directory API, signed in as a service
+/// account with domain-wide delegation that acts as `admin_email`.
+#[derive(Debug)]
+pub struct GoogleDirectory {
+    account: ServiceAccount,
+    admin_email: String,
+    customer: String,
+    client: reqwest::Client,
+}
+
+impl GoogleDirectory {
+    pub fn new(
+        key_file: &Path,
+        admin_email: &str,
+        customer: &str,
+    ) -> Result<Self, DirectoryErrors> {
+        let to_error = |e: anyhow::Error| DirectoryErrors::UnableToReadCredentials {
+            path: key_file.display().to_string(),
+            source: e,
+        };
+        let contents = fs::read_to_string(key_file).map_err(|e| to_error(e.into()))?;
+        let account: ServiceAccount =
+            serde_json::from_str(&contents).map_err(|e| to_error(e.into()))?;
+
+        let client = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| DirectoryErrors::UnableToBuildClient {
+                directory: SourceKind::Google.to_string(),
+                source: e,
+            })?;
+
+        info!(
+            "Reading Google Workspace as {} on behalf of {}",
+            account.client_email, admin_email
+        );
+        Ok(Self {
+            account,
+            admin_email: admin_email.to_owned(),
+            customer: customer.to_owned(),
+            client,
+        })
+    }
+
+    /// Exchanges a signed assertion for an access token. Listing users and groups only takes a
+    /// couple of minutes, so each listing signs in anew rather than keeping the token around.
+    async fn access_token(&self) -> Result<String, anyhow::Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let claims = AssertionClaims {
+            iss: &self.account.client_email,
+            sub: &self.admin_email,
+            scope: DIRECTORY_SCOPES,
+            aud: &self.account.token_uri,
+            iat: now,
+            exp: now + ASSERTION_LIFETIME_SECONDS,
+        };
+        let key = EncodingKey::from_rsa_pem(self.account.private_key.as_bytes())?;
+        let assertion = jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &key)?;
+
+        let body = self
+            .client
+            .post(&self.account.token_uri)
+            .form(&[("grant_type", JWT_BEARER_GRANT), ("assertion", &assertion)])
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let response: TokenResponse = serde_json::from_str(&body)?;
+        Ok(response.access_token)
+    }
+
+    async fn get<T>(
+        &self,
+        token: &str,
+        url: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, anyhow::Error>
+    where
+        T: DeserializeOwned,
+    {
+        let body = self
+            .client
+            .get(url)
+            .query(query)
+            .header(AUTHORIZATION, format!("Bearer {}", token))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    async fn fetch_users(&self) -> Result<BTreeSet<SlackUser>, anyhow::Error> {
+        let token = self.access_token().await?;
+        let url = format!("{}/users", DIRECTORY_API_URL);
+
+        let mut users = BTreeSet::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut query = vec![
+                ("customer", self.customer.as_str()),
+                ("maxResults", USERS_PAGE_SIZE),
+            ];
+            if let Some(page_token) = &page_token {
+                query.push(("pageToken", page_token));
+            }
+
+            let page: UsersResponse = self.get(&token, &url, &query).await?;
+            users.extend(
+                page.users
+                    .into_iter()
+                    .filter(|user| !user.suspended && !user.archived)
+                    .filter_map(|user| match to_user(user) {
+                        Ok(user) => Some(user),
+                        Err(e) => {
+                            warn!("Leaving out Google user. Error: {}", e);
+                            None
+                        }
+                    }),
+            );
+
+            page_token = page.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+
+        info!("Fetched {} users from Google Workspace", users.len());
+        Ok(users)
+    }
+
+    async fn fetch_groups(&self) -> Result<BTreeSet<SlackUserGroup>, anyhow::Error> {
+        let token = self.access_token().await?;
+        let url = format!("{}/groups", DIRECTORY_API_URL);
+
+        let mut groups = BTreeSet::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut query = vec![("customer", self.customer.as_str())];
+            if let Some(page_token) = &page_token {
+                query.push(("pageToken", page_token));
+            }
+
+            let page: GroupsResponse = self.get(&token, &url, &query).await?;
+            for group in page.groups {
+                let users = self.fetch_members(&token, &group.id).await?;
+                groups.insert(SlackUserGroup {
+                    id: GroupId::unchecked(group.id),
+                    name: group.name,
+                    users,
+                    previous_names: BTreeSet::new(),
+                    synthetic: false,
+                });
+            }
+
+            page_token = page.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+
+        info!("Fetched {} groups from Google Workspace", groups.len());
+        Ok(groups)
+    }
+
+    /// The users in group `id`. Nested groups aren't expanded.
+    async fn fetch_members(
+        &self,
+        token: &str,
+        id: &str,
+    ) -> Result<BTreeSet<SlackUserId>, anyhow::Error> {
+        debug!("Fetching members of Google group {}", id);
+        let url = format!("{}/groups/{}/members", DIRECTORY_API_URL, id);
+
+        let mut members = BTreeSet::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut query = vec![("maxResults", MEMBERS_PAGE_SIZE)];
+            if let Some(page_token) = &page_token {
+                query.push(("pageToken", page_token));
+            }
+
+            let page: MembersResponse = self.get(token, &url, &query).await?;
+            members.extend(
+                page.members
+                    .into_iter()
+                    .filter(|member| member.kind == "USER")
+                    .map(|member| SlackUserId {
+                        id: UserId::unchecked(member.id),
+                    }),
+            );
+
+            page_token = page.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(members)
+    }
+}
+
+impl DirectorySource for GoogleDirectory {
+    fn kind(&self) -> SourceKind {
+        SourceKind::Google
+    }
+
+    fn list_users(&self) -> SourceResult<'_, BTreeSet<SlackUser>> {
+        async move {
+            self.fetch_users()
+                .await
+                .map_err(|e| DirectoryErrors::UnableToList {
+                    directory: SourceKind::Google.to_string(),
+                    source: e,
+                })
+        }
+        .boxed()
+    }
+
+    fn list_groups(&self) -> SourceResult<'_, BTreeSet<SlackUserGroup>> {
+        async move {
+            self.fetch_groups()
+                .await
+                .map_err(|e| DirectoryErrors::UnableToList {
+                    directory: SourceKind::Google.to_string(),
+                    source: e,
+                })
+        }
+        .boxed()
+    }
+}
+
+/// Maps a Google user onto the fields a Slack user has. Google has no timezone or profile photo
+/// sizes to offer, so those are left empty.
+fn to_user(user: GoogleUser) -> Result<SlackUser, String> {
+    let GoogleUser {
+        id,
+        primary_email,
+        name,
+        ..
+    } = user;
+    let email: Email = primary_email.parse()?;
+    let name = name
+        .and_then(|name| name.full_name)
+        .unwrap_or(primary_email);
+
+    Ok(SlackUser {
+        id: UserId::unchecked(id),
+        name,
+        display_name: None,
+        real_name_normalized: None,
+        email,
+        previous_emails: BTreeSet::new(),
+        avatars: BTreeMap::new(),
+        tz: None,
+        annotations: BTreeMap::new(),
+        external: false,
+        guest: false,
+        backfilled: false,
+        github_login: None,
+        team_id: None,
+        teams: BTreeSet::new(),
+    })
+}
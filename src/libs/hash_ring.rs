@@ -0,0 +1,47 @@
+use std::collections::BTreeMap;
+
+use sha2::{Digest, Sha256};
+
+/// Points each node gets on the ring. More points spread keys more evenly between nodes.
+const POINTS_PER_NODE: usize = 160;
+
+/// A consistent hash ring, which maps keys to one of a fixed set of nodes. Adding or removing a
+/// node only moves the keys that land on its points, rather than reshuffling all of them.
+///
+/// Nodes are placed by name rather than by their position in the list, so listing them in a
+/// different order doesn't move any keys.
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    points: BTreeMap<u64, usize>,
+}
+
+impl HashRing {
+    pub fn new<S: AsRef<str>>(nodes: &[S]) -> Self {
+        let mut points = BTreeMap::new();
+        for (index, node) in nodes.iter().enumerate() {
+            for point in 0..POINTS_PER_NODE {
+                points.insert(hash(&format!("{}#{}", node.as_ref(), point)), index);
+            }
+        }
+
+        Self { points }
+    }
+
+    /// Index of the node `key` belongs to: the owner of the first point at or after its hash.
+    pub fn node(&self, key: &str) -> usize {
+        let hash = hash(key);
+        self.points
+            .range(hash..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map_or(0, |(_, node)| *node)
+    }
+}
+
+/// A hash that stays the same across builds and machines, which `std`'s hashers don't promise.
+fn hash(value: &str) -> u64 {
+    let digest = Sha256::digest(value.as_bytes());
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(bytes)
+}
@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// How a component last said it was doing.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Serialize)]
+pub struct ComponentStatus {
+    pub healthy: bool,
+    pub message: String,
+    /// When the component last reported, in seconds since the epoch.
+    pub updated_at: u64,
+}
+
+/// One part of the process, like the web server or the updater, reporting its own health so a
+/// failing one doesn't hide how the others are doing.
+#[derive(Debug, Clone)]
+pub struct ComponentHealth {
+    status: Arc<RwLock<ComponentStatus>>,
+}
+
+impl ComponentHealth {
+    fn new() -> Self {
+        Self {
+            status: Arc::new(RwLock::new(ComponentStatus {
+                healthy: false,
+                message: "starting".to_owned(),
+                updated_at: now(),
+            })),
+        }
+    }
+
+    pub fn healthy(&self, message: &str) {
+        self.set(true, message);
+    }
+
+    pub fn unhealthy(&self, message: &str) {
+        self.set(false, message);
+    }
+
+    pub fn status(&self) -> ComponentStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    fn set(&self, healthy: bool, message: &str) {
+        *self.status.write().unwrap() = ComponentStatus {
+            healthy,
+            message: message.to_owned(),
+            updated_at: now(),
+        };
+    }
+}
+
+/// Every component running in the process, by name. Each starts out unhealthy until it reports.
+#[derive(Debug, Clone, Default)]
+pub struct Components {
+    components: Arc<RwLock<BTreeMap<String, ComponentHealth>>>,
+}
+
+impl Components {
+    pub fn register(&self, name: &str) -> ComponentHealth {
+        let health = ComponentHealth::new();
+        self.components
+            .write()
+            .unwrap()
+            .insert(name.to_owned(), health.clone());
+        health
+    }
+
+    pub fn get(&self, name: &str) -> Option<ComponentStatus> {
+        self.components
+            .read()
+            .unwrap()
+            .get(name)
+            .map(ComponentHealth::status)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,93 @@
+use std::collections::BTreeSet;
+
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+
+use super::slack::UserId;
+use super::{SlackUser, SlackUserGroup};
+
+/// A group's name and members as one sync saw them.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupSnapshot {
+    pub timestamp: u64,
+    pub name: String,
+    pub members: BTreeSet<UserId>,
+}
+
+impl GroupSnapshot {
+    pub fn new(group: &SlackUserGroup, timestamp: u64) -> Self {
+        Self {
+            timestamp,
+            name: group.name.clone(),
+            members: group.users.iter().map(|user| user.id.clone()).collect(),
+        }
+    }
+
+    /// Whether `other` has the same name and members, whenever it was taken.
+    pub fn same_as(&self, other: &GroupSnapshot) -> bool {
+        self.name == other.name && self.members == other.members
+    }
+}
+
+/// A user's record as one sync saw it.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserVersion {
+    pub timestamp: u64,
+    pub user: SlackUser,
+}
+
+/// A snapshot along with how its members differ from the snapshot before it.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Serialize)]
+pub struct MembershipChange {
+    pub timestamp: u64,
+    pub name: String,
+    pub members: BTreeSet<UserId>,
+    pub added: BTreeSet<UserId>,
+    pub removed: BTreeSet<UserId>,
+}
+
+/// The changes between consecutive `snapshots`, which are oldest first. With `since`, the first
+/// change is the snapshot that was current at that time.
+pub fn changes(snapshots: &[GroupSnapshot], since: Option<u64>) -> Vec<MembershipChange> {
+    let start = since
+        .and_then(|since| {
+            snapshots
+                .iter()
+                .rposition(|snapshot| snapshot.timestamp <= since)
+        })
+        .unwrap_or(0);
+
+    let no_members = BTreeSet::new();
+    snapshots
+        .iter()
+        .enumerate()
+        .skip(start)
+        .map(|(index, snapshot)| {
+            let previous = match index {
+                0 => &no_members,
+                _ => &snapshots[index - 1].members,
+            };
+            MembershipChange {
+                timestamp: snapshot.timestamp,
+                name: snapshot.name.clone(),
+                members: snapshot.members.clone(),
+                added: snapshot.members.difference(previous).cloned().collect(),
+                removed: previous.difference(&snapshot.members).cloned().collect(),
+            }
+        })
+        .collect()
+}
+
+/// Reads a time given as seconds since the epoch or as RFC 3339, e.g. `2024-01-01T00:00:00Z`.
+pub fn parse_timestamp(value: &str) -> Result<u64, String> {
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Ok(seconds);
+    }
+
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.timestamp().max(0) as u64)
+        .map_err(|e| format!("invalid time `{}`: {}", value, e))
+}
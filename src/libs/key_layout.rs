@@ -0,0 +1,214 @@
+use std::borrow::Cow;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Keys whose last part is looked up lowercased, but that versions from before lowercasing wrote
+/// as given.
+const LOWERCASED_KEY_PREFIXES: &[&str] =
+    &["user:email:", "user_group:name:", "user_group:renamed:"];
+
+/// How group names are turned into the keys groups are looked up by name under.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum NameFolding {
+    /// Only lowercased, so `José` and `jose` are different groups.
+    Lowercase,
+    /// NFKC normalized, case folded and stripped of diacritics, so `José`, `JOSÉ` and `jose` are
+    /// all the same group.
+    Unicode,
+}
+
+impl Default for NameFolding {
+    fn default() -> Self {
+        NameFolding::Lowercase
+    }
+}
+
+impl FromStr for NameFolding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lowercase" => Ok(NameFolding::Lowercase),
+            "unicode" => Ok(NameFolding::Unicode),
+            other => Err(format!(
+                "unknown name folding {}, expected lowercase or unicode",
+                other
+            )),
+        }
+    }
+}
+
+impl NameFolding {
+    pub fn fold(self, name: &str) -> String {
+        match self {
+            NameFolding::Lowercase => name.to_lowercase(),
+            NameFolding::Unicode => {
+                let folded: String = name.nfkc().flat_map(fold_case).collect();
+                folded
+                    .nfd()
+                    .filter(|c| !is_combining_mark(*c))
+                    .nfc()
+                    .collect()
+            }
+        }
+    }
+}
+
+/// `c` case folded. Lowercasing covers all but the few letters whose folded form differs.
+fn fold_case(c: char) -> Vec<char> {
+    match c {
+        'ß' | 'ẞ' => vec!['s', 's'],
+        'ς' => vec!['σ'],
+        c => c.to_lowercase().collect(),
+    }
+}
+
+/// Where keys live in Redis: under an optional prefix, and, while a migration to a prefix is under
+/// way, also in the unprefixed layout earlier versions read.
+#[derive(Debug, Clone, Default)]
+pub struct KeyLayout {
+    prefix: String,
+    dual_write: bool,
+    name_folding: NameFolding,
+}
+
+impl KeyLayout {
+    /// Keys under `prefix`. With `dual_write`, writes also go to the unprefixed keys and lookups
+    /// that miss fall back to them, so web servers that don't know about the prefix yet keep
+    /// working and nothing goes missing before `migrate` has copied it over.
+    pub fn new(prefix: Option<&str>, dual_write: bool) -> Self {
+        let prefix = prefix.unwrap_or_default().to_owned();
+        Self {
+            dual_write: dual_write && !prefix.is_empty(),
+            prefix,
+            name_folding: NameFolding::default(),
+        }
+    }
+
+    /// Group names folded with `name_folding` in the keys they're looked up under.
+    pub fn with_name_folding(mut self, name_folding: NameFolding) -> Self {
+        self.name_folding = name_folding;
+        self
+    }
+
+    /// `name` as it appears in the keys groups are looked up by name under.
+    pub fn fold_name(&self, name: &str) -> String {
+        self.name_folding.fold(name)
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn dual_write(&self) -> bool {
+        self.dual_write
+    }
+
+    pub fn name_folding(&self) -> NameFolding {
+        self.name_folding
+    }
+
+    /// Where `key` is read from and written to.
+    pub fn key<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        if self.prefix.is_empty() {
+            Cow::Borrowed(key)
+        } else {
+            Cow::Owned(format!("{}{}", self.prefix, key))
+        }
+    }
+
+    /// The unprefixed `key` too, while dual writing.
+    pub fn legacy_key<'a>(&self, key: &'a str) -> Option<&'a str> {
+        if self.dual_write {
+            Some(key)
+        } else {
+            None
+        }
+    }
+
+    /// Every key a write to `key` goes to.
+    pub fn write_keys(&self, key: &str) -> Vec<String> {
+        let mut keys = vec![self.key(key).into_owned()];
+        keys.extend(self.legacy_key(key).map(str::to_owned));
+        keys
+    }
+
+    /// A SCAN `pattern` narrowed to keys under the prefix.
+    pub fn pattern(&self, pattern: &str) -> String {
+        let mut prefixed = String::with_capacity(self.prefix.len() + pattern.len());
+        for c in self.prefix.chars() {
+            if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+                prefixed.push('\\');
+            }
+            prefixed.push(c);
+        }
+        prefixed.push_str(pattern);
+        prefixed
+    }
+
+    /// `key` as the rest of the app knows it, without the prefix.
+    pub fn strip(&self, mut key: String) -> String {
+        if key.starts_with(&self.prefix) {
+            key.replace_range(..self.prefix.len(), "");
+        }
+        key
+    }
+}
+
+/// What `migrate` found in the layout earlier versions wrote, and what it did about it.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MigrationReport {
+    /// Keys the app wrote without a prefix.
+    pub legacy_keys: usize,
+    /// Legacy keys that belong somewhere else in the new layout.
+    pub to_migrate: usize,
+    pub copied: usize,
+    /// Keys whose new home had already been written, e.g. by a dual writing updater. The newer
+    /// value there is kept.
+    pub already_present: usize,
+    /// Keys that expired or were deleted between being found and copied.
+    pub vanished: usize,
+    pub deleted: usize,
+    /// Whether keys were copied, rather than only counted.
+    pub applied: bool,
+}
+
+/// Where a key written by an earlier version belongs: under `layout`'s prefix, with emails and
+/// group names lowercased. `None` when it's already there.
+pub fn migrated_key(layout: &KeyLayout, key: &str) -> Option<String> {
+    let migrated = match LOWERCASED_KEY_PREFIXES
+        .iter()
+        .find(|prefix| key.starts_with(*prefix))
+    {
+        Some(prefix) => format!("{}{}", prefix, key[prefix.len()..].to_lowercase()),
+        None => key.to_owned(),
+    };
+    let migrated = layout.key(&migrated).into_owned();
+
+    if migrated == key {
+        None
+    } else {
+        Some(migrated)
+    }
+}
+
+/// Checks a `--key-prefix` can't be mistaken for the start of an unprefixed key, which would mix
+/// the two layouts up.
+pub fn parse_prefix(prefix: &str) -> Result<String, String> {
+    if prefix.is_empty() {
+        return Err("prefix can't be empty".to_owned());
+    }
+
+    match super::redis::app_key_prefix(prefix) {
+        Some(app_prefix) => Err(format!(
+            "prefix can't start with `{}`, which unprefixed keys start with",
+            app_prefix
+        )),
+        None => Ok(prefix.to_owned()),
+    }
+}
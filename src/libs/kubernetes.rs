@@ -0,0 +1,361 @@
+use std::env;
+use std::fs;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::debug;
+
+use crate::error::KubernetesErrors;
+
+/// Where Kubernetes mounts the pod's service account token, its CA and the pod's namespace.
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a sync holds the Lease for, the same time the Redis lock is kept for.
+pub const SYNC_LEASE_DURATION: Duration = Duration::from_secs(2 * 60);
+/// Longest event message kept, as the API server rejects much longer ones.
+const MAX_EVENT_MESSAGE_LENGTH: usize = 1024;
+
+/// Where the lock keeping two servers from syncing at once is kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockBackend {
+    /// A key in Redis.
+    Redis,
+    /// A coordination.k8s.io Lease in the pod's namespace.
+    K8s,
+}
+
+impl FromStr for LockBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "redis" => Ok(LockBackend::Redis),
+            "k8s" => Ok(LockBackend::K8s),
+            other => Err(format!(
+                "unknown lock backend {}, expected redis or k8s",
+                other
+            )),
+        }
+    }
+}
+
+/// The name of the pod this runs in: `POD_NAME` when the downward API sets it, otherwise the
+/// hostname, which Kubernetes sets to the pod's name. `None` outside Kubernetes.
+pub fn pod_name() -> Option<String> {
+    let non_empty = |name: String| Some(name).filter(|name| !name.is_empty());
+    env::var("POD_NAME").ok().and_then(non_empty).or_else(|| {
+        env::var_os("KUBERNETES_SERVICE_HOST")?;
+        env::var("HOSTNAME").ok().and_then(non_empty)
+    })
+}
+
+/// Talks to the API server of the cluster the pod runs in, as the pod's service account.
+#[derive(Debug, Clone)]
+pub struct KubernetesClient {
+    base_url: String,
+    namespace: String,
+    client: reqwest::Client,
+}
+
+impl KubernetesClient {
+    /// Connects the way in-cluster clients do, through the `kubernetes` service and with the
+    /// mounted service account. The namespace can be given as `POD_NAMESPACE` instead.
+    pub fn in_cluster() -> Result<Self, KubernetesErrors> {
+        let host =
+            env::var("KUBERNETES_SERVICE_HOST").map_err(|_| KubernetesErrors::NotInCluster)?;
+        let port = env::var("KUBERNETES_SERVICE_PORT").unwrap_or_else(|_| "443".to_owned());
+        // IPv6 service addresses have to be bracketed in URLs.
+        let host = if host.contains(':') {
+            format!("[{}]", host)
+        } else {
+            host
+        };
+
+        let namespace = match env::var("POD_NAMESPACE") {
+            Ok(namespace) => namespace,
+            Err(_) => read_service_account_file("namespace")?.trim().to_owned(),
+        };
+
+        let ca_path = format!("{}/ca.crt", SERVICE_ACCOUNT_DIR);
+        let ca = reqwest::Certificate::from_pem(read_service_account_file("ca.crt")?.as_bytes())
+            .map_err(|e| KubernetesErrors::InvalidCertificate {
+                path: ca_path,
+                source: e,
+            })?;
+        let client = reqwest::Client::builder()
+            .add_root_certificate(ca)
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| KubernetesErrors::UnableToConnect { source: e })?;
+
+        Ok(Self {
+            base_url: format!("https://{}:{}", host, port),
+            namespace,
+            client,
+        })
+    }
+
+    /// Takes the Lease `name`, or renews it if `holder` already has it, holding it for
+    /// `duration`. Returns whether `holder` now has it. A Lease another holder let lapse is taken
+    /// over. Writes carry the version that was read, so two replicas can't both take it.
+    pub async fn acquire_lease(
+        &self,
+        name: &str,
+        holder: &str,
+        duration: Duration,
+    ) -> Result<bool, KubernetesErrors> {
+        let leases = format!(
+            "{}/apis/coordination.k8s.io/v1/namespaces/{}/leases",
+            self.base_url, self.namespace
+        );
+        let resource = format!("lease {}", name);
+        let now = Utc::now();
+
+        let response = self
+            .send(self.client.get(&format!("{}/{}", leases, name)), &resource)
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            let lease = Lease::new(name, holder, duration, now);
+            let response = self
+                .send(self.client.post(&leases).body(lease.to_json()), &resource)
+                .await?;
+            return written(response, &resource);
+        }
+
+        let lease: Lease = parse(response, &resource).await?;
+        if !lease.spec.is_available_to(holder, now) {
+            debug!(
+                "Lease {} is held by {}",
+                name,
+                lease.spec.holder_identity.as_deref().unwrap_or_default()
+            );
+            return Ok(false);
+        }
+
+        let lease = lease.renewed_by(holder, duration, now);
+        let response = self
+            .send(
+                self.client
+                    .put(&format!("{}/{}", leases, name))
+                    .body(lease.to_json()),
+                &resource,
+            )
+            .await?;
+        written(response, &resource)
+    }
+
+    /// Records an event on the pod `pod`, shown by `kubectl describe pod`. Warnings are for
+    /// things that went wrong.
+    pub async fn record_pod_event(
+        &self,
+        pod: &str,
+        reason: &str,
+        message: &str,
+        warning: bool,
+    ) -> Result<(), KubernetesErrors> {
+        let now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        let mut involved = json!({
+            "apiVersion": "v1",
+            "kind": "Pod",
+            "namespace": self.namespace,
+            "name": pod,
+        });
+        if let Ok(uid) = env::var("POD_UID") {
+            involved["uid"] = json!(uid);
+        }
+        let event = json!({
+            "apiVersion": "v1",
+            "kind": "Event",
+            "metadata": {
+                "generateName": format!("{}.", pod),
+                "namespace": self.namespace,
+            },
+            "involvedObject": involved,
+            "reason": reason,
+            "message": message.chars().take(MAX_EVENT_MESSAGE_LENGTH).collect::<String>(),
+            "type": if warning { "Warning" } else { "Normal" },
+            "firstTimestamp": now,
+            "lastTimestamp": now,
+            "count": 1,
+            "source": { "component": "slack-user-cache" },
+        });
+
+        let resource = format!("event on pod {}", pod);
+        let url = format!(
+            "{}/api/v1/namespaces/{}/events",
+            self.base_url, self.namespace
+        );
+        self.send(self.client.post(&url).body(event.to_string()), &resource)
+            .await?
+            .error_for_status()
+            .map_err(|e| KubernetesErrors::Request {
+                resource,
+                source: e,
+            })?;
+
+        Ok(())
+    }
+
+    /// Sends `request` as the service account. Its token is read for each request, as the ones
+    /// Kubernetes mounts are rotated.
+    async fn send(
+        &self,
+        request: RequestBuilder,
+        resource: &str,
+    ) -> Result<Response, KubernetesErrors> {
+        let token = read_service_account_file("token")?;
+        request
+            .header(AUTHORIZATION, format!("Bearer {}", token.trim()))
+            .header(CONTENT_TYPE, "application/json")
+            .send()
+            .await
+            .map_err(|e| KubernetesErrors::Request {
+                resource: resource.to_owned(),
+                source: e,
+            })
+    }
+}
+
+/// A coordination.k8s.io/v1 Lease, with only the fields used here.
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Lease {
+    api_version: String,
+    kind: String,
+    metadata: LeaseMetadata,
+    #[serde(default)]
+    spec: LeaseSpec,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LeaseMetadata {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    resource_version: Option<String>,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct LeaseSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    holder_identity: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lease_duration_seconds: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    acquire_time: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    renew_time: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lease_transitions: Option<u64>,
+}
+
+impl Lease {
+    fn new(name: &str, holder: &str, duration: Duration, now: DateTime<Utc>) -> Self {
+        let lease = Lease {
+            api_version: "coordination.k8s.io/v1".to_owned(),
+            kind: "Lease".to_owned(),
+            metadata: LeaseMetadata {
+                name: name.to_owned(),
+                resource_version: None,
+            },
+            spec: LeaseSpec::default(),
+        };
+        lease.renewed_by(holder, duration, now)
+    }
+
+    /// The Lease held by `holder` from `now` for `duration`, counting a transition when it
+    /// changes hands.
+    fn renewed_by(mut self, holder: &str, duration: Duration, now: DateTime<Utc>) -> Self {
+        let now = micro_time(now);
+        if self.spec.holder_identity.as_deref() != Some(holder) {
+            self.spec.holder_identity = Some(holder.to_owned());
+            self.spec.acquire_time = Some(now.clone());
+            if self.metadata.resource_version.is_some() {
+                self.spec.lease_transitions = Some(self.spec.lease_transitions.unwrap_or(0) + 1);
+            }
+        }
+        self.spec.renew_time = Some(now);
+        self.spec.lease_duration_seconds = Some(duration.as_secs().max(1));
+        self
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("leases are always serializable")
+    }
+}
+
+impl LeaseSpec {
+    /// Whether `holder` can take the Lease at `now`: it already has it, no one does, or it
+    /// wasn't renewed in time. One without a renew time that can be read counts as lapsed.
+    fn is_available_to(&self, holder: &str, now: DateTime<Utc>) -> bool {
+        let current = match self.holder_identity.as_deref() {
+            None | Some("") => return true,
+            Some(current) => current,
+        };
+        if current == holder {
+            return true;
+        }
+
+        let renewed = self
+            .renew_time
+            .as_deref()
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok());
+        match (renewed, self.lease_duration_seconds) {
+            (Some(renewed), Some(seconds)) => {
+                renewed.with_timezone(&Utc) + chrono::Duration::seconds(seconds as i64) < now
+            }
+            _ => true,
+        }
+    }
+}
+
+/// A time in the `MicroTime` format Leases use.
+fn micro_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/// Whether a write went through. A conflict means another replica wrote the Lease first.
+fn written(response: Response, resource: &str) -> Result<bool, KubernetesErrors> {
+    if response.status() == StatusCode::CONFLICT {
+        return Ok(false);
+    }
+
+    response
+        .error_for_status()
+        .map_err(|e| KubernetesErrors::Request {
+            resource: resource.to_owned(),
+            source: e,
+        })?;
+    Ok(true)
+}
+
+async fn parse<T: serde::de::DeserializeOwned>(
+    response: Response,
+    resource: &str,
+) -> Result<T, KubernetesErrors> {
+    let request_error = |e| KubernetesErrors::Request {
+        resource: resource.to_owned(),
+        source: e,
+    };
+    let body = response
+        .error_for_status()
+        .map_err(request_error)?
+        .text()
+        .await
+        .map_err(request_error)?;
+    serde_json::from_str(&body).map_err(|e| KubernetesErrors::MalformedResponse {
+        resource: resource.to_owned(),
+        source: e,
+    })
+}
+
+fn read_service_account_file(name: &str) -> Result<String, KubernetesErrors> {
+    let path = format!("{}/{}", SERVICE_ACCOUNT_DIR, name);
+    fs::read_to_string(&path).map_err(|e| KubernetesErrors::UnableToRead { path, source: e })
+}
@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Upper bounds of the latency buckets, in seconds. Lookups are expected to take a few
+/// milliseconds, so the buckets are finest there.
+const BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The last observation that fell into a bucket, linking it to the trace it was part of.
+#[derive(Debug, Clone)]
+struct Exemplar {
+    trace_id: String,
+    seconds: f64,
+    timestamp: f64,
+}
+
+#[derive(Debug)]
+struct Histogram {
+    /// Observations per bucket, with the last one for those over every bound.
+    counts: Vec<u64>,
+    exemplars: Vec<Option<Exemplar>>,
+    sum: f64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; BUCKETS.len() + 1],
+            exemplars: vec![None; BUCKETS.len() + 1],
+            sum: 0.0,
+        }
+    }
+}
+
+/// Latency histograms of one metric, one for each value of its label.
+#[derive(Debug)]
+pub struct LatencyHistograms {
+    name: &'static str,
+    help: &'static str,
+    label: &'static str,
+    histograms: Mutex<BTreeMap<String, Histogram>>,
+}
+
+impl LatencyHistograms {
+    pub fn new(name: &'static str, help: &'static str, label: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            label,
+            histograms: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Records that something labelled `value` took `elapsed`, as part of the trace `trace_id`.
+    pub fn observe(&self, value: &str, elapsed: Duration, trace_id: Option<&str>) {
+        let seconds = elapsed.as_secs_f64();
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or_else(|| BUCKETS.len());
+
+        let mut histograms = match self.histograms.lock() {
+            Ok(histograms) => histograms,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let histogram = histograms.entry(value.to_owned()).or_default();
+        histogram.counts[bucket] += 1;
+        histogram.sum += seconds;
+        if let Some(trace_id) = trace_id {
+            histogram.exemplars[bucket] = Some(Exemplar {
+                trace_id: trace_id.to_owned(),
+                seconds,
+                timestamp: now(),
+            });
+        }
+    }
+
+    /// Starts timing something labelled `value`, which is observed once the timer is dropped.
+    pub fn start_timer(&self, value: &'static str) -> Timer<'_> {
+        Timer {
+            histograms: self,
+            value,
+            started: Instant::now(),
+        }
+    }
+
+    /// Appends the histograms to `body` in the Prometheus text format, or in OpenMetrics with
+    /// their exemplars when `open_metrics` is set. The text format has no room for exemplars.
+    pub fn render(&self, body: &mut String, open_metrics: bool) {
+        let histograms = match self.histograms.lock() {
+            Ok(histograms) => histograms,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if histograms.is_empty() {
+            return;
+        }
+
+        let _ = writeln!(body, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(body, "# TYPE {} histogram", self.name);
+        for (value, histogram) in histograms.iter() {
+            let label = format!("{}=\"{}\"", self.label, escape_label(value));
+            let mut cumulative = 0;
+            for (index, count) in histogram.counts.iter().enumerate() {
+                cumulative += count;
+                let bound = BUCKETS
+                    .get(index)
+                    .map_or_else(|| "+Inf".to_owned(), |bound| format!("{:?}", bound));
+                let _ = write!(
+                    body,
+                    "{}_bucket{{{},le=\"{}\"}} {}",
+                    self.name, label, bound, cumulative
+                );
+                if let (true, Some(exemplar)) = (open_metrics, &histogram.exemplars[index]) {
+                    let _ = write!(
+                        body,
+                        " # {{trace_id=\"{}\"}} {} {:.3}",
+                        escape_label(&exemplar.trace_id),
+                        exemplar.seconds,
+                        exemplar.timestamp
+                    );
+                }
+                body.push('\n');
+            }
+            let _ = writeln!(body, "{}_sum{{{}}} {}", self.name, label, histogram.sum);
+            let _ = writeln!(body, "{}_count{{{}}} {}", self.name, label, cumulative);
+        }
+    }
+}
+
+/// Observes the time since it was started when dropped, with no trace to link it to.
+pub struct Timer<'a> {
+    histograms: &'a LatencyHistograms,
+    value: &'static str,
+    started: Instant,
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        self.histograms
+            .observe(self.value, self.started.elapsed(), None);
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or_default()
+}
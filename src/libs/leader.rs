@@ -0,0 +1,68 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use super::RedisServer;
+
+const LEADER_LEASE_KEY: &str = "leader_lease";
+
+/// Keeps one updater replica in charge of syncing. Every replica keeps trying to claim a Redis
+/// lease; the holder renews it well before it expires, and when the holder dies the lease lapses
+/// and a standby takes over.
+#[derive(Debug, Clone)]
+pub struct LeaderElection {
+    leading: Arc<AtomicBool>,
+}
+
+impl LeaderElection {
+    /// Makes a first claim and then keeps claiming in the background every third of `ttl`, which
+    /// is at least a second.
+    pub async fn start(redis_server: RedisServer, holder: &str, ttl: Duration) -> Self {
+        let election = Self {
+            leading: Arc::new(AtomicBool::new(false)),
+        };
+
+        let ttl_seconds = ttl.as_secs().max(1) as usize;
+        election.claim(&redis_server, holder, ttl_seconds).await;
+
+        let background = election.clone();
+        let holder = holder.to_owned();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(ttl_seconds as u64) / 3);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                background.claim(&redis_server, &holder, ttl_seconds).await;
+            }
+        });
+
+        election
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.leading.load(Ordering::SeqCst)
+    }
+
+    async fn claim(&self, redis_server: &RedisServer, holder: &str, ttl_seconds: usize) {
+        let leading = match redis_server
+            .claim_lease(LEADER_LEASE_KEY, holder, ttl_seconds)
+            .await
+        {
+            Ok(leading) => leading,
+            Err(e) => {
+                // Without Redis the lease can't be renewed either, so stop acting as leader.
+                warn!("Unable to claim leader lease. Error: {}", e);
+                false
+            }
+        };
+
+        let was_leading = self.leading.swap(leading, Ordering::SeqCst);
+        if leading && !was_leading {
+            info!("{} is now the leader", holder);
+        } else if !leading && was_leading {
+            info!("{} is no longer the leader", holder);
+        }
+    }
+}
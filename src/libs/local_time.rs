@@ -0,0 +1,60 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Timelike, Utc};
+use chrono_tz::Tz;
+
+/// A range of local hours, like `9-17` for 09:00 to 16:59. Ranges past midnight, like `22-6`,
+/// wrap around.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HourWindow {
+    start: u32,
+    end: u32,
+}
+
+impl Default for HourWindow {
+    fn default() -> Self {
+        Self { start: 9, end: 17 }
+    }
+}
+
+impl FromStr for HourWindow {
+    type Err = String;
+
+    fn from_str(window: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected hours like `9-17`, got `{}`", window);
+
+        let mut hours = window.splitn(2, '-');
+        let mut hour = || -> Result<u32, String> {
+            hours
+                .next()
+                .and_then(|hour| hour.trim().parse().ok())
+                .filter(|hour| *hour <= 24)
+                .ok_or_else(invalid)
+        };
+        let (start, end) = (hour()?, hour()?);
+        if start == end {
+            return Err(invalid());
+        }
+
+        Ok(Self { start, end })
+    }
+}
+
+impl HourWindow {
+    fn contains(&self, hour: u32) -> bool {
+        if self.start < self.end {
+            self.start <= hour && hour < self.end
+        } else {
+            hour >= self.start || hour < self.end
+        }
+    }
+
+    /// Whether it's within the window at `now` in the IANA timezone `tz`. Unknown timezones
+    /// never are.
+    pub fn contains_in(&self, tz: &str, now: DateTime<Utc>) -> bool {
+        match tz.parse::<Tz>() {
+            Ok(tz) => self.contains(now.with_timezone(&tz).hour()),
+            Err(_) => false,
+        }
+    }
+}
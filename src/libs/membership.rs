@@ -0,0 +1,61 @@
+use std::collections::BTreeSet;
+
+use super::slack::UserId;
+use super::SlackUserGroup;
+
+/// Which users are cached, going by the user groups they're in.
+#[derive(Debug, Default)]
+pub struct MembershipFilter {
+    /// Only members of these groups, when set.
+    only: Option<BTreeSet<UserId>>,
+    /// Never members of these groups, even when they're in an `only` group too.
+    exclude: BTreeSet<UserId>,
+}
+
+impl MembershipFilter {
+    /// Resolves `only` and `exclude`, each a group name or id, against `groups`. Returns the first
+    /// group that doesn't exist, as filtering by a misspelled group would quietly drop users.
+    pub fn new(
+        groups: &BTreeSet<SlackUserGroup>,
+        only: &[String],
+        exclude: &[String],
+    ) -> Result<Self, String> {
+        let members = |names: &[String]| -> Result<BTreeSet<UserId>, String> {
+            let mut members = BTreeSet::new();
+            for name in names {
+                let group = find_group(groups, name).ok_or_else(|| name.clone())?;
+                members.extend(group.users.iter().map(|user| user.id.clone()));
+            }
+            Ok(members)
+        };
+
+        Ok(Self {
+            only: if only.is_empty() {
+                None
+            } else {
+                Some(members(only)?)
+            },
+            exclude: members(exclude)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.only.is_none() && self.exclude.is_empty()
+    }
+
+    pub fn allows(&self, id: &UserId) -> bool {
+        let included = match &self.only {
+            Some(only) => only.contains(id),
+            None => true,
+        };
+        included && !self.exclude.contains(id)
+    }
+}
+
+/// The group named or with the id `name`, ignoring case and a leading `@`.
+fn find_group<'a>(groups: &'a BTreeSet<SlackUserGroup>, name: &str) -> Option<&'a SlackUserGroup> {
+    let name = name.trim().trim_start_matches('@');
+    groups
+        .iter()
+        .find(|group| group.id.as_str() == name || group.name.eq_ignore_ascii_case(name))
+}
@@ -1,5 +1,66 @@
+pub mod access_log;
+pub mod alerting;
+pub mod anonymize;
+pub mod audit;
+pub mod auth;
+pub mod backfill;
+pub mod build_info;
+pub mod changes;
+// Only `--chaos` turns it on, which release builds leave out unless built with `chaos`.
+#[cfg_attr(not(any(debug_assertions, feature = "chaos")), allow(dead_code))]
+pub mod chaos;
+pub mod codec;
+pub mod compression;
+pub mod concurrency;
+pub mod consistency;
+pub mod directory;
+pub mod email;
+pub mod fuzzy;
+pub mod github;
+pub mod google;
+pub mod hash_ring;
+pub mod health;
+pub mod history;
+pub mod key_layout;
+pub mod kubernetes;
+pub mod latency;
+pub mod leader;
+pub mod local_time;
+pub mod membership;
+pub mod oidc;
+pub mod oncall;
+pub mod paging;
+pub mod progress;
+pub mod redact;
 pub mod redis;
+pub mod report;
+pub mod schedule;
+pub mod schema;
+pub mod secrets;
+pub mod settings;
+pub mod shadow;
+pub mod shell;
 pub mod slack;
+pub mod staging;
+pub mod stats;
+pub mod summary;
+pub mod synthetic;
+pub mod systemd;
+#[cfg(feature = "transform")]
+pub mod transform;
+pub mod typeahead;
+#[cfg(feature = "ui")]
+pub mod ui;
+pub mod watchers;
+pub mod write_back;
 
-pub use redis::{RedisResponse, RedisServer};
-pub use slack::{SlackApi, SlackUser, SlackUserGroup};
+pub use access_log::{AccessEntry, AccessLog};
+pub use audit::{AuditEntry, AuditLog};
+pub use auth::ApiTokens;
+pub use email::EmailHasher;
+pub use oidc::OidcValidator;
+pub use redis::{RedisPools, RedisResponse, RedisServer};
+pub use report::SyncReport;
+pub use slack::{
+    SlackApi, SlackClientConfig, SlackUser, SlackUserGroup, SocketModeClient, TokenRotation,
+};
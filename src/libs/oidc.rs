@@ -0,0 +1,194 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tracing::{debug, info};
+
+use super::auth::{Permission, Principal};
+use crate::error::OidcErrors;
+
+/// Signing algorithms accepted from the issuer. Only RSA keys are read from the JWKS.
+const ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+];
+
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Jwk {
+    kid: Option<String>,
+    kty: String,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Scopes {
+    Joined(String),
+    List(Vec<String>),
+}
+
+impl Scopes {
+    fn iter(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+        match self {
+            Scopes::Joined(scopes) => Box::new(scopes.split_whitespace()),
+            Scopes::List(scopes) => Box::new(scopes.iter().map(String::as_str)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    scope: Option<Scopes>,
+    scp: Option<Scopes>,
+}
+
+/// Validates JWTs minted by an OIDC issuer. Scopes named like permissions (`read-users`,
+/// `read-emails`, `read-groups`, `admin`) grant them; other scopes are ignored.
+#[derive(Debug)]
+pub struct OidcValidator {
+    issuer: String,
+    audience: String,
+    jwks_url: String,
+    keys: RwLock<BTreeMap<String, Jwk>>,
+    client: reqwest::Client,
+}
+
+impl OidcValidator {
+    /// Fetches the signing keys, finding the JWKS through the issuer's discovery document when
+    /// `jwks_url` isn't given.
+    pub async fn new(
+        issuer: &str,
+        audience: &str,
+        jwks_url: Option<&str>,
+    ) -> Result<Self, OidcErrors> {
+        let client = reqwest::Client::new();
+        let jwks_url = match jwks_url {
+            Some(url) => url.to_owned(),
+            None => {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    issuer.trim_end_matches('/')
+                );
+                fetch::<Discovery>(&client, &url).await?.jwks_uri
+            }
+        };
+
+        let validator = Self {
+            issuer: issuer.to_owned(),
+            audience: audience.to_owned(),
+            jwks_url,
+            keys: RwLock::new(BTreeMap::new()),
+            client,
+        };
+        validator.refresh().await?;
+
+        Ok(validator)
+    }
+
+    /// Re-reads the JWKS so rotated keys are picked up.
+    pub async fn refresh(&self) -> Result<(), OidcErrors> {
+        let set = fetch::<JwkSet>(&self.client, &self.jwks_url).await?;
+        let keys: BTreeMap<String, Jwk> = set
+            .keys
+            .into_iter()
+            .filter(|key| key.kty == "RSA" && key.n.is_some() && key.e.is_some())
+            .map(|key| (key.kid.clone().unwrap_or_default(), key))
+            .collect();
+
+        info!("Loaded {} signing keys from {}", keys.len(), self.jwks_url);
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    /// Returns the caller for a valid token, or `None` if the token isn't a JWT from the issuer.
+    pub fn validate(&self, token: &str) -> Option<Principal> {
+        let header = jsonwebtoken::decode_header(token).ok()?;
+        let key = {
+            let keys = self.keys.read().unwrap();
+            match &header.kid {
+                Some(kid) => keys.get(kid).cloned(),
+                None if keys.len() == 1 => keys.values().next().cloned(),
+                None => None,
+            }
+        };
+        let key = match key {
+            Some(key) => key,
+            None => {
+                debug!("No signing key matches JWT key id {:?}", header.kid);
+                return None;
+            }
+        };
+
+        let mut validation = Validation {
+            iss: Some(self.issuer.clone()),
+            algorithms: ALGORITHMS.to_vec(),
+            ..Validation::default()
+        };
+        validation.set_audience(&[&self.audience]);
+
+        let decoding_key = DecodingKey::from_rsa_components(
+            key.n.as_deref().unwrap_or_default(),
+            key.e.as_deref().unwrap_or_default(),
+        );
+        let claims = match jsonwebtoken::decode::<Claims>(token, &decoding_key, &validation) {
+            Ok(data) => data.claims,
+            Err(e) => {
+                debug!("Rejecting JWT. Error: {}", e);
+                return None;
+            }
+        };
+
+        let permissions: BTreeSet<Permission> = claims
+            .scope
+            .iter()
+            .chain(claims.scp.iter())
+            .flat_map(Scopes::iter)
+            .filter_map(|scope| Permission::from_str(scope).ok())
+            .collect();
+
+        Some(Principal::new(claims.sub, permissions))
+    }
+}
+
+async fn fetch<T>(client: &reqwest::Client, url: &str) -> Result<T, OidcErrors>
+where
+    T: DeserializeOwned,
+{
+    let to_error = |e: reqwest::Error| OidcErrors::UnableToFetch {
+        url: url.to_owned(),
+        source: e,
+    };
+    let body = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(to_error)?
+        .text()
+        .await
+        .map_err(to_error)?;
+
+    serde_json::from_str(&body).map_err(|e| OidcErrors::MalformedResponse {
+        url: url.to_owned(),
+        source: e,
+    })
+}
@@ -0,0 +1,263 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use derivative::Derivative;
+use reqwest::header::{ACCEPT, AUTHORIZATION};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use super::email::Email;
+use super::slack::GroupId;
+use crate::error::OncallErrors;
+
+const PAGERDUTY_API_URL: &str = "https://api.pagerduty.com";
+const OPSGENIE_API_URL: &str = "https://api.opsgenie.com";
+/// How long to wait for PagerDuty or Opsgenie. They're asked while a request waits on the
+/// answer, so they're given up on well before the caller would give up on it.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where an on-call schedule is kept.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OncallProvider {
+    Pagerduty,
+    Opsgenie,
+}
+
+impl FromStr for OncallProvider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pagerduty" => Ok(OncallProvider::Pagerduty),
+            "opsgenie" => Ok(OncallProvider::Opsgenie),
+            other => Err(format!(
+                "unknown on-call provider {}, expected pagerduty or opsgenie",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OncallProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OncallProvider::Pagerduty => f.write_str("PagerDuty"),
+            OncallProvider::Opsgenie => f.write_str("Opsgenie"),
+        }
+    }
+}
+
+/// The on-call schedule a user group is paged through.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OncallSchedule {
+    pub provider: OncallProvider,
+    pub schedule_id: String,
+}
+
+impl OncallSchedule {
+    /// Checks the schedule id looks like a PagerDuty id or an Opsgenie UUID, as it goes into URLs.
+    pub fn validate(&self) -> Result<(), String> {
+        let id = &self.schedule_id;
+        if !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            Ok(())
+        } else {
+            Err(format!("`{}` is not a schedule id", id))
+        }
+    }
+}
+
+/// Reads one mapping per line as `<group id> <provider> <schedule id>`, skipping blank lines and
+/// `#` comments.
+pub fn load_schedules(path: &Path) -> Result<Vec<(GroupId, OncallSchedule)>, OncallErrors> {
+    let contents = fs::read_to_string(path).map_err(|e| OncallErrors::UnableToRead {
+        path: path.display().to_string(),
+        source: e,
+    })?;
+
+    let mut schedules = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let schedule = parse_line(line).map_err(|message| OncallErrors::Malformed {
+            path: path.display().to_string(),
+            line: index + 1,
+            message,
+        })?;
+        schedules.push(schedule);
+    }
+
+    info!(
+        "Loaded {} on-call schedules from {}",
+        schedules.len(),
+        path.display()
+    );
+    Ok(schedules)
+}
+
+fn parse_line(line: &str) -> Result<(GroupId, OncallSchedule), String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    match fields.as_slice() {
+        [group, provider, schedule_id] => {
+            let schedule = OncallSchedule {
+                provider: provider.parse()?,
+                schedule_id: (*schedule_id).to_owned(),
+            };
+            schedule.validate()?;
+            Ok((group.parse()?, schedule))
+        }
+        _ => Err("expected `<group id> <provider> <schedule id>`".to_owned()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PagerdutyOncalls {
+    #[serde(default)]
+    oncalls: Vec<PagerdutyOncall>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PagerdutyOncall {
+    user: Option<PagerdutyUser>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PagerdutyUser {
+    email: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpsgenieOncalls {
+    data: OpsgenieOncallData,
+}
+
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Deserialize)]
+struct OpsgenieOncallData {
+    #[serde(default)]
+    on_call_recipients: Vec<String>,
+}
+
+/// Asks PagerDuty and Opsgenie who is on call. A provider can only be asked once its API key is
+/// set.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct OncallClient {
+    #[derivative(Debug = "ignore")]
+    pagerduty_token: Option<String>,
+    #[derivative(Debug = "ignore")]
+    opsgenie_key: Option<String>,
+    client: reqwest::Client,
+}
+
+impl OncallClient {
+    pub fn new(
+        pagerduty_token: Option<String>,
+        opsgenie_key: Option<String>,
+    ) -> Result<Self, OncallErrors> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| OncallErrors::UnableToBuildClient { source: e })?;
+
+        Ok(Self {
+            pagerduty_token,
+            opsgenie_key,
+            client,
+        })
+    }
+
+    /// Emails of everyone on call in `schedule` right now.
+    pub async fn on_call(
+        &self,
+        schedule: &OncallSchedule,
+    ) -> Result<BTreeSet<Email>, OncallErrors> {
+        let emails: Vec<String> = match schedule.provider {
+            OncallProvider::Pagerduty => {
+                let url = format!(
+                    "{}/oncalls?schedule_ids[]={}&include[]=users&earliest=true",
+                    PAGERDUTY_API_URL, schedule.schedule_id
+                );
+                let token = api_key(schedule.provider, &self.pagerduty_token)?;
+                let response: PagerdutyOncalls = fetch(
+                    schedule.provider,
+                    self.client
+                        .get(&url)
+                        .header(ACCEPT, "application/vnd.pagerduty+json;version=2")
+                        .header(AUTHORIZATION, format!("Token token={}", token)),
+                )
+                .await?;
+
+                response
+                    .oncalls
+                    .into_iter()
+                    .filter_map(|oncall| oncall.user.and_then(|user| user.email))
+                    .collect()
+            }
+            OncallProvider::Opsgenie => {
+                let url = format!(
+                    "{}/v2/schedules/{}/on-calls?scheduleIdentifierType=id&flat=true",
+                    OPSGENIE_API_URL, schedule.schedule_id
+                );
+                let key = api_key(schedule.provider, &self.opsgenie_key)?;
+                let response: OpsgenieOncalls = fetch(
+                    schedule.provider,
+                    self.client
+                        .get(&url)
+                        .header(AUTHORIZATION, format!("GenieKey {}", key)),
+                )
+                .await?;
+
+                response.data.on_call_recipients
+            }
+        };
+
+        Ok(emails
+            .iter()
+            .filter_map(|email| email.parse::<Email>().ok())
+            .collect())
+    }
+}
+
+fn api_key(provider: OncallProvider, key: &Option<String>) -> Result<&str, OncallErrors> {
+    key.as_deref().ok_or(OncallErrors::NotConfigured {
+        provider: provider.to_string(),
+    })
+}
+
+async fn fetch<T>(
+    provider: OncallProvider,
+    request: reqwest::RequestBuilder,
+) -> Result<T, OncallErrors>
+where
+    T: DeserializeOwned,
+{
+    let to_error = |e: reqwest::Error| OncallErrors::UnableToFetch {
+        provider: provider.to_string(),
+        source: e,
+    };
+    let body = request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(to_error)?
+        .text()
+        .await
+        .map_err(to_error)?;
+
+    serde_json::from_str(&body).map_err(|e| OncallErrors::MalformedResponse {
+        provider: provider.to_string(),
+        source: e,
+    })
+}
@@ -0,0 +1,79 @@
+/// How a list response is cut into pages. Clients page through with `limit` and `cursor`; ones
+/// that ask for neither get at most `max_entries`, and are told when that left entries out.
+#[derive(Debug, Clone)]
+pub struct Paging {
+    limit: Option<usize>,
+    cursor: Option<String>,
+    max_entries: usize,
+}
+
+/// One page of a list, sorted by key.
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Where the next page starts, when there's one.
+    pub next_cursor: Option<String>,
+    /// Whether entries were left out of a response that didn't ask to be paged.
+    pub truncated: bool,
+}
+
+impl Paging {
+    /// Pages of `limit` entries after `cursor`, with `limit` capped at `max_entries`.
+    pub fn new(
+        limit: Option<usize>,
+        cursor: Option<String>,
+        max_entries: usize,
+    ) -> Result<Self, String> {
+        if limit == Some(0) {
+            return Err("limit must be at least 1".to_owned());
+        }
+        if cursor.as_deref() == Some("") {
+            return Err("cursor can't be empty".to_owned());
+        }
+
+        Ok(Self {
+            limit,
+            cursor,
+            max_entries,
+        })
+    }
+
+    /// The page of `items` this asks for. A cursor is the key of the entry before the page, so
+    /// entries added or removed between requests don't shift the pages after them.
+    pub fn page<T, F>(&self, mut items: Vec<T>, key: F) -> Page<T>
+    where
+        F: Fn(&T) -> &str,
+    {
+        items.sort_by(|a, b| key(a).cmp(key(b)));
+        if let Some(cursor) = &self.cursor {
+            items.retain(|item| key(item) > cursor.as_str());
+        }
+
+        let limit = self
+            .limit
+            .map_or(self.max_entries, |limit| limit.min(self.max_entries));
+        if items.len() <= limit {
+            return Page {
+                items,
+                next_cursor: None,
+                truncated: false,
+            };
+        }
+
+        items.truncate(limit);
+        Page {
+            next_cursor: items.last().map(|item| key(item).to_owned()),
+            truncated: self.limit.is_none() && self.cursor.is_none(),
+            items,
+        }
+    }
+}
+
+/// Reads `--max-list-entries`, which has to let at least one entry through.
+pub fn parse_max_entries(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_owned()),
+        Ok(max_entries) => Ok(max_entries),
+        Err(e) => Err(e.to_string()),
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use derivative::Derivative;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// How often a bar is redrawn while nothing happens, so a sync waiting on Slack's rate limits
+/// still looks alive.
+const TICK_MILLIS: u64 = 200;
+
+/// Progress bars for long syncs, drawn on stderr when it's a terminal. Anywhere else no bar is
+/// drawn, and the log lines are all there is to go by.
+///
+/// It's also what log lines are written with, so while a bar is drawn they're printed above it
+/// rather than over it.
+#[derive(Derivative, Clone, Default)]
+#[derivative(Debug)]
+pub struct Progress {
+    #[derivative(Debug = "ignore")]
+    active: Arc<Mutex<Option<ProgressBar>>>,
+    /// Log lines go to stderr rather than stdout.
+    stderr: bool,
+}
+
+impl Progress {
+    /// Writes log lines to stderr, for commands whose output goes to stdout.
+    pub fn logging_to_stderr() -> Self {
+        Self {
+            stderr: true,
+            ..Self::default()
+        }
+    }
+
+    /// Whether bars are drawn at all, so estimates that take work to come by can be skipped.
+    pub fn is_drawn(&self) -> bool {
+        !ProgressDrawTarget::stderr().is_hidden()
+    }
+
+    /// Starts a bar counting `unit` up to `estimate`, or a spinner with a running count when
+    /// there's no estimate. It's drawn until the returned `Bar` is dropped.
+    pub fn start(&self, unit: &'static str, estimate: Option<u64>) -> Bar {
+        let bar = match estimate {
+            Some(len) => ProgressBar::new(len).with_style(
+                ProgressStyle::default_bar()
+                    .template("{spinner} {msg} [{bar:40}] {pos}/{len} (eta {eta})")
+                    .progress_chars("=> "),
+            ),
+            None => ProgressBar::new_spinner().with_style(
+                ProgressStyle::default_spinner().template("{spinner} {msg} {pos} ({elapsed})"),
+            ),
+        };
+        bar.set_message(unit);
+        if !bar.is_hidden() {
+            bar.enable_steady_tick(TICK_MILLIS);
+        }
+
+        *self.lock() = Some(bar.clone());
+        Bar {
+            bar,
+            progress: self.clone(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<ProgressBar>> {
+        match self.active.lock() {
+            Ok(active) => active,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl MakeWriter for Progress {
+    type Writer = LogLine;
+
+    fn make_writer(&self) -> Self::Writer {
+        LogLine {
+            buffer: Vec::new(),
+            progress: self.clone(),
+        }
+    }
+}
+
+/// A bar being drawn, which is cleared away when dropped.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct Bar {
+    #[derivative(Debug = "ignore")]
+    bar: ProgressBar,
+    progress: Progress,
+}
+
+impl Bar {
+    pub fn inc(&self, delta: u64) {
+        self.bar.inc(delta);
+    }
+
+    pub fn set_position(&self, position: u64) {
+        self.bar.set_position(position);
+    }
+}
+
+impl Drop for Bar {
+    fn drop(&mut self) {
+        self.bar.finish_and_clear();
+        *self.progress.lock() = None;
+    }
+}
+
+/// One log line, held until it's complete so it can be printed above the bar in one go.
+pub struct LogLine {
+    buffer: Vec<u8>,
+    progress: Progress,
+}
+
+impl Write for LogLine {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogLine {
+    fn drop(&mut self) {
+        let active = self.progress.lock().clone();
+        match active {
+            Some(bar) if !bar.is_hidden() => {
+                bar.println(String::from_utf8_lossy(&self.buffer).trim_end());
+            }
+            _ if self.progress.stderr => {
+                let _ = io::stderr().write_all(&self.buffer);
+            }
+            _ => {
+                let _ = io::stdout().write_all(&self.buffer);
+            }
+        }
+    }
+}
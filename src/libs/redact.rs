@@ -0,0 +1,58 @@
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use reqwest::Url;
+
+pub const REDACTED: &str = "[redacted]";
+
+/// Redis key prefixes whose suffix is an email address or a name.
+const PII_KEY_PREFIXES: &[&str] = &["user:email:", "user_group:name:", "user:trigram:"];
+
+static REDACT_PII: AtomicBool = AtomicBool::new(false);
+
+pub fn set_redact_pii(enabled: bool) {
+    REDACT_PII.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_redacting_pii() -> bool {
+    REDACT_PII.load(Ordering::Relaxed)
+}
+
+/// Returns `value` unless `--redact-pii` is set. Use for emails, names and serialized records.
+pub fn pii(value: &str) -> &str {
+    if is_redacting_pii() {
+        REDACTED
+    } else {
+        value
+    }
+}
+
+/// Hides the email or name part of a Redis key, which may be under a `--key-prefix`, when
+/// `--redact-pii` is set.
+pub fn key(key: &str) -> Cow<str> {
+    if !is_redacting_pii() {
+        return Cow::Borrowed(key);
+    }
+
+    match PII_KEY_PREFIXES
+        .iter()
+        .filter_map(|prefix| key.find(prefix).map(|start| start + prefix.len()))
+        .min()
+    {
+        Some(end) => Cow::Owned(format!("{}{}", &key[..end], REDACTED)),
+        None => Cow::Borrowed(key),
+    }
+}
+
+/// Masks the password of a connection URL like `redis://:secret@host/`. Always applied.
+pub fn url_password(address: &str) -> String {
+    match Url::parse(address) {
+        Ok(mut url) => {
+            if url.password().is_some() && url.set_password(Some(REDACTED)).is_err() {
+                return REDACTED.to_owned();
+            }
+            url.to_string()
+        }
+        Err(_) => REDACTED.to_owned(),
+    }
+}
@@ -1,15 +1,46 @@
-use tracing::{trace, warn};
+use tracing::{info, trace, warn};
 
-use super::slack::{SlackUser, SlackUserGroup};
+use super::changes::CHANGES_STREAM_KEY;
+use super::chaos;
+use super::codec::{self, ValueFormat};
+use super::compression::{self, CompressionStats, CompressionTotals};
+use super::consistency::ConsistencyReport;
+use super::email::{self, AliasRule, DomainAllowlist, Email, EmailHasher, ExternalUsers};
+use super::fuzzy::{self, FuzzyMatch};
+use super::hash_ring::HashRing;
+use super::history::{GroupSnapshot, UserVersion};
+use super::key_layout::{self, KeyLayout, MigrationReport};
+use super::latency::LatencyHistograms;
+use super::oncall::OncallSchedule;
+use super::redact;
+use super::schema;
+use super::settings::CacheSettings;
+use super::slack::{
+    ApiUsageSummary, FetchedGroups, GroupId, SlackUser, SlackUserGroup, UserAvailability, UserId,
+    UsersCrawl,
+};
+use super::staging::SyncFailure;
+use super::stats::CacheStats;
+use super::summary::DirectorySummary;
+use super::synthetic::{GroupChange, Mirrors};
+#[cfg(feature = "transform")]
+use super::transform::Transform;
+use super::typeahead;
+use super::watchers::GroupWatchers;
+use super::write_back::WriteBackPlan;
 use crate::error::RedisErrors;
-use std::collections::BTreeSet;
-use std::time::Duration;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::anyhow;
 use derivative::Derivative;
 use mobc::{Connection, Pool};
+use mobc_redis::redis::streams::{StreamRangeReply, StreamReadOptions, StreamReadReply};
 use mobc_redis::redis::{AsyncCommands, FromRedisValue};
 use mobc_redis::{redis, RedisConnectionManager};
+use serde::{Deserialize, Serialize};
 
 pub type MobcPool = Pool<RedisConnectionManager>;
 pub type MobcCon = Connection<RedisConnectionManager>;
@@ -21,217 +52,3480 @@ const CACHE_POOL_TIMEOUT_SECONDS: u64 = 1;
 const CACHE_POOL_EXPIRE_SECONDS: u64 = 60;
 const REDIS_ENTITY_TIMEOUT: usize = 12 * 60 * 60;
 const REDIS_LOCK_TIMEOUT: usize = 2 * 60;
+/// How long the email key a user was under before changing their email keeps pointing to them.
+const EMAIL_REDIRECT_TIMEOUT: usize = 30 * 24 * 60 * 60;
 const WRITE_LOCK_KEY: &str = "write_lock";
+const LAST_SYNC_KEY: &str = "last_sync";
+/// How many users and groups are cached, moved as they're added and removed and reset by syncs.
+const USER_COUNT_KEY: &str = "user:count";
+const GROUP_COUNT_KEY: &str = "user_group:count";
+const SYNC_CURSOR_KEY: &str = "sync:cursor";
+const SYNC_API_USAGE_KEY: &str = "sync:api_usage";
+const SYNC_GROUP_MEMBERS_KEY: &str = "sync:group_members";
+const SYNC_SUMMARY_KEY: &str = "sync:summary";
+/// The options the last sync wrote the cache with.
+const SYNC_SETTINGS_KEY: &str = "sync:settings";
+/// Hash of the users a staged sync fetched, by id, until they're validated and committed.
+const SYNC_STAGED_USERS_KEY: &str = "sync:staged_users";
+/// Why the last staged sync that wasn't committed was rejected.
+const SYNC_FAILURE_KEY: &str = "sync:last_failure";
+/// Sorted set of the emails backfilling found no Slack user for, hashed when emails are, scored
+/// by when they can be looked up again.
+const BACKFILL_MISSES_KEY: &str = "sync:backfill_misses";
+/// Sorted set of user ids, scored by when each last changed.
+const USER_UPDATES_KEY: &str = "user:updated";
+/// Sorted set of `{term}\0{user id}` entries, all scored 0 so they're ordered by term, that
+/// typeahead looks names and emails up in by prefix.
+const TYPEAHEAD_KEY: &str = "user:typeahead";
+/// Index entries read for each user typeahead is asked for, as users have an entry for each word
+/// of their names.
+const TYPEAHEAD_ENTRIES_PER_USER: usize = 4;
+/// Start of the sets of user ids whose names have each trigram, that fuzzy lookups find
+/// candidates in.
+const TRIGRAM_KEY_PREFIX: &str = "user:trigram:";
+/// Start of the sets of ids of the users with an email in each domain.
+const DOMAIN_KEY_PREFIX: &str = "user:domain:";
+/// Start of the sets of ids of the groups each user was in when they were last expanded, so those
+/// expansions can be dropped when the user changes.
+const EXPANDED_IN_KEY_PREFIX: &str = "user:expanded_in:";
+/// Start of the sets of ids of the users in each workspace.
+const TEAM_KEY_PREFIX: &str = "user:team:";
+/// Users sharing the most trigrams with a fuzzy query that are scored against it.
+const FUZZY_CANDIDATES: usize = 200;
+const ONCALL_SCHEDULES_KEY: &str = "oncall_schedules";
+/// Hash with a field `{group id}:{channel}` for each channel watching a group.
+const GROUP_WATCHERS_KEY: &str = "group_watchers";
+/// Hash of the synthetic groups made through the admin API, by id.
+const SYNTHETIC_GROUPS_KEY: &str = "synthetic_groups";
+/// Hash of the changes to synthetic groups waiting for approval, by change id.
+const SYNTHETIC_GROUP_CHANGES_KEY: &str = "synthetic_groups:changes";
+/// Set for `stats::SCAN_INTERVAL` once `/admin/cache_stats` scans the cache.
+const CACHE_STATS_SCAN_KEY: &str = "cache_stats_scan";
+/// Start of the counters of the requests each API token made in a quota window.
+const QUOTA_KEY_PREFIX: &str = "quota:";
+/// Hash of the Slack group written back for each synthetic group, by synthetic group id.
+const SYNTHETIC_GROUP_MIRRORS_KEY: &str = "synthetic_groups:slack_ids";
+/// Changes to Slack saved by `plan` for `apply` to make.
+const WRITE_BACK_PLAN_KEY: &str = "synthetic_groups:plan";
+const STATS_BATCH_SIZE: usize = 100;
+const PURGE_BATCH_SIZE: usize = 500;
+const MGET_BATCH_SIZE: usize = 500;
+
+/// Starts of every key this app writes, including the leader lease and Redis Streams.
+const APP_KEY_PREFIXES: &[&str] = &[
+    "user:",
+    "user_group:",
+    "sync:",
+    LAST_SYNC_KEY,
+    WRITE_LOCK_KEY,
+    "leader_lease",
+    CHANGES_STREAM_KEY,
+    "audit_log",
+    ONCALL_SCHEDULES_KEY,
+    GROUP_WATCHERS_KEY,
+    SYNTHETIC_GROUPS_KEY,
+    QUOTA_KEY_PREFIX,
+    CACHE_STATS_SCAN_KEY,
+];
+
+/// Sets the lease to ARGV[1] if it is free, and extends it if ARGV[1] already holds it.
+pub const CLAIM_LEASE_SCRIPT: &str = r"
+local holder = redis.call('GET', KEYS[1])
+if holder == false or holder == ARGV[1] then
+    redis.call('SET', KEYS[1], ARGV[1], 'EX', ARGV[2])
+    return 1
+end
+return 0
+";
+
+/// Counts a request against KEYS[1], starting it at 1 for ARGV[1] seconds if it's not set.
+pub const COUNT_REQUEST_SCRIPT: &str = r"
+local count = redis.call('INCR', KEYS[1])
+if count == 1 then
+    redis.call('EXPIRE', KEYS[1], ARGV[1])
+end
+return count
+";
+
+/// Moves the count under KEYS[1] by ARGV[1], only if it's set. A count that expired, or was
+/// never set by a sync, stays missing rather than starting again from the change alone.
+pub const ADJUST_COUNT_SCRIPT: &str = r"
+if redis.call('EXISTS', KEYS[1]) == 1 then
+    return redis.call('INCRBY', KEYS[1], ARGV[1])
+end
+return false
+";
+
+/// Sets when a record last changed to ARGV[1] if ARGV[3] is 1 or it was never set, and keeps it
+/// for another ARGV[2] seconds either way.
+pub const MARK_MODIFIED_SCRIPT: &str = r"
+if ARGV[3] == '1' or redis.call('EXISTS', KEYS[1]) == 0 then
+    redis.call('SET', KEYS[1], ARGV[1], 'EX', ARGV[2])
+    return 1
+end
+redis.call('EXPIRE', KEYS[1], ARGV[2])
+return 0
+";
+
+/// Left under the email key a user was under before their email changed, so they can still be
+/// found by it for `EMAIL_REDIRECT_TIMEOUT`.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct EmailRedirect {
+    id: UserId,
+    /// The user's email at the time, hashed when hashing is on.
+    moved_to: Email,
+}
+
+/// Just who an email key is for, read without the rest of the user when it holds one. Only
+/// redirects have `moved_to`.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Deserialize)]
+struct EmailTarget {
+    id: UserId,
+    #[serde(default)]
+    moved_to: Option<Email>,
+}
+
+/// What an email key holds.
+#[derive(Debug, Clone)]
+enum EmailRecord {
+    User(SlackUser),
+    Moved(EmailRedirect),
+}
+
+/// Told apart by `moved-to`, which only redirects have. `#[serde(untagged)]` buffers the value
+/// before trying each variant, and users with avatars fail to read back from the buffer, as it
+/// doesn't turn their width keys from strings into numbers.
+impl<'de> Deserialize<'de> for EmailRecord {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let record = if value.get("moved-to").is_some() {
+            serde_json::from_value(value).map(EmailRecord::Moved)
+        } else {
+            serde_json::from_value(value).map(EmailRecord::User)
+        };
+        record.map_err(serde::de::Error::custom)
+    }
+}
 
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct RedisServer {
+    shards: Arc<Vec<Shard>>,
+    ring: HashRing,
+    layout: KeyLayout,
+    read_replicas: Vec<ReadReplica>,
+    next_replica: AtomicUsize,
+    email_hasher: Option<EmailHasher>,
+    email_aliases: Vec<AliasRule>,
+    domain_allowlist: DomainAllowlist,
+    external_users: ExternalUsers,
+    github_logins: Option<BTreeMap<String, String>>,
+    #[cfg(feature = "transform")]
+    transform: Option<Transform>,
+    value_format: ValueFormat,
+    compress_over: Option<usize>,
+    /// Whether listing every user or group fails on a value that can't be read.
+    strict_scans: bool,
+    compressed: CompressionStats,
+    decompressed: CompressionStats,
+    latency: LatencyHistograms,
+}
+
+/// One of the Redis servers keys are spread across.
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct Shard {
+    #[derivative(Debug = "ignore")]
+    pool: MobcPool,
+    address: String,
+}
+
+/// A replica reads are spread across while it's healthy.
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct ReadReplica {
     #[derivative(Debug = "ignore")]
-    redis_client: MobcPool,
-    redis_address: String,
+    pool: MobcPool,
+    address: String,
+    healthy: AtomicBool,
+}
+
+impl ReadReplica {
+    async fn check(&self) -> anyhow::Result<()> {
+        let mut con = self.pool.get().await?;
+        let info: String = redis::cmd("INFO")
+            .arg("replication")
+            .query_async(&mut *con)
+            .await?;
+
+        // A replica cut off from its primary keeps serving what it had, which only gets staler.
+        if info
+            .lines()
+            .any(|line| line.trim() == "master_link_status:down")
+        {
+            return Err(anyhow!("its link to the primary is down"));
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, Eq, PartialEq, PartialOrd)]
-enum RedisResult {
-    String(String),
-    Nil,
-}
+#[derive(Debug)]
+enum CopyResult {
+    Copied,
+    AlreadyPresent,
+    Vanished,
+}
+
+#[derive(Debug, Eq, PartialEq, PartialOrd)]
+enum RedisResult {
+    String(String),
+    Nil,
+}
+
+/// Pooled connections to each Redis server, for `RedisServer`s in the same process to share.
+#[derive(Debug, Clone)]
+pub struct RedisPools {
+    shards: Arc<Vec<Shard>>,
+}
+
+impl RedisPools {
+    /// Opens a pool for each of `redis_addresses`, which connect as they're used.
+    pub fn connect(redis_addresses: &[String]) -> Result<Self> {
+        let mut shards = Vec::with_capacity(redis_addresses.len());
+        for address in redis_addresses {
+            shards.push(Shard {
+                pool: build_pool(address)?,
+                address: redact::url_password(address),
+            });
+        }
+        if shards.is_empty() {
+            return Err(RedisErrors::NoServers);
+        }
+
+        Ok(Self {
+            shards: Arc::new(shards),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum RedisResponse<T, E> {
+    Err(E),
+    Missing,
+    Ok(T),
+}
+
+impl RedisServer {
+    /// Connects to the Redis servers at `redis_addresses`. With more than one, keys are sharded
+    /// across them by consistent hashing: each key lives on one server, which lookups go straight
+    /// to, while listing and scanning ask every server and merge what they return.
+    pub async fn new(redis_addresses: &[String]) -> Result<Self> {
+        Ok(Self::from_pools(&RedisPools::connect(redis_addresses)?))
+    }
+
+    /// Uses the connections in `pools`, which other `RedisServer`s configured differently can
+    /// share.
+    pub fn from_pools(pools: &RedisPools) -> Self {
+        let shards = pools.shards.clone();
+        let names: Vec<&str> = shards.iter().map(|shard| shard.address.as_str()).collect();
+        Self {
+            ring: HashRing::new(&names),
+            shards,
+            layout: KeyLayout::default(),
+            read_replicas: Vec::new(),
+            next_replica: AtomicUsize::new(0),
+            email_hasher: None,
+            email_aliases: Vec::new(),
+            domain_allowlist: DomainAllowlist::default(),
+            external_users: ExternalUsers::Exclude,
+            github_logins: None,
+            #[cfg(feature = "transform")]
+            transform: None,
+            value_format: ValueFormat::Json,
+            compress_over: None,
+            strict_scans: false,
+            compressed: CompressionStats::default(),
+            decompressed: CompressionStats::default(),
+            latency: LatencyHistograms::new(
+                "redis_operation_duration_seconds",
+                "Time Redis operations took, including waiting for a pooled connection",
+                "operation",
+            ),
+        }
+    }
+
+    /// Reads and writes keys as `layout` says.
+    pub fn with_key_layout(mut self, layout: KeyLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Sends reads to the replicas at `addresses` in turn, leaving writes to the primary. Each
+    /// replica is used until a health check or a connection to it fails, and again once it
+    /// passes one. While none are healthy, reads go to the primary. Replicas can't be combined
+    /// with sharding, as there'd be no telling which shard a replica follows.
+    pub fn with_read_replicas(mut self, addresses: &[String]) -> Result<Self> {
+        if self.shards.len() > 1 && !addresses.is_empty() {
+            return Err(RedisErrors::ReplicasWithShards);
+        }
+        for address in addresses {
+            self.read_replicas.push(ReadReplica {
+                pool: build_pool(address)?,
+                address: redact::url_password(address),
+                healthy: AtomicBool::new(true),
+            });
+        }
+        Ok(self)
+    }
+
+    /// Pings every read replica, taking the ones that don't answer or have lost their link to
+    /// the primary out of rotation and putting the ones that have recovered back.
+    pub async fn check_read_replicas(&self) {
+        for replica in &self.read_replicas {
+            let healthy = match replica.check().await {
+                Ok(()) => true,
+                Err(e) => {
+                    if replica.healthy.load(Ordering::Relaxed) {
+                        warn!(
+                            "Read replica {} failed a health check. Error: {}",
+                            replica.address, e
+                        );
+                    }
+                    false
+                }
+            };
+            if healthy && !replica.healthy.load(Ordering::Relaxed) {
+                info!("Read replica {} is healthy again", replica.address);
+            }
+            replica.healthy.store(healthy, Ordering::Relaxed);
+        }
+    }
+
+    /// Each read replica's address and whether reads are being sent to it.
+    pub fn read_replica_health(&self) -> Vec<(String, bool)> {
+        self.read_replicas
+            .iter()
+            .map(|replica| {
+                (
+                    replica.address.clone(),
+                    replica.healthy.load(Ordering::Relaxed),
+                )
+            })
+            .collect()
+    }
+
+    /// Pings every shard, so a sync finds out Redis is unreachable before it starts.
+    pub async fn ping(&self) -> Result<()> {
+        for shard in 0..self.shards.len() {
+            let mut con = self.shard_con(shard).await?;
+            let _: String = redis::cmd("PING")
+                .query_async(&mut *con)
+                .await
+                .map_err(|e| RedisErrors::UnableToConnect {
+                    address: self.shards[shard].address.clone(),
+                    source: anyhow!(e),
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Connection pool statistics of each shard, for diagnostics.
+    pub async fn pool_state(&self) -> Vec<(String, mobc::State)> {
+        let mut states = Vec::with_capacity(self.shards.len());
+        for shard in self.shards.iter() {
+            states.push((shard.address.clone(), shard.pool.state().await));
+        }
+        states
+    }
+
+    /// Store and look up users by a salted hash of their email instead of the email itself.
+    pub fn with_email_hasher(mut self, email_hasher: Option<EmailHasher>) -> Self {
+        self.email_hasher = email_hasher;
+        self
+    }
+
+    /// Also index users under the addresses these rules derive from their email.
+    pub fn with_email_aliases(mut self, email_aliases: Vec<AliasRule>) -> Self {
+        self.email_aliases = email_aliases;
+        self
+    }
+
+    /// Only cache users with an email in `domain_allowlist`, handling the rest as
+    /// `external_users` says.
+    pub fn with_domain_allowlist(
+        mut self,
+        domain_allowlist: DomainAllowlist,
+        external_users: ExternalUsers,
+    ) -> Self {
+        self.domain_allowlist = domain_allowlist;
+        self.external_users = external_users;
+        self
+    }
+
+    /// Set the GitHub login of the users in `github_logins`, keyed by normalized email, and
+    /// clear it for the rest. Without it, users keep the login they're cached with.
+    pub fn with_github_logins(mut self, github_logins: Option<BTreeMap<String, String>>) -> Self {
+        self.github_logins = github_logins;
+        self
+    }
+
+    /// Run users and groups through `transform` before they're cached.
+    #[cfg(feature = "transform")]
+    pub fn with_transform(mut self, transform: Option<Transform>) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    /// Write users and groups in `value_format`. Either format is read regardless.
+    pub fn with_value_format(mut self, value_format: ValueFormat) -> Self {
+        self.value_format = value_format;
+        self
+    }
+
+    /// zstd compress values of at least `compress_over` bytes. Compressed values are read
+    /// regardless.
+    pub fn with_compression(mut self, compress_over: Option<usize>) -> Self {
+        self.compress_over = compress_over;
+        self
+    }
+
+    /// Fail listing every user or group on a value that can't be read, rather than leaving it
+    /// out with a warning as serving does.
+    pub fn with_strict_scans(mut self, strict_scans: bool) -> Self {
+        self.strict_scans = strict_scans;
+        self
+    }
+
+    /// Totals of the values this process has compressed before writing.
+    pub fn compressed_totals(&self) -> CompressionTotals {
+        self.compressed.totals()
+    }
+
+    /// Totals of the compressed values this process has read.
+    pub fn decompressed_totals(&self) -> CompressionTotals {
+        self.decompressed.totals()
+    }
+
+    /// How long the reads and writes this process made took.
+    pub fn latency(&self) -> &LatencyHistograms {
+        &self.latency
+    }
+
+    fn email_key(&self, email: &str) -> String {
+        match &self.email_hasher {
+            None => format!("user:email:{}", email),
+            Some(hasher) => format!("user:email_hash:{}", hasher.hash(email)),
+        }
+    }
+
+    /// A user or group in the configured value format, along with the schema version it's
+    /// written in.
+    fn to_stored<T: serde::Serialize>(&self, record: &T) -> Vec<u8> {
+        self.value_format
+            .encode(&schema::stamp(serde_json::to_value(record).unwrap()))
+    }
+
+    pub async fn get_all_users(&self) -> RedisResponse<Vec<SlackUser>, RedisErrors> {
+        let results: Result<Vec<SlackUser>> = self.str_scan("user:id:*").await;
+
+        match results {
+            Ok(value) => RedisResponse::Ok(value),
+            Err(e) => RedisResponse::Err(e),
+        }
+    }
+
+    pub async fn get_all_user_groups(&self) -> RedisResponse<Vec<SlackUserGroup>, RedisErrors> {
+        let results: Result<Vec<SlackUserGroup>> = self.str_scan("user_group:id:*").await;
+
+        match results {
+            Ok(value) => RedisResponse::Ok(value),
+            Err(e) => RedisResponse::Err(e),
+        }
+    }
+
+    pub async fn get_user_by_id(&self, id: &UserId) -> RedisResponse<SlackUser, RedisErrors> {
+        self.unwrap_object(&format!("user:id:{}", id)).await
+    }
+
+    /// The user with GitHub `login`. Keys left over from a login the user no longer has are
+    /// taken as missing.
+    pub async fn get_user_by_github(&self, login: &str) -> RedisResponse<SlackUser, RedisErrors> {
+        match self.unwrap_object::<SlackUser>(&github_key(login)).await {
+            RedisResponse::Ok(user) if user.github_login.as_deref() != Some(login) => {
+                RedisResponse::Missing
+            }
+            response => response,
+        }
+    }
+
+    /// The user with `email`. Users cached with an email hasher only carry a digest of their
+    /// email, so the address that was looked up, normalized, is put back in its place.
+    pub async fn get_user_by_email(&self, email: &Email) -> RedisResponse<SlackUser, RedisErrors> {
+        let normalized = email.normalized();
+        let response = self
+            .unwrap_object_or_legacy::<EmailRecord>(
+                &self.email_key(&normalized),
+                &self.email_key(email),
+            )
+            .await;
+
+        match (response, &self.email_hasher) {
+            (RedisResponse::Ok(EmailRecord::User(mut user)), Some(_)) => {
+                user.email = normalized;
+                RedisResponse::Ok(user)
+            }
+            (RedisResponse::Ok(EmailRecord::User(user)), None) => RedisResponse::Ok(user),
+            (RedisResponse::Ok(EmailRecord::Moved(_)), _) | (RedisResponse::Missing, _) => {
+                RedisResponse::Missing
+            }
+            (RedisResponse::Err(e), _) => RedisResponse::Err(e),
+        }
+    }
+
+    /// Whether `email` maps to a user, as `get_user_by_email` or `get_user_by_previous_email`
+    /// would find them, only checking for the email key with EXISTS. When the key is a redirect
+    /// left by a user who changed their email, the user it points to is checked for the same way,
+    /// so a redirect to a removed user doesn't count.
+    pub async fn user_email_exists(&self, email: &Email) -> Result<bool> {
+        let key = self.email_key(&email.normalized());
+        let legacy_key = self.email_key(email);
+        if !self.exists(&key).await? {
+            return Ok(legacy_key != key && self.exists(&legacy_key).await?);
+        }
+
+        let target = match self.get_bytes(&key).await? {
+            Some(value) => {
+                from_stored::<EmailTarget>(&value).map_err(|e| RedisErrors::UnableToReadValue {
+                    key: redact::key(&key).into_owned(),
+                    source: e,
+                })?
+            }
+            // Expired since it was checked for.
+            None => return Ok(false),
+        };
+        match target.moved_to {
+            Some(_) => self.exists(&format!("user:id:{}", target.id)).await,
+            None => Ok(true),
+        }
+    }
+
+    /// The user who had `email` before changing it, for `EMAIL_REDIRECT_TIMEOUT` after the sync
+    /// that saw the change.
+    pub async fn get_user_by_previous_email(
+        &self,
+        email: &Email,
+    ) -> RedisResponse<SlackUser, RedisErrors> {
+        match self
+            .unwrap_object::<EmailRecord>(&self.email_key(&email.normalized()))
+            .await
+        {
+            RedisResponse::Ok(EmailRecord::Moved(redirect)) => {
+                self.get_user_by_id(&redirect.id).await
+            }
+            RedisResponse::Ok(EmailRecord::User(_)) | RedisResponse::Missing => {
+                RedisResponse::Missing
+            }
+            RedisResponse::Err(e) => RedisResponse::Err(e),
+        }
+    }
+
+    pub async fn get_user_group_by_id(
+        &self,
+        id: &GroupId,
+    ) -> RedisResponse<SlackUserGroup, RedisErrors> {
+        self.unwrap_object(&format!("user_group:id:{}", id)).await
+    }
+
+    pub async fn get_user_group_by_name(
+        &self,
+        name: String,
+    ) -> RedisResponse<SlackUserGroup, RedisErrors> {
+        self.unwrap_object_or_legacy(
+            &format!("user_group:name:{}", self.layout.fold_name(&name)),
+            &format!("user_group:name:{}", name),
+        )
+        .await
+    }
+
+    /// The group that was called `name` before being renamed.
+    pub async fn get_user_group_by_previous_name(
+        &self,
+        name: &str,
+    ) -> RedisResponse<SlackUserGroup, RedisErrors> {
+        let key = format!("user_group:renamed:{}", self.layout.fold_name(name));
+        match self.get_str(&key).await {
+            Err(e) => RedisResponse::Err(e),
+            Ok(RedisResult::Nil) => RedisResponse::Missing,
+            Ok(RedisResult::String(id)) => match id.parse() {
+                Ok(id) => self.get_user_group_by_id(&id).await,
+                Err(e) => RedisResponse::Err(RedisErrors::UnableToReadValue {
+                    key,
+                    source: anyhow!(e),
+                }),
+            },
+        }
+    }
+
+    /// Reads `key`, falling back to `legacy_key` for entries written before keys were lowercased.
+    /// Those expire along with every other entity, so the fallback only matters after upgrading.
+    async fn unwrap_object_or_legacy<T>(
+        &self,
+        key: &str,
+        legacy_key: &str,
+    ) -> RedisResponse<T, RedisErrors>
+    where
+        T: serde::de::DeserializeOwned + Clone,
+    {
+        match self.unwrap_object(key).await {
+            RedisResponse::Missing if key != legacy_key => self.unwrap_object(legacy_key).await,
+            other => other,
+        }
+    }
+
+    async fn unwrap_object<T>(&self, query_string: &str) -> RedisResponse<T, RedisErrors>
+    where
+        T: serde::de::DeserializeOwned + Clone,
+    {
+        match self.get_bytes(query_string).await {
+            Err(e) => RedisResponse::Err(e),
+            Ok(None) => RedisResponse::Missing,
+            Ok(Some(bytes)) => match from_stored(&bytes) {
+                Ok(value) => RedisResponse::Ok(value),
+                Err(e) => RedisResponse::Err(RedisErrors::UnableToDeserialize {
+                    input: redact::pii(&String::from_utf8_lossy(&bytes)).to_owned(),
+                    source: e,
+                }),
+            },
+        }
+    }
+
+    pub async fn insert_users(&self, slack_users: &BTreeSet<SlackUser>) -> Result<()> {
+        self.insert_user_records(slack_users).await?;
+        self.insert_email_aliases(slack_users).await
+    }
+
+    /// Writes users under their id and email, leaving out aliases. Users whose email changed
+    /// keep the ones they had before, and the email key they were under points to them for
+    /// `EMAIL_REDIRECT_TIMEOUT`, unless another user has taken it since. Groups with users that
+    /// changed have their expanded members dropped.
+    pub async fn insert_user_records(&self, slack_users: &BTreeSet<SlackUser>) -> Result<()> {
+        let id_keys: Vec<String> = slack_users
+            .iter()
+            .map(|user| format!("user:id:{}", user.id))
+            .collect();
+        let cached: BTreeMap<UserId, SlackUser> = self
+            .get_many(&id_keys)
+            .await?
+            .into_iter()
+            .filter_map(|(_, value)| from_stored::<SlackUser>(&value).ok())
+            .map(|user| (user.id.clone(), user))
+            .collect();
+        let current_keys: BTreeSet<String> = slack_users
+            .iter()
+            .map(|user| self.email_key(&email::normalize(&user.email)))
+            .collect();
+
+        let mut changed_ids = BTreeSet::new();
+        for user in slack_users {
+            let cached = cached.get(&user.id);
+            let stored = with_previous_emails(self.stored_user(user), cached);
+            let value = self.to_stored(&stored);
+
+            let email_key = self.stored_email_key(&stored);
+            if let Err(e) = self
+                .set_bytes(&email_key, &value, REDIS_ENTITY_TIMEOUT)
+                .await
+            {
+                warn!("Unable to insert user {}. Error: {}", user.id, e);
+            }
+
+            if let Err(e) = self.index_names(cached, Some(&stored)).await {
+                warn!("Unable to index name of user {}. Error: {}", user.id, e);
+            }
+            if let Err(e) = self.index_domain(cached, Some(&stored)).await {
+                warn!("Unable to index domain of user {}. Error: {}", user.id, e);
+            }
+            if let Err(e) = self.index_teams(cached, Some(&stored)).await {
+                warn!(
+                    "Unable to index workspaces of user {}. Error: {}",
+                    user.id, e
+                );
+            }
+
+            if let Some(cached) = cached {
+                let cached_key = self.stored_email_key(cached);
+                if cached_key != email_key && !current_keys.contains(&cached_key) {
+                    if let Err(e) = self.redirect_email(&cached_key, &stored).await {
+                        warn!(
+                            "Unable to record email change of user {}. Error: {}",
+                            user.id, e
+                        );
+                    }
+                }
+            }
+
+            match self
+                .set_bytes(
+                    &format!("user:id:{}", user.id),
+                    &value,
+                    REDIS_ENTITY_TIMEOUT,
+                )
+                .await
+            {
+                Ok(previous) => {
+                    let changed = previous.as_deref() != Some(value.as_slice());
+                    if changed {
+                        changed_ids.insert(user.id.clone());
+                    }
+                    if previous.is_none() {
+                        if let Err(e) = self.adjust_count(USER_COUNT_KEY, 1).await {
+                            warn!("Unable to count user {}. Error: {}", user.id, e);
+                        }
+                    }
+                    if let Err(e) = self.mark_user_modified(&user.id, changed).await {
+                        warn!(
+                            "Unable to record when user {} changed. Error: {}",
+                            user.id, e
+                        );
+                    }
+                }
+                Err(e) => warn!("Unable to insert user {}. Error: {}", user.id, e),
+            }
+
+            if let Some(login) = &user.github_login {
+                if let Err(e) = self
+                    .set_bytes(&github_key(login), &value, REDIS_ENTITY_TIMEOUT)
+                    .await
+                {
+                    warn!("Unable to insert user {}. Error: {}", user.id, e);
+                }
+            }
+        }
+
+        if let Err(e) = self.drop_expanded_groups_of(&changed_ids).await {
+            warn!(
+                "Unable to drop the expanded members of groups with changed users. Error: {}",
+                e
+            );
+        }
+        Ok(())
+    }
+
+    /// Writes the alias keys of `slack_users`. Clashes are only found between the users given,
+    /// so a sync passes all of them at once.
+    pub async fn insert_email_aliases(&self, slack_users: &BTreeSet<SlackUser>) -> Result<()> {
+        if self.email_aliases.is_empty() {
+            return Ok(());
+        }
+
+        let aliases = self.unambiguous_aliases(slack_users);
+        for user in slack_users {
+            let user_aliases = match aliases.get(user.id.as_str()) {
+                Some(user_aliases) => user_aliases,
+                None => continue,
+            };
+
+            let value = self.to_stored(&self.stored_user(user));
+            for address in user_aliases {
+                if let Err(e) = self
+                    .set_bytes(&self.email_key(address), &value, REDIS_ENTITY_TIMEOUT)
+                    .await
+                {
+                    warn!("Unable to insert user {}. Error: {}", user.id, e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes a single user, leaving a redirect under the email key they were under if their
+    /// email changed. Users screened out are removed instead.
+    pub async fn update_user(&self, user: &SlackUser) -> Result<()> {
+        let mut users = BTreeSet::new();
+        users.insert(user.clone());
+        let mut user = match self.screen_users(users).into_iter().next() {
+            Some(user) => user,
+            None => return self.remove_user(&user.id).await,
+        };
+
+        // Slack events don't carry the GitHub login, only syncs look it up.
+        if self.github_logins.is_none() && user.github_login.is_none() {
+            if let RedisResponse::Ok(cached) = self.get_user_by_id(&user.id).await {
+                user.github_login = cached.github_login;
+            }
+        }
+
+        let mut users = BTreeSet::new();
+        users.insert(user);
+        self.insert_users(&users).await
+    }
+
+    /// `users` with the ones outside the email domain allowlist left out or flagged and their
+    /// GitHub logins set, then run through the transform script. Callers screen users before
+    /// inserting them, so what's compared and recorded matches the cache.
+    pub fn screen_users(&self, users: BTreeSet<SlackUser>) -> BTreeSet<SlackUser> {
+        #[cfg(feature = "transform")]
+        {
+            if let Some(transform) = &self.transform {
+                return transform.users(self.add_github_logins(self.allowlisted_users(users)));
+            }
+        }
+
+        self.add_github_logins(self.allowlisted_users(users))
+    }
+
+    /// `groups` run through the transform script. Like users, groups are screened before
+    /// they're inserted.
+    pub fn screen_groups(&self, groups: BTreeSet<SlackUserGroup>) -> BTreeSet<SlackUserGroup> {
+        #[cfg(feature = "transform")]
+        {
+            if let Some(transform) = &self.transform {
+                return transform.groups(groups);
+            }
+        }
+
+        groups
+    }
+
+    fn add_github_logins(&self, users: BTreeSet<SlackUser>) -> BTreeSet<SlackUser> {
+        let github_logins = match &self.github_logins {
+            Some(github_logins) => github_logins,
+            None => return users,
+        };
+
+        users
+            .into_iter()
+            .map(|mut user| {
+                user.github_login = github_logins.get(&email::normalize(&user.email)).cloned();
+                user
+            })
+            .collect()
+    }
+
+    fn allowlisted_users(&self, users: BTreeSet<SlackUser>) -> BTreeSet<SlackUser> {
+        if self.domain_allowlist.is_empty() {
+            return users;
+        }
+
+        users
+            .into_iter()
+            .filter_map(|mut user| {
+                if self.domain_allowlist.allows(&email::normalize(&user.email)) {
+                    return Some(user);
+                }
+                match self.external_users {
+                    ExternalUsers::Exclude => None,
+                    ExternalUsers::Flag => {
+                        user.external = true;
+                        Some(user)
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Removes the user with `id` along with the email and GitHub keys they were under and any
+    /// annotations they were given. Alias keys are left to expire.
+    pub async fn remove_user(&self, id: &UserId) -> Result<()> {
+        if let RedisResponse::Ok(cached) = self.get_user_by_id(id).await {
+            self.adjust_count(USER_COUNT_KEY, -1).await?;
+            self.delete(&self.stored_email_key(&cached)).await?;
+            self.index_names(Some(&cached), None).await?;
+            self.index_domain(Some(&cached), None).await?;
+            self.index_teams(Some(&cached), None).await?;
+            if let Some(login) = &cached.github_login {
+                self.delete(&github_key(login)).await?;
+            }
+        }
+        self.delete(&annotations_key(id)).await?;
+        self.delete(&user_modified_key(id)).await?;
+        self.remove_user_updates(&[id.to_string()]).await?;
+        if let Err(e) = self
+            .drop_expanded_groups_of(&vec![id.clone()].into_iter().collect())
+            .await
+        {
+            warn!(
+                "Unable to drop the expanded members of groups with user {}. Error: {}",
+                id, e
+            );
+        }
+        self.delete(&format!("user:id:{}", id)).await
+    }
+
+    /// Sets the annotations in `changes` that have a value and removes the ones that don't,
+    /// returning all of the user's annotations afterwards. They're kept apart from the user, and
+    /// don't expire, so syncs leave them alone.
+    pub async fn update_annotations(
+        &self,
+        id: &UserId,
+        changes: &BTreeMap<String, Option<String>>,
+    ) -> Result<BTreeMap<String, String>> {
+        let key = annotations_key(id);
+        let annotations = self
+            .change_annotations(self.layout.key(&key).into_owned(), changes)
+            .await?;
+        if let Some(legacy_key) = self.layout.legacy_key(&key) {
+            self.change_annotations(legacy_key.to_owned(), changes)
+                .await?;
+        }
+
+        // Annotations are served as part of the user, so the user counts as changed.
+        self.mark_user_modified(id, true).await?;
+        Ok(annotations)
+    }
+
+    async fn change_annotations(
+        &self,
+        key: String,
+        changes: &BTreeMap<String, Option<String>>,
+    ) -> Result<BTreeMap<String, String>> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (name, value) in changes {
+            match value {
+                Some(value) => pipe.hset(&key, name, value).ignore(),
+                None => pipe.hdel(&key, name).ignore(),
+            };
+        }
+        pipe.hgetall(&key);
+
+        let mut con = self.get_con(&key).await?;
+        let (annotations,): (BTreeMap<String, String>,) = pipe
+            .query_async(&mut *con)
+            .await
+            .map_err(|e| RedisErrors::UnableToSet {
+                key,
+                source: anyhow!(e),
+            })?;
+
+        Ok(annotations)
+    }
+
+    /// Adds the annotations each of `users` was given.
+    pub async fn annotate(&self, users: &mut [SlackUser]) -> Result<()> {
+        let mut by_shard: BTreeMap<usize, Vec<&mut SlackUser>> = BTreeMap::new();
+        for user in users.iter_mut() {
+            let shard = self.ring.node(&self.layout.key(&annotations_key(&user.id)));
+            by_shard.entry(shard).or_default().push(user);
+        }
+
+        for (shard, mut users) in by_shard {
+            self.annotate_on_shard(shard, &mut users).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn annotate_on_shard(&self, shard: usize, users: &mut [&mut SlackUser]) -> Result<()> {
+        let mut con = self.shard_con(shard).await?;
+        for batch in users.chunks_mut(MGET_BATCH_SIZE) {
+            let mut pipe = redis::pipe();
+            for user in batch.iter() {
+                pipe.hgetall(&*self.layout.key(&annotations_key(&user.id)));
+            }
+            let annotations: Vec<BTreeMap<String, String>> = pipe
+                .query_async(&mut *con)
+                .await
+                .map_err(|e| RedisErrors::UnableToGet {
+                    key: "user:annotations:*".to_owned(),
+                    source: anyhow!(e),
+                })?;
+
+            for (user, annotations) in batch.iter_mut().zip(annotations) {
+                user.annotations = annotations;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the typeahead entries and trigrams of the user as `previous` was cached with the
+    /// ones for `current`, both as written to Redis.
+    async fn index_names(
+        &self,
+        previous: Option<&SlackUser>,
+        current: Option<&SlackUser>,
+    ) -> Result<()> {
+        self.index_typeahead(previous, current).await?;
+
+        let id = match current.or(previous) {
+            Some(user) => user.id.to_string(),
+            None => return Ok(()),
+        };
+        let trigrams = current.map(fuzzy::user_trigrams).unwrap_or_default();
+        let stale = previous.map(fuzzy::user_trigrams).unwrap_or_default();
+        let stale: BTreeSet<&String> = stale.difference(&trigrams).collect();
+        self.update_trigrams(&id, &trigrams.iter().collect(), &stale)
+            .await
+    }
+
+    /// Adds the user with `id` to the sets of `added` trigrams and takes them out of the `removed`
+    /// ones, with a pipeline for each shard.
+    async fn update_trigrams(
+        &self,
+        id: &str,
+        added: &BTreeSet<&String>,
+        removed: &BTreeSet<&String>,
+    ) -> Result<()> {
+        let mut pipes: BTreeMap<usize, redis::Pipeline> = BTreeMap::new();
+        for (trigrams, add) in &[(added, true), (removed, false)] {
+            for trigram in trigrams.iter() {
+                for key in self
+                    .layout
+                    .write_keys(&format!("{}{}", TRIGRAM_KEY_PREFIX, trigram))
+                {
+                    let pipe = pipes
+                        .entry(self.ring.node(&key))
+                        .or_insert_with(redis::pipe);
+                    if *add {
+                        pipe.sadd(&key, id).ignore();
+                    } else {
+                        pipe.srem(&key, id).ignore();
+                    }
+                }
+            }
+        }
+
+        for (shard, pipe) in pipes {
+            let mut con = self.shard_con(shard).await?;
+            let _: () =
+                pipe.query_async(&mut *con)
+                    .await
+                    .map_err(|e| RedisErrors::UnableToSet {
+                        key: format!("{}*", TRIGRAM_KEY_PREFIX),
+                        source: anyhow!(e),
+                    })?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the typeahead entries of the user as `previous` was cached with the ones for
+    /// `current`. Emails are only indexed when they aren't hashed.
+    async fn index_typeahead(
+        &self,
+        previous: Option<&SlackUser>,
+        current: Option<&SlackUser>,
+    ) -> Result<()> {
+        let with_email = self.email_hasher.is_none();
+        let entries = current
+            .map(|user| typeahead::entries(user, with_email))
+            .unwrap_or_default();
+        let stale: Vec<String> = previous
+            .map(|user| typeahead::entries(user, with_email))
+            .unwrap_or_default()
+            .difference(&entries)
+            .cloned()
+            .collect();
+        let entries: Vec<(usize, String)> = entries.into_iter().map(|entry| (0, entry)).collect();
+        if stale.is_empty() && entries.is_empty() {
+            return Ok(());
+        }
+
+        for key in self.layout.write_keys(TYPEAHEAD_KEY) {
+            let mut pipe = redis::pipe();
+            if !stale.is_empty() {
+                pipe.zrem(&key, stale.as_slice()).ignore();
+            }
+            if !entries.is_empty() {
+                pipe.zadd_multiple(&key, &entries).ignore();
+            }
+
+            let mut con = self.get_con(&key).await?;
+            let _: () =
+                pipe.query_async(&mut *con)
+                    .await
+                    .map_err(|e| RedisErrors::UnableToSet {
+                        key: key.clone(),
+                        source: anyhow!(e),
+                    })?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves the user from the set of the domain `previous` had an email in to the one `current`
+    /// has. Domains are only indexed when emails aren't hashed, as they'd give part of them away.
+    async fn index_domain(
+        &self,
+        previous: Option<&SlackUser>,
+        current: Option<&SlackUser>,
+    ) -> Result<()> {
+        if !self.indexes_domains() {
+            return Ok(());
+        }
+
+        let domain_of = |user: Option<&SlackUser>| -> BTreeSet<String> {
+            user.and_then(|user| email::domain(&email::normalize(&user.email)).map(str::to_owned))
+                .into_iter()
+                .collect()
+        };
+        self.update_index(
+            DOMAIN_KEY_PREFIX,
+            previous.or(current),
+            &domain_of(previous),
+            &domain_of(current),
+        )
+        .await
+    }
+
+    /// Moves the user from the sets of the workspaces `previous` was in to the ones `current` is.
+    async fn index_teams(
+        &self,
+        previous: Option<&SlackUser>,
+        current: Option<&SlackUser>,
+    ) -> Result<()> {
+        let teams_of =
+            |user: Option<&SlackUser>| user.map(SlackUser::all_teams).unwrap_or_default();
+        self.update_index(
+            TEAM_KEY_PREFIX,
+            previous.or(current),
+            &teams_of(previous),
+            &teams_of(current),
+        )
+        .await
+    }
+
+    /// Takes `user` out of the sets under `prefix` for the `previous` values it no longer has and
+    /// adds it to the ones for its `current` values, which are kept as long as users are.
+    async fn update_index(
+        &self,
+        prefix: &str,
+        user: Option<&SlackUser>,
+        previous: &BTreeSet<String>,
+        current: &BTreeSet<String>,
+    ) -> Result<()> {
+        let id = match user {
+            Some(user) => user.id.to_string(),
+            None => return Ok(()),
+        };
+
+        for value in previous.difference(current) {
+            for key in self.layout.write_keys(&format!("{}{}", prefix, value)) {
+                let mut con = self.get_con(&key).await?;
+                let _: () = con
+                    .srem(&key, &id)
+                    .await
+                    .map_err(|e| RedisErrors::UnableToSet {
+                        key: key.clone(),
+                        source: anyhow!(e),
+                    })?;
+            }
+        }
+
+        for value in current {
+            for key in self.layout.write_keys(&format!("{}{}", prefix, value)) {
+                let mut pipe = redis::pipe();
+                pipe.sadd(&key, &id)
+                    .ignore()
+                    .expire(&key, REDIS_ENTITY_TIMEOUT)
+                    .ignore();
+
+                let mut con = self.get_con(&key).await?;
+                let _: () =
+                    pipe.query_async(&mut *con)
+                        .await
+                        .map_err(|e| RedisErrors::UnableToSet {
+                            key: key.clone(),
+                            source: anyhow!(e),
+                        })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether users are indexed by the domain of their email, which they are unless emails are
+    /// hashed.
+    pub fn indexes_domains(&self) -> bool {
+        self.email_hasher.is_none()
+    }
+
+    /// The users with an email in `domain`, by id.
+    pub async fn get_users_by_domain(
+        &self,
+        domain: &str,
+    ) -> RedisResponse<Vec<SlackUser>, RedisErrors> {
+        let in_domain =
+            |user: &SlackUser| email::domain(&email::normalize(&user.email)) == Some(domain);
+        match self
+            .indexed_users(DOMAIN_KEY_PREFIX, domain, in_domain)
+            .await
+        {
+            Ok(users) => RedisResponse::Ok(users),
+            Err(e) => RedisResponse::Err(e),
+        }
+    }
+
+    /// The users that belong to the workspace `team`, by id.
+    pub async fn get_users_by_team(
+        &self,
+        team: &str,
+    ) -> RedisResponse<Vec<SlackUser>, RedisErrors> {
+        let in_team = |user: &SlackUser| user.in_team(team);
+        match self.indexed_users(TEAM_KEY_PREFIX, team, in_team).await {
+            Ok(users) => RedisResponse::Ok(users),
+            Err(e) => RedisResponse::Err(e),
+        }
+    }
+
+    /// The users in the set under `prefix` for `value` that still `belong` in it, by id.
+    async fn indexed_users<F>(
+        &self,
+        prefix: &str,
+        value: &str,
+        belongs: F,
+    ) -> Result<Vec<SlackUser>>
+    where
+        F: Fn(&SlackUser) -> bool,
+    {
+        let key = self
+            .layout
+            .key(&format!("{}{}", prefix, value))
+            .into_owned();
+        let mut con = self.get_read_con(&key).await?;
+        let ids: Vec<String> = con
+            .smembers(&key)
+            .await
+            .map_err(|e| RedisErrors::UnableToGet {
+                key: key.clone(),
+                source: anyhow!(e),
+            })?;
+        drop(con);
+        trace!("SMEMBERS `{}` - {} ids", key, ids.len());
+
+        // Users that have expired or changed since being indexed are left out.
+        let keys: Vec<String> = ids.iter().map(|id| format!("user:id:{}", id)).collect();
+        let mut users = Vec::new();
+        for (key, value) in self.get_many(&keys).await? {
+            match from_stored::<SlackUser>(&value) {
+                Ok(user) if belongs(&user) => users.push(user),
+                Ok(_) => {}
+                Err(e) => warn!("Unable to read {}. Error: {}", redact::key(&key), e),
+            }
+        }
+        users.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(users)
+    }
+
+    /// Up to `limit` users with a name, or with `with_emails` an email when they aren't hashed,
+    /// that starts with `query`, ignoring case and accents. A name matches from any of its words.
+    pub async fn get_users_by_prefix(
+        &self,
+        query: &str,
+        limit: usize,
+        with_emails: bool,
+    ) -> RedisResponse<Vec<SlackUser>, RedisErrors> {
+        match self.users_by_prefix(query, limit, with_emails).await {
+            Ok(users) => RedisResponse::Ok(users),
+            Err(e) => RedisResponse::Err(e),
+        }
+    }
+
+    async fn users_by_prefix(
+        &self,
+        query: &str,
+        limit: usize,
+        with_emails: bool,
+    ) -> Result<Vec<SlackUser>> {
+        let query = match typeahead::query(query) {
+            Some(query) => query,
+            None => return Ok(Vec::new()),
+        };
+
+        // Every entry starting with the query sorts between it and it followed by 0xff, which
+        // UTF-8 never has.
+        let mut min = b"[".to_vec();
+        min.extend_from_slice(query.as_bytes());
+        let mut max = min.clone();
+        max.push(0xff);
+
+        // Callers that can't see emails mustn't be able to find who has one either. Entries are
+        // read a batch at a time until there are enough for other users, so the ones for emails
+        // don't use the limit up.
+        let key = self.layout.key(TYPEAHEAD_KEY).into_owned();
+        let batch = limit * TYPEAHEAD_ENTRIES_PER_USER;
+        let mut ids = Vec::new();
+        let mut offset = 0;
+        let mut con = self.get_read_con(&key).await?;
+        loop {
+            let entries: Vec<String> = redis::cmd("ZRANGEBYLEX")
+                .arg(&key)
+                .arg(&min[..])
+                .arg(&max[..])
+                .arg("LIMIT")
+                .arg(offset)
+                .arg(batch)
+                .query_async(&mut *con)
+                .await
+                .map_err(|e| RedisErrors::UnableToGet {
+                    key: key.clone(),
+                    source: anyhow!(e),
+                })?;
+            trace!("ZRANGEBYLEX `{}` - {} entries", key, entries.len());
+
+            for id in entries
+                .iter()
+                .filter(|entry| with_emails || !typeahead::is_email(entry))
+                .filter_map(|entry| typeahead::user_id(entry))
+            {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+            if entries.len() < batch || ids.len() >= limit {
+                break;
+            }
+            offset += batch;
+        }
+        drop(con);
+
+        // Users that have expired since being indexed are left out.
+        let keys: Vec<String> = ids.iter().map(|id| format!("user:id:{}", id)).collect();
+        let mut found = BTreeMap::new();
+        for (key, value) in self.get_many(&keys).await? {
+            match from_stored::<SlackUser>(&value) {
+                Ok(user) => {
+                    found.insert(user.id.clone(), user);
+                }
+                Err(e) => warn!("Unable to read {}. Error: {}", redact::key(&key), e),
+            }
+        }
+
+        Ok(ids
+            .iter()
+            .filter_map(|id| found.remove(id))
+            .take(limit)
+            .collect())
+    }
+
+    /// Up to `limit` users whose name is close to `query`, best first, for names typed from
+    /// memory. Candidates are the users sharing the most trigrams with the query, which are then
+    /// scored by edit distance.
+    pub async fn get_users_by_similarity(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> RedisResponse<Vec<FuzzyMatch>, RedisErrors> {
+        match self.users_by_similarity(query, limit).await {
+            Ok(matches) => RedisResponse::Ok(matches),
+            Err(e) => RedisResponse::Err(e),
+        }
+    }
+
+    async fn users_by_similarity(&self, query: &str, limit: usize) -> Result<Vec<FuzzyMatch>> {
+        let query = match typeahead::query(query) {
+            Some(query) => query,
+            None => return Ok(Vec::new()),
+        };
+
+        let keys: Vec<String> = fuzzy::trigrams(&query)
+            .iter()
+            .map(|trigram| {
+                self.layout
+                    .key(&format!("{}{}", TRIGRAM_KEY_PREFIX, trigram))
+                    .into_owned()
+            })
+            .collect();
+        let mut shared: BTreeMap<String, usize> = BTreeMap::new();
+        for (shard, keys) in self.by_shard(&keys) {
+            let mut pipe = redis::pipe();
+            for key in &keys {
+                pipe.smembers(key);
+            }
+            let mut con = self.read_shard_con(shard).await?;
+            let members: Vec<Vec<String>> =
+                pipe.query_async(&mut *con)
+                    .await
+                    .map_err(|e| RedisErrors::UnableToGet {
+                        key: format!("{}*", TRIGRAM_KEY_PREFIX),
+                        source: anyhow!(e),
+                    })?;
+            for id in members.into_iter().flatten() {
+                *shared.entry(id).or_default() += 1;
+            }
+        }
+
+        let mut candidates: Vec<(String, usize)> = shared.into_iter().collect();
+        candidates.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let keys: Vec<String> = candidates
+            .iter()
+            .take(FUZZY_CANDIDATES)
+            .map(|(id, _)| format!("user:id:{}", id))
+            .collect();
+
+        // Users that have expired since being indexed are left out.
+        let mut matches = Vec::new();
+        for (key, value) in self.get_many(&keys).await? {
+            match from_stored::<SlackUser>(&value) {
+                Ok(user) => {
+                    let score = fuzzy::score(&query, &user);
+                    if score >= fuzzy::MIN_SCORE {
+                        matches.push(FuzzyMatch { score, user });
+                    }
+                }
+                Err(e) => warn!("Unable to read {}. Error: {}", redact::key(&key), e),
+            }
+        }
+        matches.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.user.name.cmp(&b.user.name))
+        });
+        matches.truncate(limit);
+        Ok(matches)
+    }
+
+    /// Drops the typeahead entries and trigrams of users that have expired, returning how many
+    /// entries there were. Expired users' trigrams are worked out from their typeahead entries,
+    /// which hold their names.
+    pub async fn prune_name_indexes(&self) -> Result<usize> {
+        let key = self.layout.key(TYPEAHEAD_KEY).into_owned();
+        let mut con = self.get_con(&key).await?;
+        let entries: Vec<String> =
+            con.zrange(&key, 0, -1)
+                .await
+                .map_err(|e| RedisErrors::UnableToGet {
+                    key: key.clone(),
+                    source: anyhow!(e),
+                })?;
+        drop(con);
+
+        let ids: BTreeSet<String> = entries
+            .iter()
+            .filter_map(|entry| typeahead::user_id(entry))
+            .map(|id| id.to_string())
+            .collect();
+        let expired = self.expired_user_ids(ids.into_iter().collect()).await?;
+        let stale: Vec<String> = entries
+            .into_iter()
+            .filter(|entry| match typeahead::user_id(entry) {
+                Some(id) => expired.contains(id.as_str()),
+                None => true,
+            })
+            .collect();
+        if stale.is_empty() {
+            return Ok(0);
+        }
+
+        let mut names: BTreeMap<UserId, BTreeSet<&str>> = BTreeMap::new();
+        for entry in &stale {
+            if let Some(id) = typeahead::user_id(entry) {
+                if !typeahead::is_email(entry) {
+                    names.entry(id).or_default().insert(typeahead::term(entry));
+                }
+            }
+        }
+        for (id, names) in names {
+            let trigrams: BTreeSet<String> = names
+                .iter()
+                .flat_map(|name| fuzzy::trigrams(name))
+                .collect();
+            self.update_trigrams(id.as_str(), &BTreeSet::new(), &trigrams.iter().collect())
+                .await?;
+        }
+
+        for key in self.layout.write_keys(TYPEAHEAD_KEY) {
+            let mut con = self.get_con(&key).await?;
+            for batch in stale.chunks(PURGE_BATCH_SIZE) {
+                let removed: usize =
+                    con.zrem(&key, batch)
+                        .await
+                        .map_err(|e| RedisErrors::UnableToDelete {
+                            key: key.clone(),
+                            source: anyhow!(e),
+                        })?;
+                trace!("ZREM `{}` - RESULT: `{}`", key, removed);
+            }
+        }
+
+        Ok(stale.len())
+    }
+
+    /// Points `key` to the user `stored` for `EMAIL_REDIRECT_TIMEOUT`, unless another user is
+    /// under it now.
+    async fn redirect_email(&self, key: &str, stored: &SlackUser) -> Result<()> {
+        if let Some(value) = self.get_bytes(key).await? {
+            if let Ok(EmailRecord::User(holder)) = from_stored::<EmailRecord>(&value) {
+                if holder.id != stored.id {
+                    return Ok(());
+                }
+            }
+        }
+
+        let redirect = EmailRedirect {
+            id: stored.id.clone(),
+            moved_to: stored.email.normalized(),
+        };
+        self.set_bytes(key, &self.to_stored(&redirect), EMAIL_REDIRECT_TIMEOUT)
+            .await?;
+        Ok(())
+    }
+
+    /// The email key a user read back from Redis was written under.
+    fn stored_email_key(&self, stored: &SlackUser) -> String {
+        match &self.email_hasher {
+            None => format!("user:email:{}", email::normalize(&stored.email)),
+            Some(_) => format!("user:email_hash:{}", stored.email),
+        }
+    }
+
+    /// `user` as it's written to Redis, with the email hashed when hashing is on.
+    pub fn stored_user(&self, user: &SlackUser) -> SlackUser {
+        match &self.email_hasher {
+            None => user.clone(),
+            Some(hasher) => SlackUser {
+                email: Email::unchecked(hasher.hash(&email::normalize(&user.email))),
+                ..user.clone()
+            },
+        }
+    }
+
+    /// Whether `key` is one of the alias keys a user read back from Redis can be under. Aliases
+    /// can't be worked out from hashed emails, so with hashing on any key counts when there are
+    /// alias rules.
+    fn is_alias_key(&self, stored: &SlackUser, key: &str) -> bool {
+        if self.email_aliases.is_empty() {
+            return false;
+        }
+        if self.email_hasher.is_some() {
+            return true;
+        }
+
+        email::aliases(&self.email_aliases, &email::normalize(&stored.email))
+            .iter()
+            .any(|alias| self.email_key(alias) == key)
+    }
+
+    /// Cross-checks every email key against the id record of the user it holds. With `repair`,
+    /// orphaned and stale keys are deleted, and mismatched and missing ones written from the id
+    /// record. Redirects left by email changes are counted and otherwise left to expire.
+    pub async fn verify_email_keys(&self, repair: bool) -> Result<ConsistencyReport> {
+        let mut report = ConsistencyReport {
+            repaired: repair,
+            ..ConsistencyReport::default()
+        };
+
+        let mut users: BTreeMap<UserId, (SlackUser, Vec<u8>)> = BTreeMap::new();
+        let id_keys = self.scan_keys("user:id:*").await?;
+        for (key, value) in self.get_many(&id_keys).await? {
+            match from_stored::<SlackUser>(&value) {
+                Ok(user) => {
+                    users.insert(user.id.clone(), (user, value));
+                }
+                Err(_) => report.unreadable.push(redact::key(&key).into_owned()),
+            }
+        }
+        report.users = users.len();
+
+        let email_keys = self.scan_keys(&prefix_pattern("user:email")).await?;
+        report.email_keys = email_keys.len();
+
+        let mut to_delete = Vec::new();
+        let mut to_write = Vec::new();
+        for (key, value) in self.get_many(&email_keys).await? {
+            let indexed: SlackUser = match from_stored(&value) {
+                Ok(EmailRecord::User(indexed)) => indexed,
+                Ok(EmailRecord::Moved(_)) => {
+                    report.redirects += 1;
+                    continue;
+                }
+                Err(_) => {
+                    report.unreadable.push(redact::key(&key).into_owned());
+                    continue;
+                }
+            };
+
+            let (user, user_value) = match users.get(&indexed.id) {
+                Some(user) => user,
+                None => {
+                    report.orphaned.push(redact::key(&key).into_owned());
+                    to_delete.push(key);
+                    continue;
+                }
+            };
+
+            if key != self.stored_email_key(user) && !self.is_alias_key(user, &key) {
+                report.stale.push(redact::key(&key).into_owned());
+                to_delete.push(key);
+            } else if indexed != *user {
+                report.mismatched.push(redact::key(&key).into_owned());
+                to_write.push((key, user_value));
+            }
+        }
+
+        let email_keys: BTreeSet<&str> = email_keys.iter().map(String::as_str).collect();
+        for (user, user_value) in users.values() {
+            let key = self.stored_email_key(user);
+            if !email_keys.contains(key.as_str()) {
+                report.missing.push(redact::key(&key).into_owned());
+                to_write.push((key, user_value));
+            }
+        }
+
+        if repair {
+            self.unlink(&to_delete).await?;
+            for (key, value) in to_write {
+                self.set_bytes(&key, value, REDIS_ENTITY_TIMEOUT).await?;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Aliases for each user id, leaving out any that are someone's real email or that more than
+    /// one user would claim.
+    fn unambiguous_aliases(
+        &self,
+        slack_users: &BTreeSet<SlackUser>,
+    ) -> BTreeMap<String, Vec<String>> {
+        let primaries: BTreeSet<String> = slack_users
+            .iter()
+            .map(|user| email::normalize(&user.email))
+            .collect();
+
+        let mut claims: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        for user in slack_users {
+            for alias in email::aliases(&self.email_aliases, &email::normalize(&user.email)) {
+                claims.entry(alias).or_default().push(&user.id);
+            }
+        }
+
+        let mut aliases: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (alias, ids) in claims {
+            if primaries.contains(&alias) {
+                continue;
+            }
+            match ids.as_slice() {
+                [id] => aliases.entry((*id).to_owned()).or_default().push(alias),
+                _ => warn!(
+                    "Skipping email alias {} shared by {} users",
+                    redact::pii(&alias),
+                    ids.len()
+                ),
+            }
+        }
+
+        aliases
+    }
+
+    pub async fn insert_user_groups(&self, slack_users: &BTreeSet<SlackUserGroup>) -> Result<()> {
+        let current_names: BTreeSet<String> = slack_users
+            .iter()
+            .map(|group| self.layout.fold_name(&group.name))
+            .collect();
+
+        for group in slack_users {
+            let group = self.with_previous_names(group).await;
+            let value = self.to_stored(&group);
+
+            match self
+                .set_bytes(
+                    &format!("user_group:id:{}", group.id),
+                    &value,
+                    REDIS_ENTITY_TIMEOUT,
+                )
+                .await
+            {
+                Ok(previous) => {
+                    let changed = previous.as_deref() != Some(value.as_slice());
+                    let key = group_modified_key(&group.id);
+                    if let Err(e) = self.mark_modified(&key, changed).await {
+                        warn!(
+                            "Unable to record when group {} changed. Error: {}",
+                            group.id, e
+                        );
+                    }
+                    if previous.is_none() {
+                        if let Err(e) = self.adjust_count(GROUP_COUNT_KEY, 1).await {
+                            warn!("Unable to count group {}. Error: {}", group.id, e);
+                        }
+                    }
+                    if changed {
+                        if let Err(e) = self.delete(&group_expanded_key(&group.id)).await {
+                            warn!(
+                                "Unable to drop the expanded members of group {}. Error: {}",
+                                group.id, e
+                            );
+                        }
+                    }
+                }
+                Err(e) => warn!("Unable to insert group {}. Error: {}", group.id, e),
+            }
+
+            if let Err(e) = self
+                .set_bytes(
+                    &format!("user_group:name:{}", self.layout.fold_name(&group.name)),
+                    &value,
+                    REDIS_ENTITY_TIMEOUT,
+                )
+                .await
+            {
+                warn!("Unable to insert group {}. Error: {}", group.id, e);
+            }
+
+            // Old names stop resolving to the group, unless another group has taken them since.
+            for previous_name in &group.previous_names {
+                let previous_name = self.layout.fold_name(previous_name);
+                if current_names.contains(&previous_name) {
+                    continue;
+                }
+
+                if let Err(e) = self
+                    .delete(&format!("user_group:name:{}", previous_name))
+                    .await
+                {
+                    warn!(
+                        "Unable to remove old name of group {}. Error: {}",
+                        group.id, e
+                    );
+                }
+
+                if let Err(e) = self
+                    .set_str(
+                        &format!("user_group:renamed:{}", previous_name),
+                        &group.id,
+                        REDIS_ENTITY_TIMEOUT,
+                    )
+                    .await
+                {
+                    warn!(
+                        "Unable to record rename of group {}. Error: {}",
+                        group.id, e
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the group with `id` and its name key.
+    pub async fn remove_user_group(&self, id: &GroupId) -> Result<()> {
+        if let RedisResponse::Ok(cached) = self.get_user_group_by_id(id).await {
+            self.adjust_count(GROUP_COUNT_KEY, -1).await?;
+            self.delete(&format!(
+                "user_group:name:{}",
+                self.layout.fold_name(&cached.name)
+            ))
+            .await?;
+        }
+        self.delete(&group_modified_key(id)).await?;
+        self.delete(&group_expanded_key(id)).await?;
+        self.delete(&format!("user_group:id:{}", id)).await
+    }
+
+    /// `group`'s members in full, as the last sync stored them, or looked up and stored now if
+    /// they've been dropped since. Members the cache doesn't have are left out.
+    pub async fn get_expanded_members(
+        &self,
+        group: &SlackUserGroup,
+    ) -> RedisResponse<Vec<SlackUser>, RedisErrors> {
+        match self.unwrap_object(&group_expanded_key(&group.id)).await {
+            RedisResponse::Missing => match self.expand_user_group(group).await {
+                Ok(members) => RedisResponse::Ok(members),
+                Err(e) => RedisResponse::Err(e),
+            },
+            other => other,
+        }
+    }
+
+    /// Stores the members of each of `groups` in full, for `?expand=members` to read at once.
+    pub async fn expand_user_groups(&self, groups: &BTreeSet<SlackUserGroup>) -> Result<()> {
+        for group in groups {
+            self.expand_user_group(group).await?;
+        }
+        Ok(())
+    }
+
+    async fn expand_user_group(&self, group: &SlackUserGroup) -> Result<Vec<SlackUser>> {
+        let keys: Vec<String> = group
+            .users
+            .iter()
+            .map(|member| format!("user:id:{}", member.id))
+            .collect();
+        let mut members: Vec<SlackUser> = self
+            .get_many(&keys)
+            .await?
+            .into_iter()
+            .filter_map(|(_, value)| from_stored(&value).ok())
+            .collect();
+        members.sort_by(|a, b| a.id.cmp(&b.id));
+
+        self.set_bytes(
+            &group_expanded_key(&group.id),
+            &self.to_stored(&members),
+            REDIS_ENTITY_TIMEOUT,
+        )
+        .await?;
+
+        let keys: Vec<String> = group
+            .users
+            .iter()
+            .flat_map(|member| self.layout.write_keys(&expanded_in_key(&member.id)))
+            .collect();
+        for (shard, keys) in self.by_shard(&keys) {
+            let mut pipe = redis::pipe();
+            for key in &keys {
+                pipe.sadd(key, group.id.as_str())
+                    .ignore()
+                    .expire(key, REDIS_ENTITY_TIMEOUT)
+                    .ignore();
+            }
+            let mut con = self.shard_con(shard).await?;
+            let _: () =
+                pipe.query_async(&mut *con)
+                    .await
+                    .map_err(|e| RedisErrors::UnableToSet {
+                        key: format!("{}*", EXPANDED_IN_KEY_PREFIX),
+                        source: anyhow!(e),
+                    })?;
+        }
+        Ok(members)
+    }
+
+    /// Drops the expanded members of the groups any of `ids` was in when they were expanded, so
+    /// they aren't served stale.
+    async fn drop_expanded_groups_of(&self, ids: &BTreeSet<UserId>) -> Result<()> {
+        let keys: Vec<String> = ids
+            .iter()
+            .map(|id| self.layout.key(&expanded_in_key(id)).into_owned())
+            .collect();
+        let mut groups = BTreeSet::new();
+        for (shard, keys) in self.by_shard(&keys) {
+            let mut pipe = redis::pipe();
+            for key in &keys {
+                pipe.smembers(key);
+            }
+            let mut con = self.read_shard_con(shard).await?;
+            let members: Vec<Vec<String>> =
+                pipe.query_async(&mut *con)
+                    .await
+                    .map_err(|e| RedisErrors::UnableToGet {
+                        key: format!("{}*", EXPANDED_IN_KEY_PREFIX),
+                        source: anyhow!(e),
+                    })?;
+            groups.extend(members.into_iter().flatten());
+        }
+
+        for group in groups.iter().filter_map(|id| id.parse::<GroupId>().ok()) {
+            self.delete(&group_expanded_key(&group)).await?;
+        }
+        for id in ids {
+            self.delete(&expanded_in_key(id)).await?;
+        }
+        Ok(())
+    }
+
+    /// `group` along with the names earlier syncs saw it under, including the one it had on the
+    /// last sync if it has been renamed since.
+    async fn with_previous_names(&self, group: &SlackUserGroup) -> SlackUserGroup {
+        let mut group = group.clone();
+        if let RedisResponse::Ok(cached) = self
+            .unwrap_object::<SlackUserGroup>(&format!("user_group:id:{}", group.id))
+            .await
+        {
+            group.previous_names = cached.previous_names;
+            group.previous_names.insert(cached.name);
+        }
+
+        let current_name = self.layout.fold_name(&group.name);
+        group.previous_names = group
+            .previous_names
+            .into_iter()
+            .filter(|name| self.layout.fold_name(name) != current_name)
+            .collect();
+        group
+    }
+
+    pub async fn acquire_lock(&self, id: &str) -> Result<bool> {
+        let key = self.layout.key(WRITE_LOCK_KEY);
+        let mut con = self.get_con(&key).await?;
+        let result = con
+            .set_nx(&*key, id)
+            .await
+            .map_err(|e| RedisErrors::UnableToSet {
+                key: key.to_string(),
+                source: anyhow!(e),
+            })?;
+        con.expire(&*key, REDIS_LOCK_TIMEOUT)
+            .await
+            .map_err(|e| RedisErrors::UnableToExpire {
+                key: key.to_string(),
+                source: anyhow!(e),
+            })?;
+        trace!("SETNX `{:?}` => `{:?}` - RESULT: `{:?}`", key, id, result);
+
+        match u8::from_redis_value(&result) {
+            Err(e) => Err(RedisErrors::UnableToReadValue {
+                key: key.to_string(),
+                source: anyhow!(e),
+            }),
+            Ok(value) => Ok(value == 1),
+        }
+    }
+
+    /// Takes or renews a lease held for `ttl_seconds`. Returns whether `holder` now has it.
+    pub async fn claim_lease(&self, key: &str, holder: &str, ttl_seconds: usize) -> Result<bool> {
+        let key = &self.layout.key(key).into_owned();
+        let mut con = self.get_con(key).await?;
+        let claimed: u8 = redis::Script::new(CLAIM_LEASE_SCRIPT)
+            .key(key)
+            .arg(holder)
+            .arg(ttl_seconds)
+            .invoke_async(&mut *con)
+            .await
+            .map_err(|e| RedisErrors::UnableToSet {
+                key: key.to_owned(),
+                source: anyhow!(e),
+            })?;
+        trace!("CLAIM `{}` => `{}` - RESULT: `{}`", key, holder, claimed);
+
+        Ok(claimed == 1)
+    }
+
+    /// Counts a request by the API token `token_id` in the quota window `window` started at
+    /// `window_start`, returning how many it has made in it. Counters expire with their window.
+    pub async fn count_request(
+        &self,
+        token_id: &str,
+        window: &str,
+        window_start: u64,
+        window_seconds: u64,
+    ) -> Result<u64> {
+        let key = &self
+            .layout
+            .key(&format!(
+                "{}{}:{}:{}",
+                QUOTA_KEY_PREFIX, token_id, window, window_start
+            ))
+            .into_owned();
+        let mut con = self.get_con(key).await?;
+        let count: u64 = redis::Script::new(COUNT_REQUEST_SCRIPT)
+            .key(key)
+            .arg(window_seconds)
+            .invoke_async(&mut *con)
+            .await
+            .map_err(|e| RedisErrors::UnableToSet {
+                key: key.to_owned(),
+                source: anyhow!(e),
+            })?;
+        trace!("INCR `{}` - RESULT: `{}`", key, count);
+
+        Ok(count)
+    }
+
+    /// Records when `key`'s record last changed: now if `changed` or it's not been recorded yet,
+    /// otherwise the time already there is kept. Returns the time when it was set.
+    async fn mark_modified(&self, key: &str, changed: bool) -> Result<Option<u64>> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        let mut set = false;
+        for key in self.layout.write_keys(key) {
+            let mut con = self.get_con(&key).await?;
+            let marked: u8 = redis::Script::new(MARK_MODIFIED_SCRIPT)
+                .key(&key)
+                .arg(now)
+                .arg(REDIS_ENTITY_TIMEOUT)
+                .arg(if changed { 1 } else { 0 })
+                .invoke_async(&mut *con)
+                .await
+                .map_err(|e| RedisErrors::UnableToSet {
+                    key: key.clone(),
+                    source: anyhow!(e),
+                })?;
+            set = set || marked == 1;
+        }
+        trace!("MODIFIED `{}` - CHANGED: `{}`", key, changed);
+
+        Ok(if set { Some(now) } else { None })
+    }
+
+    /// Marks the user with `id` as modified, and indexes the time so the user turns up in
+    /// `get_users_updated_since`.
+    async fn mark_user_modified(&self, id: &UserId, changed: bool) -> Result<()> {
+        let updated_at = match self.mark_modified(&user_modified_key(id), changed).await? {
+            Some(updated_at) => updated_at,
+            None => return Ok(()),
+        };
+
+        for key in self.layout.write_keys(USER_UPDATES_KEY) {
+            let mut con = self.get_con(&key).await?;
+            let _: usize = con
+                .zadd(&key, id.to_string(), updated_at)
+                .await
+                .map_err(|e| RedisErrors::UnableToSet {
+                    key: key.clone(),
+                    source: anyhow!(e),
+                })?;
+        }
+        trace!("ZADD `{}` {} {}", USER_UPDATES_KEY, updated_at, id);
+
+        Ok(())
+    }
+
+    /// Users that changed at or after `since`, in seconds since the epoch. Users that have
+    /// expired since are left out.
+    pub async fn get_users_updated_since(
+        &self,
+        since: u64,
+    ) -> RedisResponse<Vec<SlackUser>, RedisErrors> {
+        match self.users_updated_since(since).await {
+            Ok(users) => RedisResponse::Ok(users),
+            Err(e) => RedisResponse::Err(e),
+        }
+    }
+
+    async fn users_updated_since(&self, since: u64) -> Result<Vec<SlackUser>> {
+        let key = self.layout.key(USER_UPDATES_KEY).into_owned();
+        let mut con = self.get_read_con(&key).await?;
+        let ids: Vec<String> =
+            con.zrangebyscore(&key, since, "+inf")
+                .await
+                .map_err(|e| RedisErrors::UnableToGet {
+                    key: key.clone(),
+                    source: anyhow!(e),
+                })?;
+        trace!("ZRANGEBYSCORE `{}` {} - {} ids", key, since, ids.len());
+
+        let keys: Vec<String> = ids.iter().map(|id| format!("user:id:{}", id)).collect();
+        let mut users = Vec::with_capacity(keys.len());
+        for (key, value) in self.get_many(&keys).await? {
+            match from_stored::<SlackUser>(&value) {
+                Ok(user) => users.push(user),
+                Err(e) => warn!("Unable to read {}. Error: {}", redact::key(&key), e),
+            }
+        }
+
+        Ok(users)
+    }
+
+    /// Drops users that have expired from the index of update times, returning how many there
+    /// were. Users are only removed from it when they're deleted, not when they expire.
+    pub async fn prune_user_updates(&self) -> Result<usize> {
+        let key = self.layout.key(USER_UPDATES_KEY).into_owned();
+        let mut con = self.get_con(&key).await?;
+        let ids: Vec<String> =
+            con.zrange(&key, 0, -1)
+                .await
+                .map_err(|e| RedisErrors::UnableToGet {
+                    key: key.clone(),
+                    source: anyhow!(e),
+                })?;
+        drop(con);
+
+        let expired: Vec<String> = self.expired_user_ids(ids).await?.into_iter().collect();
+        self.remove_user_updates(&expired).await?;
+        Ok(expired.len())
+    }
+
+    /// Which of the users with `ids` no longer have an id record.
+    async fn expired_user_ids(&self, ids: Vec<String>) -> Result<BTreeSet<String>> {
+        // Whatever's left once the users that still exist are taken out has expired.
+        let mut expired: BTreeMap<String, String> = ids
+            .into_iter()
+            .map(|id| (self.layout.key(&format!("user:id:{}", id)).into_owned(), id))
+            .collect();
+        let keys: Vec<String> = expired.keys().cloned().collect();
+        for (shard, keys) in self.by_shard(&keys) {
+            let mut con = self.shard_con(shard).await?;
+            for batch in keys.chunks(MGET_BATCH_SIZE) {
+                let mut pipe = redis::pipe();
+                for key in batch {
+                    pipe.exists(key);
+                }
+                let exists: Vec<bool> =
+                    pipe.query_async(&mut *con)
+                        .await
+                        .map_err(|e| RedisErrors::UnableToGet {
+                            key: "user:id:*".to_owned(),
+                            source: anyhow!(e),
+                        })?;
+                for (key, exists) in batch.iter().zip(exists) {
+                    if exists {
+                        expired.remove(key);
+                    }
+                }
+            }
+        }
+
+        Ok(expired.into_iter().map(|(_, id)| id).collect())
+    }
+
+    async fn remove_user_updates(&self, ids: &[String]) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        for key in self.layout.write_keys(USER_UPDATES_KEY) {
+            let mut con = self.get_con(&key).await?;
+            for batch in ids.chunks(PURGE_BATCH_SIZE) {
+                let removed: usize =
+                    con.zrem(&key, batch)
+                        .await
+                        .map_err(|e| RedisErrors::UnableToDelete {
+                            key: key.clone(),
+                            source: anyhow!(e),
+                        })?;
+                trace!("ZREM `{}` - RESULT: `{}`", key, removed);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// When the user with `id` last changed, as seconds since the epoch.
+    pub async fn get_user_modified(&self, id: &UserId) -> Result<Option<u64>> {
+        self.get_timestamp(&user_modified_key(id)).await
+    }
+
+    /// When the group with `id` last changed, as seconds since the epoch.
+    pub async fn get_group_modified(&self, id: &GroupId) -> Result<Option<u64>> {
+        self.get_timestamp(&group_modified_key(id)).await
+    }
+
+    /// Records when the last successful sync finished, as seconds since the epoch.
+    pub async fn set_last_sync(&self, finished_at: u64) -> Result<()> {
+        self.set_str(LAST_SYNC_KEY, &finished_at.to_string(), 0)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_last_sync(&self) -> Result<Option<u64>> {
+        self.get_timestamp(LAST_SYNC_KEY).await
+    }
+
+    /// Saves the Slack API calls the last successful sync made.
+    pub async fn set_last_sync_api_usage(&self, usage: &ApiUsageSummary) -> Result<()> {
+        let value = serde_json::to_string(usage).unwrap();
+        self.set_str(SYNC_API_USAGE_KEY, &value, 0).await?;
+        Ok(())
+    }
+
+    pub async fn get_last_sync_api_usage(&self) -> Result<Option<ApiUsageSummary>> {
+        match self.get_str(SYNC_API_USAGE_KEY).await? {
+            RedisResult::String(value) => {
+                serde_json::from_str(&value)
+                    .map(Some)
+                    .map_err(|e| RedisErrors::UnableToReadValue {
+                        key: SYNC_API_USAGE_KEY.to_owned(),
+                        source: anyhow!(e),
+                    })
+            }
+            RedisResult::Nil => Ok(None),
+        }
+    }
+
+    /// Saves the headline numbers of the last successful sync, for `/slack/stats`.
+    pub async fn set_directory_summary(&self, summary: &DirectorySummary) -> Result<()> {
+        let value = serde_json::to_string(summary).unwrap();
+        self.set_str(SYNC_SUMMARY_KEY, &value, 0).await?;
+        Ok(())
+    }
+
+    pub async fn get_directory_summary(&self) -> Result<Option<DirectorySummary>> {
+        match self.get_str(SYNC_SUMMARY_KEY).await? {
+            RedisResult::String(value) => {
+                serde_json::from_str(&value)
+                    .map(Some)
+                    .map_err(|e| RedisErrors::UnableToReadValue {
+                        key: SYNC_SUMMARY_KEY.to_owned(),
+                        source: anyhow!(e),
+                    })
+            }
+            RedisResult::Nil => Ok(None),
+        }
+    }
+
+    /// The options this server writes the cache with.
+    pub fn cache_settings(&self) -> CacheSettings {
+        CacheSettings::new(
+            &self.layout,
+            self.email_hasher.as_ref(),
+            &self.email_aliases,
+            self.value_format,
+        )
+    }
+
+    /// Saves the options this server writes the cache with, as the ones the cache was synced with.
+    pub async fn set_cache_settings(&self) -> Result<()> {
+        let value = serde_json::to_string(&self.cache_settings()).unwrap();
+        self.set_str(SYNC_SETTINGS_KEY, &value, 0).await?;
+        Ok(())
+    }
+
+    /// The options the last sync wrote the cache with, if a sync has saved them.
+    pub async fn get_cache_settings(&self) -> Result<Option<CacheSettings>> {
+        match self.get_str(SYNC_SETTINGS_KEY).await? {
+            RedisResult::String(value) => {
+                serde_json::from_str(&value)
+                    .map(Some)
+                    .map_err(|e| RedisErrors::UnableToReadValue {
+                        key: SYNC_SETTINGS_KEY.to_owned(),
+                        source: anyhow!(e),
+                    })
+            }
+            RedisResult::Nil => Ok(None),
+        }
+    }
+
+    /// Sets how many users and groups are cached to what a sync wrote. The counts expire along
+    /// with what they count, so they go away with it if syncs stop.
+    pub async fn set_counts(&self, users: u64, groups: u64) -> Result<()> {
+        self.set_str(USER_COUNT_KEY, &users.to_string(), REDIS_ENTITY_TIMEOUT)
+            .await?;
+        self.set_str(GROUP_COUNT_KEY, &groups.to_string(), REDIS_ENTITY_TIMEOUT)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_user_count(&self) -> Result<Option<u64>> {
+        self.get_count(USER_COUNT_KEY).await
+    }
+
+    pub async fn get_user_group_count(&self) -> Result<Option<u64>> {
+        self.get_count(GROUP_COUNT_KEY).await
+    }
+
+    /// Moves the count under `key` by `by` with INCRBY, keeping the time it has left. Counts
+    /// that aren't set are left for the next sync to set.
+    async fn adjust_count(&self, key: &str, by: i64) -> Result<()> {
+        for key in self.layout.write_keys(key) {
+            let mut con = self.get_con(&key).await?;
+            let count: Option<i64> = redis::Script::new(ADJUST_COUNT_SCRIPT)
+                .key(&key)
+                .arg(by)
+                .invoke_async(&mut *con)
+                .await
+                .map_err(|e| RedisErrors::UnableToSet {
+                    key: key.clone(),
+                    source: anyhow!(e),
+                })?;
+            trace!("INCRBY `{}` {} - RESULT: `{:?}`", key, by, count);
+        }
+        Ok(())
+    }
+
+    /// A count kept by `adjust_count`. Removals racing a sync can take it below zero for a
+    /// while, which is read as zero.
+    async fn get_count(&self, key: &str) -> Result<Option<u64>> {
+        match self.get_str(key).await? {
+            RedisResult::String(value) => value
+                .parse::<i64>()
+                .map(|count| Some(count.max(0) as u64))
+                .map_err(|e| RedisErrors::UnableToReadValue {
+                    key: key.to_owned(),
+                    source: anyhow!(e),
+                }),
+            RedisResult::Nil => Ok(None),
+        }
+    }
+
+    async fn get_timestamp(&self, key: &str) -> Result<Option<u64>> {
+        match self.get_str(key).await? {
+            RedisResult::String(value) => {
+                value
+                    .parse::<u64>()
+                    .map(Some)
+                    .map_err(|e| RedisErrors::UnableToReadValue {
+                        key: key.to_owned(),
+                        source: anyhow!(e),
+                    })
+            }
+            RedisResult::Nil => Ok(None),
+        }
+    }
+
+    /// Adds `users` to the ones a staged sync has fetched so far. The staging area expires like
+    /// cached users do, so one left behind by a sync that died goes away on its own.
+    pub async fn stage_users(&self, users: &BTreeSet<SlackUser>) -> Result<()> {
+        if users.is_empty() {
+            return Ok(());
+        }
+        let key = self.layout.key(SYNC_STAGED_USERS_KEY);
+        let fields: Vec<(&str, String)> = users
+            .iter()
+            .map(|user| (user.id.as_str(), serde_json::to_string(user).unwrap()))
+            .collect();
+
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .hset_multiple(&*key, &fields)
+            .ignore()
+            .expire(&*key, REDIS_ENTITY_TIMEOUT)
+            .ignore();
+        let mut con = self.get_con(&key).await?;
+        pipe.query_async(&mut *con)
+            .await
+            .map_err(|e| RedisErrors::UnableToSet {
+                key: key.to_string(),
+                source: anyhow!(e),
+            })
+    }
+
+    pub async fn get_staged_users(&self) -> Result<BTreeSet<SlackUser>> {
+        Ok(self
+            .hash_values(SYNC_STAGED_USERS_KEY)
+            .await?
+            .into_iter()
+            .collect())
+    }
+
+    pub async fn clear_staged_users(&self) -> Result<()> {
+        self.delete(SYNC_STAGED_USERS_KEY).await
+    }
+
+    pub async fn set_sync_failure(&self, failure: &SyncFailure) -> Result<()> {
+        let value = serde_json::to_string(failure).unwrap();
+        self.set_str(SYNC_FAILURE_KEY, &value, 0).await?;
+        Ok(())
+    }
+
+    pub async fn clear_sync_failure(&self) -> Result<()> {
+        self.delete(SYNC_FAILURE_KEY).await
+    }
+
+    pub async fn get_sync_failure(&self) -> Result<Option<SyncFailure>> {
+        match self.get_str(SYNC_FAILURE_KEY).await? {
+            RedisResult::String(value) => {
+                serde_json::from_str(&value)
+                    .map(Some)
+                    .map_err(|e| RedisErrors::UnableToReadValue {
+                        key: SYNC_FAILURE_KEY.to_owned(),
+                        source: anyhow!(e),
+                    })
+            }
+            RedisResult::Nil => Ok(None),
+        }
+    }
+
+    /// Saves how far a sync got through users.list. It's kept for `ttl`, after which the next
+    /// sync starts over instead of resuming from it.
+    pub async fn save_sync_cursor(&self, crawl: &UsersCrawl, ttl: Duration) -> Result<()> {
+        let value = serde_json::to_string(crawl).unwrap();
+        self.set_str(SYNC_CURSOR_KEY, &value, ttl.as_secs().max(1) as usize)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_sync_cursor(&self) -> Result<Option<UsersCrawl>> {
+        match self.get_str(SYNC_CURSOR_KEY).await? {
+            RedisResult::String(value) => {
+                serde_json::from_str(&value)
+                    .map(Some)
+                    .map_err(|e| RedisErrors::UnableToReadValue {
+                        key: SYNC_CURSOR_KEY.to_owned(),
+                        source: anyhow!(e),
+                    })
+            }
+            RedisResult::Nil => Ok(None),
+        }
+    }
+
+    pub async fn clear_sync_cursor(&self) -> Result<()> {
+        self.delete(SYNC_CURSOR_KEY).await
+    }
+
+    /// Saves the members each group had as Slack listed them, before any filtering or merging,
+    /// so the next sync can skip fetching the groups that haven't changed. They're kept as long
+    /// as the groups themselves, after which every group is fetched again.
+    pub async fn set_fetched_groups(&self, fetched: &FetchedGroups) -> Result<()> {
+        let value = serde_json::to_string(fetched).unwrap();
+        self.set_str(SYNC_GROUP_MEMBERS_KEY, &value, REDIS_ENTITY_TIMEOUT)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_fetched_groups(&self) -> Result<FetchedGroups> {
+        match self.get_str(SYNC_GROUP_MEMBERS_KEY).await? {
+            RedisResult::String(value) => {
+                serde_json::from_str(&value).map_err(|e| RedisErrors::UnableToReadValue {
+                    key: SYNC_GROUP_MEMBERS_KEY.to_owned(),
+                    source: anyhow!(e),
+                })
+            }
+            RedisResult::Nil => Ok(FetchedGroups::new()),
+        }
+    }
+
+    /// Of `emails`, the ones backfilling found no Slack user for less than their miss TTL ago.
+    pub async fn get_backfill_misses(&self, emails: &BTreeSet<Email>) -> Result<BTreeSet<Email>> {
+        let key = self.layout.key(BACKFILL_MISSES_KEY).into_owned();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        // Misses are kept until the second they run out, exclusive.
+        let min = format!("({}", now);
+        let mut con = self.get_read_con(&key).await?;
+        let misses: BTreeSet<String> =
+            con.zrangebyscore(&key, &min, "+inf")
+                .await
+                .map_err(|e| RedisErrors::UnableToGet {
+                    key: key.clone(),
+                    source: anyhow!(e),
+                })?;
+        trace!("ZRANGEBYSCORE `{}` ({} - {} misses", key, now, misses.len());
+
+        Ok(emails
+            .iter()
+            .filter(|email| misses.contains(&self.backfill_member(email)))
+            .cloned()
+            .collect())
+    }
+
+    /// Remembers that Slack had no user for `emails`, so they aren't looked up again for `ttl`.
+    /// Misses whose time has run out are dropped.
+    pub async fn add_backfill_misses(&self, emails: &[Email], ttl: Duration) -> Result<()> {
+        let key = self.layout.key(BACKFILL_MISSES_KEY).into_owned();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let entries: Vec<(u64, String)> = emails
+            .iter()
+            .map(|email| (now + ttl.as_secs(), self.backfill_member(email)))
+            .collect();
+
+        let mut pipe = redis::pipe();
+        pipe.atomic().zrembyscore(&key, "-inf", now).ignore();
+        if !entries.is_empty() {
+            pipe.zadd_multiple(&key, &entries).ignore();
+        }
+        let mut con = self.get_con(&key).await?;
+        let _: () = pipe
+            .query_async(&mut *con)
+            .await
+            .map_err(|e| RedisErrors::UnableToSet {
+                key: key.clone(),
+                source: anyhow!(e),
+            })?;
+
+        Ok(())
+    }
+
+    /// How `email` is kept among the backfill misses, hashed like email keys are.
+    fn backfill_member(&self, email: &Email) -> String {
+        let normalized = email.normalized();
+        match &self.email_hasher {
+            None => normalized.to_string(),
+            Some(hasher) => hasher.hash(&normalized.to_string()),
+        }
+    }
+
+    /// Caches the availability of each user for `ttl`. It's refreshed by every sync, so `ttl`
+    /// should outlast the time between them.
+    pub async fn insert_availability(
+        &self,
+        availability: &BTreeMap<UserId, UserAvailability>,
+        ttl: Duration,
+    ) -> Result<()> {
+        let ttl_seconds = ttl.as_secs().max(1) as usize;
+        for (id, user) in availability {
+            if let Err(e) = self
+                .set_bytes(
+                    &format!("user:dnd:{}", id),
+                    &self.to_stored(user),
+                    ttl_seconds,
+                )
+                .await
+            {
+                warn!("Unable to cache availability of {}. Error: {}", id, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_availability(
+        &self,
+        id: &UserId,
+    ) -> RedisResponse<UserAvailability, RedisErrors> {
+        self.unwrap_object(&format!("user:dnd:{}", id)).await
+    }
+
+    /// Registers the on-call schedule of a group, replacing any it had. Unlike the cache, the
+    /// registry doesn't expire.
+    pub async fn set_oncall_schedule(&self, id: &GroupId, schedule: &OncallSchedule) -> Result<()> {
+        let key = self.layout.key(ONCALL_SCHEDULES_KEY);
+        let mut con = self.get_con(&key).await?;
+        con.hset(&*key, id.as_str(), serde_json::to_string(schedule).unwrap())
+            .await
+            .map_err(|e| RedisErrors::UnableToSet {
+                key: key.to_string(),
+                source: anyhow!(e),
+            })
+    }
+
+    /// Unregisters the on-call schedule of a group, returning whether it had one.
+    pub async fn remove_oncall_schedule(&self, id: &GroupId) -> Result<bool> {
+        let key = self.layout.key(ONCALL_SCHEDULES_KEY);
+        let mut con = self.get_con(&key).await?;
+        let removed: usize =
+            con.hdel(&*key, id.as_str())
+                .await
+                .map_err(|e| RedisErrors::UnableToDelete {
+                    key: key.to_string(),
+                    source: anyhow!(e),
+                })?;
+        Ok(removed > 0)
+    }
+
+    pub async fn get_oncall_schedule(
+        &self,
+        id: &GroupId,
+    ) -> RedisResponse<OncallSchedule, RedisErrors> {
+        let key = self.layout.key(ONCALL_SCHEDULES_KEY);
+        let mut con = match self.get_con(&key).await {
+            Ok(con) => con,
+            Err(e) => return RedisResponse::Err(e),
+        };
+        let value: Option<String> = match con.hget(&*key, id.as_str()).await {
+            Ok(value) => value,
+            Err(e) => {
+                return RedisResponse::Err(RedisErrors::UnableToGet {
+                    key: key.to_string(),
+                    source: anyhow!(e),
+                })
+            }
+        };
+
+        match value.map(|value| serde_json::from_str(&value)) {
+            None => RedisResponse::Missing,
+            Some(Ok(schedule)) => RedisResponse::Ok(schedule),
+            Some(Err(e)) => RedisResponse::Err(RedisErrors::UnableToReadValue {
+                key: key.to_string(),
+                source: anyhow!(e),
+            }),
+        }
+    }
+
+    /// Has `channel` told about membership changes of a group, returning every channel that now
+    /// watches it. Like on-call schedules, watchers don't expire.
+    pub async fn add_group_watcher(&self, id: &GroupId, channel: &str) -> Result<BTreeSet<String>> {
+        let key = self.layout.key(GROUP_WATCHERS_KEY);
+        let mut con = self.get_con(&key).await?;
+        let _: usize = con
+            .hset(&*key, watcher_field(id, channel), 1)
+            .await
+            .map_err(|e| RedisErrors::UnableToSet {
+                key: key.to_string(),
+                source: anyhow!(e),
+            })?;
+
+        Ok(self
+            .get_all_group_watchers()
+            .await?
+            .remove(id)
+            .unwrap_or_default())
+    }
+
+    /// Stops telling `channel` about a group, returning whether it was watching it.
+    pub async fn remove_group_watcher(&self, id: &GroupId, channel: &str) -> Result<bool> {
+        let key = self.layout.key(GROUP_WATCHERS_KEY);
+        let mut con = self.get_con(&key).await?;
+        let removed: usize = con
+            .hdel(&*key, watcher_field(id, channel))
+            .await
+            .map_err(|e| RedisErrors::UnableToDelete {
+                key: key.to_string(),
+                source: anyhow!(e),
+            })?;
+        Ok(removed > 0)
+    }
+
+    /// The channels watching each group that has any.
+    pub async fn get_all_group_watchers(&self) -> Result<GroupWatchers> {
+        let key = self.layout.key(GROUP_WATCHERS_KEY);
+        let mut con = self.get_con(&key).await?;
+        let fields: Vec<String> = con
+            .hkeys(&*key)
+            .await
+            .map_err(|e| RedisErrors::UnableToGet {
+                key: key.to_string(),
+                source: anyhow!(e),
+            })?;
+
+        let mut watchers = GroupWatchers::new();
+        for field in fields {
+            let mut parts = field.splitn(2, ':');
+            if let (Some(id), Some(channel)) = (parts.next(), parts.next()) {
+                watchers
+                    .entry(GroupId::unchecked(id.to_owned()))
+                    .or_default()
+                    .insert(channel.to_owned());
+            }
+        }
+        Ok(watchers)
+    }
+
+    /// Every synthetic group. The registry doesn't expire, and each sync writes the groups in it
+    /// back into the cache.
+    pub async fn get_synthetic_groups(&self) -> Result<Vec<SlackUserGroup>> {
+        self.hash_values(SYNTHETIC_GROUPS_KEY).await
+    }
+
+    pub async fn get_synthetic_group(&self, id: &GroupId) -> Result<Option<SlackUserGroup>> {
+        self.hash_value(SYNTHETIC_GROUPS_KEY, id.as_str()).await
+    }
+
+    /// Saves a synthetic group and caches it, so the group routes see it right away.
+    pub async fn set_synthetic_group(&self, group: &SlackUserGroup) -> Result<()> {
+        self.hash_set(SYNTHETIC_GROUPS_KEY, group.id.as_str(), group)
+            .await?;
+        self.insert_user_groups(&vec![group.clone()].into_iter().collect())
+            .await
+    }
+
+    /// Deletes a synthetic group along with its cached copy, returning whether it existed. A
+    /// Slack group written back for it is left in Slack, and cached like any other from then on.
+    pub async fn remove_synthetic_group(&self, id: &GroupId) -> Result<bool> {
+        let removed = self.hash_delete(SYNTHETIC_GROUPS_KEY, id.as_str()).await?;
+        self.hash_delete(SYNTHETIC_GROUP_MIRRORS_KEY, id.as_str())
+            .await?;
+        self.remove_user_group(id).await?;
+        Ok(removed)
+    }
+
+    /// The Slack group written back for each synthetic group that has one.
+    pub async fn get_synthetic_group_mirrors(&self) -> Result<Mirrors> {
+        let key = self.layout.key(SYNTHETIC_GROUP_MIRRORS_KEY);
+        let mut con = self.get_con(&key).await?;
+        let mirrors: BTreeMap<String, String> =
+            con.hgetall(&*key)
+                .await
+                .map_err(|e| RedisErrors::UnableToGet {
+                    key: key.to_string(),
+                    source: anyhow!(e),
+                })?;
+
+        Ok(mirrors
+            .into_iter()
+            .map(|(id, slack_id)| (GroupId::unchecked(id), GroupId::unchecked(slack_id)))
+            .collect())
+    }
+
+    pub async fn set_synthetic_group_mirror(&self, id: &GroupId, slack_id: &GroupId) -> Result<()> {
+        let key = self.layout.key(SYNTHETIC_GROUP_MIRRORS_KEY);
+        let mut con = self.get_con(&key).await?;
+        con.hset(&*key, id.as_str(), slack_id.as_str())
+            .await
+            .map_err(|e| RedisErrors::UnableToSet {
+                key: key.to_string(),
+                source: anyhow!(e),
+            })
+    }
+
+    /// Saves the plan `apply` makes, replacing any saved before.
+    pub async fn set_write_back_plan(&self, plan: &WriteBackPlan) -> Result<()> {
+        let value = serde_json::to_string(plan).unwrap();
+        self.set_str(WRITE_BACK_PLAN_KEY, &value, 0).await?;
+        Ok(())
+    }
+
+    pub async fn get_write_back_plan(&self) -> Result<Option<WriteBackPlan>> {
+        match self.get_str(WRITE_BACK_PLAN_KEY).await? {
+            RedisResult::String(value) => {
+                serde_json::from_str(&value)
+                    .map(Some)
+                    .map_err(|e| RedisErrors::UnableToReadValue {
+                        key: WRITE_BACK_PLAN_KEY.to_owned(),
+                        source: anyhow!(e),
+                    })
+            }
+            RedisResult::Nil => Ok(None),
+        }
+    }
+
+    /// Drops the saved plan, returning whether it was still there. Only the caller that drops it
+    /// goes on to make it, so a plan can't be applied twice.
+    pub async fn remove_write_back_plan(&self) -> Result<bool> {
+        let mut removed = 0;
+        for key in self.layout.write_keys(WRITE_BACK_PLAN_KEY) {
+            let mut con = self.get_con(&key).await?;
+            let count: usize = con
+                .del(&key)
+                .await
+                .map_err(|e| RedisErrors::UnableToDelete {
+                    key: key.to_string(),
+                    source: anyhow!(e),
+                })?;
+            removed += count;
+        }
+        Ok(removed > 0)
+    }
+
+    pub async fn add_group_change(&self, change: &GroupChange) -> Result<()> {
+        self.hash_set(SYNTHETIC_GROUP_CHANGES_KEY, &change.id, change)
+            .await
+    }
+
+    /// Changes to synthetic groups waiting for approval, oldest first.
+    pub async fn get_group_changes(&self) -> Result<Vec<GroupChange>> {
+        let mut changes: Vec<GroupChange> = self.hash_values(SYNTHETIC_GROUP_CHANGES_KEY).await?;
+        changes.sort_by(|a, b| (a.requested_at, &a.id).cmp(&(b.requested_at, &b.id)));
+        Ok(changes)
+    }
+
+    pub async fn get_group_change(&self, id: &str) -> Result<Option<GroupChange>> {
+        self.hash_value(SYNTHETIC_GROUP_CHANGES_KEY, id).await
+    }
+
+    /// Drops a change waiting for approval, returning whether it was still waiting. Only the
+    /// caller that drops it goes on to make it, so a change can't be approved twice.
+    pub async fn remove_group_change(&self, id: &str) -> Result<bool> {
+        self.hash_delete(SYNTHETIC_GROUP_CHANGES_KEY, id).await
+    }
+
+    async fn hash_values<T: serde::de::DeserializeOwned>(&self, name: &str) -> Result<Vec<T>> {
+        let key = self.layout.key(name);
+        let mut con = self.get_con(&key).await?;
+        let values: Vec<String> = con
+            .hvals(&*key)
+            .await
+            .map_err(|e| RedisErrors::UnableToGet {
+                key: key.to_string(),
+                source: anyhow!(e),
+            })?;
+
+        values
+            .iter()
+            .map(|value| {
+                serde_json::from_str(value).map_err(|e| RedisErrors::UnableToReadValue {
+                    key: key.to_string(),
+                    source: anyhow!(e),
+                })
+            })
+            .collect()
+    }
+
+    async fn hash_value<T: serde::de::DeserializeOwned>(
+        &self,
+        name: &str,
+        field: &str,
+    ) -> Result<Option<T>> {
+        let key = self.layout.key(name);
+        let mut con = self.get_con(&key).await?;
+        let value: Option<String> =
+            con.hget(&*key, field)
+                .await
+                .map_err(|e| RedisErrors::UnableToGet {
+                    key: key.to_string(),
+                    source: anyhow!(e),
+                })?;
+
+        value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()
+            .map_err(|e| RedisErrors::UnableToReadValue {
+                key: key.to_string(),
+                source: anyhow!(e),
+            })
+    }
+
+    async fn hash_set<T: serde::Serialize>(
+        &self,
+        name: &str,
+        field: &str,
+        value: &T,
+    ) -> Result<()> {
+        let key = self.layout.key(name);
+        let mut con = self.get_con(&key).await?;
+        con.hset(&*key, field, serde_json::to_string(value).unwrap())
+            .await
+            .map_err(|e| RedisErrors::UnableToSet {
+                key: key.to_string(),
+                source: anyhow!(e),
+            })
+    }
+
+    async fn hash_delete(&self, name: &str, field: &str) -> Result<bool> {
+        let key = self.layout.key(name);
+        let mut con = self.get_con(&key).await?;
+        let removed: usize =
+            con.hdel(&*key, field)
+                .await
+                .map_err(|e| RedisErrors::UnableToDelete {
+                    key: key.to_string(),
+                    source: anyhow!(e),
+                })?;
+        Ok(removed > 0)
+    }
+
+    /// Adds a snapshot of each group to its history when the group changed since the latest one,
+    /// keeping at most `keep` snapshots per group.
+    pub async fn record_group_history(
+        &self,
+        groups: &BTreeSet<SlackUserGroup>,
+        timestamp: u64,
+        keep: usize,
+    ) -> Result<()> {
+        for group in groups {
+            let snapshot = GroupSnapshot::new(group, timestamp);
+            if let Err(e) = self.add_group_snapshot(&group.id, &snapshot, keep).await {
+                warn!(
+                    "Unable to record history of group {}. Error: {}",
+                    group.id, e
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn add_group_snapshot(
+        &self,
+        id: &str,
+        snapshot: &GroupSnapshot,
+        keep: usize,
+    ) -> Result<()> {
+        let key = self
+            .layout
+            .key(&format!("user_group:history:{}", id))
+            .into_owned();
+        let added = self
+            .append_history(
+                &key,
+                snapshot,
+                snapshot.timestamp,
+                |latest: &GroupSnapshot| latest.same_as(snapshot),
+            )
+            .await?;
+        if !added {
+            return Ok(());
+        }
+
+        let mut con = self.get_con(&key).await?;
+        let trimmed: usize = con
+            .zremrangebyrank(&key, 0, -(keep as isize) - 1)
+            .await
+            .map_err(|e| RedisErrors::UnableToDelete {
+                key: key.clone(),
+                source: anyhow!(e),
+            })?;
+        trace!("ZREMRANGEBYRANK `{}` - RESULT: `{}`", key, trimmed);
+
+        Ok(())
+    }
+
+    /// Adds each user to its history when it changed since the latest version, and drops the
+    /// versions that had been replaced more than `retention` ago.
+    pub async fn record_user_history(
+        &self,
+        users: &BTreeSet<SlackUser>,
+        timestamp: u64,
+        retention: Duration,
+    ) -> Result<()> {
+        let cutoff = timestamp.saturating_sub(retention.as_secs());
+        for user in users {
+            let version = UserVersion {
+                timestamp,
+                user: self.stored_user(user),
+            };
+            if let Err(e) = self.add_user_version(&version, cutoff).await {
+                warn!("Unable to record history of user {}. Error: {}", user.id, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn add_user_version(&self, version: &UserVersion, cutoff: u64) -> Result<()> {
+        let key = self
+            .layout
+            .key(&format!("user:history:{}", version.user.id))
+            .into_owned();
+        self.append_history(&key, version, version.timestamp, |latest: &UserVersion| {
+            latest.user == version.user
+        })
+        .await?;
+
+        // The newest version from before the cutoff stays, as it was still current at the cutoff.
+        let mut con = self.get_con(&key).await?;
+        let before_cutoff: usize = con
+            .zcount(&key, "-inf", format!("({}", cutoff))
+            .await
+            .map_err(|e| RedisErrors::UnableToGet {
+                key: key.clone(),
+                source: anyhow!(e),
+            })?;
+        if before_cutoff > 1 {
+            let trimmed: usize = con
+                .zremrangebyrank(&key, 0, before_cutoff as isize - 2)
+                .await
+                .map_err(|e| RedisErrors::UnableToDelete {
+                    key: key.clone(),
+                    source: anyhow!(e),
+                })?;
+            trace!("ZREMRANGEBYRANK `{}` - RESULT: `{}`", key, trimmed);
+        }
+
+        Ok(())
+    }
+
+    /// The version of a user that was current at `as_of`.
+    pub async fn get_user_as_of(
+        &self,
+        id: &UserId,
+        as_of: u64,
+    ) -> RedisResponse<SlackUser, RedisErrors> {
+        let key = self
+            .layout
+            .key(&format!("user:history:{}", id))
+            .into_owned();
+        let mut con = match self.get_con(&key).await {
+            Ok(con) => con,
+            Err(e) => return RedisResponse::Err(e),
+        };
+
+        let versions: Vec<String> =
+            match con.zrevrangebyscore_limit(&key, as_of, "-inf", 0, 1).await {
+                Ok(versions) => versions,
+                Err(e) => {
+                    return RedisResponse::Err(RedisErrors::UnableToGet {
+                        key,
+                        source: anyhow!(e),
+                    })
+                }
+            };
+
+        match versions.first() {
+            None => RedisResponse::Missing,
+            Some(version) => match serde_json::from_str::<UserVersion>(version) {
+                Ok(version) => RedisResponse::Ok(version.user),
+                Err(e) => RedisResponse::Err(RedisErrors::UnableToDeserialize {
+                    input: key,
+                    source: anyhow!(e),
+                }),
+            },
+        }
+    }
+
+    /// Adds `entry` to the sorted set at `key` unless `unchanged` says it matches the latest entry
+    /// there. Returns whether it was added.
+    async fn append_history<T>(
+        &self,
+        key: &str,
+        entry: &T,
+        timestamp: u64,
+        unchanged: impl Fn(&T) -> bool,
+    ) -> Result<bool>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let mut con = self.get_con(key).await?;
+        let latest: Vec<String> =
+            con.zrevrange(key, 0, 0)
+                .await
+                .map_err(|e| RedisErrors::UnableToGet {
+                    key: key.to_owned(),
+                    source: anyhow!(e),
+                })?;
+        let is_unchanged = latest
+            .first()
+            .and_then(|latest| serde_json::from_str::<T>(latest).ok())
+            .map_or(false, |latest| unchanged(&latest));
+        if is_unchanged {
+            return Ok(false);
+        }
+
+        let value = serde_json::to_string(entry).unwrap();
+        let added: usize =
+            con.zadd(key, &value, timestamp)
+                .await
+                .map_err(|e| RedisErrors::UnableToSet {
+                    key: key.to_owned(),
+                    source: anyhow!(e),
+                })?;
+        trace!("ZADD `{}` {} - RESULT: `{}`", key, timestamp, added);
+
+        Ok(true)
+    }
+
+    /// Every snapshot kept of a group, oldest first.
+    pub async fn get_group_history(
+        &self,
+        id: &GroupId,
+    ) -> RedisResponse<Vec<GroupSnapshot>, RedisErrors> {
+        let key = self
+            .layout
+            .key(&format!("user_group:history:{}", id))
+            .into_owned();
+        let mut con = match self.get_con(&key).await {
+            Ok(con) => con,
+            Err(e) => return RedisResponse::Err(e),
+        };
+
+        let snapshots: Vec<String> = match con.zrange(&key, 0, -1).await {
+            Ok(snapshots) => snapshots,
+            Err(e) => {
+                return RedisResponse::Err(RedisErrors::UnableToGet {
+                    key,
+                    source: anyhow!(e),
+                })
+            }
+        };
+        if snapshots.is_empty() {
+            return RedisResponse::Missing;
+        }
+
+        let parsed: serde_json::Result<Vec<GroupSnapshot>> = snapshots
+            .iter()
+            .map(|snapshot| serde_json::from_str(snapshot))
+            .collect();
+        match parsed {
+            Ok(snapshots) => RedisResponse::Ok(snapshots),
+            Err(e) => RedisResponse::Err(RedisErrors::UnableToDeserialize {
+                input: key,
+                source: anyhow!(e),
+            }),
+        }
+    }
+
+    /// Appends an entry to a Redis Stream, creating the stream if needed. With `max_len`, the
+    /// oldest entries are dropped once the stream grows to about that many.
+    pub async fn append_to_stream(
+        &self,
+        key: &str,
+        fields: &[(&str, String)],
+        max_len: Option<usize>,
+    ) -> Result<()> {
+        let key = &self.layout.key(key).into_owned();
+        let mut con = self.get_con(key).await?;
+        let mut command = redis::cmd("XADD");
+        command.arg(key);
+        if let Some(max_len) = max_len {
+            command.arg("MAXLEN").arg("~").arg(max_len);
+        }
+        let id: String = command
+            .arg("*")
+            .arg(fields)
+            .query_async(&mut *con)
+            .await
+            .map_err(|e| RedisErrors::UnableToSet {
+                key: key.to_owned(),
+                source: anyhow!(e),
+            })?;
+        trace!("XADD `{}` - RESULT: `{}`", key, id);
+
+        Ok(())
+    }
+
+    /// Up to `count` entries of a Redis Stream that come after `after`, as their id and the value
+    /// of `field`. Entries without `field` are skipped.
+    pub async fn read_stream(
+        &self,
+        key: &str,
+        field: &str,
+        after: &str,
+        count: usize,
+    ) -> Result<Vec<(String, String)>> {
+        let key = &self.layout.key(key).into_owned();
+        let mut con = self.get_con(key).await?;
+        let reply: StreamReadReply = con
+            .xread_options(&[key], &[after], StreamReadOptions::default().count(count))
+            .await
+            .map_err(|e| RedisErrors::UnableToGet {
+                key: key.to_owned(),
+                source: anyhow!(e),
+            })?;
+
+        Ok(reply
+            .keys
+            .into_iter()
+            .flat_map(|stream| stream.ids)
+            .filter_map(|entry| {
+                let value: String = entry.get(field)?;
+                Some((entry.id, value))
+            })
+            .collect())
+    }
+
+    /// Id of the newest entry in a Redis Stream.
+    pub async fn last_stream_id(&self, key: &str) -> Result<Option<String>> {
+        let key = &self.layout.key(key).into_owned();
+        let mut con = self.get_con(key).await?;
+        let reply: StreamRangeReply =
+            con.xrevrange_count(key, "+", "-", 1)
+                .await
+                .map_err(|e| RedisErrors::UnableToGet {
+                    key: key.to_owned(),
+                    source: anyhow!(e),
+                })?;
+
+        Ok(reply.ids.into_iter().next().map(|entry| entry.id))
+    }
+
+    /// Copies the keys earlier versions wrote without a prefix to where the configured layout
+    /// keeps them, lowercasing emails and group names on the way. Keys keep their TTL, and ones
+    /// already written in the new layout are left alone, so it's safe to run while servers are
+    /// dual writing. With `delete_legacy`, the old keys are removed once copied. Without `apply`,
+    /// only counts what would be copied.
+    pub async fn migrate_legacy_keys(
+        &self,
+        apply: bool,
+        delete_legacy: bool,
+    ) -> Result<MigrationReport> {
+        let mut legacy_keys = BTreeSet::new();
+        for shard in 0..self.shards.len() {
+            for app_prefix in APP_KEY_PREFIXES {
+                let pattern = prefix_pattern(app_prefix);
+                legacy_keys.extend(self.scan_shard_keys(shard, &pattern).await?);
+            }
+        }
+
+        let mut report = MigrationReport {
+            legacy_keys: legacy_keys.len(),
+            applied: apply,
+            ..MigrationReport::default()
+        };
+        for key in legacy_keys {
+            let target = match key_layout::migrated_key(&self.layout, &key) {
+                Some(target) => target,
+                None => continue,
+            };
+            report.to_migrate += 1;
+            if !apply {
+                continue;
+            }
+
+            match self.copy_key(&key, &target).await? {
+                CopyResult::Copied => report.copied += 1,
+                CopyResult::AlreadyPresent => report.already_present += 1,
+                CopyResult::Vanished => {
+                    report.vanished += 1;
+                    continue;
+                }
+            }
+            if delete_legacy {
+                let shard = self.ring.node(&key);
+                report.deleted += self.unlink_on_shard(shard, &[key]).await?;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Copies `from` to `to` with DUMP and RESTORE, which keeps the value's type and TTL.
+    async fn copy_key(&self, from: &str, to: &str) -> Result<CopyResult> {
+        let mut con = self.get_con(from).await?;
+        let (dump, ttl_millis): (Option<Vec<u8>>, i64) = redis::pipe()
+            .cmd("DUMP")
+            .arg(from)
+            .cmd("PTTL")
+            .arg(from)
+            .query_async(&mut *con)
+            .await
+            .map_err(|e| RedisErrors::UnableToGet {
+                key: redact::key(from).into_owned(),
+                source: anyhow!(e),
+            })?;
+        let dump = match dump {
+            Some(dump) => dump,
+            None => return Ok(CopyResult::Vanished),
+        };
+
+        // PTTL is -1 for keys that don't expire, which RESTORE wants as 0.
+        let mut con = self.get_con(to).await?;
+        let restored: redis::RedisResult<()> = redis::cmd("RESTORE")
+            .arg(to)
+            .arg(ttl_millis.max(0))
+            .arg(dump)
+            .query_async(&mut *con)
+            .await;
+        trace!("RESTORE `{}` FROM `{}`", redact::key(to), redact::key(from));
+
+        match restored {
+            Ok(()) => Ok(CopyResult::Copied),
+            Err(e) if e.code() == Some("BUSYKEY") => Ok(CopyResult::AlreadyPresent),
+            Err(e) => Err(RedisErrors::UnableToSet {
+                key: redact::key(to).into_owned(),
+                source: anyhow!(e),
+            }),
+        }
+    }
+
+    /// Counts every key under the configured layout by prefix, along with the memory each uses and how long
+    /// it has left, listing the `largest` biggest. Walks the keyspace with SCAN, so it doesn't
+    /// block Redis, but takes a while on big caches.
+    pub async fn cache_stats(&self, largest: usize) -> Result<CacheStats> {
+        let mut stats = CacheStats::new(largest);
+        for shard in 0..self.shards.len() {
+            self.add_shard_stats(shard, &mut stats).await?;
+        }
 
-#[derive(Debug)]
-pub enum RedisResponse<T, E> {
-    Err(E),
-    Missing,
-    Ok(T),
-}
+        Ok(stats)
+    }
 
-impl RedisServer {
-    pub async fn new(redis_address: &str) -> Result<Self> {
-        let client: redis::Client =
-            redis::Client::open(redis_address).map_err(|e| RedisErrors::UnableToConnect {
-                address: redis_address.to_owned(),
+    /// Claims a scan of the whole cache for `/admin/cache_stats`. Returns whether it was claimed,
+    /// which it isn't when another scan was within `interval`.
+    pub async fn claim_stats_scan(&self, interval: Duration) -> Result<bool> {
+        let key = self.layout.key(CACHE_STATS_SCAN_KEY);
+        let mut con = self.get_con(&key).await?;
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(&*key)
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(interval.as_secs())
+            .query_async(&mut *con)
+            .await
+            .map_err(|e| RedisErrors::UnableToSet {
+                key: key.to_string(),
                 source: anyhow!(e),
             })?;
-        let manager = RedisConnectionManager::new(client);
-        let pool = Pool::builder()
-            .get_timeout(Some(Duration::from_secs(CACHE_POOL_TIMEOUT_SECONDS)))
-            .max_open(CACHE_POOL_MAX_OPEN)
-            .max_idle(CACHE_POOL_MAX_IDLE)
-            .max_lifetime(Some(Duration::from_secs(CACHE_POOL_EXPIRE_SECONDS)))
-            .build(manager);
+        trace!("SET `{}` NX - RESULT: `{:?}`", key, claimed);
 
-        Ok(Self {
-            redis_client: pool,
-            redis_address: redis_address.to_owned(),
-        })
+        Ok(claimed.is_some())
     }
 
-    pub async fn get_all_users(&self) -> RedisResponse<Vec<SlackUser>, RedisErrors> {
-        let results: Result<Vec<SlackUser>> = self.str_scan("user:id:*").await;
+    async fn add_shard_stats(&self, shard: usize, stats: &mut CacheStats) -> Result<()> {
+        let keys = self
+            .scan_shard_keys(shard, &self.layout.pattern("*"))
+            .await?;
+        let mut con = self.shard_con(shard).await?;
+        for batch in keys.chunks(STATS_BATCH_SIZE) {
+            let mut pipe = redis::pipe();
+            for key in batch {
+                pipe.cmd("MEMORY").arg("USAGE").arg(key).cmd("TTL").arg(key);
+            }
+            // MEMORY USAGE is nil for keys deleted since the scan.
+            let replies: Vec<Option<i64>> =
+                pipe.query_async(&mut *con)
+                    .await
+                    .map_err(|e| RedisErrors::UnableToGet {
+                        key: "*".to_owned(),
+                        source: anyhow!(e),
+                    })?;
 
-        match results {
-            Ok(value) => RedisResponse::Ok(value),
-            Err(e) => RedisResponse::Err(e),
+            for (key, reply) in batch.iter().zip(replies.chunks(2)) {
+                if let [Some(memory_bytes), Some(ttl_seconds)] = reply {
+                    let key = self.layout.strip(key.clone());
+                    stats.add(&redact::key(&key), *memory_bytes as u64, *ttl_seconds);
+                }
+            }
         }
-    }
 
-    pub async fn get_all_user_groups(&self) -> RedisResponse<Vec<SlackUserGroup>, RedisErrors> {
-        let results: Result<Vec<SlackUserGroup>> = self.str_scan("user_group:id:*").await;
+        Ok(())
+    }
 
-        match results {
-            Ok(value) => RedisResponse::Ok(value),
-            Err(e) => RedisResponse::Err(e),
+    /// Every key matching the glob `pattern`.
+    pub async fn scan_keys(&self, pattern: &str) -> Result<Vec<String>> {
+        let pattern = self.layout.pattern(pattern);
+        let mut keys = Vec::new();
+        for shard in 0..self.shards.len() {
+            let found = self.scan_shard_keys(shard, &pattern).await?;
+            keys.extend(found.into_iter().map(|key| self.layout.strip(key)));
         }
-    }
 
-    pub async fn get_user_by_id(&self, id: String) -> RedisResponse<SlackUser, RedisErrors> {
-        self.unwrap_object(&format!("user:id:{}", id)).await
+        Ok(keys)
     }
 
-    pub async fn get_user_by_email(&self, id: String) -> RedisResponse<SlackUser, RedisErrors> {
-        self.unwrap_object(&format!("user:email:{}", id)).await
+    async fn scan_shard_keys(&self, shard: usize, pattern: &str) -> Result<Vec<String>> {
+        let _timer = self.latency.start_timer("scan");
+        let mut con = self.read_shard_con(shard).await?;
+        let mut iter =
+            con.scan_match::<_, String>(pattern)
+                .await
+                .map_err(|e| RedisErrors::UnableToGet {
+                    key: pattern.to_owned(),
+                    source: anyhow!(e),
+                })?;
+
+        let mut keys = Vec::new();
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+        }
+        trace!("SCAN `{}` - {} keys", pattern, keys.len());
+
+        Ok(keys)
     }
 
-    async fn unwrap_object<T>(&self, query_string: &str) -> RedisResponse<T, RedisErrors>
-    where
-        T: serde::de::DeserializeOwned + Clone,
-    {
-        match self.get_str(query_string).await {
-            Err(e) => RedisResponse::Err(e),
-            Ok(res) => match res {
-                RedisResult::String(s) => match serde_json::from_str(&s) {
-                    Ok(value) => RedisResponse::Ok(value),
-                    Err(e) => RedisResponse::Err(RedisErrors::UnableToDeserialize {
-                        input: s,
-                        source: anyhow!(e),
-                    }),
-                },
-                RedisResult::Nil => RedisResponse::Missing,
-            },
+    /// Deletes `keys` with UNLINK, which frees their memory in the background instead of
+    /// blocking Redis, `PURGE_BATCH_SIZE` at a time. Returns how many existed.
+    pub async fn unlink(&self, keys: &[String]) -> Result<usize> {
+        let keys: Vec<String> = keys
+            .iter()
+            .flat_map(|key| self.layout.write_keys(key))
+            .collect();
+        let mut removed = 0;
+        for (shard, keys) in self.by_shard(&keys) {
+            removed += self.unlink_on_shard(shard, &keys).await?;
         }
+
+        Ok(removed)
     }
 
-    pub async fn insert_users(&self, slack_users: &BTreeSet<SlackUser>) -> Result<()> {
-        for user in slack_users {
-            if let Err(e) = self
-                .set_str(
-                    &format!("user:email:{}", user.email),
-                    &serde_json::to_string(&user).unwrap(),
-                    REDIS_ENTITY_TIMEOUT,
-                )
+    async fn unlink_on_shard(&self, shard: usize, keys: &[String]) -> Result<usize> {
+        let mut con = self.shard_con(shard).await?;
+        let mut removed = 0;
+        for batch in keys.chunks(PURGE_BATCH_SIZE) {
+            let count: usize = redis::cmd("UNLINK")
+                .arg(batch)
+                .query_async(&mut *con)
                 .await
-            {
-                warn!("Unable to insert {:?}. Error: {}", user, e);
-            }
+                .map_err(|e| RedisErrors::UnableToDelete {
+                    key: format!("{} keys", batch.len()),
+                    source: anyhow!(e),
+                })?;
+            trace!("UNLINK {} keys - RESULT: `{}`", batch.len(), count);
+            removed += count;
+        }
 
-            if let Err(e) = self
-                .set_str(
-                    &format!("user:id:{}", user.id),
-                    &serde_json::to_string(&user).unwrap(),
-                    REDIS_ENTITY_TIMEOUT,
-                )
-                .await
-            {
-                warn!("Unable to insert {:?}. Error: {}", user, e);
-            }
+        Ok(removed)
+    }
+
+    /// The values of `keys` that exist, read with MGET a batch at a time.
+    async fn get_many(&self, keys: &[String]) -> Result<Vec<(String, Vec<u8>)>> {
+        let keys: Vec<String> = keys
+            .iter()
+            .map(|key| self.layout.key(key).into_owned())
+            .collect();
+        let mut values = Vec::with_capacity(keys.len());
+        for (shard, keys) in self.by_shard(&keys) {
+            let found = self.get_many_on_shard(shard, &keys).await?;
+            values.extend(
+                found
+                    .into_iter()
+                    .map(|(key, value)| (self.layout.strip(key), value)),
+            );
         }
 
-        Ok(())
+        Ok(values)
     }
 
-    pub async fn insert_user_groups(&self, slack_users: &BTreeSet<SlackUserGroup>) -> Result<()> {
-        for group in slack_users {
-            if let Err(e) = self
-                .set_str(
-                    &format!("user_group:id:{}", group.id),
-                    &serde_json::to_string(&group).unwrap(),
-                    REDIS_ENTITY_TIMEOUT,
-                )
+    async fn get_many_on_shard(
+        &self,
+        shard: usize,
+        keys: &[String],
+    ) -> Result<Vec<(String, Vec<u8>)>> {
+        let _timer = self.latency.start_timer("mget");
+        let mut con = self.read_shard_con(shard).await?;
+        let mut values = Vec::with_capacity(keys.len());
+        for batch in keys.chunks(MGET_BATCH_SIZE) {
+            let replies: Vec<redis::Value> = redis::cmd("MGET")
+                .arg(batch)
+                .query_async(&mut *con)
                 .await
-            {
-                warn!("Unable to insert {:?}. Error: {}", group, e);
+                .map_err(|e| RedisErrors::UnableToGet {
+                    key: format!("{} keys", batch.len()),
+                    source: anyhow!(e),
+                })?;
+
+            for (key, reply) in batch.iter().zip(replies) {
+                match self.read_value(key, &reply) {
+                    Ok(Some(value)) => values.push((key.clone(), value)),
+                    Ok(None) => {}
+                    Err(e) => warn!("Unable to read {}. Error: {}", redact::key(key), e),
+                }
             }
+        }
 
-            if let Err(e) = self
-                .set_str(
-                    &format!("user_group:name:{}", group.name),
-                    &serde_json::to_string(&group).unwrap(),
-                    REDIS_ENTITY_TIMEOUT,
-                )
+        Ok(values)
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        for key in self.layout.write_keys(key) {
+            let _timer = self.latency.start_timer("del");
+            let mut con = self.get_con(&key).await?;
+            let removed: usize = con
+                .del(&key)
                 .await
-            {
-                warn!("Unable to insert {:?}. Error: {}", group, e);
-            }
+                .map_err(|e| RedisErrors::UnableToDelete {
+                    key: redact::key(&key).into_owned(),
+                    source: anyhow!(e),
+                })?;
+            trace!("DEL `{}` - RESULT: `{}`", redact::key(&key), removed);
         }
 
         Ok(())
     }
 
-    pub async fn acquire_lock(&self, id: &str) -> Result<bool> {
-        let mut con = self.get_con().await?;
-        let result = con
-            .set_nx(WRITE_LOCK_KEY, id)
-            .await
-            .map_err(|e| RedisErrors::UnableToSet {
-                key: WRITE_LOCK_KEY.to_owned(),
-                source: anyhow!(e),
-            })?;
-        con.expire(WRITE_LOCK_KEY, REDIS_LOCK_TIMEOUT)
-            .await
-            .map_err(|e| RedisErrors::UnableToExpire {
-                key: WRITE_LOCK_KEY.to_owned(),
-                source: anyhow!(e),
-            })?;
-        trace!("SETNX `{:?}` => `{:?}` - RESULT: `{:?}`", WRITE_LOCK_KEY, id, result);
+    async fn set_str(&self, key: &str, value: &str, ttl_seconds: usize) -> Result<RedisResult> {
+        let previous = self.set_bytes(key, value.as_bytes(), ttl_seconds).await?;
+        to_string_result(key, previous)
+    }
 
-        match u8::from_redis_value(&result) {
-            Err(e) => {
-                Err(RedisErrors::UnableToReadValue {
-                    key: WRITE_LOCK_KEY.to_owned(),
-                    source: anyhow!(e),
-                })
-            },
-            Ok(value) => {
-                Ok(value == 1)
-            }
+    /// Sets `key`, compressing `value` if it's large enough, and returns the value it had before.
+    async fn set_bytes(
+        &self,
+        key: &str,
+        value: &[u8],
+        ttl_seconds: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        if let Some(legacy_key) = self.layout.legacy_key(key) {
+            self.set_key_bytes(legacy_key, value, ttl_seconds).await?;
         }
+        self.set_key_bytes(&self.layout.key(key), value, ttl_seconds)
+            .await
     }
 
-    async fn set_str(&self, key: &str, value: &str, ttl_seconds: usize) -> Result<RedisResult> {
-        let mut con = self.get_con().await?;
+    async fn set_key_bytes(
+        &self,
+        key: &str,
+        value: &[u8],
+        ttl_seconds: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        let compressed = self
+            .compress_over
+            .and_then(|threshold| compression::compress(value, threshold, &self.compressed));
+
+        let _timer = self.latency.start_timer("getset");
+        let mut con = self.get_con(key).await?;
         let result = con
-            .getset(key, value)
+            .getset(key, compressed.as_deref().unwrap_or(value))
             .await
             .map_err(|e| RedisErrors::UnableToSet {
-                key: key.to_owned(),
+                key: redact::key(key).into_owned(),
                 source: anyhow!(e),
             })?;
         if ttl_seconds > 0 {
             con.expire(key, ttl_seconds)
                 .await
                 .map_err(|e| RedisErrors::UnableToExpire {
-                    key: key.to_owned(),
+                    key: redact::key(key).into_owned(),
                     source: anyhow!(e),
                 })?;
         }
-        trace!("SET `{:?}` => `{:?}` - RESULT: `{:?}`", key, value, result);
+        trace!(
+            "SET `{}` => `{}` - RESULT: `{}`",
+            redact::key(key),
+            redact::pii(&String::from_utf8_lossy(value)),
+            redact::pii(&format!("{:?}", result))
+        );
+
+        self.read_value(key, &result)
+    }
 
-        if redis::Value::Nil == result {
-            return Ok(RedisResult::Nil);
+    async fn str_scan<T>(&self, pattern: &str) -> Result<Vec<T>>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let pattern = self.layout.pattern(pattern);
+        let mut results = Vec::new();
+        for shard in 0..self.shards.len() {
+            results.extend(self.str_scan_shard(shard, &pattern).await?);
         }
 
-        FromRedisValue::from_redis_value(&result)
-            .map_err(|e| RedisErrors::UnableToReadValue {
-                key: key.to_owned(),
-                source: anyhow!(e),
-            })
-            .map(RedisResult::String)
+        Ok(results)
     }
 
-    async fn str_scan<T>(&self, pattern: &str) -> Result<Vec<T>>
+    async fn str_scan_shard<T>(&self, shard: usize, pattern: &str) -> Result<Vec<T>>
     where
         T: serde::de::DeserializeOwned,
     {
-        let mut con = self.get_con().await?;
+        let _timer = self.latency.start_timer("scan");
+        let mut con = self.read_shard_con(shard).await?;
         let mut iter = con
             .scan_match(pattern)
             .await
@@ -267,10 +3561,15 @@ impl RedisServer {
         }
 
         let mut results: Vec<_> = Vec::new();
-        let values = con.get(keys).await.map_err(|e| RedisErrors::UnableToGet {
-            key: pattern.to_owned(),
-            source: anyhow!(e),
-        })?;
+        // Not `get`, which sends GET rather than MGET for a single key and gets no array back.
+        let values = redis::cmd("MGET")
+            .arg(keys)
+            .query_async(&mut *con)
+            .await
+            .map_err(|e| RedisErrors::UnableToGet {
+                key: pattern.to_owned(),
+                source: anyhow!(e),
+            })?;
 
         let values = match values {
             redis::Value::Bulk(v) => v,
@@ -284,24 +3583,32 @@ impl RedisServer {
         };
 
         for value in values {
-            if redis::Value::Nil == value {
-                continue;
-            }
-
-            let value = match String::from_redis_value(&value) {
+            let value = match self.read_value(pattern, &value) {
+                Err(e) if self.strict_scans => return Err(e),
                 Err(e) => {
                     warn!("Unable to deserialize redis object: {}", e);
                     continue;
                 }
-                Ok(v) => v,
+                Ok(None) => continue,
+                Ok(Some(v)) => v,
             };
 
-            match serde_json::from_str::<T>(&value) {
+            match from_stored::<T>(&value) {
                 Ok(res) => {
                     results.push(res);
                 }
+                Err(e) if self.strict_scans => {
+                    return Err(RedisErrors::UnableToReadValue {
+                        key: pattern.to_owned(),
+                        source: e,
+                    });
+                }
                 Err(e) => {
-                    warn!("Unable to parse object. Input {}. Error: {}", &value, e);
+                    warn!(
+                        "Unable to parse object. Input {}. Error: {}",
+                        redact::pii(&String::from_utf8_lossy(&value)),
+                        e
+                    );
                     continue;
                 }
             }
@@ -311,33 +3618,393 @@ impl RedisServer {
     }
 
     async fn get_str(&self, key: &str) -> Result<RedisResult> {
-        let mut con = self.get_con().await?;
+        let value = self.get_bytes(key).await?;
+        to_string_result(key, value)
+    }
+
+    /// Reads `key`, falling back to the unprefixed key while dual writing, as `migrate` may not
+    /// have copied it over yet.
+    async fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let value = self.get_key_bytes(&self.layout.key(key)).await?;
+        match (value, self.layout.legacy_key(key)) {
+            (None, Some(legacy_key)) => self.get_key_bytes(legacy_key).await,
+            (value, _) => Ok(value),
+        }
+    }
+
+    /// Whether `key` is set, falling back to the unprefixed key like `get_bytes` does.
+    async fn exists(&self, key: &str) -> Result<bool> {
+        if self.key_exists(&self.layout.key(key)).await? {
+            return Ok(true);
+        }
+        match self.layout.legacy_key(key) {
+            Some(legacy_key) => self.key_exists(legacy_key).await,
+            None => Ok(false),
+        }
+    }
+
+    async fn key_exists(&self, key: &str) -> Result<bool> {
+        let _timer = self.latency.start_timer("exists");
+        let mut con = self.get_read_con(key).await?;
+        let exists: bool = con
+            .exists(key)
+            .await
+            .map_err(|e| RedisErrors::UnableToGet {
+                key: redact::key(key).into_owned(),
+                source: anyhow!(e),
+            })?;
+
+        trace!("EXISTS `{}` - RESULT: `{}`", redact::key(key), exists);
+        Ok(exists)
+    }
+
+    async fn get_key_bytes(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let _timer = self.latency.start_timer("get");
+        let mut con = self.get_read_con(key).await?;
         let value = con.get(key).await.map_err(|e| RedisErrors::UnableToGet {
-            key: key.to_owned(),
+            key: redact::key(key).into_owned(),
             source: anyhow!(e),
         })?;
 
-        trace!("GET `{:?}` - RESULT: `{:?}`", key, value);
+        trace!(
+            "GET `{}` - RESULT: `{}`",
+            redact::key(key),
+            redact::pii(&format!("{:?}", value))
+        );
 
-        if redis::Value::Nil == value {
-            return Ok(RedisResult::Nil);
+        self.read_value(key, &value)
+    }
+
+    /// The bytes of a value read from `key`, decompressed if they were stored compressed.
+    fn read_value(&self, key: &str, value: &redis::Value) -> Result<Option<Vec<u8>>> {
+        if redis::Value::Nil == *value {
+            return Ok(None);
         }
 
-        FromRedisValue::from_redis_value(&value)
-            .map_err(|e| RedisErrors::UnableToReadValue {
-                key: key.to_owned(),
-                source: anyhow!(e),
-            })
-            .map(RedisResult::String)
+        let read_error = |e: anyhow::Error| RedisErrors::UnableToReadValue {
+            key: redact::key(key).into_owned(),
+            source: e,
+        };
+        let bytes: Vec<u8> =
+            FromRedisValue::from_redis_value(value).map_err(|e| read_error(anyhow!(e)))?;
+        match compression::decompress(&bytes, &self.decompressed) {
+            Ok(Some(original)) => Ok(Some(original)),
+            Ok(None) => Ok(Some(bytes)),
+            Err(e) => Err(read_error(anyhow!(e))),
+        }
+    }
+
+    /// `keys` grouped by the shard each lives on.
+    fn by_shard(&self, keys: &[String]) -> BTreeMap<usize, Vec<String>> {
+        let mut by_shard: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+        for key in keys {
+            by_shard
+                .entry(self.ring.node(key))
+                .or_default()
+                .push(key.clone());
+        }
+        by_shard
+    }
+
+    /// A connection for reading `key`.
+    async fn get_read_con(&self, key: &str) -> Result<MobcCon> {
+        self.read_shard_con(self.ring.node(key)).await
+    }
+
+    /// A connection to the shard holding `key`.
+    async fn get_con(&self, key: &str) -> Result<MobcCon> {
+        self.shard_con(self.ring.node(key)).await
+    }
+
+    /// A connection for reading from `shard`: from the next healthy replica, or the primary if
+    /// there are none.
+    async fn read_shard_con(&self, shard: usize) -> Result<MobcCon> {
+        let healthy: Vec<&ReadReplica> = self
+            .read_replicas
+            .iter()
+            .filter(|replica| replica.healthy.load(Ordering::Relaxed))
+            .collect();
+
+        let start = self.next_replica.fetch_add(1, Ordering::Relaxed);
+        for offset in 0..healthy.len() {
+            let replica = healthy[(start + offset) % healthy.len()];
+            match replica.pool.get().await {
+                Ok(con) => return Ok(con),
+                Err(e) => {
+                    warn!(
+                        "Unable to read from replica {}. Error: {}",
+                        replica.address, e
+                    );
+                    replica.healthy.store(false, Ordering::Relaxed);
+                }
+            }
+        }
+
+        self.shard_con(shard).await
     }
 
-    async fn get_con(&self) -> Result<MobcCon> {
-        self.redis_client
+    async fn shard_con(&self, shard: usize) -> Result<MobcCon> {
+        let shard = &self.shards[shard];
+        if chaos::redis_timeout() {
+            tokio::time::sleep(Duration::from_secs(CACHE_POOL_TIMEOUT_SECONDS)).await;
+            return Err(RedisErrors::UnableToConnect {
+                address: shard.address.clone(),
+                source: anyhow!("timed out waiting for a connection (injected by --chaos)"),
+            });
+        }
+
+        shard
+            .pool
             .get()
             .await
             .map_err(|e| RedisErrors::UnableToConnect {
-                address: self.redis_address.clone(),
+                address: shard.address.clone(),
+                source: anyhow!(e),
+            })
+    }
+}
+
+fn build_pool(redis_address: &str) -> Result<MobcPool> {
+    let client: redis::Client =
+        redis::Client::open(redis_address).map_err(|e| RedisErrors::UnableToConnect {
+            address: redact::url_password(redis_address),
+            source: anyhow!(e),
+        })?;
+    let manager = RedisConnectionManager::new(client);
+    Ok(Pool::builder()
+        .get_timeout(Some(Duration::from_secs(CACHE_POOL_TIMEOUT_SECONDS)))
+        .max_open(CACHE_POOL_MAX_OPEN)
+        .max_idle(CACHE_POOL_MAX_IDLE)
+        .max_lifetime(Some(Duration::from_secs(CACHE_POOL_EXPIRE_SECONDS)))
+        .build(manager))
+}
+
+fn to_string_result(key: &str, value: Option<Vec<u8>>) -> Result<RedisResult> {
+    match value {
+        None => Ok(RedisResult::Nil),
+        Some(bytes) => String::from_utf8(bytes)
+            .map_err(|e| RedisErrors::UnableToReadValue {
+                key: redact::key(key).into_owned(),
                 source: anyhow!(e),
             })
+            .map(RedisResult::String),
+    }
+}
+
+fn github_key(login: &str) -> String {
+    format!("user:github:{}", login)
+}
+
+fn user_modified_key(id: &UserId) -> String {
+    format!("user:modified:{}", id)
+}
+
+fn group_modified_key(id: &GroupId) -> String {
+    format!("user_group:modified:{}", id)
+}
+
+/// Where a group's members are kept in full, so expanding it is a single read.
+fn expanded_in_key(id: &UserId) -> String {
+    format!("{}{}", EXPANDED_IN_KEY_PREFIX, id)
+}
+
+fn group_expanded_key(id: &GroupId) -> String {
+    format!("user_group:expanded:{}", id)
+}
+
+fn annotations_key(id: &UserId) -> String {
+    format!("user:annotations:{}", id)
+}
+
+/// Checks that `prefix` only covers keys this app writes, so purging it can't touch anything else
+/// in a shared Redis.
+pub fn parse_key_prefix(prefix: &str) -> std::result::Result<String, String> {
+    if app_key_prefix(prefix).is_some() {
+        Ok(prefix.to_owned())
+    } else {
+        Err(format!(
+            "prefix must start with one of {}",
+            APP_KEY_PREFIXES.join(", ")
+        ))
+    }
+}
+
+/// The start of one of the app's own keys that `key` begins with.
+pub fn app_key_prefix(key: &str) -> Option<&'static str> {
+    APP_KEY_PREFIXES
+        .iter()
+        .find(|app_prefix| key.starts_with(*app_prefix))
+        .copied()
+}
+
+/// `prefix` as a SCAN pattern matching every key that starts with it.
+pub fn prefix_pattern(prefix: &str) -> String {
+    let mut pattern = String::with_capacity(prefix.len() + 1);
+    for c in prefix.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('*');
+    pattern
+}
+
+/// Reads a user or group in either value format, upgrading it first if it was written in an older
+/// schema version.
+pub fn from_stored<T: serde::de::DeserializeOwned>(value: &[u8]) -> anyhow::Result<T> {
+    let record = schema::upgrade(codec::decode(value)?);
+    Ok(serde_json::from_value(record)?)
+}
+
+/// `stored` along with the emails earlier syncs saw the user with, including the one `cached` has
+/// if it has changed since. Both are as written to Redis, so with hashing on these are hashes.
+fn with_previous_emails(mut stored: SlackUser, cached: Option<&SlackUser>) -> SlackUser {
+    if let Some(cached) = cached {
+        stored.previous_emails = cached.previous_emails.clone();
+        stored.previous_emails.insert(cached.email.normalized());
+    }
+
+    let current_email = stored.email.normalized();
+    stored.previous_emails = stored
+        .previous_emails
+        .into_iter()
+        .filter(|email| *email != current_email)
+        .collect();
+    stored
+}
+
+fn watcher_field(id: &GroupId, channel: &str) -> String {
+    format!("{}:{}", id, channel)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::collection::{btree_map, btree_set, vec};
+    use proptest::option;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::libs::slack::SlackUserId;
+
+    /// Any text without control characters, unicode included.
+    fn text() -> impl Strategy<Value = String> {
+        "\\PC{0,24}"
+    }
+
+    fn email() -> impl Strategy<Value = Email> {
+        "\\PC{1,16}@\\PC{1,16}".prop_map(Email::unchecked)
+    }
+
+    fn team_id() -> impl Strategy<Value = String> {
+        "T[A-Z0-9]{1,10}"
+    }
+
+    prop_compose! {
+        fn user()(
+            id in "[UW][A-Z0-9]{1,12}",
+            (name, display_name, real_name_normalized) in (
+                text(),
+                option::of(text()),
+                option::of(text()),
+            ),
+            (email, previous_emails) in (email(), btree_set(email(), 0..3)),
+            avatars in btree_map(any::<u32>(), text(), 0..3),
+            tz in option::of(text()),
+            annotations in btree_map(text(), text(), 0..3),
+            (external, guest, backfilled) in any::<(bool, bool, bool)>(),
+            github_login in option::of("[a-z0-9-]{1,39}"),
+            (team_id, teams) in (option::of(team_id()), btree_set(team_id(), 0..3)),
+        ) -> SlackUser {
+            SlackUser {
+                id: UserId::unchecked(id),
+                name,
+                display_name,
+                real_name_normalized,
+                email,
+                previous_emails,
+                avatars,
+                tz,
+                annotations,
+                external,
+                guest,
+                backfilled,
+                github_login,
+                team_id,
+                teams,
+            }
+        }
+    }
+
+    prop_compose! {
+        fn group()(
+            id in "S[A-Z0-9]{1,12}",
+            name in text(),
+            users in btree_set("[UW][A-Z0-9]{1,12}", 0..5),
+            previous_names in btree_set(text(), 0..3),
+            synthetic in any::<bool>(),
+        ) -> SlackUserGroup {
+            SlackUserGroup {
+                name,
+                id: GroupId::unchecked(id),
+                users: users
+                    .into_iter()
+                    .map(|id| SlackUserId { id: UserId::unchecked(id) })
+                    .collect(),
+                previous_names,
+                synthetic,
+            }
+        }
+    }
+
+    fn value_format() -> impl Strategy<Value = ValueFormat> {
+        prop_oneof![Just(ValueFormat::Json), Just(ValueFormat::Msgpack)]
+    }
+
+    /// Writes `record` the way `RedisServer::to_stored` does, in `value_format`.
+    fn to_stored<T: serde::Serialize>(record: &T, value_format: ValueFormat) -> Vec<u8> {
+        value_format.encode(&schema::stamp(serde_json::to_value(record).unwrap()))
+    }
+
+    fn field_names(value: &serde_json::Value) -> Vec<String> {
+        value.as_object().unwrap().keys().cloned().collect()
+    }
+
+    proptest! {
+        #[test]
+        fn users_read_back_as_written(user in user(), value_format in value_format()) {
+            let stored = to_stored(&user, value_format);
+            prop_assert_eq!(from_stored::<SlackUser>(&stored).unwrap(), user);
+        }
+
+        #[test]
+        fn users_read_back_from_email_keys(user in user(), value_format in value_format()) {
+            let stored = to_stored(&user, value_format);
+            match from_stored::<EmailRecord>(&stored).unwrap() {
+                EmailRecord::User(read) => prop_assert_eq!(read, user),
+                EmailRecord::Moved(redirect) => prop_assert!(false, "read as {:?}", redirect),
+            }
+        }
+
+        #[test]
+        fn groups_read_back_as_written(group in group(), value_format in value_format()) {
+            let stored = to_stored(&group, value_format);
+            prop_assert_eq!(from_stored::<SlackUserGroup>(&stored).unwrap(), group);
+        }
+
+        #[test]
+        fn fields_are_kebab_case(user in user(), group in group()) {
+            let user = serde_json::to_value(&user).unwrap();
+            let group = serde_json::to_value(&group).unwrap();
+            for field in field_names(&user).iter().chain(field_names(&group).iter()) {
+                prop_assert!(!field.contains('_'), "`{}` isn't kebab-case", field);
+            }
+        }
+
+        #[test]
+        fn malformed_values_do_not_panic(bytes in vec(any::<u8>(), 0..64)) {
+            let _ = from_stored::<SlackUser>(&bytes);
+            let _ = from_stored::<SlackUserGroup>(&bytes);
+        }
     }
 }
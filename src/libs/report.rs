@@ -0,0 +1,68 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{SlackUser, SlackUserGroup};
+
+/// What a sync wrote to Redis, compared with what was there before.
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    pub finished_at: u64,
+    pub users: usize,
+    pub groups: usize,
+    pub users_added: Vec<String>,
+    pub users_removed: Vec<String>,
+    pub groups_added: Vec<String>,
+    pub groups_removed: Vec<String>,
+}
+
+impl SyncReport {
+    pub fn new(
+        previous_users: &[SlackUser],
+        users: &BTreeSet<SlackUser>,
+        previous_groups: &[SlackUserGroup],
+        groups: &BTreeSet<SlackUserGroup>,
+    ) -> Self {
+        let (users_added, users_removed) = diff(
+            previous_users
+                .iter()
+                .map(|user| (user.id.as_str(), &user.name)),
+            users.iter().map(|user| (user.id.as_str(), &user.name)),
+        );
+        let (groups_added, groups_removed) = diff(
+            previous_groups
+                .iter()
+                .map(|group| (group.id.as_str(), &group.name)),
+            groups.iter().map(|group| (group.id.as_str(), &group.name)),
+        );
+
+        Self {
+            finished_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            users: users.len(),
+            groups: groups.len(),
+            users_added,
+            users_removed,
+            groups_added,
+            groups_removed,
+        }
+    }
+}
+
+/// Names of the entries only in `current` and only in `previous`, matched by id.
+fn diff<'a>(
+    previous: impl Iterator<Item = (&'a str, &'a String)>,
+    current: impl Iterator<Item = (&'a str, &'a String)>,
+) -> (Vec<String>, Vec<String>) {
+    let mut previous: BTreeMap<&str, &String> = previous.collect();
+    let mut added = Vec::new();
+    for (id, name) in current {
+        if previous.remove(id).is_none() {
+            added.push(name.clone());
+        }
+    }
+    let removed = previous.values().map(|name| (*name).clone()).collect();
+
+    (added, removed)
+}
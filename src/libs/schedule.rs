@@ -0,0 +1,96 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::Utc;
+use chrono_tz::Tz;
+use cron::Schedule;
+use rand::Rng;
+use tracing::info;
+
+/// When the updater runs again in daemon mode.
+#[derive(Debug, Clone)]
+pub enum Timing {
+    Interval(Duration),
+    /// Runs `splay` after each time the schedule fires.
+    Cron {
+        schedule: Box<Schedule>,
+        timezone: Tz,
+        splay: Duration,
+    },
+}
+
+impl Timing {
+    /// How long to wait before the next sync, logging when that will be.
+    pub fn next_delay(&self) -> Duration {
+        match self {
+            Timing::Interval(interval) => {
+                info!("Next sync in {}s", interval.as_secs());
+                *interval
+            }
+            Timing::Cron {
+                schedule,
+                timezone,
+                splay,
+            } => match schedule.upcoming(*timezone).next() {
+                Some(next) => {
+                    let next = next
+                        + chrono::Duration::from_std(*splay)
+                            .unwrap_or_else(|_| chrono::Duration::zero());
+                    info!("Next sync planned for {}", next.to_rfc3339());
+                    (next.with_timezone(&Utc) - Utc::now())
+                        .to_std()
+                        .unwrap_or_default()
+                }
+                None => {
+                    info!("Schedule has no upcoming runs, syncing again in a day");
+                    Duration::from_secs(24 * 60 * 60)
+                }
+            },
+        }
+    }
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Timing::Interval(interval) => write!(f, "every {}s", interval.as_secs()),
+            Timing::Cron {
+                schedule, timezone, ..
+            } => write!(f, "on `{}` ({})", schedule, timezone),
+        }
+    }
+}
+
+/// A random delay of at most `max`, so replicas started together spread out.
+pub fn random_splay(max: Duration) -> Duration {
+    let millis = max.as_millis() as u64;
+    if millis == 0 {
+        return Duration::default();
+    }
+
+    Duration::from_millis(rand::thread_rng().gen_range(0..=millis))
+}
+
+/// Parses `--interval`, in seconds. An interval of 0 would start each sync as soon as the last
+/// one finished.
+pub fn parse_interval(value: &str) -> Result<u64, String> {
+    match value.parse::<u64>() {
+        Ok(0) => Err("must be at least 1".to_owned()),
+        Ok(seconds) => Ok(seconds),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Parses a crontab expression. The standard five fields (minute to day of week) are accepted
+/// alongside the cron crate's forms with seconds and years.
+pub fn parse_cron(expression: &str) -> Result<Schedule, String> {
+    let expression = expression.trim();
+    let expression = if expression.split_whitespace().count() == 5 {
+        format!("0 {}", expression)
+    } else {
+        expression.to_owned()
+    };
+
+    Schedule::from_str(&expression).map_err(|e| format!("invalid schedule: {}", e))
+}
@@ -0,0 +1,39 @@
+use serde_json::Value;
+
+/// Field the version a user or group was cached with is stored under.
+const VERSION_FIELD: &str = "schema-version";
+
+/// Upgrades from each version to the next: entry 0 turns a version 0 record into version 1 and
+/// so on. Changes that a `#[serde(default)]` covers, like a new optional field, don't need a step.
+const MIGRATIONS: &[fn(Value) -> Value] = &[from_v0];
+
+/// The version users and groups are cached as. Records written before versioning count as 0.
+pub const SCHEMA_VERSION: u64 = MIGRATIONS.len() as u64;
+
+/// Adds the current version to a serialized record.
+pub fn stamp(mut record: Value) -> Value {
+    if let Value::Object(fields) = &mut record {
+        fields.insert(VERSION_FIELD.to_owned(), SCHEMA_VERSION.into());
+    }
+    record
+}
+
+/// Brings a cached record up to the current version. Records from a newer version, written by an
+/// updater that was deployed first, are left as they are, as fields this version doesn't know
+/// about are ignored when reading them.
+pub fn upgrade(mut record: Value) -> Value {
+    let version = record
+        .get(VERSION_FIELD)
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+
+    for migration in MIGRATIONS.iter().skip(version as usize) {
+        record = migration(record);
+    }
+    record
+}
+
+/// Version 1 only added the version itself.
+fn from_v0(record: Value) -> Value {
+    record
+}
@@ -0,0 +1,74 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::SecretErrors;
+
+/// Reads a secret from a file, like the ones Docker and Kubernetes mount under `/run/secrets`.
+pub fn read_secret_file(path: &Path) -> Result<String, SecretErrors> {
+    let contents = fs::read_to_string(path).map_err(|e| SecretErrors::UnableToRead {
+        path: path.display().to_string(),
+        source: e,
+    })?;
+
+    let secret = contents.trim();
+    if secret.is_empty() {
+        return Err(SecretErrors::Empty {
+            name: path.display().to_string(),
+        });
+    }
+
+    Ok(secret.to_owned())
+}
+
+/// Fetches a secret from a Vault KV v2 engine, using `VAULT_ADDR` and `VAULT_TOKEN`.
+///
+/// The reference has the form `<mount>/data/<path>#<field>`, e.g. `secret/data/slack#bot_token`.
+#[cfg(feature = "vault")]
+pub async fn read_vault_secret(reference: &str) -> Result<String, SecretErrors> {
+    use std::env;
+
+    let mut parts = reference.splitn(2, '#');
+    let path = parts.next().unwrap_or_default();
+    let field = match parts.next() {
+        Some(field) if !path.is_empty() && !field.is_empty() => field,
+        _ => {
+            return Err(SecretErrors::InvalidReference {
+                reference: reference.to_owned(),
+            })
+        }
+    };
+
+    let address = env::var("VAULT_ADDR").map_err(|_| SecretErrors::Empty {
+        name: "VAULT_ADDR".to_owned(),
+    })?;
+    let token = env::var("VAULT_TOKEN").map_err(|_| SecretErrors::Empty {
+        name: "VAULT_TOKEN".to_owned(),
+    })?;
+
+    let to_error = |e: reqwest::Error| SecretErrors::UnableToFetch {
+        reference: reference.to_owned(),
+        source: anyhow::anyhow!(e),
+    };
+    let body = reqwest::Client::new()
+        .get(&format!("{}/v1/{}", address.trim_end_matches('/'), path))
+        .header("X-Vault-Token", token)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(to_error)?
+        .text()
+        .await
+        .map_err(to_error)?;
+    let body: serde_json::Value =
+        serde_json::from_str(&body).map_err(|e| SecretErrors::UnableToFetch {
+            reference: reference.to_owned(),
+            source: anyhow::anyhow!(e),
+        })?;
+
+    match body["data"]["data"][field].as_str() {
+        Some(secret) if !secret.is_empty() => Ok(secret.to_owned()),
+        _ => Err(SecretErrors::Empty {
+            name: reference.to_owned(),
+        }),
+    }
+}
@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+
+use super::codec::ValueFormat;
+use super::email::{AliasRule, EmailHasher};
+use super::key_layout::{KeyLayout, NameFolding};
+use super::schema::SCHEMA_VERSION;
+
+/// Address hashed with the salt for `CacheSettings::email_hash_check`.
+const EMAIL_HASH_PROBE: &str = "probe@slack-user-cache.invalid";
+
+/// How the updater wrote the cache, saved by each sync so commands that rewrite keys, like
+/// `verify --repair`, can check they'd write them the same way first.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheSettings {
+    pub schema_version: u64,
+    pub key_prefix: String,
+    pub dual_write_legacy_keys: bool,
+    pub group_name_folding: NameFolding,
+    /// A fixed address hashed with the salt, so a different salt can be told apart without
+    /// saving the salt itself. Unset when emails aren't hashed.
+    pub email_hash_check: Option<String>,
+    pub email_alias_rules: Vec<AliasRule>,
+    pub value_format: ValueFormat,
+}
+
+impl CacheSettings {
+    pub fn new(
+        layout: &KeyLayout,
+        email_hasher: Option<&EmailHasher>,
+        email_aliases: &[AliasRule],
+        value_format: ValueFormat,
+    ) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            key_prefix: layout.prefix().to_owned(),
+            dual_write_legacy_keys: layout.dual_write(),
+            group_name_folding: layout.name_folding(),
+            email_hash_check: email_hasher.map(|hasher| hasher.hash(EMAIL_HASH_PROBE)),
+            email_alias_rules: email_aliases.to_vec(),
+            value_format,
+        }
+    }
+
+    /// The settings that are different in `other`, by the name of the option that sets them.
+    pub fn differences(&self, other: &CacheSettings) -> Vec<&'static str> {
+        let mut differences = Vec::new();
+        if self.schema_version != other.schema_version {
+            differences.push("schema version");
+        }
+        if self.key_prefix != other.key_prefix {
+            differences.push("--key-prefix");
+        }
+        if self.dual_write_legacy_keys != other.dual_write_legacy_keys {
+            differences.push("--dual-write-legacy-keys");
+        }
+        if self.group_name_folding != other.group_name_folding {
+            differences.push("--group-name-folding");
+        }
+        if self.email_hash_check != other.email_hash_check {
+            differences.push("--email-hash-salt");
+        }
+        if self.email_alias_rules != other.email_alias_rules {
+            differences.push("--email-alias-rules");
+        }
+        if self.value_format != other.value_format {
+            differences.push("--value-format");
+        }
+        differences
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(salt: Option<&str>) -> CacheSettings {
+        let hasher = salt.map(EmailHasher::new);
+        CacheSettings::new(
+            &KeyLayout::new(Some("slack:"), false),
+            hasher.as_ref(),
+            &[AliasRule::FirstInitialLast],
+            ValueFormat::Json,
+        )
+    }
+
+    #[test]
+    fn the_same_options_have_no_differences() {
+        assert!(settings(Some("salt"))
+            .differences(&settings(Some("salt")))
+            .is_empty());
+        assert!(settings(None).differences(&settings(None)).is_empty());
+    }
+
+    #[test]
+    fn differences_name_the_options_that_differ() {
+        assert_eq!(
+            settings(Some("salt")).differences(&settings(Some("pepper"))),
+            vec!["--email-hash-salt"]
+        );
+        assert_eq!(
+            settings(None).differences(&settings(Some("salt"))),
+            vec!["--email-hash-salt"]
+        );
+
+        let other = CacheSettings::new(
+            &KeyLayout::new(None, false).with_name_folding(NameFolding::Unicode),
+            None,
+            &[],
+            ValueFormat::Msgpack,
+        );
+        assert_eq!(
+            settings(None).differences(&other),
+            vec![
+                "--key-prefix",
+                "--group-name-folding",
+                "--email-alias-rules",
+                "--value-format"
+            ]
+        );
+    }
+
+    #[test]
+    fn settings_survive_a_round_trip() {
+        let settings = settings(Some("salt"));
+        let json = serde_json::to_string(&settings).unwrap();
+        assert_eq!(
+            serde_json::from_str::<CacheSettings>(&json).unwrap(),
+            settings
+        );
+    }
+}
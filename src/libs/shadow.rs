@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use tracing::{debug, warn};
+
+use super::redact;
+use super::slack::{SlackApi, UserId};
+
+/// What a shadow lookup found, as the `outcome` label of `shadow_lookups_total`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ShadowOutcome {
+    /// The cache and Slack agree on who has the email, or that nobody does.
+    Match,
+    /// The cache and Slack have different users under the email.
+    Mismatch,
+    /// Slack has a user the cache doesn't.
+    MissingFromCache,
+    /// The cache has a user Slack doesn't, e.g. one deleted since the last sync.
+    MissingFromSlack,
+    /// Slack couldn't be asked.
+    Error,
+    /// Left out to stay under users.lookupByEmail's rate limit.
+    Skipped,
+}
+
+impl ShadowOutcome {
+    pub fn label(self) -> &'static str {
+        match self {
+            ShadowOutcome::Match => "match",
+            ShadowOutcome::Mismatch => "mismatch",
+            ShadowOutcome::MissingFromCache => "missing_from_cache",
+            ShadowOutcome::MissingFromSlack => "missing_from_slack",
+            ShadowOutcome::Error => "error",
+            ShadowOutcome::Skipped => "skipped",
+        }
+    }
+}
+
+/// Checks a sample of the cache's by-email answers against what Slack answers at the time, to
+/// measure how often the cache is wrong. Slack is asked in the background, after the response
+/// has been sent, so lookups take no longer for it.
+#[derive(Debug)]
+pub struct ShadowLookups {
+    slack_api: SlackApi,
+    sample_rate: f64,
+    outcomes: Mutex<BTreeMap<ShadowOutcome, u64>>,
+}
+
+impl ShadowLookups {
+    /// Compares `sample_rate` of lookups, from 0 for none to 1 for all of them.
+    pub fn new(slack_api: SlackApi, sample_rate: f64) -> Self {
+        Self {
+            slack_api,
+            sample_rate,
+            outcomes: Mutex::default(),
+        }
+    }
+
+    /// Maybe compares `cached`, the user the cache answered a lookup of `email` with, with the
+    /// user Slack has under it.
+    pub fn sample(self: &Arc<Self>, email: &str, cached: Option<UserId>) {
+        if rand::random::<f64>() >= self.sample_rate {
+            return;
+        }
+
+        let shadow = self.clone();
+        let email = email.to_owned();
+        tokio::spawn(async move {
+            let outcome = shadow.compare(&email, cached).await;
+            shadow.record(outcome);
+        });
+    }
+
+    async fn compare(&self, email: &str, cached: Option<UserId>) -> ShadowOutcome {
+        let found = match self.slack_api.try_lookup_user_id(email).await {
+            None => return ShadowOutcome::Skipped,
+            Some(Err(e)) => {
+                debug!(
+                    "Unable to look up {} in Slack. Error: {}",
+                    redact::pii(email),
+                    e
+                );
+                return ShadowOutcome::Error;
+            }
+            Some(Ok(found)) => found,
+        };
+
+        let outcome = match (&cached, &found) {
+            (Some(cached), Some(found)) if cached == found => ShadowOutcome::Match,
+            (None, None) => ShadowOutcome::Match,
+            (Some(_), Some(_)) => ShadowOutcome::Mismatch,
+            (None, Some(_)) => ShadowOutcome::MissingFromCache,
+            (Some(_), None) => ShadowOutcome::MissingFromSlack,
+        };
+        if outcome != ShadowOutcome::Match {
+            warn!(
+                "Cached answer for {} differs from Slack's: cached {}, Slack {}",
+                redact::pii(email),
+                describe(cached.as_ref()),
+                describe(found.as_ref())
+            );
+        }
+
+        outcome
+    }
+
+    fn record(&self, outcome: ShadowOutcome) {
+        let mut outcomes = match self.outcomes.lock() {
+            Ok(outcomes) => outcomes,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *outcomes.entry(outcome).or_default() += 1;
+    }
+
+    /// How many lookups ended each way so far.
+    pub fn outcomes(&self) -> BTreeMap<ShadowOutcome, u64> {
+        match self.outcomes.lock() {
+            Ok(outcomes) => outcomes.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+}
+
+fn describe(id: Option<&UserId>) -> String {
+    id.map_or_else(|| "nobody".to_owned(), UserId::to_string)
+}
+
+/// Reads `--shadow-lookup-rate`, a fraction from 0 to 1.
+pub fn parse_sample_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        Ok(_) => Err("must be from 0 to 1".to_owned()),
+        Err(e) => Err(e.to_string()),
+    }
+}
@@ -0,0 +1,114 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::email::Email;
+use super::github;
+use super::slack::{GroupId, UserId};
+
+/// Printed for `help`.
+pub const HELP: &str = "\
+Commands:
+  user email <email>    The user with an email
+  user id <id>          The user with a Slack id
+  user github <login>   The user with a GitHub login
+  search <prefix>       Users whose name, or email when they aren't hashed, starts with a prefix
+  group name <name>     A user group and its members
+  group id <id>         A user group and its members, by the group's id
+  stats                 How many keys the cache has by prefix, and when it was last synced
+  help                  This list
+  exit                  Leaves the shell, as does Ctrl-D";
+
+/// A line typed into `shell`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShellCommand {
+    UserByEmail(Email),
+    UserById(UserId),
+    UserByGithub(String),
+    Search(String),
+    GroupByName(String),
+    GroupById(GroupId),
+    Stats,
+    Help,
+    Exit,
+}
+
+impl FromStr for ShellCommand {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let command = match words.as_slice() {
+            ["user", "email", email] => ShellCommand::UserByEmail(email.parse()?),
+            ["user", "id", id] => ShellCommand::UserById(id.parse()?),
+            ["user", "github", login] => ShellCommand::UserByGithub(github::parse_login(login)?),
+            ["search", query @ ..] if !query.is_empty() => ShellCommand::Search(query.join(" ")),
+            ["group", "name", name @ ..] if !name.is_empty() => {
+                ShellCommand::GroupByName(name.join(" "))
+            }
+            ["group", "id", id] => ShellCommand::GroupById(id.parse()?),
+            ["stats"] => ShellCommand::Stats,
+            ["help"] => ShellCommand::Help,
+            ["exit"] | ["quit"] => ShellCommand::Exit,
+            _ => {
+                return Err(format!(
+                    "`{}` isn't a command, type `help` to list them",
+                    line.trim()
+                ))
+            }
+        };
+
+        Ok(command)
+    }
+}
+
+/// Rows printed with their columns lined up under a header.
+pub struct Table {
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&str]) -> Self {
+        Self {
+            headers: headers.iter().map(|header| header.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Adds a row, which should have a cell for each header.
+    pub fn add_row(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    fn widths(&self) -> Vec<usize> {
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| h.chars().count()).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        widths
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let widths = self.widths();
+        let line = |f: &mut fmt::Formatter<'_>, cells: &[String]| {
+            let padded: Vec<String> = cells
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect();
+            writeln!(f, "{}", padded.join("  ").trim_end())
+        };
+
+        line(f, &self.headers)?;
+        let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+        line(f, &rule)?;
+        for row in &self.rows {
+            line(f, row)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use serde_json::{json, Value};
+
+use super::{SlackUserGroup, UserId};
+use crate::libs::SyncReport;
+
+/// Most members listed in a group card; the rest are summarised as a count.
+const MAX_LISTED_MEMBERS: usize = 20;
+/// Most names listed per kind of change on the App Home tab.
+const MAX_LISTED_CHANGES: usize = 10;
+
+pub fn section(text: &str) -> Value {
+    json!({
+        "type": "section",
+        "text": { "type": "mrkdwn", "text": text }
+    })
+}
+
+pub fn context(text: &str) -> Value {
+    json!({
+        "type": "context",
+        "elements": [{ "type": "mrkdwn", "text": text }]
+    })
+}
+
+/// A card for `user`, as the web API would answer with them once their fields are filtered, so
+/// it only shows what the caller may see. Only the id is always there.
+pub fn user_card(id: &UserId, user: &Value) -> Vec<Value> {
+    let mut text = match user.get("name").and_then(Value::as_str) {
+        Some(name) => format!("*{}* <@{}>", name, id),
+        None => format!("<@{}>", id),
+    };
+    // With email hashing enabled lookups by id only know the digest, which isn't worth showing.
+    if let Some(email) = user.get("email").and_then(Value::as_str) {
+        if email.contains('@') {
+            text.push_str(&format!("\n{}", email));
+        }
+    }
+
+    vec![section(&text), context(&format!("User ID `{}`", id))]
+}
+
+pub fn group_card(group: &SlackUserGroup) -> Vec<Value> {
+    let mut members: Vec<String> = group
+        .users
+        .iter()
+        .take(MAX_LISTED_MEMBERS)
+        .map(|user| format!("<@{}>", user.id))
+        .collect();
+    if group.users.len() > MAX_LISTED_MEMBERS {
+        members.push(format!(
+            "and {} more",
+            group.users.len() - MAX_LISTED_MEMBERS
+        ));
+    }
+
+    vec![
+        section(&format!(
+            "*{}* ({} members)\n{}",
+            group.name,
+            group.users.len(),
+            members.join(", ")
+        )),
+        context(&format!("Group ID `{}`", group.id)),
+    ]
+}
+
+/// Who joined and left a watched group, for the channels watching it.
+pub fn membership_change(
+    group: &SlackUserGroup,
+    added: &[&UserId],
+    removed: &[&UserId],
+) -> Vec<Value> {
+    let mut blocks = vec![section(&format!(
+        ":busts_in_silhouette: Membership of *{}* changed",
+        group.name
+    ))];
+    for (title, users) in [("Added", added), ("Removed", removed)].iter() {
+        if users.is_empty() {
+            continue;
+        }
+
+        let mut listed: Vec<String> = users
+            .iter()
+            .take(MAX_LISTED_MEMBERS)
+            .map(|id| format!("<@{}>", id))
+            .collect();
+        if users.len() > MAX_LISTED_MEMBERS {
+            listed.push(format!("and {} more", users.len() - MAX_LISTED_MEMBERS));
+        }
+        blocks.push(section(&format!("*{}*\n{}", title, listed.join(", "))));
+    }
+    blocks.push(context(&format!(
+        "Group ID `{}`, now {} members",
+        group.id,
+        group.users.len()
+    )));
+
+    blocks
+}
+
+/// Alert with the error and each of its causes in a code block.
+pub fn failure_alert(title: &str, causes: &[String]) -> Vec<Value> {
+    vec![
+        section(&format!(":rotating_light: *{}*", title)),
+        section(&format!("```{}```", causes.join("\ncaused by: "))),
+    ]
+}
+
+/// Reply to a slash command that only the person who ran it can see.
+pub fn ephemeral_message(text: &str, blocks: Vec<Value>) -> Value {
+    json!({
+        "response_type": "ephemeral",
+        "text": text,
+        "blocks": blocks
+    })
+}
+
+/// App Home tab describing the last sync.
+pub fn home_view(report: &SyncReport) -> Value {
+    let mut blocks = vec![
+        json!({
+            "type": "header",
+            "text": { "type": "plain_text", "text": "Slack user cache" }
+        }),
+        section(&format!(
+            "Last sync finished <!date^{}^{{date_short_pretty}} at {{time}}|{}>",
+            report.finished_at, report.finished_at
+        )),
+        section(&format!(
+            "*{}* users and *{}* user groups cached",
+            report.users, report.groups
+        )),
+        json!({ "type": "divider" }),
+    ];
+
+    let changes = [
+        ("Users added", &report.users_added),
+        ("Users removed", &report.users_removed),
+        ("Groups added", &report.groups_added),
+        ("Groups removed", &report.groups_removed),
+    ];
+    let mut changed = false;
+    for (title, names) in changes.iter() {
+        if names.is_empty() {
+            continue;
+        }
+        changed = true;
+
+        let mut listed: Vec<String> = names.iter().take(MAX_LISTED_CHANGES).cloned().collect();
+        if names.len() > MAX_LISTED_CHANGES {
+            listed.push(format!("and {} more", names.len() - MAX_LISTED_CHANGES));
+        }
+        blocks.push(section(&format!("*{}*\n{}", title, listed.join(", "))));
+    }
+    if !changed {
+        blocks.push(context("No changes since the previous sync"));
+    }
+
+    json!({ "type": "home", "blocks": blocks })
+}
@@ -0,0 +1,443 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use derivative::Derivative;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::{info, trace, warn};
+
+use super::models::{
+    ApiStatus, AppsConnectionsOpenResponse, AuthTestResponse, DndInfo, DndTeamInfoResponse,
+    OauthV2AccessResponse, ResponseMetadata, User, Usergroup, UsergroupsCreateResponse,
+    UsergroupsListResponse, UsergroupsUsersListResponse, UsersListResponse,
+    UsersLookupByEmailResponse,
+};
+use super::rotation::{self, TokenRotation, TokenState};
+use super::usage::ApiUsage;
+use super::{GroupId, UserId};
+use crate::error::SlackErrors;
+use crate::libs::chaos;
+
+const SLACK_API_URL: &str = "https://slack.com/api";
+const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+const POOL_IDLE_TIMEOUT_SECONDS: u64 = 90;
+const POOL_MAX_IDLE_PER_HOST: usize = 4;
+const DEFAULT_RETRY_AFTER_SECONDS: u64 = 60;
+
+#[derive(Debug, Clone)]
+pub struct SlackClientConfig {
+    pub connect_timeout: Duration,
+    pub request_timeout: Duration,
+    pub tcp_keepalive: Duration,
+    pub rotation: Option<TokenRotation>,
+    /// Where the calls made are counted. Clients can share one to count against one budget.
+    pub usage: Arc<ApiUsage>,
+}
+
+/// Thin typed wrapper around the Slack Web API methods this tool needs.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct SlackClient {
+    client: Client,
+    api_url: String,
+    #[derivative(Debug = "ignore")]
+    token: Mutex<TokenState>,
+    rotation: Option<TokenRotation>,
+    usage: Arc<ApiUsage>,
+}
+
+impl SlackClient {
+    pub fn new(token: &str, config: &SlackClientConfig) -> Result<Self, SlackErrors> {
+        let client = Client::builder()
+            .user_agent(USER_AGENT)
+            .connect_timeout(config.connect_timeout)
+            .timeout(config.request_timeout)
+            .tcp_keepalive(config.tcp_keepalive)
+            .pool_idle_timeout(Duration::from_secs(POOL_IDLE_TIMEOUT_SECONDS))
+            .pool_max_idle_per_host(POOL_MAX_IDLE_PER_HOST)
+            .build()
+            .map_err(|e| SlackErrors::UnableToBuildClient { source: e })?;
+
+        Ok(Self {
+            client,
+            api_url: SLACK_API_URL.to_owned(),
+            token: Mutex::new(TokenState::new(token, config.rotation.as_ref())),
+            rotation: config.rotation.clone(),
+            usage: config.usage.clone(),
+        })
+    }
+
+    /// Calls the methods at `api_url`, like a proxy or a stand-in for Slack, rather than Slack's.
+    pub fn with_api_url(mut self, api_url: &str) -> Self {
+        self.api_url = api_url.to_owned();
+        self
+    }
+
+    /// Checks the token is valid, returning who it belongs to.
+    ///
+    /// Wraps https://api.slack.com/methods/auth.test
+    pub async fn auth_test(&self) -> Result<AuthTestResponse, SlackErrors> {
+        self.call("auth.test", &[]).await
+    }
+
+    /// Lists a page of users in a Slack team.
+    ///
+    /// Wraps https://api.slack.com/methods/users.list
+    pub async fn users_list(
+        &self,
+        cursor: &Cursor,
+        limit: u16,
+    ) -> Result<UsersListResponse, SlackErrors> {
+        let mut params = vec![("limit", limit.to_string())];
+        if let Some(cursor) = cursor.value() {
+            params.push(("cursor", cursor.to_owned()));
+        }
+
+        self.call("users.list", &params).await
+    }
+
+    /// Finds a user with an email address.
+    ///
+    /// Wraps https://api.slack.com/methods/users.lookupByEmail
+    pub async fn users_lookup_by_email(&self, email: &str) -> Result<User, SlackErrors> {
+        let response: UsersLookupByEmailResponse = self
+            .call("users.lookupByEmail", &[("email", email.to_owned())])
+            .await?;
+
+        Ok(response.user)
+    }
+
+    /// Lists all enabled User Groups, including their members.
+    ///
+    /// Wraps https://api.slack.com/methods/usergroups.list
+    pub async fn usergroups_list(&self) -> Result<Vec<Usergroup>, SlackErrors> {
+        let params = [
+            ("include_disabled", "false".to_owned()),
+            ("include_count", "false".to_owned()),
+            ("include_users", "true".to_owned()),
+        ];
+        let response: UsergroupsListResponse = self.call("usergroups.list", &params).await?;
+
+        Ok(response.usergroups)
+    }
+
+    /// Lists the ids of all active users in a User Group.
+    ///
+    /// Wraps https://api.slack.com/methods/usergroups.users.list
+    pub async fn usergroups_users_list(&self, usergroup: &str) -> Result<Vec<UserId>, SlackErrors> {
+        let params = [
+            ("usergroup", usergroup.to_owned()),
+            ("include_disabled", "false".to_owned()),
+        ];
+        let response: UsergroupsUsersListResponse =
+            self.call("usergroups.users.list", &params).await?;
+
+        Ok(response.users)
+    }
+
+    /// Creates a User Group with `name` as both its name and handle.
+    ///
+    /// Wraps https://api.slack.com/methods/usergroups.create
+    pub async fn usergroups_create(&self, name: &str) -> Result<Usergroup, SlackErrors> {
+        let params = [("name", name.to_owned()), ("handle", name.to_owned())];
+        let response: UsergroupsCreateResponse = self.call("usergroups.create", &params).await?;
+
+        Ok(response.usergroup)
+    }
+
+    /// Replaces the members of a User Group. Slack won't leave a group without any.
+    ///
+    /// Wraps https://api.slack.com/methods/usergroups.users.update
+    pub async fn usergroups_users_update(
+        &self,
+        usergroup: &GroupId,
+        users: &[&UserId],
+    ) -> Result<(), SlackErrors> {
+        let users: Vec<&str> = users.iter().map(|id| id.as_str()).collect();
+        let params = [
+            ("usergroup", usergroup.to_string()),
+            ("users", users.join(",")),
+        ];
+        let _: ApiStatus = self.call("usergroups.users.update", &params).await?;
+
+        Ok(())
+    }
+
+    /// Gets the do-not-disturb schedules of up to 50 users.
+    ///
+    /// Wraps https://api.slack.com/methods/dnd.teamInfo
+    pub async fn dnd_team_info(
+        &self,
+        users: &[UserId],
+    ) -> Result<BTreeMap<UserId, DndInfo>, SlackErrors> {
+        let users: Vec<&str> = users.iter().map(UserId::as_str).collect();
+        let response: DndTeamInfoResponse = self
+            .call("dnd.teamInfo", &[("users", users.join(","))])
+            .await?;
+
+        Ok(response.users)
+    }
+
+    /// Posts a message to a channel.
+    ///
+    /// Wraps https://api.slack.com/methods/chat.postMessage
+    pub async fn chat_post_message(
+        &self,
+        channel: &str,
+        text: &str,
+        blocks: &[Value],
+    ) -> Result<(), SlackErrors> {
+        let params = [
+            ("channel", channel.to_owned()),
+            ("text", text.to_owned()),
+            ("blocks", serde_json::to_string(blocks).unwrap()),
+        ];
+        let _: ApiStatus = self.call("chat.postMessage", &params).await?;
+
+        Ok(())
+    }
+
+    /// Replaces the App Home tab a user sees.
+    ///
+    /// Wraps https://api.slack.com/methods/views.publish
+    pub async fn views_publish(&self, user_id: &str, view: &Value) -> Result<(), SlackErrors> {
+        let params = [("user_id", user_id.to_owned()), ("view", view.to_string())];
+        let _: ApiStatus = self.call("views.publish", &params).await?;
+
+        Ok(())
+    }
+
+    /// Gets a WebSocket URL to receive events over. Needs a client created with an app-level
+    /// token rather than a bot token.
+    ///
+    /// Wraps https://api.slack.com/methods/apps.connections.open
+    pub async fn apps_connections_open(&self) -> Result<String, SlackErrors> {
+        let response: AppsConnectionsOpenResponse = self.call("apps.connections.open", &[]).await?;
+
+        Ok(response.url)
+    }
+
+    async fn call<T>(&self, method: &str, params: &[(&str, String)]) -> Result<T, SlackErrors>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let token = self.current_token().await?;
+
+        match self.send(method, params, &token).await {
+            Err(SlackErrors::Api { error, .. })
+                if self.rotation.is_some() && is_expired_token_error(&error) =>
+            {
+                info!("Slack token was rejected with {}, refreshing", error);
+                self.refresh_token(&token).await?;
+                let token = self.current_token().await?;
+                self.send(method, params, &token).await
+            }
+            result => result,
+        }
+    }
+
+    async fn current_token(&self) -> Result<String, SlackErrors> {
+        let token = self.token.lock().await.clone();
+        if self.rotation.is_some() && token.is_expired() {
+            info!("Slack token is about to expire, refreshing");
+            self.refresh_token(&token.access_token).await?;
+            return Ok(self.token.lock().await.access_token.clone());
+        }
+
+        Ok(token.access_token)
+    }
+
+    /// Exchanges the refresh token for a new token pair, unless `stale_token` was already replaced.
+    ///
+    /// Wraps https://api.slack.com/methods/oauth.v2.access
+    async fn refresh_token(&self, stale_token: &str) -> Result<(), SlackErrors> {
+        let rotation = match &self.rotation {
+            Some(rotation) => rotation,
+            None => return Ok(()),
+        };
+
+        let mut state = self.token.lock().await;
+        if state.access_token != stale_token {
+            return Ok(());
+        }
+
+        let refresh_token = state
+            .refresh_token
+            .clone()
+            .unwrap_or_else(|| rotation.refresh_token.clone());
+        let params = [
+            ("client_id", rotation.client_id.clone()),
+            ("client_secret", rotation.client_secret.clone()),
+            ("grant_type", "refresh_token".to_owned()),
+            ("refresh_token", refresh_token),
+        ];
+
+        let method = "oauth.v2.access";
+        self.usage.record(method)?;
+        let response = self
+            .client
+            .post(&format!("{}/{}", self.api_url, method))
+            .form(&params)
+            .send()
+            .await
+            .map_err(|e| request_error(method, e))?;
+        let body = response
+            .text()
+            .await
+            .map_err(|e| request_error(method, e))?;
+        let response: OauthV2AccessResponse = parse_body(method, &body)?;
+
+        state.update(
+            response.access_token,
+            response.refresh_token,
+            response.expires_in,
+        );
+        info!("Refreshed Slack token");
+
+        if let Some(path) = &rotation.store {
+            if let Err(e) = rotation::save(path, &state) {
+                warn!("Unable to persist refreshed Slack token. Error: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn send<T>(
+        &self,
+        method: &str,
+        params: &[(&str, String)],
+        token: &str,
+    ) -> Result<T, SlackErrors>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let url = format!("{}/{}", self.api_url, method);
+        trace!("Calling Slack method {}", method);
+
+        if chaos::slack_rate_limited() {
+            return Err(SlackErrors::RateLimited {
+                method: method.to_owned(),
+                retry_after: chaos::RATE_LIMIT_RETRY_AFTER_SECONDS,
+            });
+        }
+
+        self.usage.record(method)?;
+        let response = self
+            .client
+            .post(&url)
+            .bearer_auth(token)
+            .form(params)
+            .send()
+            .await
+            .map_err(|e| request_error(method, e))?;
+
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_RETRY_AFTER_SECONDS);
+
+            return Err(SlackErrors::RateLimited {
+                method: method.to_owned(),
+                retry_after,
+            });
+        }
+
+        let body = response
+            .text()
+            .await
+            .map_err(|e| request_error(method, e))?;
+
+        // Pages of users.list run to megabytes, so they're parsed off the async workers.
+        let owned = method.to_owned();
+        tokio::task::spawn_blocking(move || parse_body(&owned, &body))
+            .await
+            .map_err(|e| SlackErrors::UnableToParse {
+                method: method.to_owned(),
+                source: e,
+            })?
+    }
+}
+
+fn parse_body<T>(method: &str, body: &str) -> Result<T, SlackErrors>
+where
+    T: DeserializeOwned,
+{
+    let status: ApiStatus =
+        serde_json::from_str(body).map_err(|e| SlackErrors::MalformedResponse {
+            method: method.to_owned(),
+            source: e,
+        })?;
+
+    if !status.ok {
+        return Err(SlackErrors::Api {
+            method: method.to_owned(),
+            error: status.error.unwrap_or_else(|| "unknown_error".to_owned()),
+        });
+    }
+
+    serde_json::from_str(body).map_err(|e| SlackErrors::MalformedResponse {
+        method: method.to_owned(),
+        source: e,
+    })
+}
+
+fn is_expired_token_error(error: &str) -> bool {
+    error == "invalid_auth" || error == "token_expired"
+}
+
+fn request_error(method: &str, e: reqwest::Error) -> SlackErrors {
+    if e.is_timeout() {
+        SlackErrors::Timeout {
+            method: method.to_owned(),
+        }
+    } else {
+        SlackErrors::Request {
+            method: method.to_owned(),
+            source: e,
+        }
+    }
+}
+
+/// Tracks `response_metadata.next_cursor` across calls to a paginated method.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Cursor {
+    next: Option<String>,
+    page: u32,
+    done: bool,
+}
+
+impl Cursor {
+    pub fn value(&self) -> Option<&str> {
+        self.next.as_deref()
+    }
+
+    pub fn page(&self) -> u32 {
+        self.page
+    }
+
+    pub fn has_more(&self) -> bool {
+        !self.done
+    }
+
+    /// Moves to the next page. Slack signals the last page with a missing or empty cursor.
+    pub fn advance(&mut self, metadata: &ResponseMetadata) {
+        self.page += 1;
+        match &metadata.next_cursor {
+            Some(cursor) if !cursor.is_empty() => self.next = Some(cursor.clone()),
+            _ => {
+                self.next = None;
+                self.done = true;
+            }
+        }
+    }
+}
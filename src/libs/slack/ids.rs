@@ -0,0 +1,109 @@
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Longest id taken as a Slack id. Ids are around 11 characters today, and Slack has said they
+/// may grow, so this leaves room while still turning away junk.
+const MAX_ID_LENGTH: usize = 32;
+
+/// A Slack user id, like `U024BE7LH`, or `W...` for Enterprise Grid users. Ids given by callers
+/// are checked with `parse`, while ones read from Slack or Redis are taken as they are.
+#[serde(transparent)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct UserId(String);
+
+/// A Slack User Group id, like `S0614TZR7`. Checked the same way as `UserId`.
+#[serde(transparent)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct GroupId(String);
+
+impl UserId {
+    /// Wraps an id from another directory, like Google Workspace, without checking it.
+    pub fn unchecked(value: String) -> Self {
+        UserId(value)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl GroupId {
+    /// Wraps an id from another directory, like Google Workspace, without checking it.
+    pub fn unchecked(value: String) -> Self {
+        GroupId(value)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for UserId {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if is_slack_id(value, &['U', 'W']) {
+            Ok(UserId(value.to_owned()))
+        } else {
+            Err(format!("`{}` is not a Slack user id", value))
+        }
+    }
+}
+
+impl FromStr for GroupId {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if is_slack_id(value, &['S']) {
+            Ok(GroupId(value.to_owned()))
+        } else {
+            Err(format!("`{}` is not a Slack User Group id", value))
+        }
+    }
+}
+
+impl Deref for UserId {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for GroupId {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for UserId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Display for GroupId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// One of `prefixes` followed by uppercase letters and digits, the way Slack writes its ids.
+fn is_slack_id(value: &str, prefixes: &[char]) -> bool {
+    if value.len() > MAX_ID_LENGTH {
+        return false;
+    }
+
+    let mut chars = value.chars();
+    match chars.next() {
+        Some(first) if prefixes.contains(&first) => {}
+        _ => return false,
+    }
+
+    value.len() > 1 && chars.all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+}
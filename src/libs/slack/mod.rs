@@ -0,0 +1,762 @@
+pub mod blocks;
+mod client;
+mod ids;
+mod models;
+mod paged;
+mod rotation;
+mod socket_mode;
+mod usage;
+
+use std::cmp::{Ord, Ordering};
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use derivative::Derivative;
+use governor::clock::DefaultClock;
+use governor::state::{InMemoryState, NotKeyed};
+use governor::{Jitter, Quota, RateLimiter};
+use nonzero_ext::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, trace, warn};
+
+use super::email::Email;
+use super::progress::Progress;
+use super::redact;
+use super::summary::UserCounts;
+use crate::error::SlackErrors;
+pub use client::{Cursor, SlackClient, SlackClientConfig};
+pub use ids::{GroupId, UserId};
+pub use models::ResponseMetadata;
+use models::{User, UserProfile, Usergroup, UsersListResponse};
+pub use paged::{Paged, PagedFetcher};
+pub use rotation::TokenRotation;
+pub use socket_mode::{CacheEvent, SocketModeClient};
+pub use usage::{ApiUsage, ApiUsageSummary};
+
+type UsersLimiter = RateLimiter<NotKeyed, InMemoryState, DefaultClock>;
+
+/// How many times a call rate limited by Slack is retried before giving up on it.
+const MAX_RATE_LIMITED_RETRIES: u32 = 3;
+
+/// How many fetched pages of users can wait to be handled before fetching pauses.
+const USERS_PREFETCH_DEPTH: usize = 2;
+
+/// How many users dnd.teamInfo looks up at once.
+const DND_BATCH_SIZE: usize = 50;
+
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct SlackApi {
+    client: SlackClient,
+    #[derivative(Debug = "ignore")]
+    users_limiter: UsersLimiter,
+    #[derivative(Debug = "ignore")]
+    dnd_limiter: UsersLimiter,
+    #[derivative(Debug = "ignore")]
+    lookup_limiter: UsersLimiter,
+    #[derivative(Debug = "ignore")]
+    write_limiter: UsersLimiter,
+    progress: Option<Progress>,
+}
+
+/// Progress through users.list: the cursor of the next page, how many users were fetched so far
+/// and, when the caller keeps them, those users. It can be saved between pages so an interrupted
+/// sync picks up where it left off.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UsersCrawl {
+    pub cursor: Cursor,
+    #[serde(default)]
+    pub fetched: usize,
+    #[serde(default)]
+    pub counts: UserCounts,
+    /// Whether `users` holds every user fetched so far, rather than none of them.
+    #[serde(default)]
+    pub keep_users: bool,
+    #[serde(default)]
+    pub users: BTreeSet<SlackUser>,
+}
+
+/// A group's members as last fetched, along with the group's `date_update` at the time. Slack
+/// moves `date_update` whenever the group changes, so until it does the members can be reused.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FetchedMembers {
+    pub date_update: i64,
+    pub users: Vec<UserId>,
+}
+
+pub type FetchedGroups = BTreeMap<GroupId, FetchedMembers>;
+
+/// One page of users.list, along with the cursor of the page after it.
+#[derive(Debug)]
+pub struct UsersPage {
+    pub users: BTreeSet<SlackUser>,
+    /// Statuses of the users on the page. Their do-not-disturb schedules are left for
+    /// `add_dnd_schedules` to fill in.
+    pub availability: BTreeMap<UserId, UserAvailability>,
+    pub cursor: Cursor,
+}
+
+/// Whether a user can be reached right now: their do-not-disturb schedule and custom status.
+/// Both change too often to keep with the user, so they're cached on their own, briefly.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserAvailability {
+    pub dnd_enabled: bool,
+    /// When the current or next do-not-disturb period starts, in seconds since the epoch.
+    pub next_dnd_start: Option<i64>,
+    /// When the current or next do-not-disturb period ends, in seconds since the epoch.
+    pub next_dnd_end: Option<i64>,
+    pub status_text: Option<String>,
+    pub status_emoji: Option<String>,
+    /// When the status clears itself, in seconds since the epoch.
+    pub status_expiration: Option<i64>,
+}
+
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Clone)]
+pub struct SlackUserId {
+    pub id: UserId,
+}
+
+impl PartialOrd for SlackUserId {
+    fn partial_cmp(&self, other: &SlackUserId) -> Option<Ordering> {
+       Some(self.cmp(other))
+    }
+}
+
+impl Ord for SlackUserId {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Clone)]
+pub struct SlackUser {
+    pub id: UserId,
+    pub name: String,
+    /// The name the user goes by in Slack, when they've set one apart from their real name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// Real name with the accents and non-Latin characters Slack knows how to swap out replaced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub real_name_normalized: Option<String>,
+    pub email: Email,
+    /// Emails the user had before, lowercased, hashed when hashing is on.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub previous_emails: BTreeSet<Email>,
+    /// URLs of the user's profile photo, keyed by width in pixels.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub avatars: BTreeMap<u32, String>,
+    /// IANA timezone the user has set, e.g. `Europe/Berlin`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tz: Option<String>,
+    /// Organization specific attributes set through the admin API, like a cost center. They
+    /// aren't part of the cached user and are only added when it's looked up.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    /// Set when the user's email is outside `--email-domain-allowlist` and they're cached anyway.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub external: bool,
+    /// Set for single and multi-channel guests.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub guest: bool,
+    /// Set when `--backfill-emails` found the user through users.lookupByEmail, as users.list
+    /// leaves some guests out.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub backfilled: bool,
+    /// GitHub login, lowercased, when `--github-logins` has one for the user's email.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub github_login: Option<String>,
+    /// Workspace the user was fetched from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team_id: Option<String>,
+    /// Workspaces of an Enterprise Grid org the user is a member of. Empty outside Enterprise
+    /// Grid.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub teams: BTreeSet<String>,
+}
+
+impl PartialOrd for SlackUser {
+    fn partial_cmp(&self, other: &SlackUser) -> Option<Ordering> {
+       Some(self.cmp(other))
+    }
+}
+
+impl Ord for SlackUser {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+
+impl SlackUser {
+    fn new(user: User) -> Result<Self, String> {
+        let guest = user.is_restricted == Some(true) || user.is_ultra_restricted == Some(true);
+        let id: UserId = user.id.ok_or("no user id")?;
+        let profile = user.profile.ok_or(format!("{}: no profile", id))?;
+
+        let avatars = profile.avatars();
+        let name: String = profile.real_name.ok_or(format!("{}: no name", id))?;
+        let email: Email = profile
+            .email
+            .ok_or(format!("{} - {}: no email", id, name))?;
+        let teams = user
+            .enterprise_user
+            .map(|enterprise_user| enterprise_user.teams.into_iter().collect())
+            .unwrap_or_default();
+        Ok(SlackUser {
+            id,
+            name,
+            display_name: profile.display_name.filter(|name| !name.is_empty()),
+            real_name_normalized: profile.real_name_normalized.filter(|name| !name.is_empty()),
+            email,
+            previous_emails: BTreeSet::new(),
+            avatars,
+            tz: user.tz,
+            annotations: BTreeMap::new(),
+            external: false,
+            guest,
+            backfilled: false,
+            github_login: None,
+            team_id: user.team_id,
+            teams,
+        })
+    }
+
+    /// Whether the user belongs to the workspace `team`, either as the one they were fetched
+    /// from or as one of their Enterprise Grid workspaces.
+    pub fn in_team(&self, team: &str) -> bool {
+        self.team_id.as_deref() == Some(team) || self.teams.contains(team)
+    }
+
+    /// Every workspace the user belongs to.
+    pub fn all_teams(&self) -> BTreeSet<String> {
+        self.teams.iter().chain(&self.team_id).cloned().collect()
+    }
+
+    /// The smallest profile photo at least `size` pixels wide, or the largest there is.
+    pub fn avatar(&self, size: u32) -> Option<&str> {
+        self.avatars
+            .range(size..)
+            .next()
+            .or_else(|| self.avatars.iter().next_back())
+            .map(|(_, url)| url.as_str())
+    }
+}
+
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Clone)]
+pub struct SlackUserGroup {
+    pub name: String,
+    pub id: GroupId,
+    pub users: BTreeSet<SlackUserId>,
+    /// Names the group had on earlier syncs, before it was renamed.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub previous_names: BTreeSet<String>,
+    /// Made through `/admin/groups` and kept only in the cache, rather than fetched.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub synthetic: bool,
+}
+
+impl UserAvailability {
+    fn from_profile(profile: &UserProfile) -> Self {
+        let non_empty = |value: &Option<String>| value.clone().filter(|value| !value.is_empty());
+        UserAvailability {
+            status_text: non_empty(&profile.status_text),
+            status_emoji: non_empty(&profile.status_emoji),
+            status_expiration: profile
+                .status_expiration
+                .filter(|expiration| *expiration > 0),
+            ..UserAvailability::default()
+        }
+    }
+
+    /// Whether do-not-disturb is on at `now`, in seconds since the epoch.
+    pub fn in_dnd_at(&self, now: i64) -> bool {
+        match (self.dnd_enabled, self.next_dnd_start, self.next_dnd_end) {
+            (true, Some(start), Some(end)) => start <= now && now < end,
+            _ => false,
+        }
+    }
+}
+
+impl PartialOrd for SlackUserGroup {
+    fn partial_cmp(&self, other: &SlackUserGroup) -> Option<Ordering> {
+       Some(self.cmp(other))
+    }
+}
+
+impl Ord for SlackUserGroup {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+
+impl SlackApi {
+    pub fn new(token: &str, config: &SlackClientConfig) -> Result<Self, SlackErrors> {
+        Ok(Self {
+            client: SlackClient::new(token, config)?,
+            users_limiter: RateLimiter::direct(Quota::per_minute(nonzero!(10u32))),
+            dnd_limiter: RateLimiter::direct(Quota::per_minute(nonzero!(20u32))),
+            lookup_limiter: RateLimiter::direct(Quota::per_minute(nonzero!(50u32))),
+            write_limiter: RateLimiter::direct(Quota::per_minute(nonzero!(20u32))),
+            progress: None,
+        })
+    }
+
+    /// Calls the methods at `api_url` rather than Slack's, see `SlackClient::with_api_url`.
+    pub fn with_api_url(mut self, api_url: &str) -> Self {
+        self.client = self.client.with_api_url(api_url);
+        self
+    }
+
+    /// Shows a bar while the members of each group are fetched.
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Checks Slack accepts the token, so a sync with a bad one fails before it starts.
+    pub async fn check_auth(&self) -> Result<(), SlackErrors> {
+        let auth = self.client.auth_test().await?;
+        debug!(
+            "Slack token is for {} in {}",
+            auth.user.as_deref().unwrap_or("an unknown user"),
+            auth.team.as_deref().unwrap_or("an unknown team")
+        );
+        Ok(())
+    }
+
+    pub async fn post_message(
+        &self,
+        channel: &str,
+        text: &str,
+        blocks: &[Value],
+    ) -> Result<(), SlackErrors> {
+        self.client.chat_post_message(channel, text, blocks).await
+    }
+
+    /// Shows `view` on the App Home tab of `user_id`.
+    pub async fn publish_home(&self, user_id: &str, view: &Value) -> Result<(), SlackErrors> {
+        self.client.views_publish(user_id, view).await
+    }
+
+    /// Fetches users.list pages, starting at `cursor`, on a background task that keeps up to
+    /// `USERS_PREFETCH_DEPTH` pages ahead of the receiver. Pages come one at a time, as each one's
+    /// cursor is needed to request the next. The channel closes after the last page or an error.
+    pub fn prefetch_users(
+        self: Arc<Self>,
+        cursor: Cursor,
+    ) -> mpsc::Receiver<Result<UsersPage, SlackErrors>> {
+        info!("Fetching all users from Slack");
+
+        let (sender, receiver) = mpsc::channel(USERS_PREFETCH_DEPTH);
+        tokio::spawn(async move {
+            let mut cursor = cursor;
+            while cursor.has_more() {
+                let page = self.fetch_users_page(&cursor).await;
+                let failed = page.is_err();
+                if let Ok(page) = &page {
+                    cursor = page.cursor.clone();
+                }
+                if sender.send(page).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        receiver
+    }
+
+    /// Fetches every page of users.list, for callers that don't need them a page at a time.
+    pub async fn list_all_users(&self) -> Result<BTreeSet<SlackUser>, SlackErrors> {
+        let pages = self
+            .users_fetcher()
+            .fetch_all(Cursor::default(), |cursor| async move {
+                self.client.users_list(&cursor, 200).await
+            })
+            .await?;
+
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| Self::users_page(page, Cursor::default()).users)
+            .collect())
+    }
+
+    /// Fetches the page of users at `cursor`.
+    async fn fetch_users_page(&self, cursor: &Cursor) -> Result<UsersPage, SlackErrors> {
+        let (page, next) = self
+            .users_fetcher()
+            .fetch_page(cursor, |cursor| async move {
+                self.client.users_list(&cursor, 200).await
+            })
+            .await?;
+
+        let users = Self::users_page(page, next);
+        info!(
+            "Fetched {} users from page {}",
+            users.users.len(),
+            cursor.page()
+        );
+        Ok(users)
+    }
+
+    fn users_fetcher(&self) -> PagedFetcher<'_> {
+        PagedFetcher::new("users.list", &self.users_limiter)
+    }
+
+    /// The active, human users on a page of users.list, whose next page starts at `cursor`.
+    fn users_page(paged_users: UsersListResponse, cursor: Cursor) -> UsersPage {
+        debug!("response_metadata: {:?}", paged_users.response_metadata);
+
+        let mut availability = BTreeMap::new();
+        let paged_users: BTreeSet<SlackUser> = paged_users
+            .members
+            .into_iter()
+            .filter(|user| user.deleted == Some(false))
+            .filter(|user| user.is_bot == Some(false))
+            .map(|user| {
+                if !redact::is_redacting_pii() {
+                    trace!("Raw User Data: {:?}", user);
+                }
+                if let (Some(id), Some(profile)) = (&user.id, &user.profile) {
+                    availability.insert(id.clone(), UserAvailability::from_profile(profile));
+                }
+                SlackUser::new(user)
+            })
+            .filter_map(Result::ok)
+            .collect();
+
+        UsersPage {
+            users: paged_users,
+            availability,
+            cursor,
+        }
+    }
+
+    /// The id of the active user with `email` as Slack has it right now, `None` inside when
+    /// there's no such user. Returns `None` straight away, without asking Slack, when asking
+    /// would mean waiting for users.lookupByEmail's rate limit.
+    pub async fn try_lookup_user_id(
+        &self,
+        email: &str,
+    ) -> Option<Result<Option<UserId>, SlackErrors>> {
+        if self.lookup_limiter.check().is_err() {
+            return None;
+        }
+
+        let result = match self.client.users_lookup_by_email(email).await {
+            Ok(user) if user.deleted == Some(true) || user.is_bot == Some(true) => Ok(None),
+            Ok(user) => Ok(user.id),
+            Err(SlackErrors::Api { error, .. }) if error == "users_not_found" => Ok(None),
+            Err(e) => Err(e),
+        };
+        Some(result)
+    }
+
+    /// The active user with `email`, waiting for users.lookupByEmail's rate limit rather than
+    /// skipping the lookup. `None` when there's no such user.
+    pub async fn lookup_user(&self, email: &Email) -> Result<Option<SlackUser>, SlackErrors> {
+        let result = self
+            .call_limited(&self.lookup_limiter, "users.lookupByEmail", || {
+                self.client.users_lookup_by_email(email)
+            })
+            .await;
+
+        match result {
+            Ok(user) if user.deleted == Some(true) || user.is_bot == Some(true) => Ok(None),
+            Ok(user) => match SlackUser::new(user) {
+                Ok(user) => Ok(Some(user)),
+                Err(e) => {
+                    warn!("Unable to read user found by email. Error: {}", e);
+                    Ok(None)
+                }
+            },
+            Err(SlackErrors::Api { error, .. }) if error == "users_not_found" => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Fills in the do-not-disturb schedules of the users in `availability`, asking for them in
+    /// batches. Needs the `dnd:read` scope.
+    pub async fn add_dnd_schedules(
+        &self,
+        availability: &mut BTreeMap<UserId, UserAvailability>,
+    ) -> Result<(), SlackErrors> {
+        let ids: Vec<UserId> = availability.keys().cloned().collect();
+        for batch in ids.chunks(DND_BATCH_SIZE) {
+            self.dnd_limiter
+                .until_ready_with_jitter(Jitter::up_to(Duration::from_secs(1)))
+                .await;
+
+            for (id, dnd) in self.client.dnd_team_info(batch).await? {
+                if let Some(user) = availability.get_mut(&id) {
+                    user.dnd_enabled = dnd.dnd_enabled.unwrap_or(false);
+                    user.next_dnd_start = dnd.next_dnd_start_ts;
+                    user.next_dnd_end = dnd.next_dnd_end_ts;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates a User Group named `name`, returning its id. Needs the `usergroups:write` scope.
+    pub async fn create_user_group(&self, name: &str) -> Result<GroupId, SlackErrors> {
+        let group = self
+            .call_limited(&self.write_limiter, "usergroups.create", || {
+                self.client.usergroups_create(name)
+            })
+            .await?;
+        group.id.ok_or_else(|| SlackErrors::Api {
+            method: "usergroups.create".to_owned(),
+            error: "no usergroup id in the response".to_owned(),
+        })
+    }
+
+    /// Replaces the members of the User Group `id` with `users`.
+    pub async fn set_user_group_members(
+        &self,
+        id: &GroupId,
+        users: &[&UserId],
+    ) -> Result<(), SlackErrors> {
+        self.call_limited(&self.write_limiter, "usergroups.users.update", || {
+            self.client.usergroups_users_update(id, users)
+        })
+        .await
+    }
+
+    /// Makes a call keeping under `limiter`, and waits out the `Retry-After` of calls Slack still
+    /// rate limits. Other errors aren't retried, as a write may have been made anyway.
+    async fn call_limited<T, F, R>(
+        &self,
+        limiter: &UsersLimiter,
+        method: &str,
+        call: F,
+    ) -> Result<T, SlackErrors>
+    where
+        F: Fn() -> R,
+        R: Future<Output = Result<T, SlackErrors>>,
+    {
+        let mut attempt = 0;
+        loop {
+            limiter
+                .until_ready_with_jitter(Jitter::up_to(Duration::from_secs(1)))
+                .await;
+
+            match call().await {
+                Err(e @ SlackErrors::RateLimited { .. }) if attempt < MAX_RATE_LIMITED_RETRIES => {
+                    let delay = retry_delay(&e, attempt);
+                    warn!(
+                        "Slack rate limited {}, retrying in {}s",
+                        method,
+                        delay.as_secs()
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub async fn list_all_user_groups(&self) -> Result<BTreeSet<SlackUserGroup>, SlackErrors> {
+        let (groups, _) = self.list_user_groups_since(&FetchedGroups::new()).await?;
+        Ok(groups)
+    }
+
+    /// Lists every group, reusing the members in `fetched` for groups whose `date_update` hasn't
+    /// moved since, rather than asking Slack for them again. Returns the groups along with the
+    /// members to reuse next time.
+    pub async fn list_user_groups_since(
+        &self,
+        fetched: &FetchedGroups,
+    ) -> Result<(BTreeSet<SlackUserGroup>, FetchedGroups), SlackErrors> {
+        info!("Fetching all usergroups");
+
+        let usergroup_list = match self.client.usergroups_list().await {
+            Ok(results) => results,
+            Err(e) => {
+                error!("Unable to fetch data from Slack. Error: {}", e);
+                return Err(e);
+            }
+        };
+
+        let bar = self
+            .progress
+            .as_ref()
+            .map(|progress| progress.start("user groups", Some(usergroup_list.len() as u64)));
+        let mut result_slack_user_group: BTreeSet<SlackUserGroup> = BTreeSet::new();
+        let mut now_fetched = FetchedGroups::new();
+        for usergroup in usergroup_list {
+            if let Some(bar) = &bar {
+                bar.inc(1);
+            }
+            if usergroup.is_deleted() {
+                continue;
+            }
+            let slack_user_group = self
+                .build_user_group(usergroup, fetched, &mut now_fetched)
+                .await?;
+            match slack_user_group {
+                Ok(group) => {
+                    result_slack_user_group.insert(group);
+                }
+                Err(e) => {
+                    warn!("Unable to build usergroup: {}", e);
+                }
+            }
+        }
+
+        let reused = now_fetched
+            .iter()
+            .filter(|(id, members)| fetched.get(*id) == Some(members))
+            .count();
+        if reused > 0 {
+            info!("Reused the members of {} unchanged usergroups", reused);
+        }
+
+        Ok((result_slack_user_group, now_fetched))
+    }
+
+    /// The group along with its members, or why it was left out. Running out of API calls ends
+    /// the whole listing, rather than leaving out every group after it.
+    async fn build_user_group(
+        &self,
+        user_group: Usergroup,
+        fetched: &FetchedGroups,
+        now_fetched: &mut FetchedGroups,
+    ) -> Result<Result<SlackUserGroup, String>, SlackErrors> {
+        let id = match user_group.id {
+            Some(id) => id,
+            None => return Ok(Err("no group id".to_owned())),
+        };
+        let name = match user_group.name {
+            Some(name) => name,
+            None => return Ok(Err(format!("No name for group {}", id))),
+        };
+
+        let unchanged = match (user_group.date_update, fetched.get(&id)) {
+            (Some(date_update), Some(members)) if members.date_update == date_update => {
+                Some(members.users.clone())
+            }
+            _ => None,
+        };
+        let users = match unchanged {
+            Some(users) => users,
+            None => match self.client.usergroups_users_list(&id).await {
+                Ok(users) => users,
+                Err(e @ SlackErrors::BudgetExceeded { .. }) => return Err(e),
+                Err(e) => {
+                    return Ok(Err(format!(
+                        "Error getting users from group {}. Error: {}",
+                        id, e
+                    )));
+                }
+            },
+        };
+        if let Some(date_update) = user_group.date_update {
+            now_fetched.insert(
+                id.clone(),
+                FetchedMembers {
+                    date_update,
+                    users: users.clone(),
+                },
+            );
+        }
+
+        let user_set:BTreeSet<SlackUserId> = users
+                .into_iter()
+                .map(|user_id| SlackUserId { id: user_id })
+                .collect();
+
+        Ok(Ok(SlackUserGroup {
+            id,
+            name,
+            users: user_set,
+            previous_names: BTreeSet::new(),
+            synthetic: false,
+        }))
+    }
+}
+
+/// Slack's `Retry-After` when rate limited, otherwise 2, 4, 8... seconds.
+fn retry_delay(error: &SlackErrors, attempt: u32) -> Duration {
+    match error {
+        SlackErrors::RateLimited { retry_after, .. } => Duration::from_secs(*retry_after),
+        _ => Duration::from_secs(2u64.pow(attempt + 1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+    use warp::Filter;
+
+    use super::*;
+
+    /// Serves `usergroups` from usergroups.list, and `members` from usergroups.users.list, at the
+    /// URL returned.
+    fn fake_slack(usergroups: Value, members: HashMap<String, Vec<String>>) -> String {
+        let usergroups_list = warp::path("usergroups.list")
+            .map(move || warp::reply::json(&json!({"ok": true, "usergroups": usergroups})));
+        let usergroups_users_list = warp::path("usergroups.users.list")
+            .and(warp::body::form())
+            .map(move |form: HashMap<String, String>| {
+                let users = members.get(&form["usergroup"]).cloned().unwrap_or_default();
+                warp::reply::json(&json!({"ok": true, "users": users}))
+            });
+
+        let (address, server) = warp::serve(usergroups_list.or(usergroups_users_list))
+            .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("http://{}", address)
+    }
+
+    fn slack_api(api_url: &str) -> SlackApi {
+        let config = SlackClientConfig {
+            connect_timeout: Duration::from_secs(1),
+            request_timeout: Duration::from_secs(5),
+            tcp_keepalive: Duration::from_secs(60),
+            rotation: None,
+            usage: Arc::default(),
+        };
+        SlackApi::new("xoxb-test", &config)
+            .unwrap()
+            .with_api_url(api_url)
+    }
+
+    #[tokio::test]
+    async fn deleted_groups_are_left_out() {
+        let usergroups = json!([
+            {
+                "id": "S1", "name": "active", "handle": "active",
+                "date_update": 10, "date_delete": 0, "deleted_by": null
+            },
+            {
+                "id": "S2", "name": "deleted", "handle": "deleted",
+                "date_update": 10, "date_delete": 20, "deleted_by": "U9"
+            }
+        ]);
+        let mut members = HashMap::new();
+        members.insert("S1".to_owned(), vec!["U1".to_owned(), "U2".to_owned()]);
+        members.insert("S2".to_owned(), vec!["U3".to_owned()]);
+
+        let api = slack_api(&fake_slack(usergroups, members));
+        let groups = api.list_all_user_groups().await.unwrap();
+
+        let groups: Vec<(&str, &str, usize)> = groups
+            .iter()
+            .map(|group| (group.id.as_str(), group.name.as_str(), group.users.len()))
+            .collect();
+        assert_eq!(groups, vec![("S1", "active", 2)]);
+    }
+}
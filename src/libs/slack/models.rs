@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use super::{GroupId, UserId};
+use crate::libs::email::Email;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ApiStatus {
+    #[serde(default)]
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ResponseMetadata {
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct User {
+    pub id: Option<UserId>,
+    pub deleted: Option<bool>,
+    pub is_bot: Option<bool>,
+    /// Multi-channel guest.
+    pub is_restricted: Option<bool>,
+    /// Single-channel guest.
+    pub is_ultra_restricted: Option<bool>,
+    pub tz: Option<String>,
+    pub team_id: Option<String>,
+    pub enterprise_user: Option<EnterpriseUser>,
+    pub profile: Option<UserProfile>,
+}
+
+/// Set on users of an Enterprise Grid org.
+#[derive(Clone, Debug, Deserialize)]
+pub struct EnterpriseUser {
+    /// The org's workspaces the user is a member of.
+    #[serde(default)]
+    pub teams: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct UserProfile {
+    pub real_name: Option<String>,
+    pub real_name_normalized: Option<String>,
+    pub display_name: Option<String>,
+    pub email: Option<Email>,
+    pub image_24: Option<String>,
+    pub image_32: Option<String>,
+    pub image_48: Option<String>,
+    pub image_72: Option<String>,
+    pub image_192: Option<String>,
+    pub image_512: Option<String>,
+    pub image_1024: Option<String>,
+    pub status_text: Option<String>,
+    pub status_emoji: Option<String>,
+    pub status_expiration: Option<i64>,
+}
+
+impl UserProfile {
+    /// URLs of the user's profile photo, keyed by width in pixels.
+    pub fn avatars(&self) -> BTreeMap<u32, String> {
+        let sizes = [
+            (24, &self.image_24),
+            (32, &self.image_32),
+            (48, &self.image_48),
+            (72, &self.image_72),
+            (192, &self.image_192),
+            (512, &self.image_512),
+            (1024, &self.image_1024),
+        ];
+
+        sizes
+            .iter()
+            .filter_map(|(size, url)| url.as_ref().map(|url| (*size, url.clone())))
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct DndInfo {
+    pub dnd_enabled: Option<bool>,
+    pub next_dnd_start_ts: Option<i64>,
+    pub next_dnd_end_ts: Option<i64>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Usergroup {
+    pub id: Option<GroupId>,
+    pub name: Option<String>,
+    pub handle: Option<String>,
+    pub date_update: Option<i64>,
+    pub date_delete: Option<i64>,
+    pub deleted_by: Option<String>,
+    pub users: Option<Vec<UserId>>,
+}
+
+impl Usergroup {
+    /// Whether the group was deleted. Slack has `date_delete` at 0 and no `deleted_by` for live
+    /// groups.
+    pub fn is_deleted(&self) -> bool {
+        self.date_delete
+            .map_or(false, |date_delete| date_delete != 0)
+            || self
+                .deleted_by
+                .as_deref()
+                .map_or(false, |deleted_by| !deleted_by.is_empty())
+    }
+}
+
+/// Response of https://api.slack.com/methods/auth.test
+#[derive(Clone, Debug, Deserialize)]
+pub struct AuthTestResponse {
+    pub team: Option<String>,
+    pub user: Option<String>,
+}
+
+/// Response of https://api.slack.com/methods/users.list
+#[derive(Clone, Debug, Deserialize)]
+pub struct UsersListResponse {
+    #[serde(default)]
+    pub members: Vec<User>,
+    #[serde(default)]
+    pub response_metadata: ResponseMetadata,
+}
+
+/// Response of https://api.slack.com/methods/users.lookupByEmail
+#[derive(Clone, Debug, Deserialize)]
+pub struct UsersLookupByEmailResponse {
+    pub user: User,
+}
+
+/// Response of https://api.slack.com/methods/usergroups.list
+#[derive(Clone, Debug, Deserialize)]
+pub struct UsergroupsListResponse {
+    #[serde(default)]
+    pub usergroups: Vec<Usergroup>,
+}
+
+/// Response of https://api.slack.com/methods/usergroups.users.list
+#[derive(Clone, Debug, Deserialize)]
+pub struct UsergroupsUsersListResponse {
+    #[serde(default)]
+    pub users: Vec<UserId>,
+}
+
+/// Response of https://api.slack.com/methods/usergroups.create
+#[derive(Clone, Debug, Deserialize)]
+pub struct UsergroupsCreateResponse {
+    pub usergroup: Usergroup,
+}
+
+/// Response of https://api.slack.com/methods/dnd.teamInfo
+#[derive(Clone, Debug, Deserialize)]
+pub struct DndTeamInfoResponse {
+    #[serde(default)]
+    pub users: BTreeMap<UserId, DndInfo>,
+}
+
+/// Response of https://api.slack.com/methods/oauth.v2.access
+#[derive(Clone, Debug, Deserialize)]
+pub struct OauthV2AccessResponse {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_in: Option<u64>,
+}
+
+/// Response of https://api.slack.com/methods/apps.connections.open
+#[derive(Clone, Debug, Deserialize)]
+pub struct AppsConnectionsOpenResponse {
+    pub url: String,
+}
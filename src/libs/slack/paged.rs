@@ -0,0 +1,290 @@
+use std::future::Future;
+use std::time::Duration;
+
+use governor::Jitter;
+use tracing::{error, info, warn};
+
+use super::client::Cursor;
+use super::models::{ResponseMetadata, UsersListResponse};
+use super::{retry_delay, UsersLimiter};
+use crate::error::SlackErrors;
+
+/// How many times a page is retried before the crawl gives up on it.
+const MAX_PAGE_RETRIES: u32 = 3;
+
+/// A page of a paginated Slack method, which says where the page after it starts.
+pub trait Paged {
+    fn response_metadata(&self) -> &ResponseMetadata;
+}
+
+impl Paged for UsersListResponse {
+    fn response_metadata(&self) -> &ResponseMetadata {
+        &self.response_metadata
+    }
+}
+
+/// Crawls a paginated Slack method a page at a time, keeping under `limiter`. Timeouts and rate
+/// limiting are retried a few times, backing off in between, before the crawl gives up.
+///
+/// Other tooling can crawl any method whose response implements `Paged` with it:
+///
+/// ```no_run
+/// # use governor::{Quota, RateLimiter};
+/// # use nonzero_ext::nonzero;
+/// use slack_user_cache::libs::slack::{Cursor, PagedFetcher, SlackClient};
+///
+/// # async fn crawl(client: &SlackClient) -> Result<(), slack_user_cache::error::SlackErrors> {
+/// let limiter = RateLimiter::direct(Quota::per_minute(nonzero!(20u32)));
+/// let fetcher = PagedFetcher::new("users.list", &limiter);
+/// let pages = fetcher
+///     .fetch_all(Cursor::default(), |cursor| async move {
+///         client.users_list(&cursor, 200).await
+///     })
+///     .await?;
+/// println!("Fetched {} pages of users", pages.len());
+/// # Ok(())
+/// # }
+/// ```
+pub struct PagedFetcher<'a> {
+    method: &'a str,
+    limiter: &'a UsersLimiter,
+}
+
+impl<'a> PagedFetcher<'a> {
+    pub fn new(method: &'a str, limiter: &'a UsersLimiter) -> Self {
+        Self { method, limiter }
+    }
+
+    /// Fetches the page at `cursor` with `call`, returning it along with the cursor of the page
+    /// after it. `call` is given its own copy of the cursor for each attempt.
+    pub async fn fetch_page<P, F, R>(
+        &self,
+        cursor: &Cursor,
+        call: F,
+    ) -> Result<(P, Cursor), SlackErrors>
+    where
+        P: Paged,
+        F: Fn(Cursor) -> R,
+        R: Future<Output = Result<P, SlackErrors>>,
+    {
+        let page_number = cursor.page();
+        info!("Fetching {} page number {}", self.method, page_number);
+
+        let mut attempt = 0;
+        let page = loop {
+            self.limiter
+                .until_ready_with_jitter(Jitter::up_to(Duration::from_secs(1)))
+                .await;
+
+            match call(cursor.clone()).await {
+                Ok(page) => break page,
+                Err(e) if attempt < MAX_PAGE_RETRIES && is_transient(&e) => {
+                    let delay = retry_delay(&e, attempt);
+                    warn!(
+                        "Unable to fetch {} page {}, retrying in {}s. Error: {}",
+                        self.method,
+                        page_number,
+                        delay.as_secs(),
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    error!("Unable to fetch data from Slack. Error: {}", e);
+                    return Err(e);
+                }
+            }
+        };
+
+        let mut next = cursor.clone();
+        next.advance(page.response_metadata());
+        Ok((page, next))
+    }
+
+    /// Fetches every page from `cursor` on, giving up on the whole crawl if one page can't be
+    /// fetched.
+    pub async fn fetch_all<P, F, R>(&self, cursor: Cursor, call: F) -> Result<Vec<P>, SlackErrors>
+    where
+        P: Paged,
+        F: Fn(Cursor) -> R,
+        R: Future<Output = Result<P, SlackErrors>>,
+    {
+        let mut pages = Vec::new();
+        let mut cursor = cursor;
+        while cursor.has_more() {
+            let (page, next) = self.fetch_page(&cursor, &call).await?;
+            pages.push(page);
+            cursor = next;
+        }
+
+        Ok(pages)
+    }
+}
+
+fn is_transient(error: &SlackErrors) -> bool {
+    matches!(
+        error,
+        SlackErrors::Timeout { .. } | SlackErrors::RateLimited { .. } | SlackErrors::Request { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use governor::{Quota, RateLimiter};
+    use nonzero_ext::nonzero;
+
+    use super::*;
+
+    struct Page {
+        number: u32,
+        response_metadata: ResponseMetadata,
+    }
+
+    impl Paged for Page {
+        fn response_metadata(&self) -> &ResponseMetadata {
+            &self.response_metadata
+        }
+    }
+
+    fn page(number: u32, next_cursor: Option<&str>) -> Page {
+        Page {
+            number,
+            response_metadata: ResponseMetadata {
+                next_cursor: next_cursor.map(str::to_owned),
+            },
+        }
+    }
+
+    fn limiter() -> UsersLimiter {
+        RateLimiter::direct(Quota::per_second(nonzero!(1000u32)))
+    }
+
+    fn timeout() -> SlackErrors {
+        SlackErrors::Timeout {
+            method: "test.list".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn follows_the_cursor_until_it_is_empty() {
+        let limiter = limiter();
+        let fetcher = PagedFetcher::new("test.list", &limiter);
+
+        let pages = fetcher
+            .fetch_all(Cursor::default(), |cursor| async move {
+                Ok(match cursor.value() {
+                    None => page(1, Some("b")),
+                    Some("b") => page(2, Some("c")),
+                    Some("c") => page(3, Some("")),
+                    Some(other) => panic!("unexpected cursor {}", other),
+                })
+            })
+            .await
+            .unwrap();
+
+        let numbers: Vec<u32> = pages.iter().map(|page| page.number).collect();
+        assert_eq!(numbers, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn stops_without_a_cursor() {
+        let limiter = limiter();
+        let fetcher = PagedFetcher::new("test.list", &limiter);
+
+        let (first, next) = fetcher
+            .fetch_page(&Cursor::default(), |_| async { Ok(page(1, None)) })
+            .await
+            .unwrap();
+
+        assert_eq!(first.number, 1);
+        assert_eq!(next.page(), 1);
+        assert_eq!(next.value(), None);
+        assert!(!next.has_more());
+    }
+
+    #[tokio::test]
+    async fn advances_the_cursor() {
+        let limiter = limiter();
+        let fetcher = PagedFetcher::new("test.list", &limiter);
+
+        let (_, next) = fetcher
+            .fetch_page(&Cursor::default(), |_| async { Ok(page(1, Some("b"))) })
+            .await
+            .unwrap();
+
+        assert_eq!(next.page(), 1);
+        assert_eq!(next.value(), Some("b"));
+        assert!(next.has_more());
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limiting_and_timeouts() {
+        tokio::time::pause();
+        let limiter = limiter();
+        let fetcher = PagedFetcher::new("test.list", &limiter);
+        let calls = AtomicU32::new(0);
+
+        let (page, _) = fetcher
+            .fetch_page(&Cursor::default(), |_| {
+                let call = calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match call {
+                        0 => Err(SlackErrors::RateLimited {
+                            method: "test.list".to_owned(),
+                            retry_after: 1,
+                        }),
+                        1 => Err(timeout()),
+                        _ => Ok(page(1, None)),
+                    }
+                }
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(page.number, 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_retries() {
+        tokio::time::pause();
+        let limiter = limiter();
+        let fetcher = PagedFetcher::new("test.list", &limiter);
+        let calls = AtomicU32::new(0);
+
+        let result = fetcher
+            .fetch_page(&Cursor::default(), |_| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async { Err::<Page, _>(timeout()) }
+            })
+            .await;
+
+        assert!(matches!(result, Err(SlackErrors::Timeout { .. })));
+        assert_eq!(calls.load(Ordering::SeqCst), MAX_PAGE_RETRIES + 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_other_errors() {
+        let limiter = limiter();
+        let fetcher = PagedFetcher::new("test.list", &limiter);
+        let calls = AtomicU32::new(0);
+
+        let result = fetcher
+            .fetch_page(&Cursor::default(), |_| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async {
+                    Err::<Page, _>(SlackErrors::Api {
+                        method: "test.list".to_owned(),
+                        error: "invalid_auth".to_owned(),
+                    })
+                }
+            })
+            .await;
+
+        assert!(matches!(result, Err(SlackErrors::Api { .. })));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,129 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use derivative::Derivative;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::error::SlackErrors;
+
+/// Tokens are refreshed this long before Slack says they expire.
+const EXPIRY_MARGIN_SECONDS: u64 = 5 * 60;
+
+/// App credentials used to exchange a refresh token for a new access token.
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct TokenRotation {
+    pub client_id: String,
+    #[derivative(Debug = "ignore")]
+    pub client_secret: String,
+    #[derivative(Debug = "ignore")]
+    pub refresh_token: String,
+    /// File the latest token pair is written to, so restarts don't reuse a spent refresh token.
+    pub store: Option<PathBuf>,
+}
+
+#[derive(Derivative, Clone, Serialize, Deserialize)]
+#[derivative(Debug)]
+pub struct TokenState {
+    #[derivative(Debug = "ignore")]
+    pub access_token: String,
+    #[derivative(Debug = "ignore")]
+    pub refresh_token: Option<String>,
+    pub expires_at: Option<u64>,
+}
+
+impl TokenState {
+    pub fn new(access_token: &str, rotation: Option<&TokenRotation>) -> Self {
+        let configured = Self {
+            access_token: access_token.to_owned(),
+            refresh_token: rotation.map(|r| r.refresh_token.clone()),
+            expires_at: None,
+        };
+
+        match rotation.and_then(|r| r.store.as_ref()) {
+            Some(path) => match load(path) {
+                Some(stored) => {
+                    debug!("Using Slack token stored in {}", path.display());
+                    stored
+                }
+                None => configured,
+            },
+            None => configured,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => now() + EXPIRY_MARGIN_SECONDS >= expires_at,
+            None => false,
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        access_token: String,
+        refresh_token: Option<String>,
+        expires_in: Option<u64>,
+    ) {
+        self.access_token = access_token;
+        if refresh_token.is_some() {
+            self.refresh_token = refresh_token;
+        }
+        self.expires_at = expires_in.map(|seconds| now() + seconds);
+    }
+}
+
+fn load(path: &Path) -> Option<TokenState> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            debug!("No stored Slack token at {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    match serde_json::from_str(&contents) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            warn!(
+                "Ignoring unreadable Slack token store {}: {}",
+                path.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Writes the token atomically, readable only by the current user.
+pub fn save(path: &Path, state: &TokenState) -> Result<(), SlackErrors> {
+    let to_error = |e: std::io::Error| SlackErrors::UnableToStoreToken {
+        path: path.display().to_string(),
+        source: e,
+    };
+
+    let tmp_path = path.with_extension("tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp_path)
+        .map_err(to_error)?;
+    file.write_all(&serde_json::to_vec(state).unwrap())
+        .map_err(to_error)?;
+    file.sync_all().map_err(to_error)?;
+
+    fs::rename(&tmp_path, path).map_err(to_error)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
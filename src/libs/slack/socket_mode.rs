@@ -0,0 +1,258 @@
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use derivative::Derivative;
+use futures::{SinkExt, StreamExt};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::ClientConfig;
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tracing::{debug, info, warn};
+
+use super::models::{User, Usergroup};
+use super::{GroupId, SlackClient, SlackClientConfig, SlackUser, SlackUserId, UserId};
+use crate::error::SlackErrors;
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+type Socket = WebSocketStream<TlsStream<TcpStream>>;
+
+/// A change to the cache carried by a Slack event.
+#[derive(Debug)]
+pub enum CacheEvent {
+    /// A user joined or changed their profile.
+    UserChanged(SlackUser),
+    /// A user was deactivated, or is a bot, so doesn't belong in the cache.
+    UserRemoved(UserId),
+    /// A group was created, renamed or had its members replaced. `users` is `None` when the event
+    /// didn't list them.
+    GroupChanged {
+        id: GroupId,
+        name: String,
+        users: Option<BTreeSet<SlackUserId>>,
+    },
+    /// A group was disabled.
+    GroupRemoved(GroupId),
+    /// Users were added to or removed from a group.
+    GroupMembersChanged {
+        id: GroupId,
+        added: Vec<UserId>,
+        removed: Vec<UserId>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct MembersChanged {
+    subteam_id: GroupId,
+    #[serde(default)]
+    added_users: Vec<UserId>,
+    #[serde(default)]
+    removed_users: Vec<UserId>,
+}
+
+impl CacheEvent {
+    /// The change `event` makes to the cache, if it's one of the user or group events it follows.
+    pub fn from_event(event: &Value) -> Option<Self> {
+        let field = |name: &str| event.get(name).cloned().unwrap_or(Value::Null);
+
+        match event.get("type")?.as_str()? {
+            "team_join" | "user_change" => {
+                let user: User = serde_json::from_value(field("user")).ok()?;
+                if user.deleted != Some(false) || user.is_bot != Some(false) {
+                    return user.id.map(CacheEvent::UserRemoved);
+                }
+                match SlackUser::new(user) {
+                    Ok(user) => Some(CacheEvent::UserChanged(user)),
+                    Err(e) => {
+                        warn!("Ignoring user event: {}", e);
+                        None
+                    }
+                }
+            }
+            "subteam_created" | "subteam_updated" => {
+                let group: Usergroup = serde_json::from_value(field("subteam")).ok()?;
+                let id = group.id?;
+                if group.date_delete.unwrap_or(0) > 0 {
+                    return Some(CacheEvent::GroupRemoved(id));
+                }
+                Some(CacheEvent::GroupChanged {
+                    id,
+                    name: group.name?,
+                    users: group
+                        .users
+                        .map(|users| users.into_iter().map(|id| SlackUserId { id }).collect()),
+                })
+            }
+            "subteam_members_changed" => {
+                let change: MembersChanged = serde_json::from_value(event.clone()).ok()?;
+                Some(CacheEvent::GroupMembersChanged {
+                    id: change.subteam_id,
+                    added: change.added_users,
+                    removed: change.removed_users,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Message Slack sends over a Socket Mode connection.
+///
+/// See https://api.slack.com/apis/connections/socket-implement
+#[derive(Debug, Deserialize)]
+struct Envelope {
+    #[serde(rename = "type")]
+    kind: String,
+    envelope_id: Option<String>,
+    #[serde(default)]
+    payload: Value,
+    reason: Option<String>,
+}
+
+/// Receives events over Slack's Socket Mode, which needs no public endpoint.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct SocketModeClient {
+    client: SlackClient,
+    #[derivative(Debug = "ignore")]
+    tls: TlsConnector,
+    connect_timeout: Duration,
+}
+
+impl SocketModeClient {
+    /// `app_token` is an app-level token (`xapp-...`) with the `connections:write` scope.
+    pub fn new(app_token: &str, config: &SlackClientConfig) -> Result<Self, SlackErrors> {
+        let mut tls = ClientConfig::new();
+        tls.root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+
+        Ok(Self {
+            client: SlackClient::new(app_token, config)?,
+            tls: TlsConnector::from(Arc::new(tls)),
+            connect_timeout: config.connect_timeout,
+        })
+    }
+
+    /// Passes each event Slack sends to `handle`, reconnecting whenever Slack asks to or the
+    /// connection drops. Only returns if Slack refuses to open a connection.
+    pub async fn run<F, Fut>(&self, mut handle: F) -> Result<(), SlackErrors>
+    where
+        F: FnMut(Value) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut delay = MIN_RECONNECT_DELAY;
+        loop {
+            let started = Instant::now();
+            match self.session(&mut handle).await {
+                Ok(()) => {
+                    debug!("Reconnecting to Slack Socket Mode");
+                    delay = MIN_RECONNECT_DELAY;
+                }
+                Err(e @ SlackErrors::Api { .. }) => return Err(e),
+                Err(e) => {
+                    if started.elapsed() > MAX_RECONNECT_DELAY {
+                        delay = MIN_RECONNECT_DELAY;
+                    }
+                    if let SlackErrors::RateLimited { retry_after, .. } = &e {
+                        delay = delay.max(Duration::from_secs(*retry_after));
+                    }
+                    warn!(
+                        "Lost Slack Socket Mode connection, reconnecting in {}s. Error: {}",
+                        delay.as_secs(),
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                }
+            }
+        }
+    }
+
+    /// Handles messages on one connection until Slack closes it or asks for a new one.
+    async fn session<F, Fut>(&self, handle: &mut F) -> Result<(), SlackErrors>
+    where
+        F: FnMut(Value) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let url = self.client.apps_connections_open().await?;
+        let mut socket = self.connect(&url).await.map_err(socket_error)?;
+
+        while let Some(message) = socket.next().await {
+            let text = match message.map_err(socket_error)? {
+                Message::Text(text) => text,
+                Message::Close(_) => return Ok(()),
+                _ => continue,
+            };
+            let envelope: Envelope = match serde_json::from_str(&text) {
+                Ok(envelope) => envelope,
+                Err(e) => {
+                    warn!("Ignoring malformed Socket Mode message. Error: {}", e);
+                    continue;
+                }
+            };
+
+            // Slack retries anything that isn't acknowledged within 3 seconds, so ack first.
+            if let Some(envelope_id) = &envelope.envelope_id {
+                let ack = json!({ "envelope_id": envelope_id }).to_string();
+                socket
+                    .send(Message::Text(ack))
+                    .await
+                    .map_err(socket_error)?;
+            }
+
+            match envelope.kind.as_str() {
+                "hello" => info!("Connected to Slack Socket Mode"),
+                "disconnect" => {
+                    debug!(
+                        "Slack asked to reconnect: {}",
+                        envelope.reason.as_deref().unwrap_or_default()
+                    );
+                    return Ok(());
+                }
+                "events_api" => {
+                    if let Some(event) = envelope.payload.get("event") {
+                        handle(event.clone()).await;
+                    }
+                }
+                other => debug!("Ignoring Socket Mode message {}", other),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn connect(&self, url: &str) -> anyhow::Result<Socket> {
+        let parsed = Url::parse(url)?;
+        let host = parsed
+            .host_str()
+            .ok_or_else(|| anyhow!("Socket Mode URL has no host"))?;
+        let port = parsed.port_or_known_default().unwrap_or(443);
+        let domain = DNSNameRef::try_from_ascii_str(host)
+            .map_err(|_| anyhow!("Socket Mode host {} is not a valid DNS name", host))?;
+
+        let connect = async {
+            let tcp = TcpStream::connect((host, port)).await?;
+            let tls = self.tls.connect(domain, tcp).await?;
+            let (socket, _) = tokio_tungstenite::client_async(url, tls).await?;
+            Ok(socket)
+        };
+
+        tokio::time::timeout(self.connect_timeout, connect)
+            .await
+            .map_err(|_| anyhow!("Timed out connecting to {}", host))?
+    }
+}
+
+fn socket_error(e: impl Into<anyhow::Error>) -> SlackErrors {
+    SlackErrors::SocketMode { source: e.into() }
+}
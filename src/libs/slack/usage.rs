@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::SlackErrors;
+
+/// Counts the Slack API calls made, by method, against an optional budget. Slack's rate limits
+/// are per method and shared with everything else using the token, so this is how much of them
+/// a sync used up.
+#[derive(Debug, Default)]
+pub struct ApiUsage {
+    budget: Option<u64>,
+    calls: Mutex<BTreeMap<String, u64>>,
+}
+
+/// How many calls were made to each method, as of when it was taken.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ApiUsageSummary {
+    pub total: u64,
+    pub budget: Option<u64>,
+    pub methods: BTreeMap<String, u64>,
+}
+
+impl ApiUsage {
+    pub fn new(budget: Option<u64>) -> Self {
+        Self {
+            budget,
+            calls: Mutex::default(),
+        }
+    }
+
+    /// Counts a call to `method`, or refuses it once the budget has been spent. Refused calls
+    /// aren't made, so they aren't counted.
+    pub fn record(&self, method: &str) -> Result<(), SlackErrors> {
+        let mut calls = match self.calls.lock() {
+            Ok(calls) => calls,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some(budget) = self.budget {
+            if calls.values().sum::<u64>() >= budget {
+                return Err(SlackErrors::BudgetExceeded {
+                    method: method.to_owned(),
+                    budget,
+                });
+            }
+        }
+
+        *calls.entry(method.to_owned()).or_default() += 1;
+        Ok(())
+    }
+
+    pub fn summary(&self) -> ApiUsageSummary {
+        let methods = match self.calls.lock() {
+            Ok(calls) => calls.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+
+        ApiUsageSummary {
+            total: methods.values().sum(),
+            budget: self.budget,
+            methods,
+        }
+    }
+}
+
+impl ApiUsageSummary {
+    /// The calls as `users.list 12, usergroups.list 1`, for logging.
+    pub fn describe(&self) -> String {
+        self.methods
+            .iter()
+            .map(|(method, calls)| format!("{} {}", method, calls))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use super::slack::{SlackUser, SlackUserGroup};
+use super::summary::DirectorySummary;
+
+/// A staged sync that wasn't committed, kept so it's clear why the cache still has the users of
+/// an earlier sync.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncFailure {
+    /// When the sync gave up, in seconds since the epoch.
+    pub at: u64,
+    /// How many users were staged.
+    pub users: u64,
+    /// How many groups were fetched along with them.
+    #[serde(default)]
+    pub groups: u64,
+    pub reason: String,
+}
+
+/// Checks a staged sync before it's committed: every user has what they're cached under, an id
+/// and an email, every group has an id, and there are at least `min_ratio` of the users and
+/// groups the `previous` sync cached. Without a `previous` sync, there only has to be a user.
+pub fn validate(
+    users: &BTreeSet<SlackUser>,
+    groups: &BTreeSet<SlackUserGroup>,
+    previous: Option<&DirectorySummary>,
+    min_ratio: f64,
+) -> Result<(), String> {
+    if users.is_empty() {
+        return Err("no users were staged".to_owned());
+    }
+    let incomplete = users
+        .iter()
+        .filter(|user| user.id.is_empty() || user.email.trim().is_empty())
+        .count();
+    if incomplete > 0 {
+        return Err(format!(
+            "{} of {} staged users are missing an id or email",
+            incomplete,
+            users.len()
+        ));
+    }
+    let incomplete = groups.iter().filter(|group| group.id.is_empty()).count();
+    if incomplete > 0 {
+        return Err(format!(
+            "{} of {} groups are missing an id",
+            incomplete,
+            groups.len()
+        ));
+    }
+
+    if let Some(previous) = previous {
+        if shrank(users.len(), previous.users, min_ratio) {
+            return Err(format!(
+                "{} users were staged, down from {} last sync",
+                users.len(),
+                previous.users
+            ));
+        }
+        if shrank(groups.len(), previous.groups, min_ratio) {
+            return Err(format!(
+                "{} groups were fetched, down from {} last sync",
+                groups.len(),
+                previous.groups
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn shrank(staged: usize, previous: u64, min_ratio: f64) -> bool {
+    (staged as f64) < previous as f64 * min_ratio
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn users(count: usize) -> BTreeSet<SlackUser> {
+        (0..count)
+            .map(|index| {
+                serde_json::from_value(json!({
+                    "id": format!("U{}", index),
+                    "name": "ann",
+                    "email": format!("ann{}@example.com", index)
+                }))
+                .unwrap()
+            })
+            .collect()
+    }
+
+    fn groups(count: usize) -> BTreeSet<SlackUserGroup> {
+        (0..count)
+            .map(|index| {
+                serde_json::from_value(
+                    json!({"id": format!("S{}", index), "name": "eng", "users": []}),
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn syncs_with_far_fewer_users_or_groups_than_the_last_are_rejected() {
+        let previous = DirectorySummary {
+            users: 10,
+            groups: 10,
+            ..DirectorySummary::default()
+        };
+
+        assert!(validate(&users(9), &groups(9), Some(&previous), 0.9).is_ok());
+        assert!(validate(&users(8), &groups(9), Some(&previous), 0.9).is_err());
+        assert!(validate(&users(9), &groups(8), Some(&previous), 0.9).is_err());
+        assert!(validate(&users(0), &groups(0), None, 0.9).is_err());
+        assert!(validate(&users(1), &groups(0), None, 0.9).is_ok());
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// How many of the largest keys `/admin/cache_stats` and `stats` list unless told otherwise.
+pub const DEFAULT_LARGEST: usize = 10;
+/// Most of the largest keys `/admin/cache_stats` lists.
+pub const MAX_LARGEST: usize = 1000;
+/// How often `/admin/cache_stats` may scan the whole cache, across every replica.
+pub const SCAN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Size of the cache in Redis, for capacity planning.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CacheStats {
+    pub keys: u64,
+    /// Bytes Redis reports using for the keys and their values, overhead included.
+    pub memory_bytes: u64,
+    pub prefixes: BTreeMap<String, PrefixStats>,
+    /// The keys using the most memory, largest first.
+    pub largest: Vec<KeySize>,
+    #[serde(skip)]
+    largest_limit: usize,
+}
+
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PrefixStats {
+    pub keys: u64,
+    pub memory_bytes: u64,
+    /// Keys that expire. The rest are kept until something deletes them.
+    pub expiring_keys: u64,
+    /// Average time the expiring keys have left, or `None` if none of them expire.
+    pub average_ttl_seconds: Option<u64>,
+    #[serde(skip)]
+    ttl_seconds_total: u64,
+}
+
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Serialize)]
+pub struct KeySize {
+    pub key: String,
+    pub memory_bytes: u64,
+}
+
+impl CacheStats {
+    pub fn new(largest_limit: usize) -> Self {
+        Self {
+            largest_limit,
+            ..Self::default()
+        }
+    }
+
+    /// Counts `key`, using `memory_bytes` and expiring in `ttl_seconds`. Negative TTLs are the
+    /// ones Redis gives keys without an expiry.
+    pub fn add(&mut self, key: &str, memory_bytes: u64, ttl_seconds: i64) {
+        self.keys += 1;
+        self.memory_bytes += memory_bytes;
+
+        let prefix = self.prefixes.entry(key_prefix(key).to_owned()).or_default();
+        prefix.keys += 1;
+        prefix.memory_bytes += memory_bytes;
+        if ttl_seconds >= 0 {
+            prefix.expiring_keys += 1;
+            prefix.ttl_seconds_total += ttl_seconds as u64;
+            prefix.average_ttl_seconds = Some(prefix.ttl_seconds_total / prefix.expiring_keys);
+        }
+
+        if self.largest.len() < self.largest_limit
+            || self
+                .largest
+                .last()
+                .map_or(false, |smallest| smallest.memory_bytes < memory_bytes)
+        {
+            self.largest.push(KeySize {
+                key: key.to_owned(),
+                memory_bytes,
+            });
+            self.largest
+                .sort_by(|a, b| b.memory_bytes.cmp(&a.memory_bytes));
+            self.largest.truncate(self.largest_limit);
+        }
+    }
+}
+
+/// The part of `key` naming what kind of entry it is, e.g. `user:id` for `user:id:U1234` and
+/// `last_sync` for itself. Only the first two parts count, as group names can contain `:`.
+fn key_prefix(key: &str) -> &str {
+    let mut separators = key.match_indices(':').map(|(index, _)| index);
+    match (separators.next(), separators.next()) {
+        (_, Some(second)) => &key[..second],
+        (Some(first), None) => &key[..first],
+        (None, None) => key,
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use super::slack::{SlackUser, SlackUserGroup};
+
+/// Upper bounds of the group size buckets `/slack/stats` reports, with a last bucket for the
+/// groups larger than all of them.
+const GROUP_SIZE_BUCKETS: &[usize] = &[0, 1, 5, 10, 25, 50, 100, 250, 500, 1000];
+
+/// Cached users by type, counted as a sync writes them. Bots and deleted users aren't cached,
+/// so they aren't counted either.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserCounts {
+    pub members: u64,
+    pub guests: u64,
+    /// Users outside `--email-domain-allowlist` that are cached anyway. They're also counted as
+    /// members or guests.
+    pub external: u64,
+}
+
+impl UserCounts {
+    pub fn add(&mut self, user: &SlackUser) {
+        if user.guest {
+            self.guests += 1;
+        } else {
+            self.members += 1;
+        }
+        if user.external {
+            self.external += 1;
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.members + self.guests
+    }
+}
+
+/// How many groups have between `min` and `max` members, or `min` or more without a `max`.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupSizeBucket {
+    pub min: usize,
+    pub max: Option<usize>,
+    pub groups: u64,
+}
+
+/// The headline numbers of the cache, worked out by the updater at the end of each sync so
+/// `/slack/stats` can answer without scanning the cache.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DirectorySummary {
+    pub users: u64,
+    pub groups: u64,
+    pub users_by_type: UserCounts,
+    pub group_sizes: Vec<GroupSizeBucket>,
+    pub largest_group: usize,
+    pub mean_group_size: f64,
+    /// When the sync these numbers are from finished, in seconds since the epoch. Changes
+    /// applied by `socket-listener` since then aren't counted.
+    pub as_of: u64,
+}
+
+impl DirectorySummary {
+    pub fn new(users: &UserCounts, groups: &BTreeSet<SlackUserGroup>, as_of: u64) -> Self {
+        let mut group_sizes: Vec<GroupSizeBucket> = GROUP_SIZE_BUCKETS
+            .iter()
+            .enumerate()
+            .map(|(index, max)| GroupSizeBucket {
+                min: index
+                    .checked_sub(1)
+                    .map_or(0, |previous| GROUP_SIZE_BUCKETS[previous] + 1),
+                max: Some(*max),
+                groups: 0,
+            })
+            .collect();
+        group_sizes.push(GroupSizeBucket {
+            min: GROUP_SIZE_BUCKETS.last().map_or(0, |max| max + 1),
+            max: None,
+            groups: 0,
+        });
+
+        let mut members = 0;
+        let mut largest_group = 0;
+        for group in groups {
+            let size = group.users.len();
+            members += size;
+            largest_group = largest_group.max(size);
+            let bucket = GROUP_SIZE_BUCKETS
+                .iter()
+                .position(|max| size <= *max)
+                .unwrap_or_else(|| GROUP_SIZE_BUCKETS.len());
+            group_sizes[bucket].groups += 1;
+        }
+
+        Self {
+            users: users.total(),
+            groups: groups.len() as u64,
+            users_by_type: users.clone(),
+            group_sizes,
+            largest_group,
+            mean_group_size: if groups.is_empty() {
+                0.0
+            } else {
+                members as f64 / groups.len() as f64
+            },
+            as_of,
+        }
+    }
+}
+
+/// Whether a sync that fetched `fetched` users found fewer than `min_ratio` of the users the
+/// `previous` sync cached. A `min_ratio` of 0 never counts as shrinking.
+pub fn shrank(previous: &DirectorySummary, fetched: u64, min_ratio: f64) -> bool {
+    (fetched as f64) < previous.users as f64 * min_ratio
+}
+
+/// Parses `--min-user-ratio`, a fraction from 0 to 1.
+pub fn parse_min_ratio(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(ratio) if (0.0..=1.0).contains(&ratio) => Ok(ratio),
+        Ok(_) => Err("must be from 0 to 1".to_owned()),
+        Err(e) => Err(e.to_string()),
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::slack::{GroupId, SlackUserGroup, SlackUserId, UserId};
+use super::{RedisResponse, RedisServer};
+use crate::error::SyntheticGroupErrors;
+
+/// Start of the ids given to synthetic groups. They're still valid Slack group ids, so every
+/// group route takes them.
+const SYNTHETIC_ID_PREFIX: &str = "SYN";
+
+/// Random characters after `SYNTHETIC_ID_PREFIX`.
+const SYNTHETIC_ID_LENGTH: usize = 9;
+
+/// Longest name a synthetic group can have, the same as a Slack handle.
+const MAX_NAME_LENGTH: usize = 80;
+
+/// The Slack group `--write-back-groups` made for each synthetic group, by synthetic group id.
+pub type Mirrors = BTreeMap<GroupId, GroupId>;
+
+/// Body of `POST /admin/groups`.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateGroupRequest {
+    pub name: String,
+    #[serde(default)]
+    pub users: BTreeSet<UserId>,
+}
+
+/// A change to a synthetic group. With authentication enabled one admin asks for it and another
+/// has to approve it before it's made.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupChange {
+    pub id: String,
+    pub requested_by: String,
+    pub requested_at: u64,
+    #[serde(flatten)]
+    pub action: GroupAction,
+}
+
+#[serde(tag = "action", rename_all = "kebab-case")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum GroupAction {
+    Create {
+        group: GroupId,
+        name: String,
+        users: BTreeSet<UserId>,
+    },
+    AddMember {
+        group: GroupId,
+        user: UserId,
+    },
+    RemoveMember {
+        group: GroupId,
+        user: UserId,
+    },
+    Delete {
+        group: GroupId,
+    },
+}
+
+impl GroupChange {
+    pub fn new(requested_by: &str, requested_at: u64, action: GroupAction) -> Self {
+        Self {
+            id: hex::encode(rand::random::<[u8; 8]>()),
+            requested_by: requested_by.to_owned(),
+            requested_at,
+            action,
+        }
+    }
+}
+
+/// A new id for a synthetic group, like `SYN4KQ2Z8X1M`.
+pub fn new_group_id() -> GroupId {
+    let suffix: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(SYNTHETIC_ID_LENGTH)
+        .map(|c| (c as char).to_ascii_uppercase())
+        .collect();
+    GroupId::unchecked(format!("{}{}", SYNTHETIC_ID_PREFIX, suffix))
+}
+
+/// Checks a synthetic group's name could be a Slack handle, so the two never read differently.
+pub fn parse_name(name: &str) -> Result<String, String> {
+    let name = name.trim().trim_start_matches('@');
+    if name.is_empty() {
+        return Err("name can't be empty".to_owned());
+    }
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(format!(
+            "name can be at most {} characters",
+            MAX_NAME_LENGTH
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err(format!(
+            "`{}` can only have letters, digits, `-`, `_` and `.`",
+            name
+        ));
+    }
+
+    Ok(name.to_lowercase())
+}
+
+/// Makes `action`, returning the group as it is afterwards, or `None` once it's deleted.
+pub async fn apply(
+    redis_server: &RedisServer,
+    action: &GroupAction,
+) -> Result<Option<SlackUserGroup>, SyntheticGroupErrors> {
+    let mut group = match action {
+        GroupAction::Create { group, name, users } => {
+            if let Some(taken) = name_taken(redis_server, name).await? {
+                return Err(SyntheticGroupErrors::NameTaken { name: taken });
+            }
+            SlackUserGroup {
+                name: name.clone(),
+                id: group.clone(),
+                users: users
+                    .iter()
+                    .map(|id| SlackUserId { id: id.clone() })
+                    .collect(),
+                previous_names: BTreeSet::new(),
+                synthetic: true,
+            }
+        }
+        GroupAction::AddMember { group, .. }
+        | GroupAction::RemoveMember { group, .. }
+        | GroupAction::Delete { group } => redis_server
+            .get_synthetic_group(group)
+            .await?
+            .ok_or_else(|| SyntheticGroupErrors::UnknownGroup {
+                id: group.to_string(),
+            })?,
+    };
+
+    match action {
+        GroupAction::Create { .. } => {}
+        GroupAction::AddMember { user, .. } => {
+            group.users.insert(SlackUserId { id: user.clone() });
+        }
+        GroupAction::RemoveMember { user, .. } => {
+            group.users.remove(&SlackUserId { id: user.clone() });
+        }
+        GroupAction::Delete { group } => {
+            redis_server.remove_synthetic_group(group).await?;
+            return Ok(None);
+        }
+    }
+
+    redis_server.set_synthetic_group(&group).await?;
+    Ok(Some(group))
+}
+
+/// The name of the group `name` belongs to, if one does.
+pub async fn name_taken(
+    redis_server: &RedisServer,
+    name: &str,
+) -> Result<Option<String>, SyntheticGroupErrors> {
+    let synthetic = redis_server.get_synthetic_groups().await?;
+    if let Some(group) = synthetic.iter().find(|group| group.name == name) {
+        return Ok(Some(group.name.clone()));
+    }
+
+    match redis_server.get_user_group_by_name(name.to_owned()).await {
+        RedisResponse::Ok(group) => Ok(Some(group.name)),
+        RedisResponse::Missing => Ok(None),
+        RedisResponse::Err(e) => Err(e.into()),
+    }
+}
+
+/// Adds the synthetic groups to the groups a sync fetched, in place of the Slack groups written
+/// back for them. Ones whose id or name another fetched group already has are left out.
+pub fn merge(
+    groups: &mut BTreeSet<SlackUserGroup>,
+    synthetic: Vec<SlackUserGroup>,
+    mirrors: &Mirrors,
+) -> usize {
+    let written_back: BTreeSet<&GroupId> = mirrors.values().collect();
+    let fetched = std::mem::take(groups);
+    groups.extend(
+        fetched
+            .into_iter()
+            .filter(|group| !written_back.contains(&group.id)),
+    );
+
+    let names: BTreeSet<String> = groups
+        .iter()
+        .map(|group| group.name.to_lowercase())
+        .collect();
+    let ids: BTreeSet<GroupId> = groups.iter().map(|group| group.id.clone()).collect();
+
+    let mut merged = 0;
+    for group in synthetic {
+        if ids.contains(&group.id) || names.contains(&group.name.to_lowercase()) {
+            warn!(
+                "Synthetic group {} clashes with a fetched group named {}, leaving it out",
+                group.id, group.name
+            );
+            continue;
+        }
+        groups.insert(group);
+        merged += 1;
+    }
+    merged
+}
@@ -0,0 +1,152 @@
+use std::env;
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tracing::{debug, info, warn};
+
+static WATCHDOG_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// A part of the process that has to keep making progress for the watchdog to be pinged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    Updater,
+    Web,
+}
+
+impl Component {
+    const ALL: [Component; 2] = [Component::Updater, Component::Web];
+
+    fn name(self) -> &'static str {
+        match self {
+            Component::Updater => "updater",
+            Component::Web => "web server",
+        }
+    }
+}
+
+/// A component the watchdog doesn't wait on, as it never started it.
+const NOT_WATCHED: u64 = 0;
+/// A component that's idle on purpose, like the updater between syncs.
+const WAITING: u64 = u64::MAX;
+
+/// When each component last made progress, in milliseconds since the epoch, by `Component`.
+static LAST_ALIVE: [AtomicU64; 2] = [AtomicU64::new(NOT_WATCHED), AtomicU64::new(NOT_WATCHED)];
+
+/// Tells systemd the service has started, for units with `Type=notify`.
+pub fn ready() {
+    notify("READY=1");
+}
+
+/// Sets the status `systemctl status` shows for the unit.
+pub fn status(status: &str) {
+    notify(&format!("STATUS={}", status));
+}
+
+/// When the unit has `WatchdogSec=`, has `component` watched and returns how often it should
+/// check in with `alive`. Systemd is pinged at that interval only while every watched component
+/// has made progress within the watchdog timeout, so it restarts the process when one hangs. Only
+/// the first call starts pinging, as `all-in-one` runs components that each call it.
+pub fn start_watchdog(component: Component) -> Option<Duration> {
+    let timeout = watchdog_timeout()?;
+    let interval = timeout / 2;
+    LAST_ALIVE[component as usize].store(now_millis(), Ordering::SeqCst);
+    if WATCHDOG_STARTED.swap(true, Ordering::SeqCst) {
+        return Some(interval);
+    }
+
+    info!(
+        "Pinging the systemd watchdog every {}ms",
+        interval.as_millis()
+    );
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            match stalled(timeout) {
+                None => notify("WATCHDOG=1"),
+                Some(component) => warn!(
+                    "Not pinging the systemd watchdog, the {} hasn't made progress in over {}ms",
+                    component.name(),
+                    timeout.as_millis()
+                ),
+            }
+        }
+    });
+    Some(interval)
+}
+
+/// Records that `component` is making progress. Does nothing unless it started the watchdog.
+pub fn alive(component: Component) {
+    set_last_alive(component, now_millis());
+}
+
+/// Records that `component` is idle on purpose until it's next `alive`, so the watchdog isn't
+/// held back while it waits.
+pub fn waiting(component: Component) {
+    set_last_alive(component, WAITING);
+}
+
+fn set_last_alive(component: Component, value: u64) {
+    let last_alive = &LAST_ALIVE[component as usize];
+    if last_alive.load(Ordering::SeqCst) != NOT_WATCHED {
+        last_alive.store(value, Ordering::SeqCst);
+    }
+}
+
+/// The first watched component that hasn't made progress within `timeout`.
+fn stalled(timeout: Duration) -> Option<Component> {
+    let now = now_millis();
+    Component::ALL.iter().copied().find(|component| {
+        match LAST_ALIVE[*component as usize].load(Ordering::SeqCst) {
+            NOT_WATCHED | WAITING => false,
+            last_alive => now.saturating_sub(last_alive) > timeout.as_millis() as u64,
+        }
+    })
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// The watchdog timeout systemd gave the process, see sd_watchdog_enabled(3). It's passed on to
+/// children too, so it only counts when `WATCHDOG_PID` is unset or names this process.
+fn watchdog_timeout() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+
+    env::var("WATCHDOG_USEC")
+        .ok()?
+        .parse::<u64>()
+        .ok()
+        .filter(|usec| *usec > 0)
+        .map(Duration::from_micros)
+}
+
+/// Sends `state` to the socket in `NOTIFY_SOCKET`, see sd_notify(3). Does nothing when not run by
+/// systemd. Only path sockets are supported, which is what systemd hands services.
+fn notify(state: &str) {
+    let socket = match env::var("NOTIFY_SOCKET") {
+        Ok(socket) => socket,
+        Err(_) => return,
+    };
+    if socket.starts_with('@') {
+        debug!(
+            "Not notifying systemd, abstract sockets like {} aren't supported",
+            socket
+        );
+        return;
+    }
+
+    let sent =
+        UnixDatagram::unbound().and_then(|datagram| datagram.send_to(state.as_bytes(), &socket));
+    if let Err(e) = sent {
+        warn!("Unable to notify systemd of {}. Error: {}", state, e);
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use derivative::Derivative;
+use rhai::serde::{from_dynamic, to_dynamic};
+use rhai::{Dynamic, Engine, Scope, AST};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use super::{SlackUser, SlackUserGroup};
+use crate::error::TransformErrors;
+
+/// Operations a script may run for one record, so a runaway loop can't stall a sync.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// A Rhai script users and groups are run through before they're cached.
+///
+/// The script gets the record as `record`, with the same fields the API returns, and `kind` as
+/// `"user"` or `"group"`. It changes `record` in place, or sets it to `()` to leave the record
+/// out, e.g.
+///
+/// ```text
+/// if kind == "user" {
+///     record.name = record.email.split("@")[0];
+///     record.remove("tz");
+/// }
+/// ```
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct Transform {
+    path: String,
+    #[derivative(Debug = "ignore")]
+    engine: Engine,
+    #[derivative(Debug = "ignore")]
+    ast: AST,
+}
+
+impl Transform {
+    pub fn load(path: &Path) -> Result<Self, TransformErrors> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+
+        let ast = engine.compile_file(path.to_path_buf()).map_err(|e| {
+            TransformErrors::UnableToCompile {
+                path: path.display().to_string(),
+                message: e.to_string(),
+            }
+        })?;
+
+        info!("Loaded transform script {}", path.display());
+        Ok(Self {
+            path: path.display().to_string(),
+            engine,
+            ast,
+        })
+    }
+
+    pub fn users(&self, users: BTreeSet<SlackUser>) -> BTreeSet<SlackUser> {
+        self.apply("user", users)
+    }
+
+    pub fn groups(&self, groups: BTreeSet<SlackUserGroup>) -> BTreeSet<SlackUserGroup> {
+        self.apply("group", groups)
+    }
+
+    /// Records the script fails on are left out rather than cached unchanged, as the script may
+    /// be what keeps PII out of the cache.
+    fn apply<T>(&self, kind: &str, records: BTreeSet<T>) -> BTreeSet<T>
+    where
+        T: Serialize + DeserializeOwned + Ord,
+    {
+        records
+            .into_iter()
+            .filter_map(|record| match self.run(kind, &record) {
+                Ok(transformed) => transformed,
+                Err(message) => {
+                    warn!(
+                        "Leaving out a {}, transform script {} failed. Error: {}",
+                        kind, self.path, message
+                    );
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn run<T>(&self, kind: &str, record: &T) -> Result<Option<T>, String>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        // Going through JSON turns map keys into strings, which is all Rhai object maps take.
+        let value = serde_json::to_value(record).map_err(|e| e.to_string())?;
+        let mut scope = Scope::new();
+        scope.push("kind", kind.to_owned());
+        scope.push_dynamic("record", to_dynamic(value).map_err(|e| e.to_string())?);
+
+        self.engine
+            .consume_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| e.to_string())?;
+
+        let result: Dynamic = scope.get_value("record").unwrap_or_default();
+        if result.is::<()>() {
+            return Ok(None);
+        }
+        let value: serde_json::Value = from_dynamic(&result).map_err(|e| e.to_string())?;
+        serde_json::from_value(value)
+            .map(Some)
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::BTreeSet;
+
+use super::key_layout::NameFolding;
+use super::slack::{SlackUser, UserId};
+
+/// How names and queries are folded. The index has no keys from before folding to stay
+/// compatible with, so it always ignores accents, whatever `--group-name-folding` says.
+const FOLDING: NameFolding = NameFolding::Unicode;
+
+/// Sits between the term and the user id in an index entry. It sorts before every character, so
+/// users whose name is exactly the query come first.
+const SEPARATOR: char = '\0';
+
+/// The entries the typeahead index has for `user`: each of their names from every word on, so
+/// `smi` finds `John Smith`, and with `with_email` their email.
+pub fn entries(user: &SlackUser, with_email: bool) -> BTreeSet<String> {
+    let mut terms = BTreeSet::new();
+    for name in names(user) {
+        let words: Vec<&str> = name.split(' ').collect();
+        for start in 0..words.len() {
+            terms.insert(words[start..].join(" "));
+        }
+    }
+    if with_email {
+        terms.insert(user.email.normalized().to_string());
+    }
+
+    terms
+        .into_iter()
+        .map(|term| format!("{}{}{}", term, SEPARATOR, user.id))
+        .collect()
+}
+
+/// The user's real and display names, folded, with words separated by single spaces.
+pub fn names(user: &SlackUser) -> Vec<String> {
+    std::iter::once(&user.name)
+        .chain(user.display_name.as_ref())
+        .filter_map(|name| query(name))
+        .collect()
+}
+
+/// `query` folded the way index entries are, or `None` when there's nothing left to look up.
+pub fn query(query: &str) -> Option<String> {
+    let folded = FOLDING.fold(query);
+    let query = folded.split_whitespace().collect::<Vec<_>>().join(" ");
+    if query.is_empty() {
+        None
+    } else {
+        Some(query)
+    }
+}
+
+/// The name or email an index entry is for.
+pub fn term(entry: &str) -> &str {
+    entry.splitn(2, SEPARATOR).next().unwrap_or_default()
+}
+
+/// Whether an index entry is for an email rather than a name, going by the `@` only emails have.
+pub fn is_email(entry: &str) -> bool {
+    term(entry).contains('@')
+}
+
+/// The user an index entry is for. Ids aren't checked, as users from other directories have ids
+/// Slack wouldn't give out.
+pub fn user_id(entry: &str) -> Option<UserId> {
+    match entry.rsplitn(2, SEPARATOR).next() {
+        Some(id) if !id.is_empty() => Some(UserId::unchecked(id.to_owned())),
+        _ => None,
+    }
+}
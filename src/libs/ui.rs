@@ -0,0 +1,17 @@
+use std::borrow::Cow;
+
+use rust_embed::RustEmbed;
+
+/// The browse UI's files. Release builds carry them in the binary, while debug builds read them
+/// from `ui/`, so changes show up without rebuilding.
+#[derive(RustEmbed)]
+#[folder = "ui/"]
+struct Assets;
+
+/// The UI file at `path`, with the page itself at the root, along with its content type.
+pub fn asset(path: &str) -> Option<(Cow<'static, [u8]>, String)> {
+    let path = if path.is_empty() { "index.html" } else { path };
+    let contents = Assets::get(path)?;
+    let content_type = mime_guess::from_path(path).first_or_octet_stream();
+    Some((contents, content_type.to_string()))
+}
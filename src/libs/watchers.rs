@@ -0,0 +1,86 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Deserialize;
+
+use super::slack::{GroupId, SlackUserGroup, UserId};
+
+/// Longest channel `/admin/user_group/id/{id}/watchers` accepts. Slack's own names stop at 80.
+const MAX_CHANNEL_LENGTH: usize = 80;
+
+/// Body of `PUT /admin/user_group/id/{id}/watchers`.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Deserialize)]
+pub struct WatcherRequest {
+    pub channel: String,
+}
+
+/// Channels told about membership changes, by the group they watch.
+pub type GroupWatchers = BTreeMap<GroupId, BTreeSet<String>>;
+
+/// Who joined and left a watched group in a sync.
+#[derive(Debug, Clone)]
+pub struct MembershipChange<'a> {
+    pub group: &'a SlackUserGroup,
+    pub added: Vec<&'a UserId>,
+    pub removed: Vec<&'a UserId>,
+}
+
+/// The membership changes of the groups in `watchers` between `previous` and `current`. Groups
+/// that weren't cached before, or are gone now, have no members to compare and are left out.
+pub fn membership_changes<'a>(
+    watchers: &GroupWatchers,
+    previous: &'a [SlackUserGroup],
+    current: &'a BTreeSet<SlackUserGroup>,
+) -> Vec<MembershipChange<'a>> {
+    let previous: BTreeMap<&GroupId, &SlackUserGroup> =
+        previous.iter().map(|group| (&group.id, group)).collect();
+
+    current
+        .iter()
+        .filter(|group| watchers.contains_key(&group.id))
+        .filter_map(|group| {
+            let before = previous.get(&group.id)?;
+            let change = MembershipChange {
+                group,
+                added: group
+                    .users
+                    .difference(&before.users)
+                    .map(|user| &user.id)
+                    .collect(),
+                removed: before
+                    .users
+                    .difference(&group.users)
+                    .map(|user| &user.id)
+                    .collect(),
+            };
+            if change.added.is_empty() && change.removed.is_empty() {
+                None
+            } else {
+                Some(change)
+            }
+        })
+        .collect()
+}
+
+/// Checks a channel to post to looks like one: a channel id like `C0123ABCD` or a name like
+/// `#security-audit`.
+pub fn parse_channel(channel: &str) -> Result<String, String> {
+    let channel = channel.trim();
+    if channel.is_empty() {
+        return Err("channel can't be empty".to_owned());
+    }
+    if channel.len() > MAX_CHANNEL_LENGTH {
+        return Err(format!(
+            "channel can be at most {} characters",
+            MAX_CHANNEL_LENGTH
+        ));
+    }
+    if channel
+        .chars()
+        .any(|c| c.is_whitespace() || c.is_control() || c == ':')
+    {
+        return Err(format!("`{}` isn't a channel", channel));
+    }
+
+    Ok(channel.to_owned())
+}
@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::directory::SlackDirectory;
+use super::slack::{GroupId, SlackUserGroup, UserId};
+use super::synthetic::Mirrors;
+use super::RedisServer;
+
+/// A change to Slack that writes a synthetic group back.
+#[serde(tag = "action", rename_all = "kebab-case")]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlannedChange {
+    /// No Slack group stands in for the synthetic group yet.
+    Create {
+        group: GroupId,
+        name: String,
+        users: BTreeSet<UserId>,
+    },
+    /// The members of the Slack group standing in for the synthetic group differ from its own.
+    Update {
+        group: GroupId,
+        slack_id: GroupId,
+        name: String,
+        added: BTreeSet<UserId>,
+        removed: BTreeSet<UserId>,
+    },
+}
+
+/// Changes saved by `plan` for `apply` to make.
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WriteBackPlan {
+    pub id: String,
+    pub created_at: u64,
+    pub changes: Vec<PlannedChange>,
+}
+
+impl WriteBackPlan {
+    pub fn new(created_at: u64, changes: Vec<PlannedChange>) -> Self {
+        Self {
+            id: hex::encode(rand::random::<[u8; 8]>()),
+            created_at,
+            changes,
+        }
+    }
+}
+
+impl PlannedChange {
+    pub fn group(&self) -> &GroupId {
+        match self {
+            PlannedChange::Create { group, .. } | PlannedChange::Update { group, .. } => group,
+        }
+    }
+
+    /// One line saying what the change does, with `+` for groups made, `~` for groups changed.
+    pub fn describe(&self) -> String {
+        match self {
+            PlannedChange::Create { group, name, users } => format!(
+                "+ create @{} for synthetic group {} with {} members{}",
+                name,
+                group,
+                users.len(),
+                listed(" ", users)
+            ),
+            PlannedChange::Update {
+                group,
+                slack_id,
+                name,
+                added,
+                removed,
+            } => format!(
+                "~ update @{} ({}) for synthetic group {}: {} added, {} removed{}{}",
+                name,
+                slack_id,
+                group,
+                added.len(),
+                removed.len(),
+                listed(" +", added),
+                listed(" -", removed)
+            ),
+        }
+    }
+}
+
+fn listed(prefix: &str, users: &BTreeSet<UserId>) -> String {
+    users
+        .iter()
+        .map(|user| format!("{}{}", prefix, user))
+        .collect()
+}
+
+/// What has to change in Slack for the Slack groups written back to match `synthetic`, given
+/// the groups `fetched` from Slack.
+pub fn plan(
+    synthetic: &[SlackUserGroup],
+    fetched: &BTreeSet<SlackUserGroup>,
+    mirrors: &Mirrors,
+) -> Vec<PlannedChange> {
+    let by_id: BTreeMap<&GroupId, &SlackUserGroup> =
+        fetched.iter().map(|group| (&group.id, group)).collect();
+    let names: BTreeSet<String> = fetched
+        .iter()
+        .map(|group| group.name.to_lowercase())
+        .collect();
+
+    let mut changes = Vec::new();
+    for group in synthetic {
+        let users = members(group);
+        match mirrors.get(&group.id) {
+            Some(slack_id) => match by_id.get(slack_id) {
+                Some(mirror) if mirror.users == group.users => {}
+                Some(_) if group.users.is_empty() => warn!(
+                    "Synthetic group {} has no members, which Slack group {} can't be left with",
+                    group.id, slack_id
+                ),
+                Some(mirror) => {
+                    let current = members(mirror);
+                    changes.push(PlannedChange::Update {
+                        group: group.id.clone(),
+                        slack_id: slack_id.clone(),
+                        name: group.name.clone(),
+                        added: users.difference(&current).cloned().collect(),
+                        removed: current.difference(&users).cloned().collect(),
+                    });
+                }
+                None => warn!(
+                    "Slack group {} written back for synthetic group {} is gone, not updating it",
+                    slack_id, group.id
+                ),
+            },
+            None if names.contains(&group.name.to_lowercase()) => warn!(
+                "A Slack group is already named {}, not writing back synthetic group {}",
+                group.name, group.id
+            ),
+            None => changes.push(PlannedChange::Create {
+                group: group.id.clone(),
+                name: group.name.clone(),
+                users,
+            }),
+        }
+    }
+    changes
+}
+
+/// Makes the changes in `plan` to the `synthetic` groups they were planned from, or with
+/// `dry_run` only logs them. A group that can't be written back is logged and left for next
+/// time, so one failure doesn't hold up the rest. Returns how many failed.
+pub async fn apply(
+    slack_api: &dyn SlackDirectory,
+    redis_server: &RedisServer,
+    synthetic: &[SlackUserGroup],
+    plan: &[PlannedChange],
+    dry_run: bool,
+) -> usize {
+    let by_id: BTreeMap<&GroupId, &SlackUserGroup> =
+        synthetic.iter().map(|group| (&group.id, group)).collect();
+
+    let mut failed = 0;
+    for change in plan {
+        if dry_run {
+            info!("Dry run, not making change: {}", change.describe());
+            continue;
+        }
+        let group = match by_id.get(change.group()) {
+            Some(group) => group,
+            None => continue,
+        };
+
+        let result = match change {
+            PlannedChange::Create { .. } => create(slack_api, redis_server, group).await,
+            PlannedChange::Update { slack_id, .. } => slack_api
+                .set_user_group_members(slack_id, &members(group).iter().collect::<Vec<_>>())
+                .await
+                .map_err(anyhow::Error::from),
+        };
+        match result {
+            Ok(()) => info!("Wrote back synthetic group {}", group.id),
+            Err(e) => {
+                warn!(
+                    "Unable to write back synthetic group {}. Error: {}",
+                    group.id, e
+                );
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
+
+async fn create(
+    slack_api: &dyn SlackDirectory,
+    redis_server: &RedisServer,
+    group: &SlackUserGroup,
+) -> anyhow::Result<()> {
+    let slack_id = slack_api.create_user_group(&group.name).await?;
+    // Saved before adding members, so a failure there is retried as an update rather than
+    // trying to create the group again.
+    redis_server
+        .set_synthetic_group_mirror(&group.id, &slack_id)
+        .await?;
+    if !group.users.is_empty() {
+        slack_api
+            .set_user_group_members(&slack_id, &members(group).iter().collect::<Vec<_>>())
+            .await?;
+    }
+    Ok(())
+}
+
+fn members(group: &SlackUserGroup) -> BTreeSet<UserId> {
+    group.users.iter().map(|user| user.id.clone()).collect()
+}
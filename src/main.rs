@@ -1,10 +1,36 @@
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono_tz::Tz;
 use clap::{ArgGroup, Clap};
+use cron::Schedule;
 use dotenv::dotenv;
+use slack_user_cache::{error, libs};
 use tracing::error;
+use warp::http::HeaderValue;
+
+use crate::error::DirectoryErrors;
+use crate::libs::access_log::AccessLogFormat;
+use crate::libs::alerting::Notifier;
+use crate::libs::build_info;
+use crate::libs::codec::ValueFormat;
+use crate::libs::concurrency::{self, RouteLimit};
+use crate::libs::directory::SourceKind;
+use crate::libs::email::{AliasRule, DomainAllowlist, ExternalUsers};
+use crate::libs::github::LoginSource;
+use crate::libs::google::GoogleDirectory;
+use crate::libs::key_layout::{self, KeyLayout, NameFolding};
+use crate::libs::kubernetes::{self, LockBackend};
+use crate::libs::paging;
+use crate::libs::progress::Progress;
+use crate::libs::redis::parse_key_prefix;
+use crate::libs::schedule::{parse_cron, parse_interval};
+use crate::libs::shadow;
+use crate::libs::summary;
+use crate::libs::EmailHasher;
 
 mod commands;
-mod error;
-mod libs;
 
 #[derive(Clap, Debug)]
 #[clap(group = ArgGroup::new("logging"))]
@@ -20,6 +46,10 @@ pub struct LoggingOpts {
     /// Disable everything but error logging
     #[clap(short, long, global(true), group = "logging")]
     pub error: bool,
+
+    /// Keep emails, names and cached records out of log output
+    #[clap(long, global(true), env = "REDACT_PII")]
+    pub redact_pii: bool,
 }
 
 impl LoggingOpts {
@@ -40,13 +70,185 @@ impl LoggingOpts {
     }
 }
 
+#[derive(Clap, Debug, Clone)]
+pub struct RedisOpts {
+    /// Address of the Redis Server. Repeat it, or separate addresses with commas, to shard keys
+    /// across several servers. Every command has to be given the same servers
+    #[clap(
+        long,
+        default_value = "redis://127.0.0.1/",
+        env = "REDIS_ADDRESS",
+        use_delimiter = true
+    )]
+    pub redis_address: Vec<String>,
+}
+
+#[derive(Clap, Debug, Clone)]
+pub struct PrivacyOpts {
+    /// Store emails only as salted SHA-256 hashes. Both `update-redis` and `web` need the same salt
+    #[clap(long, env = "EMAIL_HASH_SALT")]
+    pub email_hash_salt: Option<String>,
+}
+
+impl PrivacyOpts {
+    pub fn email_hasher(&self) -> Option<EmailHasher> {
+        self.email_hash_salt.as_deref().map(EmailHasher::new)
+    }
+}
+
+#[derive(Clap, Debug, Clone)]
+pub struct KeyOpts {
+    /// Prefix every key is kept under, e.g. `slack:`, so the cache can share a Redis with other
+    /// data. Every command has to be given the same prefix. `migrate` moves existing keys under it
+    #[clap(long, env = "KEY_PREFIX", parse(try_from_str = key_layout::parse_prefix))]
+    pub key_prefix: Option<String>,
+
+    /// Also write keys without `--key-prefix`, and fall back to them when a lookup misses, so
+    /// servers from before the prefix keep working alongside this one until the migration is done
+    #[clap(long, env = "DUAL_WRITE_LEGACY_KEYS")]
+    pub dual_write_legacy_keys: bool,
+
+    /// How group names are matched when looking groups up by name: `lowercase` ignores case,
+    /// `unicode` also ignores accents and Unicode compatibility forms, so `José` finds `jose`.
+    /// Every command has to be given the same folding. Lookups only find groups under a new one
+    /// once a sync has run with it
+    #[clap(long, default_value = "lowercase", env = "GROUP_NAME_FOLDING")]
+    pub group_name_folding: NameFolding,
+}
+
+impl KeyOpts {
+    pub fn layout(&self) -> KeyLayout {
+        KeyLayout::new(self.key_prefix.as_deref(), self.dual_write_legacy_keys)
+            .with_name_folding(self.group_name_folding)
+    }
+}
+
+#[derive(Clap, Debug)]
+pub struct DomainOpts {
+    /// Comma separated email domains users are cached for, e.g. `example.com,corp.example.com`.
+    /// Users with an email anywhere else are handled as `--external-users` says. Unset caches
+    /// everyone
+    #[clap(long, env = "EMAIL_DOMAIN_ALLOWLIST", use_delimiter = true)]
+    pub email_domain_allowlist: Vec<String>,
+
+    /// What to do with users outside `--email-domain-allowlist`: `exclude` leaves them out of the
+    /// cache, `flag` caches them with `external` set
+    #[clap(long, default_value = "exclude", env = "EXTERNAL_USERS")]
+    pub external_users: ExternalUsers,
+}
+
+impl DomainOpts {
+    pub fn allowlist(&self) -> DomainAllowlist {
+        DomainAllowlist::new(&self.email_domain_allowlist)
+    }
+}
+
+#[derive(Clap, Debug)]
+pub struct GoogleOpts {
+    /// Google service account key file, for the `google` source. The account needs domain-wide
+    /// delegation of the admin.directory.user.readonly and admin.directory.group.readonly scopes
+    #[clap(long, env = "GOOGLE_SERVICE_ACCOUNT_FILE")]
+    pub google_service_account_file: Option<PathBuf>,
+
+    /// Google Workspace admin the service account acts as
+    #[clap(long, env = "GOOGLE_ADMIN_EMAIL")]
+    pub google_admin_email: Option<String>,
+
+    /// Google Workspace customer id to list users and groups of
+    #[clap(long, default_value = "my_customer", env = "GOOGLE_CUSTOMER")]
+    pub google_customer: String,
+}
+
+impl GoogleOpts {
+    pub fn directory(&self) -> Result<GoogleDirectory, DirectoryErrors> {
+        let not_configured = |name: &str| DirectoryErrors::NotConfigured {
+            directory: SourceKind::Google.to_string(),
+            name: name.to_owned(),
+        };
+        let key_file = self
+            .google_service_account_file
+            .as_deref()
+            .ok_or_else(|| not_configured("GOOGLE_SERVICE_ACCOUNT_FILE"))?;
+        let admin_email = self
+            .google_admin_email
+            .as_deref()
+            .ok_or_else(|| not_configured("GOOGLE_ADMIN_EMAIL"))?;
+
+        GoogleDirectory::new(key_file, admin_email, &self.google_customer)
+    }
+}
+
+#[derive(Clap, Debug)]
+pub struct GithubOpts {
+    /// Where users' GitHub logins are found, matched by email: `csv:<path>` for a file of
+    /// `<email>,<login>` lines, `saml:<org>` for an organization's SAML SSO identities or
+    /// `scim:<JSON pointer>` for a custom field of Slack SCIM users
+    #[clap(long, env = "GITHUB_LOGINS")]
+    pub github_logins: Option<LoginSource>,
+
+    /// GitHub token of an organization owner, for `saml:<org>`
+    #[clap(long, env = "GITHUB_TOKEN")]
+    pub github_token: Option<String>,
+
+    /// Slack SCIM API token, for `scim:<JSON pointer>`
+    #[clap(long, env = "SLACK_SCIM_TOKEN")]
+    pub slack_scim_token: Option<String>,
+}
+
+#[derive(Clap, Debug)]
+pub struct AlertingOpts {
+    /// PagerDuty Events API v2 routing key. In daemon mode an incident is opened after
+    /// `--alert-after-failures` failed syncs in a row and resolved by the next successful one
+    #[clap(long, env = "PAGERDUTY_ROUTING_KEY")]
+    pub pagerduty_routing_key: Option<String>,
+
+    /// Consecutive failed syncs before an incident, or Opsgenie alert, is opened
+    #[clap(long, default_value = "3", env = "ALERT_AFTER_FAILURES")]
+    pub alert_after_failures: u32,
+}
+
+impl AlertingOpts {
+    /// Where to open incidents, given the shared `--opsgenie-api-key`.
+    pub fn notifiers(&self, opsgenie_api_key: Option<&str>) -> Vec<Notifier> {
+        self.pagerduty_routing_key
+            .iter()
+            .map(|routing_key| Notifier::pagerduty(routing_key))
+            .chain(opsgenie_api_key.map(Notifier::opsgenie))
+            .collect()
+    }
+}
+
 #[derive(Clap, Debug)]
-#[clap(author, about, version)]
+pub struct KubernetesOpts {
+    /// Where the lock keeping servers from syncing at the same time is kept: `redis`, or `k8s`
+    /// for a coordination.k8s.io Lease in the pod's namespace. The pod's service account needs to
+    /// be allowed to get, create and update leases. Not used with `--leader-election`
+    #[clap(long, default_value = "redis", env = "LOCK_BACKEND")]
+    pub lock_backend: LockBackend,
+
+    /// Name of the Lease `--lock-backend k8s` keeps the lock in
+    #[clap(long, default_value = "slack-user-cache-sync", env = "LOCK_LEASE_NAME")]
+    pub lock_lease_name: String,
+
+    /// Record an event on the pod after each sync, `SyncSucceeded` or `SyncFailed`, so syncs show
+    /// up in `kubectl describe pod`. The pod's service account needs to be allowed to create
+    /// events
+    #[clap(long, env = "KUBERNETES_EVENTS")]
+    pub kubernetes_events: bool,
+}
+
+#[derive(Clap, Debug)]
+#[clap(author, about, version, long_version = build_info::LONG_VERSION)]
 struct Opts {
     #[clap(subcommand)]
     subcmd: SubCommand,
     #[clap(flatten)]
     logging_opts: LoggingOpts,
+    /// Inject failures for resilience testing, as `redis-timeout=0.1,slack-429=0.05,slow=0.2,
+    /// slow-delay=2s`. Only debug builds and ones built with the `chaos` feature have it
+    #[cfg(any(debug_assertions, feature = "chaos"))]
+    #[clap(long, global(true), hidden = true, env = "CHAOS")]
+    chaos: Option<crate::libs::chaos::ChaosConfig>,
 }
 
 #[derive(Clap, Debug)]
@@ -55,47 +257,742 @@ enum SubCommand {
     UpdateRedis(UpdateRedisArgs),
     /// Web server that serves results from `update-redis` sub-command
     Web(WebArgs),
+    /// Keeps Redis up to date between syncs by applying user and group events Slack sends over
+    /// Socket Mode
+    SocketListener(SocketListenerArgs),
+    /// Prints how many keys the cache has and how much memory they use, by key prefix
+    Stats(StatsArgs),
+    /// Deletes cached keys starting with a prefix, e.g. to force a cold start after a schema
+    /// change. Lists them without deleting unless `--yes` is given
+    Purge(PurgeArgs),
+    /// Checks that every key users are looked up by email under agrees with the user's id record,
+    /// and optionally repairs the ones that don't
+    Verify(VerifyArgs),
+    /// Moves keys written by earlier versions to the layout `--key-prefix` sets up, lowercasing
+    /// emails and group names on the way. Counts them without copying unless `--yes` is given
+    Migrate(MigrateArgs),
+    /// Writes every cached user and group out as JSON, optionally with users replaced by
+    /// pseudonyms so the organization's structure can be studied without their details
+    Export(ExportArgs),
+    /// Shows the Slack User Groups that writing back synthetic groups would create or change,
+    /// member by member, and saves the plan for `apply`
+    Plan(WriteBackArgs),
+    /// Makes the changes to Slack the last `plan` showed, refusing if anything changed since
+    Apply(WriteBackArgs),
+    /// Opens a prompt for looking up cached users and groups straight from Redis, for when the
+    /// web server itself is the problem
+    Shell(ShellArgs),
+    /// Runs `web` and the `update-redis` daemon in one process, sharing Redis connections, for
+    /// installs too small for two deployments. Takes the options of both, with the ones they
+    /// share given once, and `--schedule` or `--interval` has to be set. Each reports its health
+    /// apart, at `/healthz/web` and `/healthz/updater`
+    AllInOne(Box<AllInOneArgs>),
+}
+
+#[derive(Clap, Debug, Clone)]
+pub struct SharedOpts {
+    #[clap(flatten)]
+    pub redis_opts: RedisOpts,
+
+    /// Slack API token. Permissions required: usergroups:read, users.profile:read, users:read,
+    /// users:read.email. `web` only needs users:read and users:read.email, for
+    /// `--shadow-lookup-rate`
+    #[clap(long, env = "SLACK_BOT_TOKEN")]
+    pub slack_token: Option<String>,
+
+    /// File containing the Slack API token. Takes precedence over `--slack-token`
+    #[clap(long, env = "SLACK_BOT_TOKEN_FILE")]
+    pub slack_token_file: Option<PathBuf>,
+
+    /// Seconds to wait for a connection to Slack to be established
+    #[clap(long, default_value = "10", env = "SLACK_CONNECT_TIMEOUT")]
+    pub slack_connect_timeout: u64,
+
+    /// Seconds to wait for Slack to respond to a single API request
+    #[clap(long, default_value = "30", env = "SLACK_REQUEST_TIMEOUT")]
+    pub slack_request_timeout: u64,
+
+    /// Seconds between TCP keepalive probes on connections to Slack
+    #[clap(long, default_value = "60", env = "SLACK_TCP_KEEPALIVE")]
+    pub slack_tcp_keepalive: u64,
+
+    /// Opsgenie API key. `web` uses it to find who is on call in Opsgenie schedules. In daemon
+    /// mode `update-redis` opens an alert with it after `--alert-after-failures` failed syncs in a
+    /// row, closed by the next successful one
+    #[clap(long, env = "OPSGENIE_API_KEY")]
+    pub opsgenie_api_key: Option<String>,
+
+    #[clap(flatten)]
+    pub key_opts: KeyOpts,
+
+    #[clap(flatten)]
+    pub privacy_opts: PrivacyOpts,
 }
 
+// The options `web` and `update-redis` share are kept apart from the rest, so `all-in-one` can
+// take them once for both.
 #[derive(Clap, Debug)]
 pub struct UpdateRedisArgs {
-    /// Unique ID to identify the server
+    #[clap(flatten)]
+    pub shared_opts: SharedOpts,
+
+    #[clap(flatten)]
+    pub updater_opts: UpdaterOpts,
+}
+
+#[derive(Clap, Debug)]
+pub struct UpdaterOpts {
+    /// Unique ID to identify the server. Defaults to the pod's name when run in Kubernetes
     #[clap(long, env = "SERVER_ID")]
     pub server_id: String,
 
-    /// Slack API token. Permissions required: usergroups:read, users.profile:read, users:read, users:read.email
-    #[clap(long, env = "SLACK_BOT_TOKEN")]
-    pub slack_token: String,
+    /// Vault KV v2 reference to the Slack API token, e.g. `secret/data/slack#bot_token`
+    #[cfg(feature = "vault")]
+    #[clap(long, env = "SLACK_BOT_TOKEN_VAULT")]
+    pub slack_token_vault: Option<String>,
+
+    /// Slack refresh token. When set the bot token is rotated automatically before it expires
+    #[clap(
+        long,
+        env = "SLACK_REFRESH_TOKEN",
+        requires_all = &["slack-client-id", "slack-client-secret"]
+    )]
+    pub slack_refresh_token: Option<String>,
+
+    /// Client ID of the Slack app, used to refresh the bot token
+    #[clap(long, env = "SLACK_CLIENT_ID")]
+    pub slack_client_id: Option<String>,
+
+    /// Client secret of the Slack app, used to refresh the bot token
+    #[clap(long, env = "SLACK_CLIENT_SECRET")]
+    pub slack_client_secret: Option<String>,
 
-    /// Address of the Redis Server
-    #[clap(long, default_value = "redis://127.0.0.1/", env = "REDIS_ADDRESS")]
-    pub redis_address: String,
+    /// File where rotated Slack tokens are kept between runs. Written with owner-only permissions
+    #[clap(long, env = "SLACK_TOKEN_STORE")]
+    pub slack_token_store: Option<PathBuf>,
+
+    /// Most Slack API calls a sync may make. A sync that runs out stops without finishing, and
+    /// leaves the cache as the pages it got through left it. Unlimited when unset
+    #[clap(long, env = "MAX_API_CALLS")]
+    pub max_api_calls: Option<u64>,
 
     /// Disable everything but error logging
     #[clap(short, long)]
     pub ignore_lock: bool,
+
+    /// Elect one replica through a lease in Redis to do all syncing, instead of racing for the
+    /// write lock on every run. Standby replicas take over when the leader stops renewing it
+    #[clap(long, env = "LEADER_ELECTION")]
+    pub leader_election: bool,
+
+    /// Seconds the leader lease lasts without being renewed
+    #[clap(long, default_value = "30", env = "LEADER_LEASE_TTL")]
+    pub leader_lease_ttl: u64,
+
+    /// Comma separated Slack user ids whose App Home tab shows the result of each sync
+    #[clap(long, env = "APP_HOME_USERS")]
+    pub app_home_users: Option<String>,
+
+    /// Slack channel that is sent the error when a sync fails. The bot needs `chat:write`
+    #[clap(long, env = "ALERT_CHANNEL")]
+    pub alert_channel: Option<String>,
+
+    /// Keep running and sync every this many seconds instead of exiting after one sync
+    #[clap(long, env = "SYNC_INTERVAL", parse(try_from_str = parse_interval))]
+    pub interval: Option<u64>,
+
+    /// Keep running and sync on a crontab schedule, e.g. `0 */4 * * *`. Syncs once at start up
+    #[clap(long, env = "SYNC_SCHEDULE", conflicts_with = "interval", parse(try_from_str = parse_cron))]
+    pub schedule: Option<Schedule>,
+
+    /// Wait a random time up to this long, e.g. `120s`, before the first sync. Scheduled syncs
+    /// are shifted by the same amount, so replicas started together don't race for the lock
+    #[clap(long, env = "START_SPLAY", parse(try_from_str = humantime::parse_duration))]
+    pub start_splay: Option<Duration>,
+
+    /// Timezone `--schedule` is evaluated in, e.g. `America/Los_Angeles`
+    #[clap(long, env = "SYNC_TIMEZONE", default_value = "UTC")]
+    pub timezone: Tz,
+
+    /// Comma separated rules for other addresses users can be looked up by.
+    /// `first-initial-last` maps `jane.doe@x.com` to `jdoe@x.com`, and `domain:<from>=<to>` maps
+    /// `jane@<from>` to `jane@<to>`. Aliases that are someone's real email, or that several users
+    /// would get, are skipped
+    #[clap(long, env = "EMAIL_ALIAS_RULES", use_delimiter = true)]
+    pub email_alias_rules: Vec<AliasRule>,
+
+    /// Comma separated user group names or ids. Only their members are cached. A group that
+    /// doesn't exist fails the sync
+    #[clap(long, env = "ONLY_GROUPS", use_delimiter = true)]
+    pub only_groups: Vec<String>,
+
+    /// Comma separated user group names or ids whose members aren't cached, even when they're
+    /// in one of `--only-groups`
+    #[clap(long, env = "EXCLUDE_GROUPS", use_delimiter = true)]
+    pub exclude_groups: Vec<String>,
+
+    /// Directory users and groups are synced from, `slack` or `google`. Users synced from
+    /// Google Workspace can only be looked up by email
+    #[clap(long, default_value = "slack", env = "SOURCE")]
+    pub source: SourceKind,
+
+    /// Comma separated directories whose users and groups are merged into the cache after
+    /// `--source`'s, matching users by email
+    #[clap(long, env = "EXTRA_SOURCES", use_delimiter = true)]
+    pub extra_sources: Vec<SourceKind>,
+
+    /// Keep up to this many changes to each group's name and members, served by
+    /// `/slack/user_group/id/{id}/history`. 0 keeps no history
+    #[clap(long, default_value = "0", env = "MEMBERSHIP_HISTORY")]
+    pub membership_history: usize,
+
+    /// Keep earlier versions of each user for this long, e.g. `90d`, so
+    /// `/slack/user/id/{id}?as_of=` can answer for times within it
+    #[clap(long, env = "USER_HISTORY_RETENTION", parse(try_from_str = humantime::parse_duration))]
+    pub user_history_retention: Option<Duration>,
+
+    /// Also cache each user's do-not-disturb schedule and status for this long, e.g. `20m`,
+    /// served by `/slack/user/id/{id}/dnd`. They're refreshed every sync, so this should be a
+    /// little longer than the time between them. Needs the `dnd:read` scope
+    #[clap(long, env = "DND_TTL", parse(try_from_str = humantime::parse_duration))]
+    pub dnd_ttl: Option<Duration>,
+
+    /// Publish every user and group a sync adds, changes or removes to the `changes` Redis
+    /// Stream, which `web` serves at `/slack/changes/stream`
+    #[clap(long, env = "PUBLISH_CHANGES")]
+    pub publish_changes: bool,
+
+    /// File of emails, one per line, of users expected to be in Slack, e.g. exported from LDAP.
+    /// Any users.list didn't return are looked up one at a time with users.lookupByEmail, which
+    /// finds guests users.list leaves out, and cached marked `backfilled`
+    #[clap(long, env = "BACKFILL_EMAILS")]
+    pub backfill_emails: Option<PathBuf>,
+
+    /// How long an email `--backfill-emails` found no Slack user for isn't looked up again
+    #[clap(
+        long,
+        default_value = "24h",
+        env = "BACKFILL_MISS_TTL",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    pub backfill_miss_ttl: Duration,
+
+    /// Most users `--backfill-emails` looks up in a sync. Any beyond it are left for later syncs
+    #[clap(long, default_value = "500", env = "BACKFILL_MAX_LOOKUPS")]
+    pub backfill_max_lookups: usize,
+
+    /// Create and update a Slack User Group for each synthetic group made through `web`'s
+    /// `/admin/groups`, so they can be mentioned in Slack. Needs Slack as the source and the bot
+    /// needs `usergroups:write`. Leave it off to review each change with `plan` and `apply`
+    #[clap(long, env = "WRITE_BACK_GROUPS")]
+    pub write_back_groups: bool,
+
+    /// Log what `--write-back-groups` would change in Slack, without changing it
+    #[clap(long, env = "WRITE_BACK_DRY_RUN", requires = "write-back-groups")]
+    pub write_back_dry_run: bool,
+
+    /// Fail a sync that fetches fewer than this fraction of the users the last sync cached, e.g.
+    /// `0.5` fails one that finds under half. A scope change or Slack bug returning a handful of
+    /// users would otherwise leave everyone else to expire. `0` turns the check off
+    #[clap(
+        long,
+        default_value = "0.5",
+        env = "MIN_USER_RATIO",
+        parse(try_from_str = summary::parse_min_ratio)
+    )]
+    pub min_user_ratio: f64,
+
+    /// Sync even when `--min-user-ratio` would fail it, once the drop in users is known to be
+    /// expected. Only the first sync is forced, and it can't be set from the environment, so it
+    /// can't be left on by mistake
+    #[clap(long)]
+    pub force: bool,
+
+    /// Save progress through users.list after every page, and have the next sync carry on from
+    /// it when it was saved within this long, e.g. `30m`. Progress can include plain emails, so
+    /// this can't be combined with `--email-hash-salt`
+    #[clap(
+        long,
+        env = "RESUME_SYNC_WITHIN",
+        conflicts_with = "email-hash-salt",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    pub resume_sync_within: Option<Duration>,
+
+    /// Collect fetched users in a staging area and only cache them once the whole sync passes
+    /// validation: there are users, every user has an id and email, every group has an id, and
+    /// `--min-user-ratio` holds for both users and groups. A sync that fails it leaves the users
+    /// and groups of the last sync in place and is recorded for `/admin/debug` until one passes.
+    /// Staged users include plain emails, so this can't be combined with `--email-hash-salt`
+    #[clap(long, env = "STAGED_SYNC", conflicts_with = "email-hash-salt")]
+    pub staged_sync: bool,
+
+    /// Encoding of cached users and groups, `json` or `msgpack`. MessagePack takes less memory
+    /// in Redis. `web` reads either, so this can be changed without restarting it
+    #[clap(long, default_value = "json", env = "VALUE_FORMAT")]
+    pub value_format: ValueFormat,
+
+    /// zstd compress cached values of at least this many bytes, such as groups with thousands of
+    /// members. `web` reads compressed values whether or not this is set
+    #[clap(long, env = "COMPRESS_VALUES_OVER")]
+    pub compress_values_over: Option<usize>,
+
+    /// Rhai script each user and group is run through before it's cached, to rename fields,
+    /// derive values or drop PII. It changes `record` in place, or sets it to `()` to skip it
+    #[cfg(feature = "transform")]
+    #[clap(long, env = "TRANSFORM_SCRIPT")]
+    pub transform_script: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub alerting_opts: AlertingOpts,
+
+    #[clap(flatten)]
+    pub kubernetes_opts: KubernetesOpts,
+
+    #[clap(flatten)]
+    pub domain_opts: DomainOpts,
+
+    #[clap(flatten)]
+    pub google_opts: GoogleOpts,
+
+    #[clap(flatten)]
+    pub github_opts: GithubOpts,
 }
 
 #[derive(Clap, Debug)]
 pub struct WebArgs {
-    /// Address of the Redis Server
-    #[clap(long, default_value = "redis://127.0.0.1/", env = "REDIS_ADDRESS")]
-    pub redis_address: String,
+    #[clap(flatten)]
+    pub shared_opts: SharedOpts,
+
+    #[clap(flatten)]
+    pub web_opts: WebOpts,
+}
+
+#[derive(Clap, Debug)]
+pub struct WebOpts {
+    /// Address of a Redis replica to read from, leaving writes to `--redis-address`. Repeat it,
+    /// or separate addresses with commas, to spread reads across several. Replicas that fail a
+    /// health check are skipped until they pass one again
+    #[clap(long, env = "REDIS_READ_ADDRESS", use_delimiter = true)]
+    pub redis_read_address: Vec<String>,
 
     /// Where the Server should listen on
     #[clap(long, default_value = "0.0.0.0:3000", env = "LISTEN_ADDRESS")]
     pub listen_server: String,
+
+    /// File with one API token per line as `<id> <token> <permission>,... <quota>`. Permissions
+    /// are `read-users`, `read-emails`, `read-groups` and `admin`. The optional quota, like
+    /// `hour=1000,day=20000`, limits the requests the token can make, with a 429 once it's used
+    /// up. Quotas are counted in Redis. When set, `/slack` endpoints require
+    /// `Authorization: Bearer <token>`. `/admin` endpoints always need an `admin` token, so
+    /// they're refused without this. Re-read on SIGHUP
+    #[clap(long, env = "API_TOKENS_FILE")]
+    pub api_tokens_file: Option<PathBuf>,
+
+    /// Accept JWTs issued by this OIDC issuer as bearer tokens. Token scopes named like the
+    /// permissions above grant them
+    #[clap(long, env = "OIDC_ISSUER", requires = "oidc-audience")]
+    pub oidc_issuer: Option<String>,
+
+    /// Audience JWTs must be issued for
+    #[clap(long, env = "OIDC_AUDIENCE", requires = "oidc-issuer")]
+    pub oidc_audience: Option<String>,
+
+    /// JWKS with the issuer's signing keys. Found through the issuer's discovery document if unset
+    #[clap(long, env = "OIDC_JWKS_URL", requires = "oidc-issuer")]
+    pub oidc_jwks_url: Option<String>,
+
+    /// Comma separated list of user and group fields that may be returned, e.g. `id,name`.
+    /// Clients can narrow this further with `?fields=`
+    #[clap(long, env = "ALLOWED_FIELDS")]
+    pub allowed_fields: Option<String>,
+
+    /// Report `/readyz` as unavailable once the last successful sync is older than this many
+    /// seconds
+    #[clap(long, env = "MAX_STALENESS")]
+    pub max_staleness: Option<u64>,
+
+    /// Add an `X-Cache-Age` header with the seconds since the last successful sync to `/slack`
+    /// responses
+    #[clap(long, env = "CACHE_AGE_HEADER")]
+    pub cache_age_header: bool,
+
+    /// `Cache-Control` sent with single users and groups, which also carry `Last-Modified` and
+    /// answer `If-Modified-Since` with `304 Not Modified`. Responses need a token by default, so
+    /// only set it to `public` when shared caches in front of the server check tokens too
+    #[clap(long, default_value = "private, no-cache", env = "CACHE_CONTROL")]
+    pub cache_control: HeaderValue,
+
+    /// Most `/slack` and `/admin` requests answered at once. Beyond it and the queue, requests
+    /// get `503 Service Unavailable` straight away
+    #[clap(
+        long,
+        env = "MAX_CONCURRENT_REQUESTS",
+        parse(try_from_str = concurrency::parse_max_requests)
+    )]
+    pub max_concurrent_requests: Option<usize>,
+
+    /// Comma separated limits on requests answered at once by single endpoints, e.g.
+    /// `/slack/users=4,/slack/user/id/{id}=32`
+    #[clap(long, env = "ROUTE_CONCURRENCY_LIMITS", use_delimiter = true)]
+    pub route_concurrency_limits: Vec<RouteLimit>,
+
+    /// Requests that may wait for a turn under each of the limits above
+    #[clap(long, default_value = "32", env = "MAX_QUEUED_REQUESTS")]
+    pub max_queued_requests: usize,
+
+    /// Seconds between TCP keepalive probes on client connections. Off when unset
+    #[clap(long, env = "TCP_KEEPALIVE")]
+    pub tcp_keepalive: Option<u64>,
+
+    /// Close HTTP/1.1 connections after every response instead of keeping them open
+    #[clap(long, env = "DISABLE_HTTP1_KEEPALIVE")]
+    pub disable_http1_keepalive: bool,
+
+    /// Seconds between pings on HTTP/2 connections, which are closed once a ping goes unanswered
+    /// for `--http2-keepalive-timeout`. Off when unset. HTTP/2 is served without TLS (h2c) to
+    /// clients that start with it, alongside HTTP/1.1
+    #[clap(long, env = "HTTP2_KEEPALIVE_INTERVAL")]
+    pub http2_keepalive_interval: Option<u64>,
+
+    /// Seconds to wait for an HTTP/2 ping to be answered
+    #[clap(long, default_value = "20", env = "HTTP2_KEEPALIVE_TIMEOUT")]
+    pub http2_keepalive_timeout: u64,
+
+    /// Most requests a single HTTP/2 connection may have open at once. Unlimited when unset
+    #[clap(long, env = "HTTP2_MAX_CONCURRENT_STREAMS")]
+    pub http2_max_concurrent_streams: Option<u32>,
+
+    /// Largest request body, in bytes, the routes that take one accept. Larger ones are answered
+    /// with a 413
+    #[clap(long, default_value = "16384", env = "MAX_BODY_SIZE")]
+    pub max_body_size: u64,
+
+    /// Most entries a list route returns at once. Clients can page through longer lists with
+    /// `limit` and `cursor`; ones that don't get the first entries, marked `truncated`
+    #[clap(
+        long,
+        default_value = "5000",
+        env = "MAX_LIST_ENTRIES",
+        parse(try_from_str = paging::parse_max_entries)
+    )]
+    pub max_list_entries: usize,
+
+    /// Puts the server in maintenance: everything under `/slack` is answered with a 503 carrying
+    /// this message, while admin routes, health checks and metrics keep working
+    #[clap(long, alias = "read-only-banner", env = "MAINTENANCE_MESSAGE")]
+    pub maintenance_message: Option<String>,
+
+    /// Serve a page at `/ui` for browsing and searching cached users and groups, for people who'd
+    /// rather not use curl. It calls the JSON routes, so with `--api-tokens-file` it asks for a
+    /// token
+    #[cfg(feature = "ui")]
+    #[clap(long, env = "SERVE_UI")]
+    pub serve_ui: bool,
+
+    /// Signing secret of the Slack app. Enables the `/whois` slash command at `POST /slack/command`
+    #[clap(long, env = "SLACK_SIGNING_SECRET")]
+    pub slack_signing_secret: Option<String>,
+
+    /// File containing the Slack signing secret. Takes precedence over `--slack-signing-secret`
+    #[clap(long, env = "SLACK_SIGNING_SECRET_FILE")]
+    pub slack_signing_secret_file: Option<PathBuf>,
+
+    /// Fraction of `/slack/user/email` lookups, from 0 to 1, that are also looked up with Slack's
+    /// users.lookupByEmail in the background. Answers that differ from the cache's are logged and
+    /// counted in `shadow_lookups_total`. Needs `--slack-token`
+    #[clap(
+        long,
+        default_value = "0",
+        env = "SHADOW_LOOKUP_RATE",
+        parse(try_from_str = shadow::parse_sample_rate)
+    )]
+    pub shadow_lookup_rate: f64,
+
+    /// Record every `/slack` request (token id, route, query, status) to this file as JSON lines,
+    /// or to the Redis stream `audit_log` when set to `redis`
+    #[clap(long, env = "AUDIT_LOG")]
+    pub audit_log: Option<String>,
+
+    /// Write an Apache-style access log of every request to this file, or to stdout when set to
+    /// `-`. Each line ends with the time taken in microseconds
+    #[clap(long, env = "ACCESS_LOG")]
+    pub access_log: Option<String>,
+
+    /// Layout of the access log, `common` or `combined`
+    #[clap(long, default_value = "combined", env = "ACCESS_LOG_FORMAT")]
+    pub access_log_format: AccessLogFormat,
+
+    /// File with one on-call schedule per line as `<group id> <provider> <schedule id>`, where
+    /// the provider is `pagerduty` or `opsgenie`. Registered at start up, alongside ones set
+    /// through `PUT /admin/user_group/id/{id}/oncall`
+    #[clap(long, env = "ONCALL_SCHEDULES_FILE")]
+    pub oncall_schedules_file: Option<PathBuf>,
+
+    /// PagerDuty REST API token, used to find who is on call in PagerDuty schedules
+    #[clap(long, env = "PAGERDUTY_API_TOKEN")]
+    pub pagerduty_api_token: Option<String>,
+}
+
+#[derive(Clap, Debug)]
+pub struct AllInOneArgs {
+    #[clap(flatten)]
+    pub shared_opts: SharedOpts,
+
+    #[clap(flatten)]
+    pub web_opts: WebOpts,
+
+    #[clap(flatten)]
+    pub updater_opts: UpdaterOpts,
+}
+
+#[derive(Clap, Debug)]
+pub struct SocketListenerArgs {
+    /// Slack app-level token with the `connections:write` scope. The app needs Socket Mode on
+    /// and to be subscribed to the `team_join`, `user_change` and `subteam_*` events
+    #[clap(long, env = "SLACK_APP_TOKEN")]
+    pub slack_app_token: Option<String>,
+
+    /// File containing the Slack app-level token. Takes precedence over `--slack-app-token`
+    #[clap(long, env = "SLACK_APP_TOKEN_FILE")]
+    pub slack_app_token_file: Option<PathBuf>,
+
+    /// Seconds to wait for a connection to Slack to be established
+    #[clap(long, default_value = "10", env = "SLACK_CONNECT_TIMEOUT")]
+    pub slack_connect_timeout: u64,
+
+    /// Seconds to wait for Slack to respond to a single API request
+    #[clap(long, default_value = "30", env = "SLACK_REQUEST_TIMEOUT")]
+    pub slack_request_timeout: u64,
+
+    /// Seconds between TCP keepalive probes on connections to Slack
+    #[clap(long, default_value = "60", env = "SLACK_TCP_KEEPALIVE")]
+    pub slack_tcp_keepalive: u64,
+
+    #[clap(flatten)]
+    pub redis_opts: RedisOpts,
+
+    /// Same rules as `update-redis`. Only the changed user is checked for clashing aliases, the
+    /// next sync catches any with other users
+    #[clap(long, env = "EMAIL_ALIAS_RULES", use_delimiter = true)]
+    pub email_alias_rules: Vec<AliasRule>,
+
+    /// Same as `update-redis`. Membership is taken from the cached groups
+    #[clap(long, env = "ONLY_GROUPS", use_delimiter = true)]
+    pub only_groups: Vec<String>,
+
+    /// Same as `update-redis`
+    #[clap(long, env = "EXCLUDE_GROUPS", use_delimiter = true)]
+    pub exclude_groups: Vec<String>,
+
+    /// Same as `update-redis`
+    #[clap(long, default_value = "json", env = "VALUE_FORMAT")]
+    pub value_format: ValueFormat,
+
+    /// Same as `update-redis`
+    #[clap(long, env = "COMPRESS_VALUES_OVER")]
+    pub compress_values_over: Option<usize>,
+
+    /// Rhai script each user and group is run through before it's cached, to rename fields,
+    /// derive values or drop PII. It changes `record` in place, or sets it to `()` to skip it
+    #[cfg(feature = "transform")]
+    #[clap(long, env = "TRANSFORM_SCRIPT")]
+    pub transform_script: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub domain_opts: DomainOpts,
+
+    #[clap(flatten)]
+    pub key_opts: KeyOpts,
+
+    #[clap(flatten)]
+    pub privacy_opts: PrivacyOpts,
+}
+
+#[derive(Clap, Debug)]
+pub struct StatsArgs {
+    #[clap(flatten)]
+    pub redis_opts: RedisOpts,
+
+    /// How many of the keys using the most memory to list
+    #[clap(long, default_value = "10")]
+    pub largest: usize,
+
+    #[clap(flatten)]
+    pub key_opts: KeyOpts,
+}
+
+#[derive(Clap, Debug)]
+pub struct PurgeArgs {
+    #[clap(flatten)]
+    pub redis_opts: RedisOpts,
+
+    /// Delete keys starting with this, e.g. `user:` or `user_group:`. Must be the start of one of
+    /// the app's own keys, so other data in the same Redis is left alone
+    #[clap(long, parse(try_from_str = parse_key_prefix))]
+    pub prefix: String,
+
+    /// Delete the keys instead of listing them
+    #[clap(long)]
+    pub yes: bool,
+
+    #[clap(flatten)]
+    pub key_opts: KeyOpts,
+}
+
+#[derive(Clap, Debug)]
+pub struct VerifyArgs {
+    #[clap(flatten)]
+    pub redis_opts: RedisOpts,
+
+    /// Delete orphaned and stale email keys, and rewrite mismatched and missing ones from the id
+    /// records, instead of only reporting them. Refused unless every option here matches the
+    /// ones the last sync ran with
+    #[clap(long)]
+    pub repair: bool,
+
+    /// The rules `update-redis` runs with, so alias keys aren't taken for stale ones
+    #[clap(long, env = "EMAIL_ALIAS_RULES", use_delimiter = true)]
+    pub email_alias_rules: Vec<AliasRule>,
+
+    /// Same as `update-redis`. Like the other options, it has to match the last sync's to repair
+    #[clap(long, default_value = "json", env = "VALUE_FORMAT")]
+    pub value_format: ValueFormat,
+
+    #[clap(flatten)]
+    pub key_opts: KeyOpts,
+
+    #[clap(flatten)]
+    pub privacy_opts: PrivacyOpts,
+}
+
+#[derive(Clap, Debug)]
+pub struct MigrateArgs {
+    #[clap(flatten)]
+    pub redis_opts: RedisOpts,
+
+    /// Prefix to move keys under. Without one, keys stay where they are and only emails and group
+    /// names are lowercased
+    #[clap(long, env = "KEY_PREFIX", parse(try_from_str = key_layout::parse_prefix))]
+    pub key_prefix: Option<String>,
+
+    /// Delete the old keys once they're copied. Only once every server runs with the new
+    /// `--key-prefix`, without `--dual-write-legacy-keys`
+    #[clap(long)]
+    pub delete_legacy_keys: bool,
+
+    /// Copy the keys instead of only counting them
+    #[clap(long)]
+    pub yes: bool,
+}
+
+#[derive(Clap, Debug)]
+pub struct ExportArgs {
+    #[clap(flatten)]
+    pub redis_opts: RedisOpts,
+
+    /// File to write the export to. Printed when not given, with logs written to stderr
+    #[clap(long)]
+    pub output: Option<PathBuf>,
+
+    /// Replace each user's id, name and email with a pseudonym, the HMAC of their id under
+    /// `--pseudonym-key`, and leave out their avatars, annotations and GitHub login. Groups list
+    /// their members by pseudonym
+    #[clap(long, requires = "pseudonym-key")]
+    pub anonymize: bool,
+
+    /// Key pseudonyms are made with. Exports made with the same key use the same pseudonyms
+    #[clap(long, env = "EXPORT_PSEUDONYM_KEY")]
+    pub pseudonym_key: Option<String>,
+
+    #[clap(flatten)]
+    pub key_opts: KeyOpts,
+
+    #[clap(flatten)]
+    pub privacy_opts: PrivacyOpts,
+}
+
+#[derive(Clap, Debug)]
+pub struct WriteBackArgs {
+    #[clap(flatten)]
+    pub redis_opts: RedisOpts,
+
+    /// Slack API token. Permissions required: usergroups:read, usergroups:write
+    #[clap(long, env = "SLACK_BOT_TOKEN")]
+    pub slack_token: Option<String>,
+
+    /// File containing the Slack API token. Takes precedence over `--slack-token`
+    #[clap(long, env = "SLACK_BOT_TOKEN_FILE")]
+    pub slack_token_file: Option<PathBuf>,
+
+    /// Same as `update-redis`
+    #[clap(long, default_value = "10", env = "SLACK_CONNECT_TIMEOUT")]
+    pub slack_connect_timeout: u64,
+
+    /// Same as `update-redis`
+    #[clap(long, default_value = "30", env = "SLACK_REQUEST_TIMEOUT")]
+    pub slack_request_timeout: u64,
+
+    /// Same as `update-redis`
+    #[clap(long, default_value = "60", env = "SLACK_TCP_KEEPALIVE")]
+    pub slack_tcp_keepalive: u64,
+
+    /// Opsgenie API key. `web` uses it to find who is on call in Opsgenie schedules. In daemon
+    /// mode `update-redis` opens an alert with it after `--alert-after-failures` failed syncs in a
+    /// row, closed by the next successful one
+    #[clap(long, env = "OPSGENIE_API_KEY")]
+    pub opsgenie_api_key: Option<String>,
+
+    #[clap(flatten)]
+    pub key_opts: KeyOpts,
+}
+
+#[derive(Clap, Debug)]
+pub struct ShellArgs {
+    #[clap(flatten)]
+    pub redis_opts: RedisOpts,
+
+    #[clap(flatten)]
+    pub key_opts: KeyOpts,
+
+    #[clap(flatten)]
+    pub privacy_opts: PrivacyOpts,
 }
 
 #[tokio::main]
 pub async fn main() {
     dotenv().ok();
+    // Pods are told apart by name, so in Kubernetes `--server-id` can be left out.
+    if env::var_os("SERVER_ID").is_none() {
+        if let Some(pod_name) = kubernetes::pod_name() {
+            env::set_var("SERVER_ID", pod_name);
+        }
+    }
 
     let opt = Opts::parse();
-    init_logger(&opt.logging_opts);
+    // An export without `--output` is printed, so logs can't be mixed in with it.
+    let progress = match &opt.subcmd {
+        SubCommand::Export(args) if args.output.is_none() => Progress::logging_to_stderr(),
+        _ => Progress::default(),
+    };
+    init_logger(&opt.logging_opts, progress.clone());
+    #[cfg(any(debug_assertions, feature = "chaos"))]
+    if let Some(chaos) = &opt.chaos {
+        crate::libs::chaos::enable(chaos);
+    }
     let result = match opt.subcmd {
-        SubCommand::UpdateRedis(args) => crate::commands::redis_update(&args).await,
+        SubCommand::UpdateRedis(args) => crate::commands::redis_update(&args, &progress).await,
         SubCommand::Web(args) => crate::commands::web_server(&args).await,
+        SubCommand::SocketListener(args) => crate::commands::socket_listener(&args).await,
+        SubCommand::Stats(args) => crate::commands::cache_stats(&args).await,
+        SubCommand::Purge(args) => crate::commands::purge(&args).await,
+        SubCommand::Verify(args) => crate::commands::verify(&args).await,
+        SubCommand::Migrate(args) => crate::commands::migrate(&args).await,
+        SubCommand::Export(args) => crate::commands::export(&args).await,
+        SubCommand::Plan(args) => crate::commands::plan_write_back(&args).await,
+        SubCommand::Apply(args) => crate::commands::apply_write_back(&args).await,
+        SubCommand::Shell(args) => crate::commands::shell(&args).await,
+        SubCommand::AllInOne(args) => crate::commands::all_in_one(*args, &progress).await,
     };
 
     if let Err(e) = result {
@@ -104,15 +1001,19 @@ pub async fn main() {
     }
 }
 
-fn init_logger(logging_opts: &LoggingOpts) {
+fn init_logger(logging_opts: &LoggingOpts, progress: Progress) {
     use tracing_subscriber::FmtSubscriber;
     // a builder for `FmtSubscriber`.
     let subscriber = FmtSubscriber::builder()
         // all spans/events with a level higher than TRACE (e.g, debug, info, warn, etc.)
         // will be written to stdout.
         .with_max_level(logging_opts.to_level())
+        // log lines are printed above any progress bar, rather than over it.
+        .with_writer(progress)
         // completes the builder.
         .finish();
 
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+
+    crate::libs::redact::set_redact_pii(logging_opts.redact_pii);
 }
@@ -0,0 +1,134 @@
+// Browses the cache through the same JSON routes any other client uses.
+(function () {
+  "use strict";
+
+  var SEARCH_LIMIT = 25;
+
+  var token = document.getElementById("token");
+  var status = document.getElementById("status");
+  var detail = document.getElementById("detail");
+  var groups = null;
+
+  token.value = localStorage.getItem("slack-user-cache-token") || "";
+  token.addEventListener("change", function () {
+    localStorage.setItem("slack-user-cache-token", token.value);
+    groups = null;
+  });
+
+  function get(path) {
+    var headers = { Accept: "application/json" };
+    if (token.value) {
+      headers.Authorization = "Bearer " + token.value;
+    }
+    return fetch(path, { headers: headers }).then(function (response) {
+      return response.json().then(function (body) {
+        if (!body.success) {
+          throw new Error(body.message || response.statusText);
+        }
+        return body;
+      });
+    });
+  }
+
+  function say(message, failed) {
+    status.textContent = message;
+    status.className = failed ? "error" : "";
+  }
+
+  function row(cells, onClick) {
+    var tr = document.createElement("tr");
+    cells.forEach(function (value) {
+      var td = document.createElement("td");
+      td.textContent = value == null ? "" : value;
+      tr.appendChild(td);
+    });
+    tr.addEventListener("click", onClick);
+    return tr;
+  }
+
+  function show(path) {
+    say("Loading " + path);
+    get(path)
+      .then(function (body) {
+        detail.textContent = JSON.stringify(body.result, null, 2);
+        detail.hidden = false;
+        say("");
+      })
+      .catch(function (e) {
+        say(e.message, true);
+      });
+  }
+
+  document.querySelectorAll(".tab").forEach(function (tab) {
+    tab.addEventListener("click", function () {
+      document.querySelectorAll(".tab").forEach(function (other) {
+        var selected = other === tab;
+        other.classList.toggle("active", selected);
+        document.getElementById(other.dataset.tab).hidden = !selected;
+      });
+      detail.hidden = true;
+    });
+  });
+
+  document.getElementById("user-search").addEventListener("submit", function (event) {
+    event.preventDefault();
+    var query = document.getElementById("user-query").value.trim();
+    if (!query) {
+      return;
+    }
+    var fuzzy = document.getElementById("user-fuzzy").checked;
+    var route = fuzzy ? "/slack/users/fuzzy" : "/slack/users/typeahead";
+    var results = document.getElementById("user-results");
+    say("Searching");
+    get(route + "?limit=" + SEARCH_LIMIT + "&q=" + encodeURIComponent(query))
+      .then(function (body) {
+        results.textContent = "";
+        body.result.forEach(function (found) {
+          var user = fuzzy ? found.user : found;
+          results.appendChild(
+            row([user.name, user["display-name"], user.email, user.id], function () {
+              show("/slack/user/id/" + encodeURIComponent(user.id));
+            })
+          );
+        });
+        say(body.result.length + " users found");
+      })
+      .catch(function (e) {
+        say(e.message, true);
+      });
+  });
+
+  function loadGroups(cursor, loaded) {
+    var path = "/slack/user_groups" + (cursor ? "?cursor=" + encodeURIComponent(cursor) : "");
+    return get(path).then(function (body) {
+      loaded = loaded.concat(body.result);
+      return body.next_cursor ? loadGroups(body.next_cursor, loaded) : loaded;
+    });
+  }
+
+  document.getElementById("group-search").addEventListener("submit", function (event) {
+    event.preventDefault();
+    var filter = document.getElementById("group-query").value.trim().toLowerCase();
+    var results = document.getElementById("group-results");
+    say("Loading groups");
+    (groups ? Promise.resolve(groups) : loadGroups(null, []))
+      .then(function (loaded) {
+        groups = loaded;
+        var matching = groups.filter(function (group) {
+          return group.name.toLowerCase().indexOf(filter) !== -1;
+        });
+        results.textContent = "";
+        matching.forEach(function (group) {
+          results.appendChild(
+            row([group.name, (group.users || []).length, group.id], function () {
+              show("/slack/user_group/name/" + encodeURIComponent(group.name));
+            })
+          );
+        });
+        say(matching.length + " of " + groups.length + " groups");
+      })
+      .catch(function (e) {
+        say(e.message, true);
+      });
+  });
+})();